@@ -328,9 +328,8 @@ extern "C" fn __primary_switched(kernel_start_pa: usize, fdt_pa: usize, _cpu_boo
     kernel::arch::arm64::mm::va_layout::set_kimage_va_offset(kimage_va_offset);
     // save fdt
     kernel::arch::arm64::kernel::setup::set_fdt_pointer(PhysAddr::from(fdt_pa));
-    // TODO: init vbar
-    //VbarEl1::write_raw();
-    isb();
+    // take the exceptions from now on
+    kernel::arch::arm64::kernel::entry::vectors_init();
 
     // init cpu task, reset sp equal task sp
     __init_cpu_task(&kernel::init::init_task::INIT_TASK_REF);
@@ -1,11 +1,420 @@
-//! amba_pl011.rs
+//! ARM AMBA PrimeCell PL011 UART
+//!
+//! Refer to linux: drivers/tty/serial/amba-pl011.c
+//!
+//! Ports are platform devices and named ttyAMA in probe order. The
+//! receive interrupt stores the characters for readers, the transmit
+//! interrupt refills the FIFO until the transmit buffer is empty. A port is
+//! started when its tty is opened. The console polls the FIFO with the
+//! interrupts masked, and takes over from the earlycon of the same port.
 
-use kernel::arch::arm64::early_debug::early_uart_put_str;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use kernel::arch::arm64::early_debug::pl011::*;
+use kernel::drivers::base::device::Device;
+use kernel::drivers::base::driver::Driver;
+use kernel::drivers::base::platform::{
+    module_platform_driver, platform_get_irq, platform_get_resource, ResourceType,
+};
+use kernel::drivers::fdt::{of_clk_get_rate, OfDeviceId};
 use kernel::drivers::tty::serial::earlycon::{earlycon_declare, EarlyConDevice};
+use kernel::drivers::tty::serial::serial_core::{
+    uart_console_write, uart_register_driver, UartDriver, UartOps, UartParity, UartSettings,
+    UartState, DEFAULT_BAUD, UART_NR,
+};
+use kernel::drivers::tty::serial::uart_port::{UartPort, UartPortIoType};
+use kernel::error::{Error, Result};
+use kernel::irq::{free_irq, request_irq, IrqReturn};
+use kernel::mm::{ioremap, iounmap, PhysAddr};
+use kernel::printk::console::{
+    register_console, Console, ConsoleFlags, ConsoleNode, CONSOLE_INDEX_ANY,
+};
+use kernel::sync::arc::{Arc, ArcInner};
+use kernel::sync::lock::RawSpinLockNoIrq;
+use kernel::types::OnceCell;
+
+/// uartclk when the device tree does not tell, as on QEMU.
+const PL011_DEFAULT_UARTCLK: u32 = 24_000_000;
+/// Part number in the peripheral id
+const PL011_PART_NUMBER: u32 = 0x011;
+/// Interrupt status is read at most this many times per interrupt.
+const AMBA_ISR_PASS_LIMIT: usize = 256;
+/// FIFO interrupts at half full, for both directions.
+const IFLS_RX4_8_TX4_8: u32 = (2 << 3) | 2;
+/// Major number of the ttyAMA ttys
+const SERIAL_AMBA_MAJOR: u32 = 204;
+/// Minor number of ttyAMA0
+const SERIAL_AMBA_MINOR: u32 = 64;
+
+fn pl011_putc(port: &UartPort, c: u8) {
+    // SAFETY: the earlycon maps the registers before the setup.
+    let uart = unsafe { Pl011Uart::new(port.membase() as *mut u8) };
+    uart.putchar(c);
+}
+
+fn pl011_early_write(port: &UartPort, s: &str) {
+    port.console_write(s, pl011_putc);
+}
+
+/// The firmware already programmed the baud rate, only printing is set up.
+fn pl011_earlycon_setup(dev: &EarlyConDevice, _options: Option<&str>) -> Result {
+    if dev.port().lock().membase() == 0 {
+        return Err(Error::Enodev);
+    }
+    dev.set_write(pl011_early_write);
+    Ok(())
+}
+
+earlycon_declare!(PL011_EARLYCON, "pl011", "arm,pl011", pl011_earlycon_setup);
+earlycon_declare!(
+    ARM_PL011_EARLYCON,
+    "pl011",
+    "amba_pl011",
+    pl011_earlycon_setup
+);
+
+/// A PL011 port
+struct AmbaPort {
+    uart: OnceCell<Pl011Uart>,
+    port: RawSpinLockNoIrq<UartPort>,
+    /// Interrupt mask, its lock serializes the register updates.
+    im: RawSpinLockNoIrq<u32>,
+    state: UartState,
+}
+
+static AMBA_PORTS: [AmbaPort; UART_NR] = [const { AmbaPort::new() }; UART_NR];
+/// Line of the next probed port
+static AMBA_NEXT_LINE: AtomicUsize = AtomicUsize::new(0);
+
+static AMBA_REG: UartDriver =
+    UartDriver::new("ttyAMA", "ttyAMA", SERIAL_AMBA_MAJOR, SERIAL_AMBA_MINOR);
+
+impl AmbaPort {
+    const fn new() -> Self {
+        Self {
+            uart: OnceCell::new(),
+            port: RawSpinLockNoIrq::new(UartPort::new_empty(), Some("pl011_port")),
+            im: RawSpinLockNoIrq::new(0, Some("pl011_im")),
+            state: UartState::new(),
+        }
+    }
+
+    fn uart(&self) -> Result<&Pl011Uart> {
+        self.uart.get().ok_or(Error::Enodev)
+    }
+
+    // Fill the FIFO, return whether characters are left to transmit.
+    fn tx_chars(&self, uart: &Pl011Uart) -> bool {
+        let fifosize = self.port.lock().fifosize();
+        for _ in 0..fifosize {
+            if uart.fr() & FR_TXFF != 0 {
+                return true;
+            }
+            match self.state.xmit_pop() {
+                Some(c) => uart.write_dr(c),
+                None => return false,
+            }
+        }
+        self.state.chars_pending() > 0
+    }
+
+    // Move the received characters to the receive buffer.
+    fn rx_chars(&self, uart: &Pl011Uart) {
+        let icount = self.state.icount();
+        while uart.fr() & FR_RXFE == 0 {
+            let ch = uart.read_dr();
+            if ch & DR_OE != 0 {
+                icount.overrun.fetch_add(1, Ordering::Relaxed);
+            }
+            if ch & DR_BE != 0 {
+                icount.brk.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+            if ch & DR_PE != 0 {
+                icount.parity.fetch_add(1, Ordering::Relaxed);
+            }
+            if ch & DR_FE != 0 {
+                icount.frame.fetch_add(1, Ordering::Relaxed);
+            }
+            self.state.insert_char(ch as u8);
+        }
+        self.state.flip_buffer_push();
+    }
+}
+
+impl UartOps for AmbaPort {
+    fn state(&self) -> &UartState {
+        &self.state
+    }
+
+    fn tx_empty(&self) -> bool {
+        self.uart()
+            .map_or(true, |uart| uart.fr() & (FR_BUSY | FR_TXFF) == 0)
+    }
+
+    fn start_tx(&self) {
+        let Ok(uart) = self.uart() else {
+            return;
+        };
+        {
+            let mut im = self.im.lock();
+            if self.tx_chars(uart) {
+                *im |= INT_TX;
+            } else {
+                *im &= !INT_TX;
+            }
+            uart.set_imsc(*im);
+        }
+        self.state.write_wakeup();
+    }
+
+    fn stop_tx(&self) {
+        if let Ok(uart) = self.uart() {
+            let mut im = self.im.lock();
+            *im &= !INT_TX;
+            uart.set_imsc(*im);
+        }
+    }
+
+    fn stop_rx(&self) {
+        if let Ok(uart) = self.uart() {
+            let mut im = self.im.lock();
+            *im &= !(INT_RX | INT_RT);
+            uart.set_imsc(*im);
+        }
+    }
+
+    fn startup(&self) -> Result {
+        let uart = self.uart()?;
+        let irq = self.port.lock().irq();
+        request_irq(irq, pl011_int, "uart-pl011", self as *const Self as usize)?;
+
+        uart.clear_interrupts(INT_ALL);
+        uart.set_ifls(IFLS_RX4_8_TX4_8);
+        uart.set_cr(CR_UARTEN | CR_TXE | CR_RXE | CR_RTS);
+        // Drop what was received before.
+        while uart.fr() & FR_RXFE == 0 {
+            uart.read_dr();
+        }
 
-fn pl011_earlycon_setup(_dev: &mut EarlyConDevice, _options: Option<&'static str>) {
-    early_uart_put_str("enter pl011_earlycon_setup\n");
+        let mut im = self.im.lock();
+        *im = INT_RX | INT_RT;
+        uart.set_imsc(*im);
+        Ok(())
+    }
+
+    fn shutdown(&self) {
+        let Ok(uart) = self.uart() else {
+            return;
+        };
+        {
+            let mut im = self.im.lock();
+            *im = 0;
+            uart.set_imsc(0);
+            uart.clear_interrupts(INT_ALL);
+        }
+        free_irq(self.port.lock().irq());
+        // The console may still print.
+        uart.set_cr(CR_UARTEN | CR_TXE);
+    }
+
+    fn set_termios(&self, settings: &UartSettings) {
+        let Ok(uart) = self.uart() else {
+            return;
+        };
+        let uartclk = self.port.lock().uartclk();
+        let baud = settings.baud.clamp(1, uartclk / 16) as u64;
+        // 16 times the baud rate, in 1/64 steps.
+        let quot = ((uartclk as u64 * 4 + baud / 2) / baud) as u32;
+
+        let mut lcrh = ((settings.bits.clamp(5, 8) - 5) as u32) << LCRH_WLEN_SHIFT | LCRH_FEN;
+        if settings.cstopb {
+            lcrh |= LCRH_STP2;
+        }
+        match settings.parity {
+            UartParity::None => {}
+            UartParity::Odd => lcrh |= LCRH_PEN,
+            UartParity::Even => lcrh |= LCRH_PEN | LCRH_EPS,
+        }
+
+        let _im = self.im.lock();
+        let mut cr = uart.cr() & !(CR_RTSEN | CR_CTSEN);
+        if settings.crtscts {
+            cr |= CR_RTSEN | CR_CTSEN;
+        }
+        while uart.fr() & FR_BUSY != 0 {}
+        uart.set_cr(0);
+        uart.set_baud_divisor(quot >> 6, quot & 0x3f);
+        // Latches the divisor.
+        uart.set_lcrh(lcrh);
+        uart.set_cr(cr);
+    }
+
+    fn port_type(&self) -> &'static str {
+        "PL011"
+    }
+
+    fn poll_put_char(&self, c: u8) {
+        if let Ok(uart) = self.uart() {
+            uart.putchar(c);
+        }
+    }
+
+    fn poll_get_char(&self) -> Option<u8> {
+        self.uart().ok()?.getchar()
+    }
+}
+
+fn pl011_int(_irq: u32, data: usize) -> IrqReturn {
+    // SAFETY: data is the static port given to request_irq.
+    let uap = unsafe { &*(data as *const AmbaPort) };
+    let Ok(uart) = uap.uart() else {
+        return IrqReturn::None;
+    };
+    let mut status = uart.mis();
+    if status == 0 {
+        return IrqReturn::None;
+    }
+    for _ in 0..AMBA_ISR_PASS_LIMIT {
+        // The FIFO interrupts clear once the FIFOs are served.
+        uart.clear_interrupts(status & !(INT_TX | INT_RT | INT_RX));
+        if status & (INT_RT | INT_RX) != 0 {
+            uap.rx_chars(uart);
+        }
+        if status & INT_TX != 0 {
+            uap.start_tx();
+        }
+        status = uart.mis();
+        if status == 0 {
+            break;
+        }
+    }
+    IrqReturn::Handled
+}
+
+fn pl011_console_write(s: &str) {
+    let index = AMBA_CONSOLE.index();
+    let Some(uap) = AMBA_PORTS.get(index as usize) else {
+        return;
+    };
+    let Ok(uart) = uap.uart() else {
+        return;
+    };
+    // Mask the interrupts while printing, and wait for the last char.
+    let im = uap.im.lock();
+    uart.set_imsc(0);
+    uart_console_write(s, |c| uart.putchar(c));
+    while uart.fr() & FR_BUSY != 0 {}
+    uart.set_imsc(*im);
+}
+
+/// Without options the probe settings are kept.
+fn pl011_console_setup(con: &Console, options: Option<&str>) -> Result {
+    let uap = AMBA_PORTS.get(con.index() as usize).ok_or(Error::Enodev)?;
+    uap.uart()?;
+    if let Some(options) = options {
+        uap.set_termios(&UartSettings::parse_options(options));
+    }
+    // Takes over from the earlycon at the same address.
+    con.set_device(uap.port.lock().mapbase() as usize);
+    Ok(())
 }
 
-earlycon_declare!(PL011_EARLYCON, "amba_pl011", pl011_earlycon_setup);
-earlycon_declare!(ARM_PL011_EARLYCON, "arm,pl011", pl011_earlycon_setup);
+/// SAFETY: we know what we are doing here.
+/// we use a satic mem to init Arc, if this init Arc refcont to 0, it will panic.
+static AMBA_CONSOLE_NODE: ArcInner<ConsoleNode> = ArcInner::new_static(ConsoleNode::new(
+    Console::empty("ttyAMA", ConsoleFlags::CON_PRINTBUFFER, CONSOLE_INDEX_ANY)
+        .with_write(pl011_console_write)
+        .with_setup(pl011_console_setup)
+        .with_tty_driver(&AMBA_REG),
+));
+
+// SAFETY: we know what we are doing here.
+// we use a satic mem to init Arc, if this init Arc refcont to 0, it will panic.
+static AMBA_CONSOLE: Arc<ConsoleNode> = unsafe { Arc::from_static(&AMBA_CONSOLE_NODE) };
+
+struct Pl011Driver;
+
+// Only the pl011 nodes, nothing unmaps the registers of another primecell.
+static PL011_IDS: [OfDeviceId; 1] = [OfDeviceId::new("arm,pl011")];
+
+impl Driver for Pl011Driver {
+    fn name(&self) -> &'static str {
+        "uart-pl011"
+    }
+
+    fn of_match_table(&self) -> &'static [OfDeviceId] {
+        &PL011_IDS
+    }
+
+    fn probe(&self, dev: &Arc<Device>) -> Result {
+        let node = dev.of_node().ok_or(Error::Enodev)?;
+        let reg = platform_get_resource(dev, ResourceType::Mem, 0).ok_or(Error::Einval)?;
+        let irq = platform_get_irq(dev, 0)?;
+        let mapbase = reg.start;
+        let size = reg.size().max(0x1000);
+        let membase = ioremap(PhysAddr::from(mapbase), size).ok_or(Error::Enomem)?;
+        // SAFETY: the registers were just mapped.
+        let uart = unsafe { Pl011Uart::new(membase.as_usize() as *mut u8) };
+        let periphid = uart.periphid();
+        // A node claiming to be a pl011 that is not
+        if periphid & 0xfff != PL011_PART_NUMBER {
+            iounmap(membase, size);
+            return Err(Error::Enodev);
+        }
+        let Ok(line) = AMBA_NEXT_LINE.fetch_update(Ordering::AcqRel, Ordering::Acquire, |line| {
+            (line < UART_NR).then_some(line + 1)
+        }) else {
+            iounmap(membase, size);
+            return Err(Error::Enospc);
+        };
+
+        let uap = &AMBA_PORTS[line];
+        uap.uart.set(uart);
+        {
+            let mut port = uap.port.lock();
+            port.set_iotype(UartPortIoType::Mem32);
+            port.set_mapbase(mapbase as u64);
+            port.set_membase(membase.as_usize());
+            port.set_irq(irq);
+            port.set_uartclk(
+                of_clk_get_rate(node, Some("uartclk")).unwrap_or(PL011_DEFAULT_UARTCLK),
+            );
+            // Revisions from r1p5 have 32 byte FIFOs.
+            port.set_fifosize(if (periphid >> 20) & 0xf >= 3 { 32 } else { 16 });
+        }
+        let baud = node
+            .property("current-speed")
+            .and_then(|p| p.as_usize())
+            .map_or(DEFAULT_BAUD, |speed| speed as u32);
+        uap.set_termios(&UartSettings::new(baud));
+
+        // The line stays used, its uart is already set.
+        if let Err(e) = AMBA_REG.add_one_port(line, uap) {
+            iounmap(membase, size);
+            return Err(e);
+        }
+        kernel::pr_info!(
+            "{}: ttyAMA{} at MMIO {:#x} (irq = {}, base_baud = {}) is a PL011\n",
+            dev.name(),
+            line,
+            mapbase,
+            irq,
+            uap.port.lock().uartclk() / 16
+        );
+        if line == 0 {
+            uart_register_driver(&AMBA_REG)?;
+        }
+
+        // Every port tries, console= may ask for a later one.
+        match register_console(AMBA_CONSOLE.clone()) {
+            Ok(()) | Err(Error::Eexist) | Err(Error::Enoent) | Err(Error::Enodev) => {}
+            Err(e) => kernel::pr_err!("{}: pl011 console failed: {:?}\n", dev.name(), e),
+        }
+        Ok(())
+    }
+}
+
+static PL011_DRIVER: Pl011Driver = Pl011Driver;
+
+module_platform_driver!(PL011_DRIVER);
@@ -0,0 +1,113 @@
+//! BCM2835 auxiliary mini UART
+//!
+//! Refer to linux: drivers/tty/serial/8250/8250_bcm2835aux.c
+//!
+//! The mini UART is a 16550 with 8 byte FIFOs, driven by the 8250 core. It
+//! divides its clock by 8 instead of 16, so the 8250 core sees twice the
+//! clock rate. Its console is `ttyS`, its earlycon `bcm2835aux`.
+
+use kernel::drivers::base::dd::driver_deferred_probe_check_state;
+use kernel::drivers::base::device::Device;
+use kernel::drivers::base::driver::Driver;
+use kernel::drivers::base::platform::{
+    module_platform_driver, platform_get_irq, platform_get_resource, ResourceType,
+};
+use kernel::drivers::fdt::{of_clk_get_rate, OfDeviceId};
+use kernel::drivers::tty::serial::earlycon::{earlycon_declare, EarlyConDevice};
+use kernel::drivers::tty::serial::serial_8250::early::early_serial8250_write;
+use kernel::drivers::tty::serial::serial_8250::{serial8250_register_8250_port, Serial8250Type};
+use kernel::drivers::tty::serial::serial_core::DEFAULT_BAUD;
+use kernel::drivers::tty::serial::uart_port::{UartPort, UartPortIoType};
+use kernel::error::{Error, Result};
+use kernel::mm::{ioremap, iounmap, PhysAddr};
+use kernel::sync::arc::Arc;
+
+/// Size of the mini UART registers
+const BCM2835_AUX_UART_SIZE: usize = 0x40;
+
+/// The firmware already programmed the baud rate, only printing is set up.
+fn early_bcm2835aux_setup(dev: &EarlyConDevice, _options: Option<&str>) -> Result {
+    {
+        let mut port = dev.port().lock();
+        if port.membase() == 0 {
+            return Err(Error::Enodev);
+        }
+        port.set_iotype(UartPortIoType::Mem32);
+        port.set_regshift(2);
+    }
+    dev.set_write(early_serial8250_write);
+    Ok(())
+}
+
+earlycon_declare!(
+    BCM2835AUX_EARLYCON,
+    "bcm2835aux",
+    "brcm,bcm2835-aux-uart",
+    early_bcm2835aux_setup
+);
+
+fn bcm2835aux_serial_probe(dev: &Device) -> Result {
+    let node = dev.of_node().ok_or(Error::Enodev)?;
+    let reg = platform_get_resource(dev, ResourceType::Mem, 0).ok_or(Error::Einval)?;
+    let irq = platform_get_irq(dev, 0)?;
+    let mapbase = reg.start;
+
+    let mut port = UartPort::new_empty();
+    port.set_iotype(UartPortIoType::Mem32);
+    port.set_regshift(2);
+    port.set_mapbase(mapbase as u64);
+    port.set_irq(irq);
+    // The clock divider is 8, the 8250 core expects 16. The clock is
+    // optional: without its rate the firmware divisor is kept.
+    let uartclk = match of_clk_get_rate(node, None) {
+        Ok(rate) => Some(rate),
+        Err(Error::Eprobedefer) => match driver_deferred_probe_check_state(dev) {
+            Error::Eprobedefer => return Err(Error::Eprobedefer),
+            _ => None,
+        },
+        Err(_) => None,
+    };
+    let uartclk = uartclk
+        .or_else(|| {
+            node.property("clock-frequency")
+                .and_then(|p| p.as_usize())
+                .map(|rate| rate as u32)
+        })
+        .map_or(0, |rate| rate * 2);
+    port.set_uartclk(uartclk);
+    let baud = node
+        .property("current-speed")
+        .and_then(|p| p.as_usize())
+        .map_or(DEFAULT_BAUD, |speed| speed as u32);
+
+    // Map last, nothing else can fail before the port is registered.
+    let membase = ioremap(PhysAddr::from(mapbase), BCM2835_AUX_UART_SIZE).ok_or(Error::Enomem)?;
+    port.set_membase(membase.as_usize());
+    if let Err(e) = serial8250_register_8250_port(port, Serial8250Type::Bcm2835Aux, baud) {
+        iounmap(membase, BCM2835_AUX_UART_SIZE);
+        return Err(e);
+    }
+    Ok(())
+}
+
+static BCM2835AUX_SERIAL_MATCH: [OfDeviceId; 1] = [OfDeviceId::new("brcm,bcm2835-aux-uart")];
+
+struct Bcm2835AuxSerialDriver;
+
+impl Driver for Bcm2835AuxSerialDriver {
+    fn name(&self) -> &'static str {
+        "bcm2835-aux-uart"
+    }
+
+    fn of_match_table(&self) -> &'static [OfDeviceId] {
+        &BCM2835AUX_SERIAL_MATCH
+    }
+
+    fn probe(&self, dev: &Arc<Device>) -> Result {
+        bcm2835aux_serial_probe(dev)
+    }
+}
+
+static BCM2835AUX_SERIAL_DRIVER: Bcm2835AuxSerialDriver = Bcm2835AuxSerialDriver;
+
+module_platform_driver!(BCM2835AUX_SERIAL_DRIVER);
@@ -0,0 +1,95 @@
+//! Devicetree 8250 ports
+//!
+//! Refer to linux: drivers/tty/serial/8250/8250_of.c
+//!
+//! Probes `ns16550a`, `ns16550` and `snps,dw-apb-uart` nodes, with their
+//! `reg-shift`, `reg-io-width`, `reg-offset` and `fifo-size` properties.
+
+use kernel::drivers::base::dd::driver_deferred_probe_check_state;
+use kernel::drivers::base::device::Device;
+use kernel::drivers::base::driver::Driver;
+use kernel::drivers::base::platform::{
+    module_platform_driver, platform_get_irq, platform_get_resource, ResourceType,
+};
+use kernel::drivers::fdt::platform::of_device_get_match_data;
+use kernel::drivers::fdt::{of_clk_get_rate, OfDeviceId};
+use kernel::drivers::tty::serial::serial_8250::{serial8250_register_8250_port, Serial8250Type};
+use kernel::drivers::tty::serial::serial_core::DEFAULT_BAUD;
+use kernel::drivers::tty::serial::uart_port::{UartPort, UartPortIoType};
+use kernel::error::{Error, Result};
+use kernel::mm::{ioremap, iounmap, PhysAddr};
+use kernel::sync::arc::Arc;
+
+static OF_PLATFORM_SERIAL_TABLE: [OfDeviceId; 3] = [
+    OfDeviceId::with_data("ns16550a", Serial8250Type::Uart16550A as usize),
+    OfDeviceId::with_data("ns16550", Serial8250Type::Uart16550A as usize),
+    OfDeviceId::with_data("snps,dw-apb-uart", Serial8250Type::DwApb as usize),
+];
+
+// Refer to linux: of_platform_serial_setup
+fn of_platform_serial_probe(dev: &Device, kind: Serial8250Type) -> Result {
+    let node = dev.of_node().ok_or(Error::Enodev)?;
+    let prop = |name| node.property(name).and_then(|p| p.as_usize());
+    let reg = platform_get_resource(dev, ResourceType::Mem, 0).ok_or(Error::Einval)?;
+    let irq = platform_get_irq(dev, 0)?;
+    let mapbase = reg.start + prop("reg-offset").unwrap_or(0);
+    let size = reg.size().max(0x100);
+
+    let mut port = UartPort::new_empty();
+    port.set_iotype(match prop("reg-io-width") {
+        Some(2) => UartPortIoType::Mem16,
+        Some(4) if node.property("big-endian").is_some() => UartPortIoType::Mem32Be,
+        Some(4) => UartPortIoType::Mem32,
+        _ => UartPortIoType::Mem,
+    });
+    port.set_regshift(prop("reg-shift").unwrap_or(0) as u8);
+    port.set_mapbase(mapbase as u64);
+    port.set_irq(irq);
+    // Without a clock rate the firmware divisor is kept.
+    let uartclk = match prop("clock-frequency") {
+        Some(rate) => rate as u32,
+        None => match of_clk_get_rate(node, Some("baudclk")) {
+            Ok(rate) => rate,
+            Err(Error::Eprobedefer) => return Err(driver_deferred_probe_check_state(dev)),
+            Err(_) => 0,
+        },
+    };
+    port.set_uartclk(uartclk);
+    if let Some(fifosize) = prop("fifo-size") {
+        port.set_fifosize(fifosize as u32);
+    }
+    let baud = prop("current-speed").map_or(DEFAULT_BAUD, |speed| speed as u32);
+
+    // Map last, nothing else can fail before the port is registered.
+    let membase = ioremap(PhysAddr::from(mapbase), size).ok_or(Error::Enomem)?;
+    port.set_membase(membase.as_usize());
+    if let Err(e) = serial8250_register_8250_port(port, kind, baud) {
+        iounmap(membase, size);
+        return Err(e);
+    }
+    Ok(())
+}
+
+struct OfPlatformSerialDriver;
+
+impl Driver for OfPlatformSerialDriver {
+    fn name(&self) -> &'static str {
+        "of_serial"
+    }
+
+    fn of_match_table(&self) -> &'static [OfDeviceId] {
+        &OF_PLATFORM_SERIAL_TABLE
+    }
+
+    fn probe(&self, dev: &Arc<Device>) -> Result {
+        let kind = match of_device_get_match_data(dev) {
+            Some(kind) if kind == Serial8250Type::DwApb as usize => Serial8250Type::DwApb,
+            _ => Serial8250Type::Uart16550A,
+        };
+        of_platform_serial_probe(dev, kind)
+    }
+}
+
+static OF_PLATFORM_SERIAL_DRIVER: OfPlatformSerialDriver = OfPlatformSerialDriver;
+
+module_platform_driver!(OF_PLATFORM_SERIAL_DRIVER);
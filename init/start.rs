@@ -31,6 +31,14 @@ extern "C" fn start_kernel() -> ! {
     early_uart_put_u64_hex(0x1234);
     // After this, we can use memblock allocator
     ArchBootSetup::setup_arch();
+    kernel::init::command_line::GLOBAL_COMMAND_LINE
+        .lock()
+        .parse_late_options();
+    kernel::drivers::irqchip::irqchip_init();
+    kernel::time::time_init();
+    IRQ::local_enable();
+    kernel::init::initcall::do_pre_smp_initcalls();
+    kernel::init::initcall::do_initcalls();
     early_uart_put_u64_hex(0x1234);
     loop {}
 }
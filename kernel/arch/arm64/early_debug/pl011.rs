@@ -51,6 +51,67 @@ register_structs! {
     }
 }
 
+/// Data register: framing error
+pub const DR_FE: u32 = 1 << 8;
+/// Data register: parity error
+pub const DR_PE: u32 = 1 << 9;
+/// Data register: break error
+pub const DR_BE: u32 = 1 << 10;
+/// Data register: overrun error
+pub const DR_OE: u32 = 1 << 11;
+
+/// Flag register: UART busy transmitting
+pub const FR_BUSY: u32 = 1 << 3;
+/// Flag register: receive FIFO empty
+pub const FR_RXFE: u32 = 1 << 4;
+/// Flag register: transmit FIFO full
+pub const FR_TXFF: u32 = 1 << 5;
+/// Flag register: transmit FIFO empty
+pub const FR_TXFE: u32 = 1 << 7;
+
+/// Line control: send break
+pub const LCRH_BRK: u32 = 1 << 0;
+/// Line control: parity enable
+pub const LCRH_PEN: u32 = 1 << 1;
+/// Line control: even parity
+pub const LCRH_EPS: u32 = 1 << 2;
+/// Line control: two stop bits
+pub const LCRH_STP2: u32 = 1 << 3;
+/// Line control: FIFOs enable
+pub const LCRH_FEN: u32 = 1 << 4;
+/// Line control: word length shift, the length minus 5.
+pub const LCRH_WLEN_SHIFT: u32 = 5;
+
+/// Control: UART enable
+pub const CR_UARTEN: u32 = 1 << 0;
+/// Control: transmit enable
+pub const CR_TXE: u32 = 1 << 8;
+/// Control: receive enable
+pub const CR_RXE: u32 = 1 << 9;
+/// Control: request to send
+pub const CR_RTS: u32 = 1 << 11;
+/// Control: RTS hardware flow control
+pub const CR_RTSEN: u32 = 1 << 14;
+/// Control: CTS hardware flow control
+pub const CR_CTSEN: u32 = 1 << 15;
+
+/// Interrupt: receive
+pub const INT_RX: u32 = 1 << 4;
+/// Interrupt: transmit
+pub const INT_TX: u32 = 1 << 5;
+/// Interrupt: receive timeout
+pub const INT_RT: u32 = 1 << 6;
+/// Interrupt: framing error
+pub const INT_FE: u32 = 1 << 7;
+/// Interrupt: parity error
+pub const INT_PE: u32 = 1 << 8;
+/// Interrupt: break error
+pub const INT_BE: u32 = 1 << 9;
+/// Interrupt: overrun error
+pub const INT_OE: u32 = 1 << 10;
+/// All interrupts
+pub const INT_ALL: u32 = 0x7ff;
+
 /// The Pl011 Uart
 ///
 /// The Pl011 Uart provides a programing interface for:
@@ -85,7 +146,7 @@ impl Pl011Uart {
     /// Initializes the Pl011 UART.
     ///
     /// It clears all irqs, sets fifo trigger level, enables rx interrupt, enables receives
-    pub fn init(&mut self) {
+    pub fn init(&self) {
         // clear all irqs
         self.regs().icr.set(0x7ff);
 
@@ -107,7 +168,7 @@ impl Pl011Uart {
 
     /// Return a Option if pl011 has received a new char
     /// Or it will return None
-    pub fn getchar(&mut self) -> Option<u8> {
+    pub fn getchar(&self) -> Option<u8> {
         if self.regs().fr.get() & (1 << 4) == 0 {
             Some(self.regs().dr.get() as u8)
         } else {
@@ -122,10 +183,81 @@ impl Pl011Uart {
     }
 
     /// Clear all interrupts
-    pub fn ack_interrupts(&mut self) {
+    pub fn ack_interrupts(&self) {
         self.regs().icr.set(0x7ff);
     }
 
+    /// Flag register, see the `FR_*` bits.
+    pub fn fr(&self) -> u32 {
+        self.regs().fr.get()
+    }
+
+    /// Read the data register, the received char and its `DR_*` error bits.
+    pub fn read_dr(&self) -> u32 {
+        self.regs().dr.get()
+    }
+
+    /// Write a char to the data register without waiting.
+    pub fn write_dr(&self, c: u8) {
+        self.regs().dr.set(c as u32);
+    }
+
+    /// Interrupt mask, see the `INT_*` bits.
+    pub fn imsc(&self) -> u32 {
+        self.regs().imsc.get()
+    }
+
+    /// Set the interrupt mask
+    pub fn set_imsc(&self, mask: u32) {
+        self.regs().imsc.set(mask);
+    }
+
+    /// Pending interrupts that are not masked.
+    pub fn mis(&self) -> u32 {
+        self.regs().mis.get()
+    }
+
+    /// Clear the interrupts in `mask`.
+    pub fn clear_interrupts(&self, mask: u32) {
+        self.regs().icr.set(mask);
+    }
+
+    /// Control register, see the `CR_*` bits.
+    pub fn cr(&self) -> u32 {
+        self.regs().cr.get()
+    }
+
+    /// Set the control register
+    pub fn set_cr(&self, cr: u32) {
+        self.regs().cr.set(cr);
+    }
+
+    /// Set the FIFO interrupt levels.
+    pub fn set_ifls(&self, ifls: u32) {
+        self.regs().ifls.set(ifls);
+    }
+
+    /// Set the line control, see the `LCRH_*` bits.
+    ///
+    /// It must be written after the divisor for the divisor to be latched.
+    pub fn set_lcrh(&self, lcrh: u32) {
+        self.regs().lcrh.set(lcrh);
+    }
+
+    /// Set the integer and fractional baud rate divisors.
+    pub fn set_baud_divisor(&self, ibrd: u32, fbrd: u32) {
+        self.regs().ibrd.set(ibrd);
+        self.regs().fbrd.set(fbrd);
+    }
+
+    /// Peripheral id, the part number is 0x011 in the low 12 bits.
+    pub fn periphid(&self) -> u32 {
+        (self.get_periphid0() & 0xff)
+            | (self.get_periphid1() & 0xff) << 8
+            | (self.get_periphid2() & 0xff) << 16
+            | (self.get_periphid3() & 0xff) << 24
+    }
+
     fn get_ris(&self) -> u32 {
         self.regs().ris.get()
     }
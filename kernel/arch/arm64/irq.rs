@@ -38,4 +38,9 @@ impl ArchIrq for Arm64Irq {
         Daif::write_raw(flags);
         barrier();
     }
+
+    #[inline(always)]
+    fn local_is_disabled() -> bool {
+        Daif::read_raw() & Daif::I.bits() != 0
+    }
 }
@@ -0,0 +1,201 @@
+//! Exception entry
+//!
+//! Refer to linux: arch/arm64/kernel/entry.S, arch/arm64/kernel/entry-common.c
+//!
+//! Every exception saves the registers in a [`PtRegs`] on the stack it was
+//! taken on, and restores them on return. The kernel runs at EL1 on SP_EL1,
+//! there is no user space yet: interrupts taken at EL1h are handled, the
+//! synchronous ones and everything else are fatal.
+
+use core::mem::{offset_of, size_of};
+
+use crate::arch::arm64::ptrace::{StackFrameMeta, StackFrameMetaType};
+use crate::arch::arm64::sysregs::{EsrEl1, FarEl1, VbarEl1};
+use crate::arch::ptrace::PtRegs;
+
+const S_FRAME_SIZE: usize = size_of::<PtRegs>();
+const S_LR: usize = offset_of!(PtRegs, regs) + 30 * 8;
+const S_SP: usize = offset_of!(PtRegs, sp);
+const S_PC: usize = offset_of!(PtRegs, pc);
+const S_STACKFRAME: usize = offset_of!(PtRegs, stackframe) + offset_of!(StackFrameMeta, record);
+const S_STACKFRAME_TYPE: usize = offset_of!(PtRegs, stackframe) + offset_of!(StackFrameMeta, ty);
+
+// The stack pointer must stay 16 bytes aligned, the saved registers are
+// stored in pairs.
+const _: () = assert!(S_FRAME_SIZE % 16 == 0);
+const _: () = assert!(S_SP == S_LR + 8);
+
+/// Vector entries that are not expected, in table order.
+const BAD_MODE_HANDLER: [&str; 4] = ["Synchronous Abort", "IRQ", "FIQ", "Error"];
+
+core::arch::global_asm!(
+    r#"
+    .macro kernel_entry
+    sub sp, sp, #{S_FRAME_SIZE}
+    stp x0, x1, [sp, #16 * 0]
+    stp x2, x3, [sp, #16 * 1]
+    stp x4, x5, [sp, #16 * 2]
+    stp x6, x7, [sp, #16 * 3]
+    stp x8, x9, [sp, #16 * 4]
+    stp x10, x11, [sp, #16 * 5]
+    stp x12, x13, [sp, #16 * 6]
+    stp x14, x15, [sp, #16 * 7]
+    stp x16, x17, [sp, #16 * 8]
+    stp x18, x19, [sp, #16 * 9]
+    stp x20, x21, [sp, #16 * 10]
+    stp x22, x23, [sp, #16 * 11]
+    stp x24, x25, [sp, #16 * 12]
+    stp x26, x27, [sp, #16 * 13]
+    stp x28, x29, [sp, #16 * 14]
+
+    add x21, sp, #{S_FRAME_SIZE}
+    mrs x22, elr_el1
+    mrs x23, spsr_el1
+    stp x30, x21, [sp, #{S_LR}]
+    stp x22, x23, [sp, #{S_PC}]
+
+    // A frame record in the pt_regs, to unwind through the exception.
+    mov x0, #{PT_REGS_FRAME}
+    str x0, [sp, #{S_STACKFRAME_TYPE}]
+    stp x29, x22, [sp, #{S_STACKFRAME}]
+    add x29, sp, #{S_STACKFRAME}
+    .endm
+
+    .macro kernel_exit
+    ldp x21, x22, [sp, #{S_PC}]
+    msr elr_el1, x21
+    msr spsr_el1, x22
+    ldp x0, x1, [sp, #16 * 0]
+    ldp x2, x3, [sp, #16 * 1]
+    ldp x4, x5, [sp, #16 * 2]
+    ldp x6, x7, [sp, #16 * 3]
+    ldp x8, x9, [sp, #16 * 4]
+    ldp x10, x11, [sp, #16 * 5]
+    ldp x12, x13, [sp, #16 * 6]
+    ldp x14, x15, [sp, #16 * 7]
+    ldp x16, x17, [sp, #16 * 8]
+    ldp x18, x19, [sp, #16 * 9]
+    ldp x20, x21, [sp, #16 * 10]
+    ldp x22, x23, [sp, #16 * 11]
+    ldp x24, x25, [sp, #16 * 12]
+    ldp x26, x27, [sp, #16 * 13]
+    ldp x28, x29, [sp, #16 * 14]
+    ldr x30, [sp, #{S_LR}]
+    add sp, sp, #{S_FRAME_SIZE}
+    eret
+    .endm
+
+    .macro ventry label
+    .balign 0x80
+    b \label
+    .endm
+
+    .macro invalid_entry label, reason
+\label:
+    kernel_entry
+    mov x0, sp
+    mov x1, #\reason
+    bl {bad_mode}
+    .endm
+
+    .pushsection ".entry.text", "ax"
+
+    .balign 0x800
+    .global vectors
+vectors:
+    ventry el1t_64_sync_invalid
+    ventry el1t_64_irq_invalid
+    ventry el1t_64_fiq_invalid
+    ventry el1t_64_error_invalid
+
+    ventry el1h_64_sync
+    ventry el1h_64_irq
+    ventry el1h_64_fiq_invalid
+    ventry el1h_64_error_invalid
+
+    ventry el0t_64_sync_invalid
+    ventry el0t_64_irq_invalid
+    ventry el0t_64_fiq_invalid
+    ventry el0t_64_error_invalid
+
+    ventry el0t_32_sync_invalid
+    ventry el0t_32_irq_invalid
+    ventry el0t_32_fiq_invalid
+    ventry el0t_32_error_invalid
+
+el1h_64_sync:
+    kernel_entry
+    mov x0, sp
+    bl {el1h_64_sync_handler}
+
+el1h_64_irq:
+    kernel_entry
+    mov x0, sp
+    bl {el1h_64_irq_handler}
+    kernel_exit
+
+    invalid_entry el1t_64_sync_invalid, 0
+    invalid_entry el1t_64_irq_invalid, 1
+    invalid_entry el1t_64_fiq_invalid, 2
+    invalid_entry el1t_64_error_invalid, 3
+    invalid_entry el1h_64_fiq_invalid, 2
+    invalid_entry el1h_64_error_invalid, 3
+    invalid_entry el0t_64_sync_invalid, 0
+    invalid_entry el0t_64_irq_invalid, 1
+    invalid_entry el0t_64_fiq_invalid, 2
+    invalid_entry el0t_64_error_invalid, 3
+    invalid_entry el0t_32_sync_invalid, 0
+    invalid_entry el0t_32_irq_invalid, 1
+    invalid_entry el0t_32_fiq_invalid, 2
+    invalid_entry el0t_32_error_invalid, 3
+
+    .popsection
+"#,
+    S_FRAME_SIZE = const S_FRAME_SIZE,
+    S_LR = const S_LR,
+    S_PC = const S_PC,
+    S_STACKFRAME = const S_STACKFRAME,
+    S_STACKFRAME_TYPE = const S_STACKFRAME_TYPE,
+    PT_REGS_FRAME = const StackFrameMetaType::PtRegs as u64,
+    bad_mode = sym bad_mode,
+    el1h_64_sync_handler = sym el1h_64_sync_handler,
+    el1h_64_irq_handler = sym el1h_64_irq_handler,
+);
+
+unsafe extern "C" {
+    static vectors: u8;
+}
+
+/// Take the exceptions of the current cpu with the vectors above.
+pub fn vectors_init() {
+    // SAFETY: only the address of the vectors is taken.
+    VbarEl1::write_raw(unsafe { &raw const vectors } as u64);
+    crate::arch::arm64::asm::barrier::isb();
+}
+
+extern "C" fn el1h_64_irq_handler(_regs: &mut PtRegs) {
+    crate::irq::handle_arch_irq();
+}
+
+extern "C" fn el1h_64_sync_handler(regs: &mut PtRegs) -> ! {
+    let esr = EsrEl1::read_raw();
+    panic!(
+        "Unhandled exception: ESR {:#x} (EC {:#x}) FAR {:#x} PC {:#x} LR {:#x}",
+        esr,
+        esr >> EsrEl1::EC_SHIFT,
+        FarEl1::read_raw(),
+        regs.pc,
+        regs.regs[30]
+    );
+}
+
+/// Refer to linux: bad_mode
+extern "C" fn bad_mode(regs: &mut PtRegs, reason: usize) -> ! {
+    panic!(
+        "Bad mode in {} handler detected: ESR {:#x} PC {:#x} PSTATE {:#x}",
+        BAD_MODE_HANDLER[reason],
+        EsrEl1::read_raw(),
+        regs.pc,
+        regs.pstate
+    );
+}
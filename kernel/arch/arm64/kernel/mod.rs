@@ -1,6 +1,9 @@
 //! ARM64-specific kernel code.
 
 pub mod cpufeature;
+// The vectors are only linked in the kernel image.
+#[cfg(not(test))]
+pub mod entry;
 pub mod image;
 pub mod setup;
 pub mod smp;
@@ -77,6 +77,12 @@ impl ArchBootSetupTrait for Arm64BootSetup {
         Self::setup_machine_fdt();
         crate::arch::arm64::mm::init::memblock_init();
         crate::arch::arm64::mm::mmu::paging_init();
+        if let Err(e) = crate::drivers::fdt::GLOBAL_FDT.build_index() {
+            crate::pr_warn!("FDT: nodes not indexed: {:?}\n", e);
+        }
+        crate::drivers::fdt::live::unflatten_device_tree();
+        #[cfg(CONFIG_BOOT_CONFIG)]
+        crate::init::bootconfig::setup_boot_config();
         crate::init::GLOBAL_COMMAND_LINE
             .lock()
             .parse_early_options();
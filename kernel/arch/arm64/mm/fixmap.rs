@@ -18,6 +18,13 @@ use crate::{
     mm::{page::PageConfig, PhysAddr, VirtAddr},
     static_assertions::const_assert_eq,
 };
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Pages of device memory [`FixMap::ioremap`] can map.
+const IO_MAP_PAGES: usize = 16;
+
+/// Pages of memory [`FixMap::early_memremap`] can map.
+const EARLY_MAP_PAGES: usize = 16;
 
 /// Here we define all the compile-time 'special' virtual addresses.
 /// The poinnt is to have a constant address at compile time.
@@ -38,10 +45,19 @@ pub enum FixMapType {
         + 1,
     /// Early con mem base.
     EarlyConMemBase,
+    /// Last page of the ioremap area.
+    IoMapEnd,
+    /// First page of the ioremap area.
+    IoMapBegin = Self::IoMapEnd as isize + IO_MAP_PAGES as isize - 1,
 
     /// End permanent mapping
     EndPermanentFixMap,
 
+    /// Last page of the early memremap area.
+    BtMapEnd,
+    /// First page of the early memremap area.
+    BtMapBegin = Self::BtMapEnd as isize + EARLY_MAP_PAGES as isize - 1,
+
     /// PTE map used for kernel page table creation
     PteMap,
     /// PMD map used for kernel page table creation
@@ -63,7 +79,11 @@ impl From<usize> for FixMapType {
             1 => FixMapType::FdtEnd,
             x if x == FixMapType::Fdt as usize => FixMapType::Fdt,
             x if x == FixMapType::EarlyConMemBase as usize => FixMapType::EarlyConMemBase,
+            x if x == FixMapType::IoMapEnd as usize => FixMapType::IoMapEnd,
+            x if x == FixMapType::IoMapBegin as usize => FixMapType::IoMapBegin,
             x if x == FixMapType::EndPermanentFixMap as usize => FixMapType::EndPermanentFixMap,
+            x if x == FixMapType::BtMapEnd as usize => FixMapType::BtMapEnd,
+            x if x == FixMapType::BtMapBegin as usize => FixMapType::BtMapBegin,
             x if x == FixMapType::PteMap as usize => FixMapType::PteMap,
             x if x == FixMapType::PmdMap as usize => FixMapType::PmdMap,
             x if x == FixMapType::PudMap as usize => FixMapType::PudMap,
@@ -166,6 +186,12 @@ impl FixMapPteTable {
     }
 }
 
+// Pages of the ioremap area in use.
+static IO_MAP_NEXT: AtomicUsize = AtomicUsize::new(0);
+
+// Whether the early memremap area is in use.
+static BT_MAP_IN_USE: AtomicBool = AtomicBool::new(false);
+
 /// FixMap configuration
 pub struct FixMap;
 
@@ -250,7 +276,15 @@ impl FixMap {
 
     #[inline(always)]
     fn set_fixmap(idx: FixMapType, phys: PhysAddr, prot: PtePgProt, clear: bool) -> VirtAddr {
-        let virt_addr = idx.to_virt();
+        Self::set_fixmap_at(idx.to_virt(), phys, prot, clear)
+    }
+
+    fn set_fixmap_at(
+        virt_addr: VirtAddr,
+        phys: PhysAddr,
+        prot: PtePgProt,
+        clear: bool,
+    ) -> VirtAddr {
         let phys_base = phys.align_down_page();
         let mut pte_tbl = PteTable::from_raw(unsafe { FixMapPteTable::mut_ptr(virt_addr) });
         let pte_entry = &mut pte_tbl[PteTable::addr_index(virt_addr)];
@@ -315,6 +349,111 @@ impl FixMap {
         );
     }
 
+    /// Map the earlycon registers page at `phys`.
+    #[inline]
+    pub fn set_earlycon_map(phys: PhysAddr) -> VirtAddr {
+        Self::set_fixmap(
+            FixMapType::EarlyConMemBase,
+            phys,
+            PtePgProt::PROT_DEVICE_nGnRE,
+            false,
+        )
+    }
+
+    /// Map `size` bytes of device memory at `phys`, return None if the
+    /// ioremap area is full.
+    ///
+    /// There is no vmalloc area yet, so device registers are mapped in the
+    /// fixmap and only the last mapping can be taken back.
+    pub fn ioremap(phys: PhysAddr, size: usize) -> Option<VirtAddr> {
+        let offset = phys.align_offset_page();
+        let pages = div_round_up(offset + size, PageConfig::PAGE_SIZE);
+        let first = IO_MAP_NEXT
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |next| {
+                (next + pages <= IO_MAP_PAGES).then_some(next + pages)
+            })
+            .ok()?;
+
+        let virt_base = FixMapType::IoMapBegin.to_virt() + (first << PageConfig::PAGE_SHIFT);
+        let phys_base = phys.align_down_page();
+        for i in 0..pages {
+            let off = i << PageConfig::PAGE_SHIFT;
+            Self::set_fixmap_at(
+                virt_base + off,
+                phys_base + off,
+                PtePgProt::PROT_DEVICE_nGnRE,
+                false,
+            );
+        }
+        Some(virt_base + offset)
+    }
+
+    /// Unmap a mapping of `size` bytes at `virt` from [`FixMap::ioremap`].
+    ///
+    /// The pages are only given back if it is the last mapping, which is
+    /// enough to undo a mapping on a probe error.
+    pub fn iounmap(virt: VirtAddr, size: usize) {
+        let io_base = FixMapType::IoMapBegin.to_virt();
+        let offset = virt.as_usize() - io_base.as_usize();
+        let first = offset >> PageConfig::PAGE_SHIFT;
+        let pages = div_round_up(virt.align_offset_page() + size, PageConfig::PAGE_SIZE);
+        debug_assert!(first + pages <= IO_MAP_PAGES);
+        for i in first..first + pages {
+            Self::set_fixmap_at(
+                io_base + (i << PageConfig::PAGE_SHIFT),
+                PhysAddr::from(0),
+                PtePgProt::empty(),
+                true,
+            );
+        }
+        let _ = IO_MAP_NEXT.compare_exchange(
+            first + pages,
+            first,
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+        );
+    }
+
+    /// Temporarily map `size` bytes of memory at `phys` with `prot`, return
+    /// None if it is too large or the area is in use.
+    ///
+    /// Only one mapping at a time, it must be unmapped with
+    /// [`FixMap::early_memunmap`].
+    #[section_init_text]
+    pub fn early_memremap(phys: PhysAddr, size: usize, prot: PtePgProt) -> Option<VirtAddr> {
+        let offset = phys.align_offset_page();
+        let pages = div_round_up(offset + size, PageConfig::PAGE_SIZE);
+        if pages == 0 || pages > EARLY_MAP_PAGES || BT_MAP_IN_USE.swap(true, Ordering::Acquire) {
+            return None;
+        }
+
+        let virt_base = FixMapType::BtMapBegin.to_virt();
+        let phys_base = phys.align_down_page();
+        for i in 0..pages {
+            let off = i << PageConfig::PAGE_SHIFT;
+            Self::set_fixmap_at(virt_base + off, phys_base + off, prot, false);
+        }
+        Some(virt_base + offset)
+    }
+
+    /// Unmap a mapping of `size` bytes at `virt` from [`FixMap::early_memremap`].
+    #[section_init_text]
+    pub fn early_memunmap(virt: VirtAddr, size: usize) {
+        let virt_base = FixMapType::BtMapBegin.to_virt();
+        let offset = virt.as_usize() - virt_base.as_usize();
+        let pages = div_round_up(offset + size, PageConfig::PAGE_SIZE);
+        debug_assert!(offset < PageConfig::PAGE_SIZE && pages <= EARLY_MAP_PAGES);
+        for i in 0..pages {
+            Self::set_fixmap_at(
+                virt_base + (i << PageConfig::PAGE_SHIFT),
+                PhysAddr::from(0),
+                PtePgProt::empty(),
+                true,
+            );
+        }
+        BT_MAP_IN_USE.store(false, Ordering::Release);
+    }
+
     /// remap fdt
     #[section_init_text]
     pub(crate) fn remap_fdt(dt_phys: PhysAddr, prot: PtePgProt) -> (VirtAddr, usize) {
@@ -328,11 +467,16 @@ impl FixMap {
 
         // Map the first page, so we can read the size from the header
         Mmu::create_map_noalloc(dt_phys_base, dt_virt_base, PageConfig::PAGE_SIZE, prot);
-        let fdt = unsafe { fdtree_rs::LinuxFdt::from_ptr(dt_virt.as_usize() as *const u8) };
-
-        match fdt {
-            Ok(fdt) => {
-                let size = fdt.total_size();
+        // SAFETY: the header is in the page mapped above
+        let header = unsafe {
+            core::slice::from_raw_parts(
+                dt_virt.as_usize() as *const u8,
+                fdtree_rs::LinuxFdt::HEADER_SIZE,
+            )
+        };
+
+        match fdtree_rs::LinuxFdt::check_header(header) {
+            Ok(size) => {
                 if size > InitIdmap::MAX_FDT_SIZE {
                     panic!("Fdt size too large: {}", size);
                 }
@@ -0,0 +1,14 @@
+//! ARM64 cntfrq_el0
+
+/// CntfrqEl0, the system counter frequency
+pub struct CntfrqEl0;
+
+impl CntfrqEl0 {
+    /// Read register.
+    #[inline(always)]
+    pub fn read_raw() -> u64 {
+        let freq: u64;
+        sys_coproc_read_raw!(u64, "CNTFRQ_EL0", "x", freq);
+        freq
+    }
+}
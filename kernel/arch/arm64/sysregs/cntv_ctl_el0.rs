@@ -0,0 +1,34 @@
+//! ARM64 cntv_ctl_el0
+
+use crate::bitflags::bitflags;
+
+bitflags! {
+    /// CNTV_CTL_EL0
+    #[repr(transparent)]
+    #[derive(Copy, Clone)]
+    pub struct CntvCtlEl0: u64 {
+        /// Timer enabled
+        const ENABLE = 1 << 0;
+        /// Timer interrupt masked
+        const IMASK = 1 << 1;
+        /// Timer condition met
+        const ISTATUS = 1 << 2;
+    }
+}
+
+impl CntvCtlEl0 {
+    /// Read register.
+    #[inline(always)]
+    pub fn read() -> Self {
+        let ctl: u64;
+        sys_coproc_read_raw!(u64, "CNTV_CTL_EL0", "x", ctl);
+        Self::from_bits_truncate(ctl)
+    }
+
+    /// Write register.
+    #[inline(always)]
+    pub fn write(self) {
+        let ctl = self.bits();
+        sys_coproc_write_raw!(u64, "CNTV_CTL_EL0", "x", ctl);
+    }
+}
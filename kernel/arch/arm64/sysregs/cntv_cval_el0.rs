@@ -0,0 +1,12 @@
+//! ARM64 cntv_cval_el0
+
+/// CntvCvalEl0, the virtual timer compare value
+pub struct CntvCvalEl0;
+
+impl CntvCvalEl0 {
+    /// Write register.
+    #[inline(always)]
+    pub fn write_raw(cval: u64) {
+        sys_coproc_write_raw!(u64, "CNTV_CVAL_EL0", "x", cval);
+    }
+}
@@ -0,0 +1,17 @@
+//! ARM64 cntvct_el0
+
+/// CntvctEl0, the virtual count
+pub struct CntvctEl0;
+
+impl CntvctEl0 {
+    /// Read register.
+    ///
+    /// The isb keeps the read from being speculated before earlier instructions.
+    #[inline(always)]
+    pub fn read_raw() -> u64 {
+        let cnt: u64;
+        unsafe { core::arch::asm!("isb", options(nostack)) };
+        sys_coproc_read_raw!(u64, "CNTVCT_EL0", "x", cnt);
+        cnt
+    }
+}
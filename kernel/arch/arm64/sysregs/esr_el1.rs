@@ -0,0 +1,17 @@
+//! ARM64 esr_el1
+
+/// ESR_EL1, the syndrome of the last exception taken to EL1.
+pub struct EsrEl1;
+
+impl EsrEl1 {
+    /// Exception class shift
+    pub const EC_SHIFT: u64 = 26;
+
+    /// Read register.
+    #[inline(always)]
+    pub fn read_raw() -> u64 {
+        let esr: u64;
+        sys_coproc_read_raw!(u64, "ESR_EL1", "x", esr);
+        esr
+    }
+}
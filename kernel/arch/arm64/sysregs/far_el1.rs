@@ -0,0 +1,14 @@
+//! ARM64 far_el1
+
+/// FAR_EL1, the faulting address of the last abort taken to EL1.
+pub struct FarEl1;
+
+impl FarEl1 {
+    /// Read register.
+    #[inline(always)]
+    pub fn read_raw() -> u64 {
+        let far: u64;
+        sys_coproc_read_raw!(u64, "FAR_EL1", "x", far);
+        far
+    }
+}
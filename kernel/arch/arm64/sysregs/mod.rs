@@ -4,10 +4,16 @@
 mod macros;
 
 pub(crate) mod amuserenr_el0;
+pub(crate) mod cntfrq_el0;
+pub(crate) mod cntv_ctl_el0;
+pub(crate) mod cntv_cval_el0;
+pub(crate) mod cntvct_el0;
 pub(crate) mod cpacr_el1;
 pub(crate) mod current_el;
 pub(crate) mod daif;
 pub(crate) mod elr_el1;
+pub(crate) mod esr_el1;
+pub(crate) mod far_el1;
 pub(crate) mod general;
 pub(crate) mod id_aa64dfr0_el1;
 pub(crate) mod id_aa64mmfr0_el1;
@@ -26,12 +32,19 @@ pub(crate) mod tcr2_el1;
 pub(crate) mod tcr_el1;
 pub(crate) mod tpidr_elx;
 pub(crate) mod ttbr_el1;
+pub(crate) mod vbar_el1;
 
 pub use amuserenr_el0::AmuserenrEl0;
+pub use cntfrq_el0::CntfrqEl0;
+pub use cntv_ctl_el0::CntvCtlEl0;
+pub use cntv_cval_el0::CntvCvalEl0;
+pub use cntvct_el0::CntvctEl0;
 pub use cpacr_el1::CpacrEl1;
 pub use current_el::CurrentEL;
 pub use daif::Daif;
 pub use elr_el1::ElrEl1;
+pub use esr_el1::EsrEl1;
+pub use far_el1::FarEl1;
 pub use general::*;
 pub use id_aa64dfr0_el1::IdAa64dfr0El1;
 pub use id_aa64mmfr0_el1::IdAa64mmfr0El1;
@@ -50,3 +63,4 @@ pub use tcr2_el1::Tcr2El1;
 pub use tcr_el1::Tcr;
 pub use tpidr_elx::TpidrEl1;
 pub use ttbr_el1::{Ttbr0El1, Ttbr1El1};
+pub use vbar_el1::VbarEl1;
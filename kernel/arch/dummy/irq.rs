@@ -21,4 +21,9 @@ impl ArchIrq for DummyIrq {
 
     #[inline(always)]
     fn local_restore(_flags: Self::IrqState) {}
+
+    #[inline(always)]
+    fn local_is_disabled() -> bool {
+        false
+    }
 }
@@ -1,30 +1,39 @@
 //! dummy thread info
 
+use core::sync::atomic::{AtomicU32, Ordering};
+
 use crate::arch::thread::ArchThreadInfoTrait;
 
 /// Thread info
 #[repr(C)]
-#[derive(Copy, Clone)]
 pub struct DummyThreadInfo {
     /// Cpu id
     pub cpu: u32,
+    preempt_count: AtomicU32,
 }
 
 impl DummyThreadInfo {
     /// Default thread info
     pub const fn default() -> Self {
-        Self { cpu: 0 }
+        Self {
+            cpu: 0,
+            preempt_count: AtomicU32::new(0),
+        }
     }
 }
 
 impl ArchThreadInfoTrait for DummyThreadInfo {
     fn preempt_count(&self) -> u32 {
-        0
+        self.preempt_count.load(Ordering::Relaxed)
     }
 
-    fn preempt_count_add(&self, _val: u32) {}
+    fn preempt_count_add(&self, val: u32) {
+        self.preempt_count.fetch_add(val, Ordering::Relaxed);
+    }
 
-    fn preempt_count_sub(&self, _val: u32) {}
+    fn preempt_count_sub(&self, val: u32) {
+        self.preempt_count.fetch_sub(val, Ordering::Relaxed);
+    }
 }
 
 use crate::arch::thread::ArchCurrentTrait;
@@ -12,10 +12,12 @@ pub trait ArchIrq {
     fn local_save_and_disable() -> Self::IrqState;
     /// Restore the IRQ state.
     fn local_restore(state: Self::IrqState);
+    /// Whether local IRQs are currently disabled.
+    fn local_is_disabled() -> bool;
 }
 
 cfg_if::cfg_if! {
-    if #[cfg(CONFIG_ARM64)] {
+    if #[cfg(all(CONFIG_ARM64, not(test)))] {
         pub use super::arm64::irq::Arm64Irq as IRQ;
     } else {
         pub use super::dummy::irq::DummyIrq as IRQ;
@@ -3,6 +3,9 @@
 cfg_if::cfg_if! {
     if #[cfg(CONFIG_ARM64)] {
         pub mod arm64;
+        // Host tests cannot touch the system registers of the target.
+        #[cfg(test)]
+        pub mod dummy;
     } else {
         pub mod dummy;
     }
@@ -7,3 +7,9 @@ use cpu_mask::CpuMask;
 use cpu_state::CpuStateManager;
 
 pub mod processor;
+
+/// Id of the cpu we are running on.
+#[inline(always)]
+pub fn smp_processor_id() -> usize {
+    crate::schedule::current().thread_info().cpu as usize
+}
@@ -0,0 +1,146 @@
+//! Buses
+//!
+//! Refer to linux: drivers/base/bus.c
+
+use super::dd::{device_attach, driver_attach, driver_detach};
+use super::device::Device;
+use super::driver::Driver;
+use super::{device_list_nth, device_list_remove, DeviceList, DeviceNode};
+use crate::alloc::AllocFlags;
+use crate::error::{Error, Result};
+use crate::list::{def_node, List};
+use crate::sync::arc::Arc;
+use crate::sync::lock::Mutex;
+
+def_node! {
+    /// A driver on a bus
+    pub struct DriverNode(&'static dyn Driver);
+}
+
+type DriverList = List<Arc<DriverNode>>;
+
+/// The devices and drivers of a bus
+///
+/// Refer to linux: struct subsys_private
+pub struct Subsys {
+    devices: Mutex<DeviceList>,
+    drivers: Mutex<DriverList>,
+}
+
+impl Subsys {
+    /// Create an empty one.
+    pub const fn new() -> Self {
+        Self {
+            devices: Mutex::new(DeviceList::new(), Some("BusDevices")),
+            drivers: Mutex::new(DriverList::new(), Some("BusDrivers")),
+        }
+    }
+
+    // Clone the `n`th device, it is unlocked on return.
+    pub(super) fn nth_device(&self, n: usize) -> Option<Arc<Device>> {
+        device_list_nth(&self.devices, n)
+    }
+
+    // The `n`th driver, it is unlocked on return.
+    pub(super) fn nth_driver(&self, n: usize) -> Option<&'static dyn Driver> {
+        self.drivers.lock().iter().nth(n).map(|d| *d.inner())
+    }
+}
+
+impl Default for Subsys {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A bus, it matches its devices with its drivers.
+///
+/// Refer to linux: struct bus_type
+pub trait Bus: Sync {
+    /// Name of the bus
+    fn name(&self) -> &'static str;
+    /// The devices and drivers on the bus
+    fn subsys(&self) -> &Subsys;
+    /// Whether `drv` can handle `dev`
+    fn match_device(&self, dev: &Device, drv: &dyn Driver) -> bool;
+    /// Bind `drv` to `dev`.
+    fn probe(&self, dev: &Arc<Device>, drv: &dyn Driver) -> Result {
+        drv.probe(dev)
+    }
+    /// Unbind `drv` from `dev`.
+    fn remove(&self, dev: &Arc<Device>, drv: &dyn Driver) {
+        drv.remove(dev)
+    }
+    /// Quiesce `dev` bound to `drv`.
+    fn shutdown(&self, dev: &Arc<Device>, drv: &dyn Driver) {
+        drv.shutdown(dev)
+    }
+}
+
+/// Call `f` with each device of `bus` until it returns an error.
+///
+/// The devices list is not locked while `f` runs.
+///
+/// Refer to linux: bus_for_each_dev
+pub fn bus_for_each_dev(bus: &dyn Bus, mut f: impl FnMut(&Arc<Device>) -> Result) -> Result {
+    let mut index = 0;
+    while let Some(dev) = bus.subsys().nth_device(index) {
+        f(&dev)?;
+        index += 1;
+    }
+    Ok(())
+}
+
+/// Find the device of `bus` for which `f` returns true.
+///
+/// Refer to linux: bus_find_device
+pub fn bus_find_device(bus: &dyn Bus, mut f: impl FnMut(&Device) -> bool) -> Option<Arc<Device>> {
+    bus.subsys()
+        .devices
+        .lock()
+        .iter()
+        .find(|d| f(d.inner()))
+        .map(|d| d.inner().clone())
+}
+
+// `entry` is the entry of the device, allocated by the caller.
+pub(super) fn bus_add_device(bus: &dyn Bus, entry: Arc<DeviceNode>) {
+    bus.subsys().devices.lock().push_back(entry);
+}
+
+// Try the drivers of its bus on a new device.
+pub(super) fn bus_probe_device(dev: &Arc<Device>) {
+    device_attach(dev);
+}
+
+pub(super) fn bus_remove_device(bus: &dyn Bus, dev: &Arc<Device>) {
+    device_list_remove(&mut bus.subsys().devices.lock(), dev);
+}
+
+pub(super) fn bus_add_driver(bus: &'static dyn Bus, drv: &'static dyn Driver) -> Result {
+    let entry =
+        Arc::new(DriverNode::new(drv), AllocFlags::GFP_KERNEL).map_err(|_| Error::Enomem)?;
+    let mut drivers = bus.subsys().drivers.lock();
+    if drivers.iter().any(|d| core::ptr::addr_eq(*d.inner(), drv)) {
+        return Err(Error::Eexist);
+    }
+    drivers.push_back(entry);
+    drop(drivers);
+
+    driver_attach(bus, drv);
+    Ok(())
+}
+
+pub(super) fn bus_remove_driver(bus: &'static dyn Bus, drv: &'static dyn Driver) {
+    driver_detach(bus, drv);
+
+    let mut drivers = bus.subsys().drivers.lock();
+    let mut cursor = drivers.cursor_front_mut();
+    while let Some(d) = cursor.current() {
+        if core::ptr::addr_eq(*d.inner(), drv) {
+            cursor.remove_current();
+            break;
+        }
+        cursor.move_next();
+    }
+}
@@ -0,0 +1,376 @@
+//! Binding devices to drivers
+//!
+//! Refer to linux: drivers/base/dd.c
+//!
+//! A device whose probe returns `Eprobedefer` waits on the pending list.
+//! Every time a driver is bound, the pending devices are tried again, so
+//! a device waiting for its interrupt parent or clock is bound once the
+//! driver providing it is. Once the late initcalls run no more driver is
+//! coming: the devices are tried one last time, without their missing
+//! dependencies.
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use super::bus::Bus;
+use super::device::Device;
+use super::driver::Driver;
+use super::{device_list_remove, device_node, DeviceList};
+use crate::error::{Error, Result};
+use crate::sync::arc::Arc;
+use crate::sync::lock::Mutex;
+
+static DEFERRED_PROBE_PENDING: Mutex<DeviceList> =
+    Mutex::new(DeviceList::new(), Some("DeferredProbePending"));
+// Bumped every time a driver is bound.
+static DEFERRED_TRIGGER_COUNT: AtomicUsize = AtomicUsize::new(0);
+static DEFERRED_PROBE_RUNNING: AtomicBool = AtomicBool::new(false);
+// Set once no more driver is coming.
+static DEFERRED_PROBE_TIMEOUT: AtomicBool = AtomicBool::new(false);
+
+/// Put a device on the pending list, to be probed again later.
+///
+/// Refer to linux: driver_deferred_probe_add
+pub fn driver_deferred_probe_add(dev: &Arc<Device>) -> Result {
+    let entry = device_node(dev)?;
+    let mut pending = DEFERRED_PROBE_PENDING.lock();
+    if !pending.iter().any(|d| Arc::ptr_eq(d.inner(), dev)) {
+        pending.push_back(entry);
+    }
+    Ok(())
+}
+
+/// Take a device off the pending list.
+///
+/// Refer to linux: driver_deferred_probe_del
+pub fn driver_deferred_probe_del(dev: &Arc<Device>) {
+    device_list_remove(&mut DEFERRED_PROBE_PENDING.lock(), dev);
+}
+
+/// Whether a device waits on the pending list
+pub fn driver_deferred_probe_pending(dev: &Arc<Device>) -> bool {
+    DEFERRED_PROBE_PENDING
+        .lock()
+        .iter()
+        .any(|d| Arc::ptr_eq(d.inner(), dev))
+}
+
+/// Probe the pending devices again, until no more driver gets bound.
+///
+/// A trigger while the pending devices are probed makes the running loop
+/// go over them once more instead of recursing.
+///
+/// Refer to linux: driver_deferred_probe_trigger, deferred_probe_work_func
+pub fn driver_deferred_probe_trigger() {
+    // SeqCst: a trigger bumps the count then looks at RUNNING, the loop
+    // clears RUNNING then looks at the count, one of them sees the other.
+    DEFERRED_TRIGGER_COUNT.fetch_add(1, Ordering::SeqCst);
+    if DEFERRED_PROBE_RUNNING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    loop {
+        let count = DEFERRED_TRIGGER_COUNT.load(Ordering::SeqCst);
+        let mut active = DeviceList::new();
+        while let Some(dev) = DEFERRED_PROBE_PENDING.lock().pop_front() {
+            active.push_back(dev);
+        }
+        while let Some(dev) = active.pop_front() {
+            device_attach(dev.inner());
+        }
+        if DEFERRED_TRIGGER_COUNT.load(Ordering::SeqCst) != count {
+            continue;
+        }
+
+        DEFERRED_PROBE_RUNNING.store(false, Ordering::SeqCst);
+        // A trigger after the check above saw the loop still running, go
+        // over the pending devices for it unless another loop started.
+        if DEFERRED_TRIGGER_COUNT.load(Ordering::SeqCst) == count
+            || DEFERRED_PROBE_RUNNING.swap(true, Ordering::SeqCst)
+        {
+            break;
+        }
+    }
+}
+
+/// What to return for a dependency of `dev` not there yet: `Eprobedefer`
+/// while drivers may still come, `Enodev` once they are all registered.
+///
+/// Refer to linux: driver_deferred_probe_check_state
+pub fn driver_deferred_probe_check_state(dev: &Device) -> Error {
+    if !DEFERRED_PROBE_TIMEOUT.load(Ordering::Acquire) {
+        return Error::Eprobedefer;
+    }
+    crate::pr_warn!(
+        "{}: ignoring dependency for device, assuming no driver\n",
+        dev.name()
+    );
+    Error::Enodev
+}
+
+/// Retry the pending devices one last time without their missing
+/// dependencies, and report the ones left.
+///
+/// Refer to linux: deferred_probe_initcall
+#[cfg(not(test))]
+#[crate::macros::initcall(late)]
+fn deferred_probe_initcall() -> Result {
+    DEFERRED_PROBE_TIMEOUT.store(true, Ordering::Release);
+    driver_deferred_probe_trigger();
+    for dev in DEFERRED_PROBE_PENDING.lock().iter() {
+        crate::pr_info!("{}: deferred probe pending\n", dev.name());
+    }
+    Ok(())
+}
+
+// Bind `drv` to `dev` if its probe succeeds, the device lock is held.
+fn really_probe(dev: &Arc<Device>, bus: &dyn Bus, drv: &'static dyn Driver) -> Result {
+    // Off the pending list while probed, a trigger from the probe must not
+    // try it again.
+    driver_deferred_probe_del(dev);
+    dev.set_driver(Some(drv));
+    let ret = bus.probe(dev, drv);
+    match ret {
+        Ok(()) => return Ok(()),
+        Err(Error::Eprobedefer) => {
+            if let Err(e) = driver_deferred_probe_add(dev) {
+                crate::pr_warn!(
+                    "{}: probe of {} not deferred: {:?}\n",
+                    drv.name(),
+                    dev.name(),
+                    e
+                );
+            }
+        }
+        // No device or no such device: the driver does not handle it.
+        Err(Error::Enodev) | Err(Error::Enxio) => {}
+        Err(e) => crate::pr_warn!(
+            "{}: probe of {} failed with error {:?}\n",
+            drv.name(),
+            dev.name(),
+            e
+        ),
+    }
+    dev.set_driver(None);
+    ret
+}
+
+/// Probe `drv` for `dev` if the bus matches them and `dev` has no driver.
+///
+/// Once bound, the pending devices are probed again.
+///
+/// Refer to linux: driver_probe_device
+pub fn driver_probe_device(drv: &'static dyn Driver, dev: &Arc<Device>) -> Result {
+    let bus = dev.bus().ok_or(Error::Enodev)?;
+    if !bus.match_device(dev, drv) {
+        return Err(Error::Enodev);
+    }
+
+    let count = DEFERRED_TRIGGER_COUNT.load(Ordering::Acquire);
+    let guard = dev.lock.lock();
+    if dev.driver().is_some() {
+        return Err(Error::Ebusy);
+    }
+    let ret = really_probe(dev, bus, drv);
+    drop(guard);
+
+    match ret {
+        Ok(()) => driver_deferred_probe_trigger(),
+        // A driver bound meanwhile may be what the device waits for.
+        Err(Error::Eprobedefer) if DEFERRED_TRIGGER_COUNT.load(Ordering::Acquire) != count => {
+            driver_deferred_probe_trigger()
+        }
+        _ => {}
+    }
+    ret
+}
+
+/// Try the drivers of its bus on `dev`, return whether one is bound.
+///
+/// Refer to linux: device_attach
+pub fn device_attach(dev: &Arc<Device>) -> bool {
+    let Some(bus) = dev.bus() else {
+        return false;
+    };
+    let mut index = 0;
+    while let Some(drv) = bus.subsys().nth_driver(index) {
+        match driver_probe_device(drv, dev) {
+            Ok(()) => return true,
+            // Bound by another one meanwhile
+            Err(Error::Ebusy) => return dev.driver().is_some(),
+            Err(_) => index += 1,
+        }
+    }
+    false
+}
+
+/// Try `drv` on each device of `bus` without a driver.
+///
+/// Refer to linux: driver_attach
+pub fn driver_attach(bus: &dyn Bus, drv: &'static dyn Driver) {
+    let mut index = 0;
+    while let Some(dev) = bus.subsys().nth_device(index) {
+        if dev.driver().is_none() {
+            let _ = driver_probe_device(drv, &dev);
+        }
+        index += 1;
+    }
+}
+
+/// Unbind the driver of `dev`, if any.
+///
+/// Refer to linux: device_release_driver
+pub fn device_release_driver(dev: &Arc<Device>) {
+    let _guard = dev.lock.lock();
+    let (Some(bus), Some(drv)) = (dev.bus(), dev.driver()) else {
+        return;
+    };
+    bus.remove(dev, drv);
+    dev.set_driver(None);
+}
+
+/// Unbind `drv` from all the devices of `bus`.
+///
+/// Refer to linux: driver_detach
+pub fn driver_detach(bus: &dyn Bus, drv: &'static dyn Driver) {
+    let mut index = 0;
+    while let Some(dev) = bus.subsys().nth_device(index) {
+        if dev.driver().is_some_and(|d| core::ptr::addr_eq(d, drv)) {
+            device_release_driver(&dev);
+        }
+        index += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drivers::base::bus::Subsys;
+    use crate::drivers::base::device::{device_add, device_del, device_shutdown};
+    use crate::drivers::base::driver::{driver_register, driver_unregister};
+    use crate::schedule::task::set_test_current;
+
+    struct TestBus {
+        subsys: Subsys,
+    }
+
+    impl Bus for TestBus {
+        fn name(&self) -> &'static str {
+            "test"
+        }
+
+        fn subsys(&self) -> &Subsys {
+            &self.subsys
+        }
+
+        fn match_device(&self, dev: &Device, drv: &dyn Driver) -> bool {
+            dev.name() == drv.name()
+        }
+    }
+
+    static TEST_BUS: TestBus = TestBus {
+        subsys: Subsys::new(),
+    };
+
+    struct TestDriver {
+        name: &'static str,
+        // Defer until the clock driver is bound.
+        needs_clk: bool,
+        probes: AtomicUsize,
+        removes: AtomicUsize,
+        shutdowns: AtomicUsize,
+    }
+
+    impl TestDriver {
+        const fn new(name: &'static str, needs_clk: bool) -> Self {
+            Self {
+                name,
+                needs_clk,
+                probes: AtomicUsize::new(0),
+                removes: AtomicUsize::new(0),
+                shutdowns: AtomicUsize::new(0),
+            }
+        }
+    }
+
+    impl Driver for TestDriver {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn probe(&self, _dev: &Arc<Device>) -> Result {
+            self.probes.fetch_add(1, Ordering::Relaxed);
+            if self.needs_clk && !CLK_READY.load(Ordering::Relaxed) {
+                return Err(Error::Eprobedefer);
+            }
+            if !self.needs_clk {
+                CLK_READY.store(true, Ordering::Relaxed);
+            }
+            Ok(())
+        }
+
+        fn remove(&self, _dev: &Arc<Device>) {
+            self.removes.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn shutdown(&self, _dev: &Arc<Device>) {
+            self.shutdowns.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    static CLK_READY: AtomicBool = AtomicBool::new(false);
+    static CLK_DRIVER: TestDriver = TestDriver::new("test-clk", false);
+    static UART_DRIVER: TestDriver = TestDriver::new("test-uart", true);
+
+    #[test]
+    fn test_probe_defer_remove() {
+        let _guard = crate::drivers::base::TEST_LOCK.lock();
+        set_test_current();
+        assert_eq!(driver_register(&TEST_BUS, &UART_DRIVER), Ok(()));
+        assert_eq!(driver_register(&TEST_BUS, &UART_DRIVER), Err(Error::Eexist));
+
+        // The uart waits for its clock.
+        let uart = Device::new("test-uart", None, Some(&TEST_BUS)).unwrap();
+        assert_eq!(device_add(&uart), Ok(()));
+        assert_eq!(device_add(&uart), Err(Error::Eexist));
+        assert!(uart.driver().is_none());
+        assert!(driver_deferred_probe_pending(&uart));
+
+        // A device without a driver is left alone.
+        let clk = Device::new("test-clk", None, Some(&TEST_BUS)).unwrap();
+        assert_eq!(device_add(&clk), Ok(()));
+        assert!(clk.driver().is_none());
+        assert!(!driver_deferred_probe_pending(&clk));
+
+        // Binding the clock probes the uart again.
+        assert_eq!(driver_register(&TEST_BUS, &CLK_DRIVER), Ok(()));
+        assert!(clk.driver().is_some());
+        assert!(uart.driver().is_some());
+        assert!(!driver_deferred_probe_pending(&uart));
+        assert_eq!(UART_DRIVER.probes.load(Ordering::Relaxed), 2);
+        assert_eq!(driver_probe_device(&UART_DRIVER, &uart), Err(Error::Ebusy));
+        assert_eq!(driver_probe_device(&CLK_DRIVER, &uart), Err(Error::Enodev));
+
+        // The children go with their parent.
+        let port = Device::new("test-port", Some(&uart), Some(&TEST_BUS)).unwrap();
+        assert_eq!(device_add(&port), Ok(()));
+        assert_eq!(uart.nr_children(), 1);
+        assert!(Arc::ptr_eq(port.parent().unwrap(), &uart));
+
+        device_shutdown();
+        assert_eq!(UART_DRIVER.shutdowns.load(Ordering::Relaxed), 1);
+        assert_eq!(CLK_DRIVER.shutdowns.load(Ordering::Relaxed), 1);
+
+        device_del(&uart);
+        assert_eq!(uart.nr_children(), 0);
+        assert!(uart.driver().is_none());
+        assert_eq!(UART_DRIVER.removes.load(Ordering::Relaxed), 1);
+        // The bus and the parent dropped theirs.
+        assert_eq!(Arc::strong_count(&port), 1);
+
+        driver_unregister(&TEST_BUS, &CLK_DRIVER);
+        assert!(clk.driver().is_none());
+        assert_eq!(CLK_DRIVER.removes.load(Ordering::Relaxed), 1);
+        device_del(&clk);
+        assert_eq!(Arc::strong_count(&clk), 1);
+    }
+}
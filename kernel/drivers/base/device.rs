@@ -0,0 +1,191 @@
+//! Devices
+//!
+//! Refer to linux: drivers/base/core.c, include/linux/device.h
+//!
+//! A [`Device`] is shared through [`Arc`]. It holds its parent, and its
+//! parent holds it in the children list until [`device_del`].
+
+use super::bus::{bus_add_device, bus_probe_device, bus_remove_device, Bus};
+use super::dd::{device_release_driver, driver_deferred_probe_del};
+use super::driver::Driver;
+use super::platform::{Resource, PLATFORM_NR_RESOURCES};
+use super::{device_list_nth, device_list_remove, device_node, DeviceList};
+use crate::alloc::AllocFlags;
+use crate::error::{Error, Result};
+use crate::fdtree_rs::FdtNode;
+use crate::sync::arc::Arc;
+use crate::sync::lock::{Mutex, RawSpinLockNoIrq};
+
+/// A device
+///
+/// Refer to linux: struct device
+pub struct Device {
+    name: &'static str,
+    parent: Option<Arc<Device>>,
+    bus: Option<&'static dyn Bus>,
+    // Held while a driver is bound or unbound, refer to linux: device_lock
+    pub(super) lock: Mutex<()>,
+    driver: RawSpinLockNoIrq<Option<&'static dyn Driver>>,
+    children: Mutex<DeviceList>,
+    of_node: Option<FdtNode<'static, 'static>>,
+    // Refer to linux: struct platform_device
+    resources: [Option<Resource>; PLATFORM_NR_RESOURCES],
+}
+
+impl Device {
+    /// Create a device on `bus` below `parent`, [`device_add`] makes it known.
+    ///
+    /// Refer to linux: device_initialize
+    pub fn new(
+        name: &'static str,
+        parent: Option<&Arc<Device>>,
+        bus: Option<&'static dyn Bus>,
+    ) -> Result<Arc<Self>> {
+        Self::new_of(name, parent, bus, None, &[])
+    }
+
+    /// Create a device described by the device tree node `of_node`, with
+    /// its resources. The resources beyond [`PLATFORM_NR_RESOURCES`] are
+    /// dropped.
+    pub fn new_of(
+        name: &'static str,
+        parent: Option<&Arc<Device>>,
+        bus: Option<&'static dyn Bus>,
+        of_node: Option<FdtNode<'static, 'static>>,
+        resources: &[Resource],
+    ) -> Result<Arc<Self>> {
+        let mut res = [None; PLATFORM_NR_RESOURCES];
+        for (slot, r) in res.iter_mut().zip(resources) {
+            *slot = Some(*r);
+        }
+        Arc::new(
+            Self {
+                name,
+                parent: parent.cloned(),
+                bus,
+                lock: Mutex::new((), Some("DeviceLock")),
+                driver: RawSpinLockNoIrq::new(None, Some("DeviceDriver")),
+                children: Mutex::new(DeviceList::new(), Some("DeviceChildren")),
+                of_node,
+                resources: res,
+            },
+            AllocFlags::GFP_KERNEL,
+        )
+        .map_err(|_| Error::Enomem)
+    }
+
+    /// Name of the device
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Parent of the device
+    pub fn parent(&self) -> Option<&Arc<Device>> {
+        self.parent.as_ref()
+    }
+
+    /// Bus of the device
+    pub fn bus(&self) -> Option<&'static dyn Bus> {
+        self.bus
+    }
+
+    /// The device tree node of the device
+    pub fn of_node(&self) -> Option<FdtNode<'static, 'static>> {
+        self.of_node
+    }
+
+    /// Resources of the device
+    pub fn resources(&self) -> impl Iterator<Item = &Resource> {
+        self.resources.iter().flatten()
+    }
+
+    /// The driver bound to the device
+    pub fn driver(&self) -> Option<&'static dyn Driver> {
+        *self.driver.lock()
+    }
+
+    pub(super) fn set_driver(&self, driver: Option<&'static dyn Driver>) {
+        *self.driver.lock() = driver;
+    }
+
+    /// Call `f` with each child, the children list is locked meanwhile.
+    pub fn for_each_child(&self, mut f: impl FnMut(&Arc<Device>)) {
+        for child in self.children.lock().iter() {
+            f(child.inner());
+        }
+    }
+
+    /// Number of children
+    pub fn nr_children(&self) -> usize {
+        self.children.lock().iter().count()
+    }
+}
+
+/// All the added devices, the children are in front of their parent.
+///
+/// Refer to linux: devices_kset
+static DEVICES: Mutex<DeviceList> = Mutex::new(DeviceList::new(), Some("Devices"));
+
+/// Add a device to its parent and its bus, then probe a driver for it.
+///
+/// Refer to linux: device_add
+pub fn device_add(dev: &Arc<Device>) -> Result {
+    // The entries are allocated first, the device is added to all its
+    // lists or none.
+    let entry = device_node(dev)?;
+    let parent_entry = dev.parent().map(|_| device_node(dev)).transpose()?;
+    let bus_entry = dev.bus().map(|_| device_node(dev)).transpose()?;
+
+    let mut devices = DEVICES.lock();
+    if devices.iter().any(|d| Arc::ptr_eq(d.inner(), dev)) {
+        return Err(Error::Eexist);
+    }
+    devices.push_front(entry);
+    drop(devices);
+
+    if let (Some(parent), Some(entry)) = (dev.parent(), parent_entry) {
+        parent.children.lock().push_back(entry);
+    }
+    if let (Some(bus), Some(entry)) = (dev.bus(), bus_entry) {
+        bus_add_device(bus, entry);
+        bus_probe_device(dev);
+    }
+    Ok(())
+}
+
+/// Release the driver of a device and of its children, then remove them
+/// all from their parent and bus.
+///
+/// Refer to linux: device_del
+pub fn device_del(dev: &Arc<Device>) {
+    while let Some(child) = device_list_nth(&dev.children, 0) {
+        device_del(&child);
+    }
+
+    device_release_driver(dev);
+    driver_deferred_probe_del(dev);
+    if let Some(bus) = dev.bus() {
+        bus_remove_device(bus, dev);
+    }
+    if let Some(parent) = dev.parent() {
+        device_list_remove(&mut parent.children.lock(), dev);
+    }
+    device_list_remove(&mut DEVICES.lock(), dev);
+}
+
+/// Shut down all the bound devices, the children before their parent.
+///
+/// Refer to linux: device_shutdown
+pub fn device_shutdown() {
+    let mut index = 0;
+    while let Some(dev) = device_list_nth(&DEVICES, index) {
+        index += 1;
+        let Some(bus) = dev.bus() else {
+            continue;
+        };
+        let _guard = dev.lock.lock();
+        if let Some(driver) = dev.driver() {
+            bus.shutdown(&dev, driver);
+        }
+    }
+}
@@ -0,0 +1,41 @@
+//! Drivers
+//!
+//! Refer to linux: drivers/base/driver.c
+
+use super::bus::{bus_add_driver, bus_remove_driver, Bus};
+use super::device::Device;
+use crate::drivers::fdt::OfDeviceId;
+use crate::error::Result;
+use crate::sync::arc::Arc;
+
+/// A driver, registered on a bus
+///
+/// Refer to linux: struct device_driver
+pub trait Driver: Sync {
+    /// Name of the driver
+    fn name(&self) -> &'static str;
+    /// The device tree `compatible` strings handled
+    fn of_match_table(&self) -> &'static [OfDeviceId] {
+        &[]
+    }
+    /// Bind to `dev`, `Eprobedefer` asks to try again later.
+    fn probe(&self, dev: &Arc<Device>) -> Result;
+    /// Unbind from `dev`.
+    fn remove(&self, _dev: &Arc<Device>) {}
+    /// Quiesce `dev` before reboot or power off.
+    fn shutdown(&self, _dev: &Arc<Device>) {}
+}
+
+/// Register a driver on `bus`, it is bound to the matching devices.
+///
+/// Refer to linux: driver_register
+pub fn driver_register(bus: &'static dyn Bus, drv: &'static dyn Driver) -> Result {
+    bus_add_driver(bus, drv)
+}
+
+/// Unbind a driver from its devices and remove it from `bus`.
+///
+/// Refer to linux: driver_unregister
+pub fn driver_unregister(bus: &'static dyn Bus, drv: &'static dyn Driver) {
+    bus_remove_driver(bus, drv)
+}
@@ -0,0 +1,56 @@
+//! Rynux device model
+//!
+//! Refer to linux: drivers/base/
+//!
+//! A [`Bus`](bus::Bus) matches its [`Device`](device::Device)s with its
+//! [`Driver`](driver::Driver)s, the first matching driver whose probe
+//! succeeds is bound. A probe that needs something not ready yet returns
+//! `Eprobedefer`, it is tried again once another driver is bound.
+
+pub mod bus;
+pub mod dd;
+pub mod device;
+pub mod driver;
+pub mod platform;
+
+use crate::alloc::AllocFlags;
+use crate::error::{Error, Result};
+use crate::list::{def_node, List};
+use crate::sync::arc::Arc;
+use crate::sync::lock::Mutex;
+use device::Device;
+
+def_node! {
+    /// A device on a device list
+    pub struct DeviceNode(Arc<Device>);
+}
+
+type DeviceList = List<Arc<DeviceNode>>;
+
+// The buses and the deferred probe list are global, the tests using them
+// take turns.
+#[cfg(test)]
+pub(crate) static TEST_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+// A new entry for `dev`, to put on a device list.
+fn device_node(dev: &Arc<Device>) -> Result<Arc<DeviceNode>> {
+    Arc::new(DeviceNode::new(dev.clone()), AllocFlags::GFP_KERNEL).map_err(|_| Error::Enomem)
+}
+
+// Clone the `n`th device of `list`, it is unlocked on return.
+fn device_list_nth(list: &Mutex<DeviceList>, n: usize) -> Option<Arc<Device>> {
+    list.lock().iter().nth(n).map(|d| d.inner().clone())
+}
+
+// Remove `dev` from `list`, return whether it was on it.
+fn device_list_remove(list: &mut DeviceList, dev: &Arc<Device>) -> bool {
+    let mut cursor = list.cursor_front_mut();
+    while let Some(d) = cursor.current() {
+        if Arc::ptr_eq(d.inner(), dev) {
+            cursor.remove_current();
+            return true;
+        }
+        cursor.move_next();
+    }
+    false
+}
@@ -0,0 +1,191 @@
+//! Platform bus
+//!
+//! Refer to linux: drivers/base/platform.c
+//!
+//! The bus of the devices known from the platform description rather than
+//! found by probing a hardware bus. A device with a device tree node is
+//! matched against the `compatible` table of the drivers, then by name.
+
+use super::bus::{Bus, Subsys};
+use super::dd::driver_deferred_probe_check_state;
+use super::device::{device_add, Device};
+use super::driver::{driver_register, driver_unregister, Driver};
+use crate::drivers::fdt::of_match_node;
+use crate::error::{Error, Result};
+use crate::irq::irq_of_parse_and_map;
+use crate::sync::arc::Arc;
+
+/// Most resources of a platform device
+pub const PLATFORM_NR_RESOURCES: usize = 8;
+
+/// Type of a [`Resource`]
+///
+/// Refer to linux: IORESOURCE_MEM, IORESOURCE_IRQ
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ResourceType {
+    /// Memory mapped registers
+    Mem,
+    /// Interrupt number
+    Irq,
+}
+
+/// A range of registers or an interrupt of a device
+///
+/// Refer to linux: struct resource
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Resource {
+    /// First address or interrupt
+    pub start: usize,
+    /// Last address or interrupt, included
+    pub end: usize,
+    /// Type of the resource
+    pub kind: ResourceType,
+}
+
+impl Resource {
+    /// `size` bytes of registers at `start`
+    pub const fn mem(start: usize, size: usize) -> Self {
+        Self {
+            start,
+            end: start + size - 1,
+            kind: ResourceType::Mem,
+        }
+    }
+
+    /// Interrupt `irq`
+    pub const fn irq(irq: u32) -> Self {
+        Self {
+            start: irq as usize,
+            end: irq as usize,
+            kind: ResourceType::Irq,
+        }
+    }
+
+    /// Size of the range
+    pub const fn size(&self) -> usize {
+        self.end - self.start + 1
+    }
+}
+
+/// The platform bus
+///
+/// Refer to linux: platform_bus_type
+pub struct PlatformBus {
+    subsys: Subsys,
+}
+
+impl Bus for PlatformBus {
+    fn name(&self) -> &'static str {
+        "platform"
+    }
+
+    fn subsys(&self) -> &Subsys {
+        &self.subsys
+    }
+
+    // Refer to linux: platform_match
+    fn match_device(&self, dev: &Device, drv: &dyn Driver) -> bool {
+        dev.of_node()
+            .is_some_and(|node| of_match_node(drv.of_match_table(), node).is_some())
+            || dev.name() == drv.name()
+    }
+}
+
+/// The platform bus
+pub static PLATFORM_BUS_TYPE: PlatformBus = PlatformBus {
+    subsys: Subsys::new(),
+};
+
+/// Add a platform device called `name`, a driver of the same name is bound.
+///
+/// Refer to linux: platform_device_register_simple
+pub fn platform_device_register_simple(
+    name: &'static str,
+    parent: Option<&Arc<Device>>,
+) -> Result<Arc<Device>> {
+    let dev = Device::new(name, parent, Some(&PLATFORM_BUS_TYPE))?;
+    device_add(&dev)?;
+    Ok(dev)
+}
+
+/// The `index`th resource of `dev` of type `kind`
+///
+/// Refer to linux: platform_get_resource
+pub fn platform_get_resource(dev: &Device, kind: ResourceType, index: usize) -> Option<Resource> {
+    dev.resources()
+        .filter(|r| r.kind == kind)
+        .nth(index)
+        .copied()
+}
+
+/// The `index`th interrupt of `dev`
+///
+/// The interrupts of a device of the device tree are mapped now, returns
+/// `Eprobedefer` while their controller has no driver.
+///
+/// Refer to linux: platform_get_irq
+pub fn platform_get_irq(dev: &Device, index: usize) -> Result<u32> {
+    if let Some(node) = dev.of_node() {
+        return irq_of_parse_and_map(node, index).map_err(|e| match e {
+            Error::Eprobedefer => driver_deferred_probe_check_state(dev),
+            e => e,
+        });
+    }
+    platform_get_resource(dev, ResourceType::Irq, index)
+        .map(|r| r.start as u32)
+        .ok_or(Error::Enxio)
+}
+
+/// Register a platform driver.
+///
+/// Refer to linux: platform_driver_register
+pub fn platform_driver_register(drv: &'static dyn Driver) -> Result {
+    driver_register(&PLATFORM_BUS_TYPE, drv)
+}
+
+/// Unregister a platform driver.
+///
+/// Refer to linux: platform_driver_unregister
+pub fn platform_driver_unregister(drv: &'static dyn Driver) {
+    driver_unregister(&PLATFORM_BUS_TYPE, drv)
+}
+
+/// Register a platform driver at the `device` initcall level.
+///
+/// # Example
+///
+/// ```ignore
+/// struct Pl031Driver;
+///
+/// impl Driver for Pl031Driver {
+///     fn name(&self) -> &'static str {
+///         "rtc-pl031"
+///     }
+///
+///     fn of_match_table(&self) -> &'static [OfDeviceId] {
+///         &[OfDeviceId::new("arm,pl031")]
+///     }
+///
+///     fn probe(&self, dev: &Arc<Device>) -> Result {
+///         Ok(())
+///     }
+/// }
+///
+/// static PL031_DRIVER: Pl031Driver = Pl031Driver;
+/// module_platform_driver!(PL031_DRIVER);
+/// ```
+///
+/// Refer to linux: module_platform_driver
+#[macro_export]
+macro_rules! module_platform_driver {
+    ($driver:ident) => {
+        const _: () = {
+            #[$crate::macros::initcall(device)]
+            fn platform_driver_init() -> $crate::error::Result {
+                $crate::drivers::base::platform::platform_driver_register(&$driver)
+            }
+        };
+    };
+}
+
+pub use module_platform_driver;
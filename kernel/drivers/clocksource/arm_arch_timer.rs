@@ -0,0 +1,115 @@
+//! ARM architected timer
+//!
+//! Refer to linux: drivers/clocksource/arm_arch_timer.c
+//!
+//! The virtual counter is the clock source of timekeeping. The virtual
+//! timer of every cpu is its clock event device, compared against the
+//! virtual counter, its PPI runs [`arch_timer_handler`].
+
+use crate::arch::arm64::sysregs::{CntfrqEl0, CntvCtlEl0, CntvCvalEl0, CntvctEl0};
+use crate::drivers::fdt::{of_match_node, OfDeviceId, GLOBAL_FDT};
+use crate::error::{Error, Result};
+use crate::irq::{irq_of_parse_and_map, request_irq, IrqReturn};
+use crate::time::clockevents::{
+    clockevents_handle_event, clockevents_register_device, ClockEventDevice,
+};
+use crate::time::clocksource::{clocksource_register, Clocksource};
+use crate::time::{ktime_get, Ktime, NSEC_PER_SEC};
+
+/// The virtual counter
+struct ArchCounter;
+
+impl Clocksource for ArchCounter {
+    fn name(&self) -> &'static str {
+        "arch_sys_counter"
+    }
+
+    fn read(&self) -> u64 {
+        CntvctEl0::read_raw()
+    }
+
+    fn frequency(&self) -> u64 {
+        CntfrqEl0::read_raw()
+    }
+
+    fn rating(&self) -> u32 {
+        400
+    }
+}
+
+static ARCH_COUNTER: ArchCounter = ArchCounter;
+
+struct ArchTimer;
+
+impl ClockEventDevice for ArchTimer {
+    fn name(&self) -> &'static str {
+        "arch_sys_timer"
+    }
+
+    fn set_next_ktime(&self, expires: Ktime) {
+        // Round up, the event must not fire early.
+        let delta = (expires - ktime_get()).max(0) as u128;
+        let cycles = (delta * ARCH_COUNTER.frequency() as u128).div_ceil(NSEC_PER_SEC as u128);
+        let cval = ARCH_COUNTER
+            .read()
+            .saturating_add(cycles.min(u64::MAX as u128) as u64);
+        CntvCvalEl0::write_raw(cval);
+        CntvCtlEl0::ENABLE.write();
+    }
+
+    fn shutdown(&self) {
+        CntvCtlEl0::empty().write();
+    }
+}
+
+static ARCH_TIMER: ArchTimer = ArchTimer;
+
+static ARCH_TIMER_IDS: [OfDeviceId; 2] = [
+    OfDeviceId::new("arm,armv8-timer"),
+    OfDeviceId::new("arm,armv7-timer"),
+];
+
+// The timer interrupts are the secure and non secure physical, the virtual
+// and the hypervisor timer PPIs.
+const ARCH_TIMER_VIRT_PPI: usize = 2;
+
+// Interrupt number of the virtual timer.
+fn arch_timer_virt_irq() -> Result<u32> {
+    let fdt = GLOBAL_FDT.get().ok_or(Error::Enodev)?;
+    let node = fdt
+        .all_nodes()
+        .find(|node| node.is_available() && of_match_node(&ARCH_TIMER_IDS, *node).is_some())
+        .ok_or(Error::Enodev)?;
+    irq_of_parse_and_map(node, ARCH_TIMER_VIRT_PPI)
+}
+
+/// Register the virtual counter, and the virtual timer of the current cpu
+/// with its interrupt.
+///
+/// Refer to linux: arch_timer_of_init
+pub fn arch_timer_init() {
+    clocksource_register(&ARCH_COUNTER);
+    CntvCtlEl0::empty().write();
+    let irq = arch_timer_virt_irq()
+        .and_then(|irq| request_irq(irq, arch_timer_handler, "arch_timer", 0).map(|_| irq));
+    match irq {
+        Ok(irq) => crate::pr_info!("arch_timer: virtual timer at irq {}\n", irq),
+        // Timers would never expire.
+        Err(e) => panic!("arch_timer: no virtual timer interrupt: {:?}", e),
+    }
+    clockevents_register_device(&ARCH_TIMER);
+}
+
+/// Virtual timer interrupt handler.
+///
+/// Refer to linux: arch_timer_handler_virt
+pub fn arch_timer_handler(_irq: u32, _data: usize) -> IrqReturn {
+    let ctl = CntvCtlEl0::read();
+    if !ctl.contains(CntvCtlEl0::ISTATUS) {
+        return IrqReturn::None;
+    }
+    // Masked until the next event is programmed.
+    (ctl | CntvCtlEl0::IMASK).write();
+    clockevents_handle_event();
+    IrqReturn::Handled
+}
@@ -0,0 +1,4 @@
+//! Clock source and clock event drivers
+
+#[cfg(CONFIG_ARM64)]
+pub mod arm_arch_timer;
@@ -0,0 +1,638 @@
+//! Live device tree
+//!
+//! Refer to linux: drivers/of/fdt.c, drivers/of/dynamic.c, drivers/of/base.c
+//!
+//! The flattened device tree is unflattened into reference counted nodes and
+//! properties, which can then be added, removed and changed. The unflattened
+//! names and values stay in the blob, the values set later are copied. The
+//! tree is flattened back into a new blob by [`of_fdt_serialize`].
+
+use core::alloc::Layout;
+use core::ops::Deref;
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::alloc::{AllocFlags, Allocator, MemblockAllocator};
+use crate::error::{Error, Result};
+use crate::fdtree_rs::{FdtBuilder, FdtError, FdtNode};
+use crate::list::{def_node, List};
+use crate::sync::arc::{Arc, Weak};
+use crate::sync::lock::RawSpinLockNoIrq;
+use crate::types::OnceCell;
+
+use super::GLOBAL_FDT;
+
+const FDT_HEADER_SIZE: usize = 40;
+
+/// Bytes copied in allocated memory
+pub struct OwnedBytes {
+    ptr: NonNull<u8>,
+    len: usize,
+    size: usize,
+}
+
+// SAFETY: the bytes are owned, and only changed through `&mut self`.
+unsafe impl Send for OwnedBytes {}
+// SAFETY: same as above.
+unsafe impl Sync for OwnedBytes {}
+
+impl OwnedBytes {
+    /// `size` zeroed bytes
+    pub fn zeroed(size: usize) -> Result<Self> {
+        let ptr = match size {
+            0 => NonNull::dangling(),
+            _ => {
+                let layout = Layout::array::<u8>(size).map_err(|_| Error::Einval)?;
+                MemblockAllocator::alloc(layout, AllocFlags::ZERO)
+                    .map_err(|_| Error::Enomem)?
+                    .cast()
+            }
+        };
+        Ok(Self {
+            ptr,
+            len: size,
+            size,
+        })
+    }
+
+    /// Copy `bytes`.
+    pub fn copy_from(bytes: &[u8]) -> Result<Self> {
+        let mut this = Self::zeroed(bytes.len())?;
+        this.as_mut_slice().copy_from_slice(bytes);
+        Ok(this)
+    }
+
+    /// The bytes, to be changed
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        // SAFETY: `ptr` points to `len` bytes owned by `self`.
+        unsafe { core::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl Deref for OwnedBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: `ptr` points to `len` bytes owned by `self`.
+        unsafe { core::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl Drop for OwnedBytes {
+    fn drop(&mut self) {
+        if self.size != 0 {
+            // SAFETY: allocated by `zeroed` with the same layout.
+            unsafe { MemblockAllocator::free(self.ptr, Layout::array::<u8>(self.size).unwrap()) };
+        }
+    }
+}
+
+enum Value {
+    Static(&'static [u8]),
+    Owned(OwnedBytes),
+}
+
+/// A property of an [`OfNode`]
+///
+/// Refer to linux: struct property
+pub struct Property {
+    name: &'static str,
+    value: Value,
+}
+
+impl Property {
+    /// A property whose value is kept in `value`, such as in the blob
+    pub fn new_static(name: &'static str, value: &'static [u8]) -> Result<Arc<Self>> {
+        Arc::new(
+            Self {
+                name,
+                value: Value::Static(value),
+            },
+            AllocFlags::GFP_KERNEL,
+        )
+        .map_err(|_| Error::Enomem)
+    }
+
+    /// A property with a copy of `value`
+    pub fn new(name: &'static str, value: &[u8]) -> Result<Arc<Self>> {
+        Self::from_owned(name, OwnedBytes::copy_from(value)?)
+    }
+
+    /// A property whose value is `value`
+    pub fn from_owned(name: &'static str, value: OwnedBytes) -> Result<Arc<Self>> {
+        Arc::new(
+            Self {
+                name,
+                value: Value::Owned(value),
+            },
+            AllocFlags::GFP_KERNEL,
+        )
+        .map_err(|_| Error::Enomem)
+    }
+
+    /// Name of the property
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Value of the property
+    pub fn value(&self) -> &[u8] {
+        match &self.value {
+            Value::Static(value) => value,
+            Value::Owned(value) => value,
+        }
+    }
+
+    /// The value as one big endian cell
+    ///
+    /// Refer to linux: of_property_read_u32
+    pub fn as_u32(&self) -> Option<u32> {
+        Some(u32::from_be_bytes(self.value().try_into().ok()?))
+    }
+
+    /// The value as a string
+    ///
+    /// Refer to linux: of_property_read_string
+    pub fn as_str(&self) -> Option<&str> {
+        let value = self.value().strip_suffix(&[0])?;
+        core::str::from_utf8(value).ok()
+    }
+}
+
+def_node! {
+    /// A property on the list of a node
+    pub struct PropertyNode(Arc<Property>);
+    /// A child on the list of a node
+    pub struct OfChildNode(Arc<OfNode>);
+}
+
+type PropertyList = List<Arc<PropertyNode>>;
+type ChildList = List<Arc<OfChildNode>>;
+
+fn property_node(prop: Arc<Property>) -> Result<Arc<PropertyNode>> {
+    Arc::new(PropertyNode::new(prop), AllocFlags::GFP_KERNEL).map_err(|_| Error::Enomem)
+}
+
+fn child_node(node: Arc<OfNode>) -> Result<Arc<OfChildNode>> {
+    Arc::new(OfChildNode::new(node), AllocFlags::GFP_KERNEL).map_err(|_| Error::Enomem)
+}
+
+/// A node of the live device tree
+///
+/// Refer to linux: struct device_node
+pub struct OfNode {
+    name: &'static str,
+    // Weak, the children are held by their parent.
+    parent: Option<Weak<OfNode>>,
+    attached: AtomicBool,
+    properties: RawSpinLockNoIrq<PropertyList>,
+    children: RawSpinLockNoIrq<ChildList>,
+}
+
+impl OfNode {
+    /// A node called `name` below `parent`, it is added to the children of
+    /// `parent` by [`of_attach_node`].
+    pub fn new(name: &'static str, parent: Option<&Arc<OfNode>>) -> Result<Arc<Self>> {
+        Arc::new(
+            Self {
+                name,
+                parent: parent.map(Arc::downgrade),
+                attached: AtomicBool::new(false),
+                properties: RawSpinLockNoIrq::new(PropertyList::new(), Some("OfNodeProperties")),
+                children: RawSpinLockNoIrq::new(ChildList::new(), Some("OfNodeChildren")),
+            },
+            AllocFlags::GFP_KERNEL,
+        )
+        .map_err(|_| Error::Enomem)
+    }
+
+    /// Name with the unit address, empty for the root
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// The parent node, `None` for the root or once the parent is freed
+    pub fn parent(&self) -> Option<Arc<OfNode>> {
+        self.parent.as_ref()?.upgrade()
+    }
+
+    /// Whether the node is in the children of its parent
+    pub fn is_attached(&self) -> bool {
+        self.attached.load(Ordering::Acquire)
+    }
+
+    /// The `n`th child, the children are unlocked on return.
+    pub fn nth_child(&self, n: usize) -> Option<Arc<OfNode>> {
+        self.children
+            .lock()
+            .iter()
+            .nth(n)
+            .map(|c| c.inner().clone())
+    }
+
+    /// The `n`th property, the properties are unlocked on return.
+    pub fn nth_property(&self, n: usize) -> Option<Arc<Property>> {
+        self.properties
+            .lock()
+            .iter()
+            .nth(n)
+            .map(|p| p.inner().clone())
+    }
+
+    /// The property called `name`
+    ///
+    /// Refer to linux: of_find_property
+    pub fn find_property(&self, name: &str) -> Option<Arc<Property>> {
+        self.properties
+            .lock()
+            .iter()
+            .find(|p| p.name == name)
+            .map(|p| p.inner().clone())
+    }
+
+    /// The `phandle` property, 0 without one
+    pub fn phandle(&self) -> u32 {
+        self.find_property("phandle")
+            .and_then(|p| p.as_u32())
+            .unwrap_or(0)
+    }
+
+    /// The first child called `name`, the unit address may be left out.
+    ///
+    /// Refer to linux: of_get_child_by_name
+    pub fn get_child_by_name(&self, name: &str) -> Option<Arc<OfNode>> {
+        let matches = |child: &str| match name.contains('@') {
+            true => child == name,
+            false => child.split('@').next() == Some(name),
+        };
+        self.children
+            .lock()
+            .iter()
+            .find(|c| matches(c.name))
+            .map(|c| c.inner().clone())
+    }
+
+    /// The node at `path` relative to `node`
+    pub fn find_node_by_path(node: &Arc<Self>, path: &str) -> Option<Arc<OfNode>> {
+        path.split('/')
+            .filter(|name| !name.is_empty())
+            .try_fold(node.clone(), |node, name| node.get_child_by_name(name))
+    }
+
+    /// The node below `node`, or `node` itself, whose phandle is `phandle`
+    pub fn find_node_by_phandle(node: &Arc<Self>, phandle: u32) -> Option<Arc<OfNode>> {
+        if phandle == 0 {
+            return None;
+        }
+        if node.phandle() == phandle {
+            return Some(node.clone());
+        }
+        (0..)
+            .map_while(|n| node.nth_child(n))
+            .find_map(|child| Self::find_node_by_phandle(&child, phandle))
+    }
+}
+
+/// Root of the live device tree, set by [`unflatten_device_tree`]
+///
+/// Refer to linux: of_root
+pub static OF_ROOT: OnceCell<Arc<OfNode>> = OnceCell::new();
+
+/// Take a reference to `node`.
+///
+/// Refer to linux: of_node_get
+pub fn of_node_get(node: &Arc<OfNode>) -> Arc<OfNode> {
+    node.clone()
+}
+
+/// Drop a reference to `node`, it is freed with the last one.
+///
+/// Refer to linux: of_node_put
+pub fn of_node_put(node: Arc<OfNode>) {
+    drop(node)
+}
+
+/// The node at `path` of the live tree
+///
+/// Refer to linux: of_find_node_by_path
+pub fn of_find_node_by_path(path: &str) -> Option<Arc<OfNode>> {
+    OfNode::find_node_by_path(OF_ROOT.get()?, path)
+}
+
+/// The node of the live tree whose phandle is `phandle`
+///
+/// Refer to linux: of_find_node_by_phandle
+pub fn of_find_node_by_phandle(phandle: u32) -> Option<Arc<OfNode>> {
+    OfNode::find_node_by_phandle(OF_ROOT.get()?, phandle)
+}
+
+/// Add `prop` to `node`, `Eexist` if it has a property of the same name.
+///
+/// Refer to linux: of_add_property
+pub fn of_add_property(node: &OfNode, prop: Arc<Property>) -> Result {
+    let entry = property_node(prop)?;
+    let mut properties = node.properties.lock();
+    if properties.iter().any(|p| p.name == entry.name) {
+        return Err(Error::Eexist);
+    }
+    properties.push_back(entry);
+    Ok(())
+}
+
+/// Remove the property called `name` from `node`.
+///
+/// The removed property is returned, it lives on while referenced.
+///
+/// Refer to linux: of_remove_property
+pub fn of_remove_property(node: &OfNode, name: &str) -> Result<Arc<Property>> {
+    let mut properties = node.properties.lock();
+    let mut cursor = properties.cursor_front_mut();
+    while let Some(p) = cursor.current() {
+        if p.name == name {
+            let removed = cursor.remove_current().ok_or(Error::Enoent)?;
+            return Ok(removed.inner().clone());
+        }
+        cursor.move_next();
+    }
+    Err(Error::Enoent)
+}
+
+/// Replace the property of `node` of the same name as `prop`, or add it.
+///
+/// Refer to linux: of_update_property
+pub fn of_update_property(node: &OfNode, prop: Arc<Property>) -> Result {
+    let entry = property_node(prop)?;
+    let mut properties = node.properties.lock();
+    let mut cursor = properties.cursor_front_mut();
+    while let Some(p) = cursor.current() {
+        if p.name == entry.name {
+            cursor.remove_current();
+            break;
+        }
+        cursor.move_next();
+    }
+    properties.push_back(entry);
+    Ok(())
+}
+
+/// Add `node` to the children of its parent.
+///
+/// Refer to linux: of_attach_node
+pub fn of_attach_node(node: &Arc<OfNode>) -> Result {
+    let parent = node.parent().ok_or(Error::Einval)?;
+    let entry = child_node(node.clone())?;
+    let mut children = parent.children.lock();
+    if node.is_attached() || children.iter().any(|c| c.name == node.name) {
+        return Err(Error::Eexist);
+    }
+    children.push_back(entry);
+    node.attached.store(true, Ordering::Release);
+    Ok(())
+}
+
+/// Remove `node` from the children of its parent, its own children stay.
+///
+/// Refer to linux: of_detach_node
+pub fn of_detach_node(node: &Arc<OfNode>) -> Result {
+    let parent = node.parent().ok_or(Error::Einval)?;
+    let mut children = parent.children.lock();
+    let mut cursor = children.cursor_front_mut();
+    while let Some(c) = cursor.current() {
+        if Arc::ptr_eq(c.inner(), node) {
+            cursor.remove_current();
+            node.attached.store(false, Ordering::Release);
+            return Ok(());
+        }
+        cursor.move_next();
+    }
+    Err(Error::Enoent)
+}
+
+/// Unflatten `fdt` and its children, the names and values stay in the blob.
+///
+/// Refer to linux: __unflatten_device_tree
+pub fn of_unflatten(fdt: FdtNode<'_, 'static>) -> Result<Arc<OfNode>> {
+    fn populate(fdt: FdtNode<'_, 'static>, node: &Arc<OfNode>) -> Result {
+        for prop in fdt.properties() {
+            let entry = property_node(Property::new_static(prop.name, prop.value)?)?;
+            node.properties.lock().push_back(entry);
+        }
+        for child in fdt.children() {
+            let np = OfNode::new(child.name, Some(node))?;
+            populate(child, &np)?;
+            node.children.lock().push_back(child_node(np.clone())?);
+            np.attached.store(true, Ordering::Release);
+        }
+        Ok(())
+    }
+
+    let root = OfNode::new(fdt.name, None)?;
+    populate(fdt, &root)?;
+    Ok(root)
+}
+
+/// Unflatten the boot device tree into [`OF_ROOT`].
+///
+/// Refer to linux: unflatten_device_tree
+pub fn unflatten_device_tree() {
+    if let Some(root) = GLOBAL_FDT.get().and_then(|fdt| fdt.find_node("/")) {
+        match of_unflatten(root) {
+            Ok(root) => OF_ROOT.set(root),
+            Err(e) => crate::pr_err!("OF: failed to unflatten the device tree: {:?}\n", e),
+        }
+    }
+}
+
+const fn fdt_align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+// Bytes of the struct block, and of the names without sharing, of `node`.
+fn fdt_sizes(node: &OfNode) -> (usize, usize) {
+    let mut sizes = (8 + fdt_align(node.name.len() + 1), 0);
+    for prop in (0..).map_while(|n| node.nth_property(n)) {
+        sizes.0 += 12 + fdt_align(prop.value().len());
+        sizes.1 += prop.name.len() + 1;
+    }
+    for child in (0..).map_while(|n| node.nth_child(n)) {
+        let (structs, strings) = fdt_sizes(&child);
+        sizes.0 += structs;
+        sizes.1 += strings;
+    }
+    sizes
+}
+
+fn fdt_error(err: FdtError) -> Error {
+    match err {
+        // The tree changed since it was measured.
+        FdtError::NoSpace => Error::Eagain,
+        _ => Error::Einval,
+    }
+}
+
+fn fdt_build(builder: &mut FdtBuilder<'_>, node: &OfNode) -> Result {
+    builder.begin_node(node.name).map_err(fdt_error)?;
+    for prop in (0..).map_while(|n| node.nth_property(n)) {
+        builder
+            .property(prop.name, prop.value())
+            .map_err(fdt_error)?;
+    }
+    for child in (0..).map_while(|n| node.nth_child(n)) {
+        fdt_build(builder, &child)?;
+    }
+    builder.end_node().map_err(fdt_error)
+}
+
+/// Flatten `root` and its children into a new blob, with the memory
+/// reservations `reserved` of `(address, size)`.
+///
+/// `Eagain` if the tree grew while flattened.
+///
+/// Refer to linux: scripts/dtc/flattree.c
+pub fn of_fdt_serialize(root: &OfNode, reserved: &[(u64, u64)]) -> Result<OwnedBytes> {
+    let (structs_size, strings_size) = fdt_sizes(root);
+    let size = FDT_HEADER_SIZE + (reserved.len() + 1) * 16 + structs_size + 4 + strings_size;
+    let mut blob = OwnedBytes::zeroed(size)?;
+
+    let mut builder = FdtBuilder::new(blob.as_mut_slice()).map_err(fdt_error)?;
+    for &(address, size) in reserved {
+        builder.add_reservation(address, size).map_err(fdt_error)?;
+    }
+    fdt_build(&mut builder, root)?;
+    blob.len = builder.finish().map_err(fdt_error)?.len();
+    Ok(blob)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fdtree_rs::LinuxFdt;
+    use crate::schedule::task::set_test_current;
+    use std::boxed::Box;
+
+    static DTB: &[u8] = include_bytes!("../../../third_lib/fdtree-rs/dtb/test.dtb");
+
+    fn fdt_of(blob: &[u8]) -> &'static LinuxFdt<'static> {
+        let blob: &'static [u8] = Box::leak(Box::from(blob));
+        Box::leak(Box::new(LinuxFdt::new(blob).unwrap()))
+    }
+
+    #[test]
+    fn test_unflatten_serialize() {
+        set_test_current();
+        let fdt = fdt_of(DTB);
+        let root = of_unflatten(fdt.find_node("/").unwrap()).unwrap();
+        let uart = OfNode::find_node_by_path(&root, "/soc/uart@10000000").unwrap();
+        assert!(Arc::ptr_eq(
+            &uart.parent().unwrap(),
+            &OfNode::find_node_by_path(&root, "/soc").unwrap()
+        ));
+        assert_eq!(
+            uart.find_property("compatible").unwrap().as_str(),
+            Some("ns16550a")
+        );
+        let plic = OfNode::find_node_by_path(&root, "/soc/plic").unwrap();
+        assert!(Arc::ptr_eq(
+            &OfNode::find_node_by_phandle(&root, plic.phandle()).unwrap(),
+            &plic
+        ));
+
+        // Unchanged, the blob has the same nodes and properties.
+        let reserved: std::vec::Vec<_> = fdt
+            .sys_memory_reservations()
+            .map(|r| (r.address() as u64, r.size() as u64))
+            .collect();
+        let blob = of_fdt_serialize(&root, &reserved).unwrap();
+        let copy = fdt_of(&blob);
+        assert_eq!(copy.all_nodes().count(), fdt.all_nodes().count());
+        for (a, b) in fdt.all_nodes().zip(copy.all_nodes()) {
+            assert_eq!(a.name, b.name);
+            assert!(a
+                .properties()
+                .map(|p| (p.name, p.value))
+                .eq(b.properties().map(|p| (p.name, p.value))));
+        }
+        assert_eq!(copy.sys_memory_reservations().count(), reserved.len());
+        assert_eq!(
+            copy.chosen().unwrap().bootargs(),
+            fdt.chosen().unwrap().bootargs()
+        );
+
+        // Fix it up.
+        let bootargs = Property::new("bootargs", b"console=ttyAMA0\0").unwrap();
+        let chosen = OfNode::find_node_by_path(&root, "/chosen").unwrap();
+        assert_eq!(
+            of_add_property(&chosen, bootargs.clone()),
+            Err(Error::Eexist)
+        );
+        assert_eq!(of_update_property(&chosen, bootargs), Ok(()));
+        let removed = of_remove_property(&uart, "interrupts").unwrap();
+        assert_eq!(removed.as_u32(), Some(10));
+        assert_eq!(
+            of_remove_property(&uart, "interrupts").err(),
+            Some(Error::Enoent)
+        );
+        assert_eq!(
+            of_add_property(
+                &uart,
+                Property::new("reg-shift", &2u32.to_be_bytes()).unwrap()
+            ),
+            Ok(())
+        );
+
+        let soc = of_node_get(&uart.parent().unwrap());
+        let node = OfNode::new("rtc@2000", Some(&soc)).unwrap();
+        of_add_property(
+            &node,
+            Property::new_static("compatible", b"arm,pl031\0").unwrap(),
+        )
+        .unwrap();
+        assert_eq!(of_attach_node(&node), Ok(()));
+        assert_eq!(of_attach_node(&node), Err(Error::Eexist));
+        let flash = soc.get_child_by_name("flash").unwrap();
+        assert_eq!(of_detach_node(&flash), Ok(()));
+        assert!(!flash.is_attached());
+        assert_eq!(of_detach_node(&flash), Err(Error::Enoent));
+        of_node_put(soc);
+
+        let blob = of_fdt_serialize(&root, &[]).unwrap();
+        let copy = fdt_of(&blob);
+        assert_eq!(copy.chosen().unwrap().bootargs(), Some("console=ttyAMA0"));
+        let uart = copy.find_node("/soc/uart@10000000").unwrap();
+        assert!(uart.property("interrupts").is_none());
+        assert_eq!(uart.property("reg-shift").unwrap().as_usize(), Some(2));
+        let rtc = copy.find_node("/soc/rtc@2000").unwrap();
+        assert_eq!(rtc.compatible().unwrap().first(), Some("arm,pl031"));
+        assert!(copy.find_node("/soc/flash").is_none());
+        assert_eq!(copy.sys_memory_reservations().count(), 0);
+    }
+
+    #[test]
+    fn test_detach_free() {
+        set_test_current();
+        let root = OfNode::new("", None).unwrap();
+        let soc = OfNode::new("soc", Some(&root)).unwrap();
+        let uart = OfNode::new("uart@1000", Some(&soc)).unwrap();
+        of_add_property(&uart, Property::new_static("status", b"okay\0").unwrap()).unwrap();
+        assert_eq!(of_attach_node(&soc), Ok(()));
+        assert_eq!(of_attach_node(&uart), Ok(()));
+        let (weak_soc, weak_uart) = (Arc::downgrade(&soc), Arc::downgrade(&uart));
+
+        // Held by the tree
+        of_node_put(uart);
+        of_node_put(soc);
+        let soc = weak_soc.upgrade().unwrap();
+        assert!(Arc::ptr_eq(
+            &weak_uart.upgrade().unwrap().parent().unwrap(),
+            &soc
+        ));
+
+        // The detached subtree is freed with its last reference.
+        assert_eq!(of_detach_node(&soc), Ok(()));
+        assert!(weak_uart.upgrade().is_some());
+        of_node_put(soc);
+        assert!(weak_soc.upgrade().is_none());
+        assert!(weak_uart.upgrade().is_none());
+        assert!(root.nth_child(0).is_none());
+    }
+}
@@ -1,8 +1,15 @@
 //! Rynux fdt driver
 
+pub mod live;
+pub mod overlay;
+pub mod platform;
+
+use core::alloc::Layout;
 use core::ops::Deref;
 
-use crate::fdtree_rs::LinuxFdt;
+use crate::alloc::{AllocFlags, Allocator, MemblockAllocator};
+use crate::error::{Error, Result};
+use crate::fdtree_rs::{FdtIndexEntry, LinuxFdt};
 use crate::mm::VirtAddr;
 use crate::types::OnceCell;
 
@@ -13,6 +20,7 @@ pub use crate::fdtree_rs::FdtNode;
 /// A wrapper for LinuxFdt to impl Deref
 pub struct LinuxFdtWrapper<'a> {
     fdt: LinuxFdt<'a>,
+    indexed: OnceCell<LinuxFdt<'a>>,
 }
 
 /// A static instance of the FDT. only init once
@@ -24,15 +32,157 @@ impl<'a> LinuxFdtWrapper<'a> {
         GLOBAL_FDT.set(unsafe {
             LinuxFdtWrapper {
                 fdt: LinuxFdt::from_ptr(fdt_va.as_usize() as *const u8).expect("Invalid fdt"),
+                indexed: OnceCell::new(),
             }
         });
     }
 }
 
+impl LinuxFdtWrapper<'static> {
+    /// Index the nodes, the phandle and path lookups use the index from
+    /// then on. The memblock allocator must be usable.
+    ///
+    /// Refer to linux: unflatten_device_tree
+    pub(crate) fn build_index(&self) -> Result {
+        let nr = self.fdt.node_count();
+        let layout = Layout::array::<FdtIndexEntry>(nr).map_err(|_| Error::Einval)?;
+        let ptr = MemblockAllocator::alloc(layout, AllocFlags::ZERO).map_err(|_| Error::Enomem)?;
+        // SAFETY: the zeroed memory holds `nr` entries and is never freed.
+        let entries =
+            unsafe { core::slice::from_raw_parts_mut(ptr.as_ptr().cast::<FdtIndexEntry>(), nr) };
+        let indexed = self.fdt.build_index(entries).map_err(|_| Error::Einval)?;
+        self.indexed.set(indexed);
+        Ok(())
+    }
+}
+
 impl Deref for LinuxFdtWrapper<'static> {
     type Target = LinuxFdt<'static>;
 
     fn deref(&self) -> &Self::Target {
-        &self.fdt
+        self.indexed.get().unwrap_or(&self.fdt)
+    }
+}
+
+/// Rate of the clock called `name` of `node`, or of its first clock.
+///
+/// Returns `Enoent` if `node` has no clock, `Einval` if its `clocks` does
+/// not parse, `Eprobedefer` if the rate is not known yet: a clock driver
+/// would give it.
+///
+/// Refer to linux: of_clk_get_by_name, clk_get_rate
+///
+/// TODO: there is no clock framework, only providers with a fixed
+/// `clock-frequency` are known.
+pub fn of_clk_get_rate(node: FdtNode<'_, '_>, name: Option<&str>) -> Result<u32> {
+    node.property("clocks").ok_or(Error::Enoent)?;
+    let index = name
+        .and_then(|name| node.property_match_string("clock-names", name))
+        .unwrap_or(0);
+    let clk = node
+        .parse_phandle_with_args("clocks", "#clock-cells", index)
+        .ok_or(Error::Einval)?;
+    clk.node
+        .property("clock-frequency")
+        .and_then(|p| p.as_usize())
+        .map(|rate| rate as u32)
+        .ok_or(Error::Eprobedefer)
+}
+
+/// An entry of the device tree match table of a driver
+///
+/// Refer to linux: struct of_device_id
+#[derive(Debug)]
+pub struct OfDeviceId {
+    /// The `compatible` string
+    pub compatible: &'static str,
+    /// Driver data for the devices matching it
+    pub data: usize,
+}
+
+impl OfDeviceId {
+    /// Create an entry without data.
+    pub const fn new(compatible: &'static str) -> Self {
+        Self {
+            compatible,
+            data: 0,
+        }
+    }
+
+    /// Create an entry with driver data.
+    pub const fn with_data(compatible: &'static str, data: usize) -> Self {
+        Self { compatible, data }
+    }
+}
+
+/// The entry of `table` matching `node`, the first `compatible` string of
+/// `node` that matches wins as it is the most specific one.
+///
+/// Refer to linux: of_match_node
+pub fn of_match_node(
+    table: &'static [OfDeviceId],
+    node: FdtNode<'_, '_>,
+) -> Option<&'static OfDeviceId> {
+    node.compatible()?
+        .all()
+        .find_map(|c| table.iter().find(|id| id.compatible == c))
+}
+
+/// Whether `node` is compatible with `compat`
+///
+/// Refer to linux: of_device_is_compatible
+pub fn of_device_is_compatible(node: FdtNode<'_, '_>, compat: &str) -> bool {
+    node.compatible()
+        .is_some_and(|c| c.all().any(|c| c == compat))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static DTB: &[u8] = include_bytes!("../../../third_lib/fdtree-rs/dtb/test.dtb");
+    static BINDINGS: &[u8] = include_bytes!("../../../third_lib/fdtree-rs/dtb/bindings.dtb");
+
+    #[test]
+    fn test_of_clk_get_rate() {
+        let fdt = LinuxFdt::new(BINDINGS).unwrap();
+        let mmc = fdt.find_node("/mmc").unwrap();
+        assert_eq!(of_clk_get_rate(mmc, Some("ciu")), Ok(24_000_000));
+        // The first clock, of a provider without a driver.
+        assert_eq!(of_clk_get_rate(mmc, Some("biu")), Err(Error::Eprobedefer));
+        assert_eq!(of_clk_get_rate(mmc, None), Err(Error::Eprobedefer));
+        let broken = fdt.find_node("/broken").unwrap();
+        assert_eq!(of_clk_get_rate(broken, None), Err(Error::Einval));
+        let psci = fdt.find_node("/psci").unwrap();
+        assert_eq!(of_clk_get_rate(psci, None), Err(Error::Enoent));
+    }
+
+    #[test]
+    fn test_build_index() {
+        let fdt = LinuxFdtWrapper {
+            fdt: LinuxFdt::new(DTB).unwrap(),
+            indexed: OnceCell::new(),
+        };
+        let uart = fdt.find_node("/soc/uart@10000000").unwrap();
+        let pci = fdt.find_node("/soc/pci").unwrap();
+        assert_eq!(fdt.build_index(), Ok(()));
+        assert!(fdt.indexed.get().is_some());
+
+        // Same nodes, the names point in the blob.
+        let indexed = fdt.find_node("/soc/uart@10000000").unwrap();
+        assert!(core::ptr::eq(indexed.name, uart.name));
+        assert!(core::ptr::eq(
+            fdt.find_node("/soc/pci").unwrap().name,
+            pci.name
+        ));
+        let parent = uart.interrupt_parent().unwrap();
+        let phandle = parent
+            .property("phandle")
+            .and_then(|p| p.as_usize())
+            .unwrap();
+        assert!(core::ptr::eq(
+            fdt.find_phandle(phandle as u32).unwrap().name,
+            parent.name
+        ));
     }
 }
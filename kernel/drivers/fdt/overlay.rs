@@ -0,0 +1,371 @@
+//! Device tree overlays
+//!
+//! Refer to linux: drivers/of/overlay.c, drivers/of/resolver.c
+//!
+//! An overlay blob is unflattened, its phandles are moved past the ones of
+//! the live tree, its references to the labels of the live tree are resolved
+//! through `/__symbols__`, then the `__overlay__` node of each fragment is
+//! merged into the node named by `target` or `target-path`.
+
+use crate::error::{Error, Result};
+use crate::fdtree_rs::LinuxFdt;
+use crate::sync::arc::Arc;
+
+use super::live::{
+    of_attach_node, of_unflatten, of_update_property, OfNode, OwnedBytes, Property, OF_ROOT,
+};
+
+const OF_PHANDLE_ILLEGAL: u32 = 0xffff_ffff;
+
+fn children(node: &OfNode) -> impl Iterator<Item = Arc<OfNode>> + '_ {
+    (0..).map_while(|n| node.nth_child(n))
+}
+
+fn properties(node: &OfNode) -> impl Iterator<Item = Arc<Property>> + '_ {
+    (0..).map_while(|n| node.nth_property(n))
+}
+
+// The child whose name, with the unit address, is `name`.
+fn child(node: &OfNode, name: &str) -> Option<Arc<OfNode>> {
+    children(node).find(|c| c.name() == name)
+}
+
+fn child_by_path(node: &Arc<OfNode>, path: &str) -> Option<Arc<OfNode>> {
+    path.split('/')
+        .filter(|name| !name.is_empty())
+        .try_fold(node.clone(), |node, name| child(&node, name))
+}
+
+// The largest phandle of the tree, `OF_PHANDLE_ILLEGAL` is not one.
+fn max_phandle(node: &OfNode) -> u32 {
+    let phandle = match node.phandle() {
+        OF_PHANDLE_ILLEGAL => 0,
+        phandle => phandle,
+    };
+    children(node)
+        .map(|c| max_phandle(&c))
+        .fold(phandle, u32::max)
+}
+
+// `phandle` moved by `delta`, `None` if it would overflow or be illegal.
+fn move_phandle(phandle: u32, delta: u32) -> Option<u32> {
+    phandle
+        .checked_add(delta)
+        .filter(|&phandle| phandle != OF_PHANDLE_ILLEGAL)
+}
+
+// Replace the cell at byte `offset` of the property `name` by `f` of it.
+fn update_cell(
+    node: &OfNode,
+    name: &str,
+    offset: usize,
+    f: impl FnOnce(u32) -> Option<u32>,
+) -> Result {
+    let prop = node.find_property(name).ok_or(Error::Einval)?;
+    let mut value = OwnedBytes::copy_from(prop.value())?;
+    let cell = value
+        .as_mut_slice()
+        .get_mut(offset..offset + 4)
+        .ok_or(Error::Einval)?;
+    let new = f(u32::from_be_bytes((&*cell).try_into().unwrap())).ok_or(Error::Einval)?;
+    cell.copy_from_slice(&new.to_be_bytes());
+    of_update_property(node, Property::from_owned(prop.name(), value)?)
+}
+
+fn adjust_phandles(node: &OfNode, delta: u32) -> Result {
+    let phandle = node.phandle();
+    if phandle != 0 && phandle != OF_PHANDLE_ILLEGAL {
+        update_cell(node, "phandle", 0, |phandle| move_phandle(phandle, delta))?;
+    }
+    children(node).try_for_each(|c| adjust_phandles(&c, delta))
+}
+
+// The properties of `fixups` list the offsets of the phandles in the same
+// named properties of `node`, its children mirror the children of `node`.
+fn adjust_local_phandles(fixups: &OfNode, node: &OfNode, delta: u32) -> Result {
+    for fixup in properties(fixups) {
+        if !fixup.value().len().is_multiple_of(4) {
+            return Err(Error::Einval);
+        }
+        for offset in fixup.value().chunks_exact(4) {
+            let offset = u32::from_be_bytes(offset.try_into().unwrap()) as usize;
+            update_cell(node, fixup.name(), offset, |phandle| {
+                move_phandle(phandle, delta)
+            })?;
+        }
+    }
+    for fixups_child in children(fixups) {
+        let node_child = child(node, fixups_child.name()).ok_or(Error::Einval)?;
+        adjust_local_phandles(&fixups_child, &node_child, delta)?;
+    }
+    Ok(())
+}
+
+// Each property of `fixups` is named after a label of the live tree and
+// lists the "path:property:offset" of its references in the overlay.
+fn resolve_fixups(base: &Arc<OfNode>, overlay: &Arc<OfNode>, fixups: &OfNode) -> Result {
+    let symbols = OfNode::find_node_by_path(base, "/__symbols__");
+    for fixup in properties(fixups) {
+        let phandle = symbols
+            .as_ref()
+            .and_then(|symbols| symbols.find_property(fixup.name()))
+            .and_then(|path| OfNode::find_node_by_path(base, path.as_str()?))
+            .map(|node| node.phandle())
+            .filter(|&phandle| phandle != 0);
+        let Some(phandle) = phandle else {
+            crate::pr_err!("overlay: symbol {} not found\n", fixup.name());
+            return Err(Error::Enoent);
+        };
+
+        for location in fixup.value().split(|&b| b == 0).filter(|l| !l.is_empty()) {
+            let location = core::str::from_utf8(location).map_err(|_| Error::Einval)?;
+            let mut fields = location.splitn(3, ':');
+            let (Some(path), Some(name), Some(offset)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                return Err(Error::Einval);
+            };
+            let offset = offset.parse().map_err(|_| Error::Einval)?;
+            let node = child_by_path(overlay, path).ok_or(Error::Einval)?;
+            update_cell(&node, name, offset, |_| Some(phandle))?;
+        }
+    }
+    Ok(())
+}
+
+/// Move the phandles of `overlay` past the ones of `base`, and resolve its
+/// references to the labels of `base`.
+///
+/// Refer to linux: of_resolve_phandles
+pub fn of_resolve_phandles(base: &Arc<OfNode>, overlay: &Arc<OfNode>) -> Result {
+    let delta = max_phandle(base).checked_add(1).ok_or(Error::Einval)?;
+    adjust_phandles(overlay, delta)?;
+    if let Some(local_fixups) = child(overlay, "__local_fixups__") {
+        adjust_local_phandles(&local_fixups, overlay, delta)?;
+    }
+    if let Some(fixups) = child(overlay, "__fixups__") {
+        resolve_fixups(base, overlay, &fixups)?;
+    }
+    Ok(())
+}
+
+// Refer to linux: find_target
+fn fragment_target(base: &Arc<OfNode>, fragment: &OfNode) -> Result<Arc<OfNode>> {
+    let target = if let Some(target) = fragment.find_property("target") {
+        target
+            .as_u32()
+            .and_then(|phandle| OfNode::find_node_by_phandle(base, phandle))
+    } else if let Some(path) = fragment.find_property("target-path") {
+        path.as_str()
+            .and_then(|path| OfNode::find_node_by_path(base, path))
+    } else {
+        None
+    };
+    target.ok_or_else(|| {
+        crate::pr_err!("overlay: target of {} not found\n", fragment.name());
+        Error::Einval
+    })
+}
+
+// The target of the fragment of the overlay path `path`, and the rest of
+// the path below the `__overlay__` node.
+fn symbol_target<'a>(
+    base: &Arc<OfNode>,
+    overlay: &OfNode,
+    path: &'a str,
+) -> Result<(Arc<OfNode>, &'a str)> {
+    let (fragment, rest) = path
+        .strip_prefix('/')
+        .and_then(|path| path.split_once('/'))
+        .ok_or(Error::Einval)?;
+    let rest = rest.strip_prefix("__overlay__").ok_or(Error::Einval)?;
+    if !rest.is_empty() && !rest.starts_with('/') {
+        return Err(Error::Einval);
+    }
+    let fragment = child(overlay, fragment).ok_or(Error::Einval)?;
+    Ok((fragment_target(base, &fragment)?, rest))
+}
+
+fn path_len(node: &OfNode) -> usize {
+    node.parent()
+        .map_or(0, |parent| path_len(&parent) + 1 + node.name().len())
+}
+
+// Writes the path of `node`, empty for the root, at the start of `buf`.
+fn write_path(node: &OfNode, buf: &mut [u8]) -> usize {
+    let Some(parent) = node.parent() else {
+        return 0;
+    };
+    let pos = write_path(&parent, buf);
+    buf[pos] = b'/';
+    buf[pos + 1..][..node.name().len()].copy_from_slice(node.name().as_bytes());
+    pos + 1 + node.name().len()
+}
+
+// Refer to linux: add_changeset_node
+fn merge(target: &Arc<OfNode>, overlay: &OfNode) -> Result {
+    for prop in properties(overlay) {
+        of_update_property(target, prop)?;
+    }
+    for overlay_child in children(overlay) {
+        match child(target, overlay_child.name()) {
+            Some(node) => merge(&node, &overlay_child)?,
+            None => {
+                let node = OfNode::new(overlay_child.name(), Some(target))?;
+                merge(&node, &overlay_child)?;
+                of_attach_node(&node)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// The labels of the overlay are added to `/__symbols__` with the paths of
+// their nodes in the live tree.
+fn add_symbols(base: &Arc<OfNode>, overlay: &OfNode, symbols: &OfNode) -> Result {
+    let base_symbols = match child(base, "__symbols__") {
+        Some(base_symbols) => base_symbols,
+        None => {
+            let base_symbols = OfNode::new("__symbols__", Some(base))?;
+            of_attach_node(&base_symbols)?;
+            base_symbols
+        }
+    };
+    for symbol in properties(symbols) {
+        let (target, rest) = symbol_target(base, overlay, symbol.as_str().ok_or(Error::Einval)?)?;
+        let len = path_len(&target) + rest.len();
+        let mut value = OwnedBytes::zeroed(len.max(1) + 1)?;
+        let buf = value.as_mut_slice();
+        let pos = write_path(&target, buf);
+        buf[pos..len].copy_from_slice(rest.as_bytes());
+        if len == 0 {
+            buf[0] = b'/';
+        }
+        of_update_property(&base_symbols, Property::from_owned(symbol.name(), value)?)?;
+    }
+    Ok(())
+}
+
+/// Apply the overlay `blob` to the tree `base`.
+///
+/// The overlay is resolved and all its targets are looked up before the
+/// tree is changed, so it is left unchanged if one of them fails. There is
+/// no rollback though: if the merge itself fails, e.g. on `Enomem`, the
+/// fragments merged so far stay applied and the tree is partly changed.
+///
+/// Refer to linux: of_overlay_apply
+pub fn of_overlay_apply(base: &Arc<OfNode>, blob: &'static [u8]) -> Result {
+    let fdt = LinuxFdt::new(blob).map_err(|_| Error::Einval)?;
+    let overlay = of_unflatten(fdt.find_node("/").ok_or(Error::Einval)?)?;
+    of_resolve_phandles(base, &overlay)?;
+
+    let fragments = || {
+        children(&overlay).filter_map(|fragment| Some((child(&fragment, "__overlay__")?, fragment)))
+    };
+    let symbols = child(&overlay, "__symbols__");
+    for (_, fragment) in fragments() {
+        fragment_target(base, &fragment)?;
+    }
+    for symbol in symbols.iter().flat_map(|symbols| properties(symbols)) {
+        symbol_target(base, &overlay, symbol.as_str().ok_or(Error::Einval)?)?;
+    }
+
+    for (node, fragment) in fragments() {
+        merge(&fragment_target(base, &fragment)?, &node)?;
+    }
+    if let Some(symbols) = symbols {
+        add_symbols(base, &overlay, &symbols)?;
+    }
+    Ok(())
+}
+
+/// Apply the overlay `blob` to the live tree.
+///
+/// Refer to linux: of_overlay_fdt_apply
+pub fn of_overlay_fdt_apply(blob: &'static [u8]) -> Result {
+    of_overlay_apply(OF_ROOT.get().ok_or(Error::Enodev)?, blob)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schedule::task::set_test_current;
+
+    static BASE: &[u8] = include_bytes!("../../../third_lib/fdtree-rs/dtb/overlay-base.dtb");
+    static OVERLAY: &[u8] = include_bytes!("../../../third_lib/fdtree-rs/dtb/overlay.dtb");
+    static DTB: &[u8] = include_bytes!("../../../third_lib/fdtree-rs/dtb/test.dtb");
+
+    fn unflatten(blob: &'static [u8]) -> Arc<OfNode> {
+        of_unflatten(LinuxFdt::new(blob).unwrap().find_node("/").unwrap()).unwrap()
+    }
+
+    fn cells(prop: &Property) -> std::vec::Vec<u32> {
+        prop.value()
+            .chunks_exact(4)
+            .map(|c| u32::from_be_bytes(c.try_into().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_overlay_apply() {
+        set_test_current();
+
+        // Without the labels the overlay refers to, nothing is applied.
+        let root = unflatten(DTB);
+        assert_eq!(of_overlay_apply(&root, OVERLAY), Err(Error::Enoent));
+        assert!(root.get_child_by_name("hat-gpio").is_none());
+
+        let root = unflatten(BASE);
+        assert_eq!(of_overlay_apply(&root, OVERLAY), Ok(()));
+
+        let i2c = OfNode::find_node_by_path(&root, "/soc/i2c@2000").unwrap();
+        let status = i2c.find_property("status").unwrap();
+        assert_eq!(status.as_str(), Some("okay"));
+        let eeprom = i2c.get_child_by_name("eeprom@50").unwrap();
+        assert!(eeprom.is_attached());
+        assert_eq!(eeprom.phandle(), 5);
+        let hat = root.get_child_by_name("hat-gpio").unwrap();
+        assert_eq!(hat.phandle(), 6);
+        assert!(Arc::ptr_eq(
+            &OfNode::find_node_by_phandle(&root, 6).unwrap(),
+            &hat
+        ));
+
+        let sensor = i2c.get_child_by_name("sensor").unwrap();
+        let interrupt_parent = sensor.find_property("interrupt-parent").unwrap();
+        assert_eq!(interrupt_parent.as_u32(), Some(1));
+        let wp_gpios = sensor.find_property("wp-gpios").unwrap();
+        assert_eq!(cells(&wp_gpios), [6, 1, 0, 3, 4, 0]);
+
+        let symbols = root.get_child_by_name("__symbols__").unwrap();
+        let hat_eeprom = symbols.find_property("hat_eeprom").unwrap();
+        assert_eq!(hat_eeprom.as_str(), Some("/soc/i2c@2000/eeprom@50"));
+        let hat_gpio = symbols.find_property("hat_gpio").unwrap();
+        assert_eq!(hat_gpio.as_str(), Some("/hat-gpio"));
+        let gpio = symbols.find_property("gpio").unwrap();
+        assert_eq!(gpio.as_str(), Some("/soc/gpio@3000"));
+    }
+
+    #[test]
+    fn test_resolve_illegal_phandle() {
+        set_test_current();
+
+        // A node without a valid phandle does not move the ones of the overlay.
+        let root = unflatten(BASE);
+        let max = max_phandle(&root);
+        let node = OfNode::new("illegal", Some(&root)).unwrap();
+        of_update_property(&node, Property::new_static("phandle", &[0xff; 4]).unwrap()).unwrap();
+        of_attach_node(&node).unwrap();
+        assert_eq!(max_phandle(&root), max);
+        assert_eq!(of_overlay_apply(&root, OVERLAY), Ok(()));
+        let hat = root.get_child_by_name("hat-gpio").unwrap();
+        assert_eq!(hat.phandle(), 6);
+
+        // No phandle is left past the largest one.
+        static LAST: [u8; 4] = 0xffff_fffeu32.to_be_bytes();
+        let root = unflatten(BASE);
+        of_update_property(&root, Property::new_static("phandle", &LAST).unwrap()).unwrap();
+        assert_eq!(of_overlay_apply(&root, OVERLAY), Err(Error::Einval));
+        assert!(root.get_child_by_name("hat-gpio").is_none());
+    }
+}
@@ -0,0 +1,247 @@
+//! Platform devices from the device tree
+//!
+//! Refer to linux: drivers/of/platform.c, drivers/of/device.c
+//!
+//! Every available node with a `compatible` property below the root becomes
+//! a platform device, and so do the children of the bus nodes, such as
+//! `simple-bus`. The `reg` ranges and the interrupts are the resources of the
+//! device, it is named after its node. The `reg` addresses are translated
+//! through the `ranges` of the parent buses.
+
+use crate::drivers::base::bus::bus_find_device;
+use crate::drivers::base::device::{device_add, Device};
+use crate::drivers::base::platform::{Resource, PLATFORM_BUS_TYPE, PLATFORM_NR_RESOURCES};
+use crate::error::{Error, Result};
+use crate::fdtree_rs::FdtNode;
+use crate::irq::irq_of_parse_and_map;
+use crate::sync::arc::Arc;
+
+use super::{of_match_node, OfDeviceId};
+
+/// Buses whose children are platform devices too
+///
+/// Refer to linux: of_default_bus_match_table
+pub static OF_DEFAULT_BUS_MATCH_TABLE: [OfDeviceId; 4] = [
+    OfDeviceId::new("simple-bus"),
+    OfDeviceId::new("simple-mfd"),
+    OfDeviceId::new("isa"),
+    OfDeviceId::new("arm,amba-bus"),
+];
+
+// Nodes are compared by their name, it points in the blob.
+fn of_node_eq(a: FdtNode<'_, '_>, b: FdtNode<'_, '_>) -> bool {
+    core::ptr::eq(a.name, b.name)
+}
+
+/// The platform device of `node`
+///
+/// Refer to linux: of_find_device_by_node
+pub fn of_find_device_by_node(node: FdtNode<'_, '_>) -> Option<Arc<Device>> {
+    bus_find_device(&PLATFORM_BUS_TYPE, |dev| {
+        dev.of_node().is_some_and(|n| of_node_eq(n, node))
+    })
+}
+
+/// Driver data of the match table entry of `dev`
+///
+/// Refer to linux: of_device_get_match_data
+pub fn of_device_get_match_data(dev: &Device) -> Option<usize> {
+    let table = dev.driver()?.of_match_table();
+    of_match_node(table, dev.of_node()?).map(|id| id.data)
+}
+
+/// Create and add the platform device of `node` below `parent`.
+///
+/// Refer to linux: of_platform_device_create, of_device_alloc
+pub fn of_platform_device_create(
+    node: FdtNode<'static, 'static>,
+    parent: Option<&Arc<Device>>,
+) -> Result<Arc<Device>> {
+    let mut resources = [Resource::irq(0); PLATFORM_NR_RESOURCES];
+    let mut nr = 0;
+    let regs = node.reg().into_iter().flatten().filter_map(|reg| {
+        let Some(addr) = node.translate_address(reg.starting_address as u64) else {
+            crate::pr_warn!("{}: reg not translated\n", node.name);
+            return None;
+        };
+        Some(Resource::mem(addr as usize, reg.size.max(1)))
+    });
+    // The interrupts of a controller without a driver yet are left out,
+    // `platform_get_irq` defers the probe for them.
+    let irqs = (0..)
+        .take_while(|&index| node.interrupt(index).is_some())
+        .filter_map(|index| match irq_of_parse_and_map(node, index) {
+            Ok(irq) => Some(irq),
+            Err(Error::Eprobedefer) => None,
+            Err(e) => {
+                crate::pr_warn!("{}: interrupt {} not mapped: {:?}\n", node.name, index, e);
+                None
+            }
+        });
+    for res in regs.chain(irqs.map(Resource::irq)) {
+        if nr == PLATFORM_NR_RESOURCES {
+            crate::pr_warn!("{}: too many resources\n", node.name);
+            break;
+        }
+        resources[nr] = res;
+        nr += 1;
+    }
+
+    let dev = Device::new_of(
+        node.name,
+        parent,
+        Some(&PLATFORM_BUS_TYPE),
+        Some(node),
+        &resources[..nr],
+    )?;
+    device_add(&dev)?;
+    Ok(dev)
+}
+
+// Create the device of `bus`, and the devices of its children if it
+// matches `matches`.
+//
+// Refer to linux: of_platform_bus_create
+fn of_platform_bus_create(
+    bus: FdtNode<'static, 'static>,
+    matches: &'static [OfDeviceId],
+    parent: Option<&Arc<Device>>,
+) -> Result {
+    // Only the nodes with a compatible are devices.
+    if bus.compatible().is_none() || !bus.is_available() {
+        return Ok(());
+    }
+    // Populated already
+    if of_find_device_by_node(bus).is_some() {
+        return Ok(());
+    }
+
+    let dev = of_platform_device_create(bus, parent)?;
+    if of_match_node(matches, bus).is_none() {
+        return Ok(());
+    }
+    for child in bus.children() {
+        of_platform_bus_create(child, matches, Some(&dev))?;
+    }
+    Ok(())
+}
+
+/// Create the platform devices of the children of `root`, and of their
+/// children if they are buses matching `matches`.
+///
+/// Refer to linux: of_platform_populate
+pub fn of_platform_populate(
+    root: FdtNode<'static, 'static>,
+    matches: &'static [OfDeviceId],
+    parent: Option<&Arc<Device>>,
+) -> Result {
+    for child in root.children() {
+        of_platform_bus_create(child, matches, parent)?;
+    }
+    Ok(())
+}
+
+/// Populate the platform devices of the whole device tree.
+///
+/// Refer to linux: of_platform_default_populate_init
+#[cfg(not(test))]
+#[crate::macros::initcall(arch)]
+fn of_platform_default_populate_init() -> Result {
+    let fdt = super::GLOBAL_FDT.get().ok_or(crate::error::Error::Enodev)?;
+    let root = fdt.find_node("/").ok_or(crate::error::Error::Enodev)?;
+    of_platform_populate(root, &OF_DEFAULT_BUS_MATCH_TABLE, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drivers::base::driver::Driver;
+    use crate::drivers::base::platform::{
+        platform_driver_register, platform_get_irq, platform_get_resource, ResourceType,
+    };
+    use crate::fdtree_rs::LinuxFdt;
+    use crate::schedule::task::set_test_current;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use std::boxed::Box;
+
+    static DTB: &[u8] = include_bytes!("../../../third_lib/fdtree-rs/dtb/test.dtb");
+
+    struct TestSerial {
+        data: AtomicUsize,
+    }
+
+    static TEST_SERIAL_MATCH: [OfDeviceId; 2] = [
+        OfDeviceId::with_data("ns16550", 1),
+        OfDeviceId::with_data("ns16550a", 2),
+    ];
+
+    impl Driver for TestSerial {
+        fn name(&self) -> &'static str {
+            "test-serial"
+        }
+
+        fn of_match_table(&self) -> &'static [OfDeviceId] {
+            &TEST_SERIAL_MATCH
+        }
+
+        fn probe(&self, dev: &Arc<Device>) -> Result {
+            let data = of_device_get_match_data(dev).ok_or(Error::Einval)?;
+            self.data.store(data, Ordering::Relaxed);
+            Ok(())
+        }
+    }
+
+    static TEST_SERIAL: TestSerial = TestSerial {
+        data: AtomicUsize::new(0),
+    };
+
+    #[test]
+    fn test_of_platform_populate() {
+        let _guard = crate::drivers::base::TEST_LOCK.lock();
+        set_test_current();
+        let fdt: &'static LinuxFdt<'static> = Box::leak(Box::new(LinuxFdt::new(DTB).unwrap()));
+        let root = fdt.find_node("/").unwrap();
+        assert_eq!(platform_driver_register(&TEST_SERIAL), Ok(()));
+        assert_eq!(
+            of_platform_populate(root, &OF_DEFAULT_BUS_MATCH_TABLE, None),
+            Ok(())
+        );
+
+        // The simple-bus and its children, not the nodes without compatible.
+        let soc = of_find_device_by_node(fdt.find_node("/soc").unwrap()).unwrap();
+        assert!(soc.parent().is_none());
+        assert_eq!(soc.nr_children(), 17);
+        assert!(of_find_device_by_node(fdt.find_node("/cpus/cpu@0").unwrap()).is_none());
+        assert!(
+            of_find_device_by_node(fdt.find_node("/reserved-memory/linux,cma").unwrap()).is_none()
+        );
+
+        let uart = of_find_device_by_node(fdt.find_node("/soc/uart@10000000").unwrap()).unwrap();
+        assert_eq!(uart.name(), "uart@10000000");
+        assert!(Arc::ptr_eq(uart.parent().unwrap(), &soc));
+        assert_eq!(
+            platform_get_resource(&uart, ResourceType::Mem, 0),
+            Some(Resource::mem(0x1000_0000, 0x100))
+        );
+        // The PLIC has no driver, its interrupts are left out and wait for it.
+        assert_eq!(platform_get_resource(&uart, ResourceType::Irq, 0), None);
+        assert_eq!(platform_get_irq(&uart, 0), Err(Error::Eprobedefer));
+        assert_eq!(platform_get_irq(&uart, 1), Err(Error::Enxio));
+        assert!(uart.driver().is_some());
+        assert_eq!(TEST_SERIAL.data.load(Ordering::Relaxed), 2);
+
+        let flash = of_find_device_by_node(fdt.find_node("/soc/flash@20000000").unwrap()).unwrap();
+        assert_eq!(
+            platform_get_resource(&flash, ResourceType::Mem, 1),
+            Some(Resource::mem(0x2200_0000, 0x200_0000))
+        );
+        assert!(flash.driver().is_none());
+
+        // Populating again adds nothing.
+        assert_eq!(
+            of_platform_populate(root, &OF_DEFAULT_BUS_MATCH_TABLE, None),
+            Ok(())
+        );
+        assert_eq!(soc.nr_children(), 17);
+    }
+}
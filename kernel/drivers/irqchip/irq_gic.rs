@@ -0,0 +1,407 @@
+//! ARM Generic Interrupt Controller v2
+//!
+//! Refer to linux: drivers/irqchip/irq-gic.c
+//!
+//! The official documentation: <https://developer.arm.com/documentation/ihi0048/latest>
+//!
+//! Interrupt numbers are GIC interrupt ids: SGIs are 0-15, the per cpu
+//! PPIs 16-31 and the shared SPIs from 32. Every interrupt is masked until
+//! it is requested, SPIs are routed to the boot cpu. SPIs start level
+//! triggered, [`crate::irq::irq_set_irq_type`] makes them edge triggered.
+//!
+//! The device tree specifiers are `<type number flags>`, the type is 0 for
+//! an SPI and 1 for a PPI, the flags are the trigger type.
+
+use core::ptr::NonNull;
+
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    register_structs,
+    registers::{ReadOnly, ReadWrite, WriteOnly},
+};
+
+use crate::drivers::fdt::{of_match_node, FdtNode, OfDeviceId, GLOBAL_FDT};
+use crate::error::{Error, Result};
+use crate::fdtree_rs::PhandleArgs;
+use crate::irq::{
+    generic_handle_irq, irq_set_chip, IrqChip, IRQ_TYPE_EDGE_FALLING, IRQ_TYPE_EDGE_RISING,
+    IRQ_TYPE_LEVEL_HIGH, IRQ_TYPE_LEVEL_LOW, IRQ_TYPE_SENSE_MASK,
+};
+use crate::mm::{ioremap, PhysAddr};
+use crate::types::OnceCell;
+
+register_structs! {
+    /// Distributor registers.
+    GicDistRegs {
+        /// Distributor Control Register.
+        (0x0000 => ctlr: ReadWrite<u32>),
+        /// Interrupt Controller Type Register.
+        (0x0004 => typer: ReadOnly<u32>),
+        (0x0008 => _reserved0),
+        /// Interrupt Set-Enable Registers.
+        (0x0100 => isenabler: [ReadWrite<u32>; 32]),
+        /// Interrupt Clear-Enable Registers.
+        (0x0180 => icenabler: [ReadWrite<u32>; 32]),
+        (0x0200 => _reserved1),
+        /// Interrupt Clear-Active Registers.
+        (0x0380 => icactiver: [ReadWrite<u32>; 32]),
+        /// Interrupt Priority Registers, a byte per interrupt.
+        (0x0400 => ipriorityr: [ReadWrite<u8>; 1020]),
+        (0x07fc => _reserved2),
+        /// Interrupt Processor Targets Registers, a byte per interrupt.
+        (0x0800 => itargetsr: [ReadWrite<u8>; 1020]),
+        (0x0bfc => _reserved3),
+        /// Interrupt Configuration Registers, two bits per interrupt.
+        (0x0c00 => icfgr: [ReadWrite<u32>; 64]),
+        (0x0d00 => _reserved4),
+        (0x1000 => @END),
+    }
+}
+
+register_structs! {
+    /// CPU interface registers.
+    GicCpuRegs {
+        /// CPU Interface Control Register.
+        (0x0000 => ctlr: ReadWrite<u32>),
+        /// Interrupt Priority Mask Register.
+        (0x0004 => pmr: ReadWrite<u32>),
+        /// Binary Point Register.
+        (0x0008 => bpr: ReadWrite<u32>),
+        /// Interrupt Acknowledge Register.
+        (0x000c => iar: ReadOnly<u32>),
+        /// End of Interrupt Register.
+        (0x0010 => eoir: WriteOnly<u32>),
+        (0x0014 => _reserved0),
+        (0x1000 => @END),
+    }
+}
+
+const GICD_ENABLE: u32 = 1;
+const GICC_ENABLE: u32 = 1;
+/// Typer: number of 32 interrupt lines, minus one.
+const GICD_TYPER_LINES: u32 = 0x1f;
+/// Default priority, in the middle of the range.
+const GICD_INT_DEF_PRI: u8 = 0xa0;
+/// Let every priority above the lowest ones through.
+const GICC_INT_PRI_THRESHOLD: u32 = 0xf0;
+const GICC_IAR_INT_ID_MASK: u32 = 0x3ff;
+/// Icfgr: edge triggered, the high bit of the two of each interrupt.
+const GICD_INT_EDGE: u32 = 0x2;
+/// Interrupt ids from 1020 are special, 1023 is spurious.
+const GIC_MAX_IRQS: u32 = 1020;
+/// First PPI
+const GIC_PPI_BASE: u32 = 16;
+/// First SPI
+const GIC_SPI_BASE: u32 = 32;
+/// Device tree specifier types
+const GIC_SPI: u32 = 0;
+const GIC_PPI: u32 = 1;
+
+/// A GICv2
+pub struct Gic {
+    dist: NonNull<GicDistRegs>,
+    cpu: NonNull<GicCpuRegs>,
+    nr_irqs: u32,
+    node: Option<FdtNode<'static, 'static>>,
+}
+
+// SAFETY: the registers are only accessed with single reads and writes, the
+// enable registers are write one to set or clear.
+unsafe impl Send for Gic {}
+// SAFETY: see above.
+unsafe impl Sync for Gic {}
+
+impl Gic {
+    /// Construct a GIC from its mapped registers.
+    ///
+    /// # Safety
+    ///
+    /// `dist` and `cpu` must map the distributor and the cpu interface.
+    pub unsafe fn new(dist: NonNull<u8>, cpu: NonNull<u8>) -> Self {
+        let mut gic = Self {
+            dist: dist.cast(),
+            cpu: cpu.cast(),
+            nr_irqs: 0,
+            node: None,
+        };
+        let lines = (gic.dist().typer.get() & GICD_TYPER_LINES) + 1;
+        gic.nr_irqs = (lines * 32).min(GIC_MAX_IRQS);
+        gic
+    }
+
+    fn dist(&self) -> &GicDistRegs {
+        // SAFETY: guaranteed by `new`.
+        unsafe { self.dist.as_ref() }
+    }
+
+    fn cpu(&self) -> &GicCpuRegs {
+        // SAFETY: guaranteed by `new`.
+        unsafe { self.cpu.as_ref() }
+    }
+
+    // Targets mask of the current cpu, the banked targets of the SGIs read
+    // as it. Uniprocessor implementations read zero.
+    fn cpumask(&self) -> u8 {
+        let dist = self.dist();
+        (0..GIC_PPI_BASE as usize)
+            .map(|i| dist.itargetsr[i].get())
+            .find(|&mask| mask != 0)
+            .unwrap_or(1)
+    }
+
+    /// Mask all SPIs, route them to the current cpu and enable the
+    /// distributor.
+    ///
+    /// Refer to linux: gic_dist_init
+    pub fn dist_init(&self) {
+        let dist = self.dist();
+        dist.ctlr.set(0);
+
+        let cpumask = self.cpumask();
+        for i in (GIC_SPI_BASE..self.nr_irqs).map(|i| i as usize) {
+            dist.itargetsr[i].set(cpumask);
+            dist.ipriorityr[i].set(GICD_INT_DEF_PRI);
+        }
+        // Level triggered.
+        for i in (GIC_SPI_BASE / 16..self.nr_irqs / 16).map(|i| i as usize) {
+            dist.icfgr[i].set(0);
+        }
+        for i in (GIC_SPI_BASE / 32..self.nr_irqs / 32).map(|i| i as usize) {
+            dist.icactiver[i].set(u32::MAX);
+            dist.icenabler[i].set(u32::MAX);
+        }
+
+        dist.ctlr.set(GICD_ENABLE);
+    }
+
+    /// Mask the PPIs of the current cpu, enable its SGIs and its cpu
+    /// interface.
+    ///
+    /// Refer to linux: gic_cpu_init
+    pub fn cpu_init(&self) {
+        let dist = self.dist();
+        dist.icactiver[0].set(0xffff_0000);
+        dist.icenabler[0].set(0xffff_0000);
+        dist.isenabler[0].set(0x0000_ffff);
+        for i in 0..GIC_SPI_BASE as usize {
+            dist.ipriorityr[i].set(GICD_INT_DEF_PRI);
+        }
+
+        let cpu = self.cpu();
+        cpu.pmr.set(GICC_INT_PRI_THRESHOLD);
+        cpu.ctlr.set(GICC_ENABLE);
+    }
+
+    fn enable_bit(&self, irq: u32) -> Option<(usize, u32)> {
+        (irq < self.nr_irqs).then_some(((irq / 32) as usize, 1 << (irq % 32)))
+    }
+}
+
+impl IrqChip for Gic {
+    fn name(&self) -> &'static str {
+        "GICv2"
+    }
+
+    fn irq_unmask(&self, irq: u32) {
+        if let Some((reg, bit)) = self.enable_bit(irq) {
+            self.dist().isenabler[reg].set(bit);
+        }
+    }
+
+    fn irq_mask(&self, irq: u32) {
+        if let Some((reg, bit)) = self.enable_bit(irq) {
+            self.dist().icenabler[reg].set(bit);
+        }
+    }
+
+    fn of_node(&self) -> Option<FdtNode<'static, 'static>> {
+        self.node
+    }
+
+    /// Refer to linux: gic_irq_domain_translate
+    fn irq_xlate(&self, spec: PhandleArgs<'_, '_>) -> Result<(u32, u32)> {
+        let mut args = spec.args();
+        let (Some(kind), Some(number), Some(flags)) = (args.next(), args.next(), args.next())
+        else {
+            return Err(Error::Einval);
+        };
+        let irq = match kind {
+            GIC_SPI => number.checked_add(GIC_SPI_BASE),
+            GIC_PPI => Some(number).filter(|&n| n < 16).map(|n| n + GIC_PPI_BASE),
+            _ => None,
+        };
+        let irq = irq.filter(|&irq| irq < self.nr_irqs).ok_or(Error::Einval)?;
+        Ok((irq, flags & IRQ_TYPE_SENSE_MASK))
+    }
+
+    /// Refer to linux: gic_set_type, gic_configure_irq
+    fn irq_set_type(&self, irq: u32, flow: u32) -> Result {
+        // SGIs are always edge triggered.
+        if irq < GIC_PPI_BASE || irq >= self.nr_irqs {
+            return Err(Error::Einval);
+        }
+        let edge = match flow {
+            IRQ_TYPE_LEVEL_HIGH => false,
+            IRQ_TYPE_EDGE_RISING => true,
+            // The GIC does not invert: the PPIs of the cpu, such as the
+            // timer, are declared active low and taken as they are.
+            IRQ_TYPE_LEVEL_LOW if irq < GIC_SPI_BASE => false,
+            IRQ_TYPE_EDGE_FALLING if irq < GIC_SPI_BASE => true,
+            _ => return Err(Error::Einval),
+        };
+        let icfgr = &self.dist().icfgr[(irq / 16) as usize];
+        let mask = GICD_INT_EDGE << ((irq % 16) * 2);
+        let val = icfgr.get() & !mask;
+        icfgr.set(if edge { val | mask } else { val });
+        Ok(())
+    }
+
+    /// Refer to linux: gic_handle_irq
+    fn handle_irq(&self) {
+        let cpu = self.cpu();
+        loop {
+            let iar = cpu.iar.get();
+            let irq = iar & GICC_IAR_INT_ID_MASK;
+            if irq >= GIC_MAX_IRQS {
+                break;
+            }
+            // Only one cpu runs, nobody sends SGIs.
+            if irq >= GIC_PPI_BASE {
+                // Interrupts without a handler are masked, one the
+                // handler did not recognize is ended all the same.
+                let _ = generic_handle_irq(irq);
+            }
+            cpu.eoir.set(iar);
+        }
+    }
+}
+
+static GIC: OnceCell<Gic> = OnceCell::new();
+
+static GIC_IDS: [OfDeviceId; 3] = [
+    OfDeviceId::new("arm,gic-400"),
+    OfDeviceId::new("arm,cortex-a15-gic"),
+    OfDeviceId::new("arm,cortex-a9-gic"),
+];
+
+// Map `size` bytes of the `index`th register range of `node`, the ranges
+// of the device tree may be larger than the registers used.
+fn gic_map(node: FdtNode<'_, '_>, index: usize, size: usize) -> Result<NonNull<u8>> {
+    let reg = node
+        .reg()
+        .and_then(|mut r| r.nth(index))
+        .ok_or(Error::Einval)?;
+    let addr = node
+        .translate_address(reg.starting_address as u64)
+        .ok_or(Error::Einval)?;
+    let base = ioremap(PhysAddr::from(addr as usize), size).ok_or(Error::Enomem)?;
+    NonNull::new(base.as_usize() as *mut u8).ok_or(Error::Enomem)
+}
+
+/// Probe the GIC of the device tree, initialize it for the boot cpu and
+/// take the interrupts with it.
+///
+/// Refer to linux: gic_of_init
+pub fn gic_of_init() -> Result {
+    let fdt = GLOBAL_FDT.get().ok_or(Error::Enodev)?;
+    let node = fdt
+        .all_nodes()
+        .find(|node| node.is_available() && of_match_node(&GIC_IDS, *node).is_some())
+        .ok_or(Error::Enodev)?;
+    let dist = gic_map(node, 0, size_of::<GicDistRegs>())?;
+    let cpu = gic_map(node, 1, size_of::<GicCpuRegs>())?;
+    // SAFETY: both are mapped just above.
+    let mut gic = unsafe { Gic::new(dist, cpu) };
+    gic.node = Some(node);
+    GIC.set(gic);
+    let gic = &*GIC;
+    gic.dist_init();
+    gic.cpu_init();
+    irq_set_chip(gic)?;
+    crate::pr_info!("GIC: {} irqs, {}\n", gic.nr_irqs, node.name);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::irq::{free_irq, request_irq, IrqReturn};
+    use crate::schedule::task::set_test_current;
+    use core::sync::atomic::{AtomicU32, Ordering};
+    use std::boxed::Box;
+
+    // Registers in memory, the enable registers do not set and clear.
+    #[repr(C, align(4096))]
+    struct FakeRegs([u32; 1024]);
+
+    const IAR: usize = 3;
+    const EOIR: usize = 4;
+
+    static GIC_ACKED: AtomicU32 = AtomicU32::new(0);
+
+    fn gic_test_handler(irq: u32, data: usize) -> IrqReturn {
+        // SAFETY: data is the fake IAR of the test, which reads as spurious
+        // from now on.
+        unsafe { (data as *mut u32).write_volatile(1023) };
+        GIC_ACKED.store(irq, Ordering::Relaxed);
+        IrqReturn::Handled
+    }
+
+    #[test]
+    fn test_gic() {
+        set_test_current();
+        let dist = Box::into_raw(Box::new(FakeRegs([0; 1024]))).cast::<u32>();
+        let cpu = Box::into_raw(Box::new(FakeRegs([0; 1024]))).cast::<u32>();
+        // SAFETY: the fake registers are leaked, and only accessed with
+        // volatile reads and writes.
+        let gic = unsafe {
+            // 64 lines
+            dist.add(1).write_volatile(1);
+            Gic::new(
+                NonNull::new(dist).unwrap().cast(),
+                NonNull::new(cpu).unwrap().cast(),
+            )
+        };
+        assert_eq!(gic.nr_irqs, 64);
+
+        gic.dist_init();
+        gic.cpu_init();
+        let regs = gic.dist();
+        assert_eq!(regs.ctlr.get(), GICD_ENABLE);
+        assert_eq!(regs.itargetsr[40].get(), 1);
+        assert_eq!(regs.ipriorityr[63].get(), GICD_INT_DEF_PRI);
+        assert_eq!(regs.icenabler[1].get(), u32::MAX);
+        assert_eq!(regs.icenabler[0].get(), 0xffff_0000);
+        assert_eq!(gic.cpu().pmr.get(), GICC_INT_PRI_THRESHOLD);
+        assert_eq!(gic.cpu().ctlr.get(), GICC_ENABLE);
+
+        gic.irq_unmask(27);
+        assert_eq!(regs.isenabler[0].get(), 1 << 27);
+        gic.irq_mask(33);
+        assert_eq!(regs.icenabler[1].get(), 1 << 1);
+        gic.irq_unmask(64);
+        assert_eq!(regs.isenabler[2].get(), 0);
+
+        // Two configuration bits per interrupt, the high one for edge.
+        assert_eq!(gic.irq_set_type(40, IRQ_TYPE_EDGE_RISING), Ok(()));
+        assert_eq!(regs.icfgr[2].get(), 0x2 << 16);
+        assert_eq!(gic.irq_set_type(40, IRQ_TYPE_LEVEL_HIGH), Ok(()));
+        assert_eq!(regs.icfgr[2].get(), 0);
+        assert_eq!(gic.irq_set_type(40, IRQ_TYPE_LEVEL_LOW), Err(Error::Einval));
+        assert_eq!(gic.irq_set_type(30, IRQ_TYPE_LEVEL_LOW), Ok(()));
+        assert_eq!(
+            gic.irq_set_type(3, IRQ_TYPE_EDGE_RISING),
+            Err(Error::Einval)
+        );
+
+        // Acknowledge 58, which is handled and ended.
+        // SAFETY: see above.
+        let iar = unsafe { cpu.add(IAR) };
+        request_irq(58, gic_test_handler, "gic-test", iar as usize).unwrap();
+        unsafe { iar.write_volatile(58) };
+        gic.handle_irq();
+        assert_eq!(GIC_ACKED.load(Ordering::Relaxed), 58);
+        assert_eq!(unsafe { cpu.add(EOIR).read_volatile() }, 58);
+        free_irq(58);
+    }
+}
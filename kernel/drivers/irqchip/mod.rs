@@ -0,0 +1,14 @@
+//! Interrupt controller drivers
+//!
+//! Refer to linux: drivers/irqchip/
+
+pub mod irq_gic;
+
+/// Probe the interrupt controller of the device tree and register it.
+///
+/// Refer to linux: irqchip_init
+pub fn irqchip_init() {
+    if let Err(e) = irq_gic::gic_of_init() {
+        crate::pr_err!("irqchip: no interrupt controller: {:?}\n", e);
+    }
+}
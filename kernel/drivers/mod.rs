@@ -1,4 +1,8 @@
 //! Rynux drivers
 
+pub mod base;
+pub mod clocksource;
 pub mod fdt;
+pub mod irqchip;
+pub mod rtc;
 pub mod tty;
@@ -0,0 +1,16 @@
+//! Real time clock drivers
+//!
+//! Refer to linux: drivers/rtc/
+
+pub mod rtc_pl031;
+
+use crate::error::{Error, Result};
+use crate::time::{do_settimeofday64, Timespec64};
+
+/// Set the wall clock from the RTC.
+///
+/// Refer to linux: drivers/rtc/class.c `rtc_hctosys`
+pub fn rtc_hctosys() -> Result {
+    let rtc = rtc_pl031::Pl031::probe().ok_or(Error::Enodev)?;
+    do_settimeofday64(&Timespec64::new(rtc.read_time(), 0))
+}
@@ -0,0 +1,96 @@
+//! ARM AMBA PrimeCell PL031 RTC
+//!
+//! Refer to linux: drivers/rtc/rtc-pl031.c
+//!
+//! The official documentation: <https://developer.arm.com/documentation/ddi0224/latest>
+
+use core::ptr::NonNull;
+
+use tock_registers::{
+    interfaces::{Readable, Writeable},
+    register_structs,
+    registers::{ReadOnly, ReadWrite},
+};
+
+use crate::drivers::fdt::GLOBAL_FDT;
+use crate::mm::{ioremap, PhysAddr};
+
+register_structs! {
+    /// Pl031 registers.
+    Pl031Regs {
+        /// Data Register, seconds since the epoch.
+        (0x0000 => dr: ReadOnly<u32>),
+        /// Match Register.
+        (0x0004 => mr: ReadWrite<u32>),
+        /// Load Register.
+        (0x0008 => lr: ReadWrite<u32>),
+        /// Control Register.
+        (0x000c => cr: ReadWrite<u32>),
+        (0x0010 => @END),
+    }
+}
+
+const RTC_CR_EN: u32 = 1 << 0;
+
+/// Device tree compatible
+pub const PL031_COMPATIBLE: &str = "arm,pl031";
+
+/// The Pl031 RTC
+pub struct Pl031 {
+    base: NonNull<Pl031Regs>,
+}
+
+// SAFETY: the registers are only accessed with single reads and writes.
+unsafe impl Send for Pl031 {}
+// SAFETY: see above.
+unsafe impl Sync for Pl031 {}
+
+impl Pl031 {
+    /// Construct a Pl031 from its mapped registers.
+    ///
+    /// # Safety
+    ///
+    /// `base` must map the Pl031 registers.
+    pub const unsafe fn new(base: NonNull<u8>) -> Self {
+        Self { base: base.cast() }
+    }
+
+    /// Find the first available Pl031 in the device tree, map and enable it.
+    pub fn probe() -> Option<Self> {
+        let node = GLOBAL_FDT.all_nodes().find(|node| {
+            node.is_available()
+                && node
+                    .compatible()
+                    .is_some_and(|c| c.all().any(|c| c == PL031_COMPATIBLE))
+        })?;
+        let reg = node.reg()?.next()?;
+        let addr = node.translate_address(reg.starting_address as u64)?;
+        let base = ioremap(PhysAddr::from(addr as usize), reg.size)?;
+        // SAFETY: the registers are mapped just above.
+        let rtc = unsafe { Self::new(NonNull::new(base.as_usize() as *mut u8)?) };
+        rtc.enable();
+        Some(rtc)
+    }
+
+    fn regs(&self) -> &Pl031Regs {
+        // SAFETY: guaranteed by `new`.
+        unsafe { self.base.as_ref() }
+    }
+
+    // Start counting, the RTC keeps counting once enabled.
+    fn enable(&self) {
+        if self.regs().cr.get() & RTC_CR_EN == 0 {
+            self.regs().cr.set(RTC_CR_EN);
+        }
+    }
+
+    /// Seconds since the epoch.
+    pub fn read_time(&self) -> i64 {
+        self.regs().dr.get() as i64
+    }
+
+    /// Set the seconds since the epoch, the counter is 32 bits.
+    pub fn set_time(&self, secs: i64) {
+        self.regs().lr.set(secs as u32);
+    }
+}
@@ -1,3 +1,6 @@
 //! tty driver
 
+pub mod n_tty;
 pub mod serial;
+pub mod termios;
+pub mod tty_io;
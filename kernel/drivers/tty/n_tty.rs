@@ -0,0 +1,699 @@
+//! N_TTY line discipline
+//!
+//! Refer to linux: drivers/tty/n_tty.c
+//!
+//! Received characters go through [`NTty::receive_buf`]: the input flags
+//! translate them, the signal characters call the tty signal hook, and in
+//! canonical mode the editing characters work on the line being typed. A
+//! line can be read once it ends with NL, EOL, EOL2 or EOF. Echo goes
+//! straight to the port, with the output processing of `c_oflag`.
+//!
+//! TODO: tabs are erased as a single column, the output column is not
+//! tracked.
+
+use super::termios::*;
+use super::tty_io::{Tty, SIGINT, SIGQUIT, SIGTSTP};
+use crate::error::Result;
+use crate::schedule::WaitQueue;
+use crate::sync::lock::RawSpinLockNoIrq;
+use crate::time::jiffies::msecs_to_jiffies;
+
+/// Size of the read buffer
+pub const N_TTY_BUF_SIZE: usize = 4096;
+const N_TTY_BUF_MASK: usize = N_TTY_BUF_SIZE - 1;
+
+// The indexes run freely and are masked on access, as in a CircBuf.
+struct NTtyData {
+    buf: [u8; N_TTY_BUF_SIZE],
+    // Marks the characters ending a line.
+    eol: [u64; N_TTY_BUF_SIZE / 64],
+    read_head: usize,
+    // End of the last complete line
+    canon_head: usize,
+    read_tail: usize,
+    // The next character is taken literally.
+    lnext: bool,
+}
+
+impl NTtyData {
+    fn count(&self) -> usize {
+        self.read_head.wrapping_sub(self.read_tail)
+    }
+
+    fn at(&self, i: usize) -> u8 {
+        self.buf[i & N_TTY_BUF_MASK]
+    }
+
+    fn is_eol(&self, i: usize) -> bool {
+        let i = i & N_TTY_BUF_MASK;
+        self.eol[i / 64] & (1 << (i % 64)) != 0
+    }
+
+    fn set_eol(&mut self, i: usize, eol: bool) {
+        let i = i & N_TTY_BUF_MASK;
+        if eol {
+            self.eol[i / 64] |= 1 << (i % 64);
+        } else {
+            self.eol[i / 64] &= !(1 << (i % 64));
+        }
+    }
+
+    fn put(&mut self, c: u8) {
+        self.set_eol(self.read_head, false);
+        self.buf[self.read_head & N_TTY_BUF_MASK] = c;
+        self.read_head = self.read_head.wrapping_add(1);
+    }
+
+    fn flush(&mut self) {
+        self.read_head = 0;
+        self.canon_head = 0;
+        self.read_tail = 0;
+        self.eol = [0; N_TTY_BUF_SIZE / 64];
+        self.lnext = false;
+    }
+}
+
+/// Whether `c` echoes as `^X` with `ECHOCTL`.
+fn is_ctl(c: u8) -> bool {
+    (c < b' ' && c != b'\t' && c != b'\n') || c == 0x7f
+}
+
+/// Output processing of `c`, return the length written to `out`.
+///
+/// Refer to linux: do_output_char
+pub fn process_output(termios: &Termios, c: u8, out: &mut [u8; 2]) -> usize {
+    let oflag = termios.c_oflag;
+    if oflag & OPOST == 0 {
+        out[0] = c;
+        return 1;
+    }
+    match c {
+        b'\n' if oflag & ONLCR != 0 => {
+            *out = [b'\r', b'\n'];
+            2
+        }
+        b'\r' if oflag & OCRNL != 0 => {
+            out[0] = b'\n';
+            1
+        }
+        _ if oflag & OLCUC != 0 => {
+            out[0] = c.to_ascii_uppercase();
+            1
+        }
+        _ => {
+            out[0] = c;
+            1
+        }
+    }
+}
+
+// Echo on the port, the echo is dropped if the port is full.
+struct Echo<'a> {
+    tty: &'a Tty,
+    termios: &'a Termios,
+}
+
+impl Echo<'_> {
+    fn raw(&self, s: &[u8]) {
+        self.tty.port_write(s);
+    }
+
+    fn output(&self, c: u8) {
+        let mut out = [0; 2];
+        let len = process_output(self.termios, c, &mut out);
+        self.raw(&out[..len]);
+    }
+
+    // Refer to linux: echo_char
+    fn char(&self, c: u8) {
+        if self.termios.c_lflag & ECHOCTL != 0 && is_ctl(c) {
+            self.raw(&[b'^', c ^ 0x40]);
+        } else {
+            self.output(c);
+        }
+    }
+
+    // Erase `c` on screen.
+    fn erase(&self, c: u8) {
+        if self.termios.c_lflag & ECHOCTL != 0 && is_ctl(c) {
+            self.raw(b"\x08 \x08\x08 \x08");
+        } else {
+            self.raw(b"\x08 \x08");
+        }
+    }
+}
+
+/// The N_TTY line discipline of a tty
+pub struct NTty {
+    data: RawSpinLockNoIrq<NTtyData>,
+    read_wait: WaitQueue,
+}
+
+impl NTty {
+    /// Create an empty line discipline
+    pub const fn new() -> Self {
+        Self {
+            data: RawSpinLockNoIrq::new(
+                NTtyData {
+                    buf: [0; N_TTY_BUF_SIZE],
+                    eol: [0; N_TTY_BUF_SIZE / 64],
+                    read_head: 0,
+                    canon_head: 0,
+                    read_tail: 0,
+                    lnext: false,
+                },
+                Some("n_tty"),
+            ),
+            read_wait: WaitQueue::new(),
+        }
+    }
+
+    /// Characters a read takes now.
+    pub fn chars_readable(&self, termios: &Termios) -> usize {
+        let data = self.data.lock();
+        if termios.c_lflag & ICANON != 0 {
+            data.canon_head.wrapping_sub(data.read_tail)
+        } else {
+            data.count()
+        }
+    }
+
+    /// Drop the input not read yet.
+    pub fn flush_buffer(&self) {
+        self.data.lock().flush();
+    }
+
+    /// Wake up the readers, as when the tty is closed.
+    pub fn wakeup_readers(&self) {
+        self.read_wait.notify_all();
+    }
+
+    /// The settings changed from `old`.
+    ///
+    /// Refer to linux: n_tty_set_termios
+    pub fn set_termios(&self, old: &Termios, new: &Termios) {
+        if (old.c_lflag ^ new.c_lflag) & ICANON == 0 {
+            return;
+        }
+        let mut data = self.data.lock();
+        data.eol = [0; N_TTY_BUF_SIZE / 64];
+        data.canon_head = data.read_head;
+        data.lnext = false;
+        // What was typed is one line for canonical reads.
+        if new.c_lflag & ICANON != 0 && data.count() > 0 {
+            let last = data.read_head.wrapping_sub(1);
+            data.set_eol(last, true);
+        }
+        drop(data);
+        self.read_wait.notify_all();
+    }
+
+    /// Take the characters received by the port.
+    ///
+    /// Refer to linux: n_tty_receive_buf
+    pub fn receive_buf(&self, tty: &Tty, buf: &[u8]) {
+        let termios = tty.termios();
+        let echo = Echo {
+            tty,
+            termios: &termios,
+        };
+        let mut data = self.data.lock();
+        for &c in buf {
+            if let Some(sig) = self.receive_char(&mut data, &echo, c) {
+                // The hook may take the tty locks.
+                drop(data);
+                tty.signal(sig);
+                data = self.data.lock();
+            }
+        }
+        let readable = if termios.c_lflag & ICANON != 0 {
+            data.canon_head != data.read_tail
+        } else {
+            data.count() > 0
+        };
+        drop(data);
+        if readable {
+            self.read_wait.notify_all();
+        }
+    }
+
+    // Return the signal to send, if any.
+    fn receive_char(&self, data: &mut NTtyData, echo: &Echo<'_>, mut c: u8) -> Option<u32> {
+        let termios = echo.termios;
+        let (iflag, lflag) = (termios.c_iflag, termios.c_lflag);
+        if iflag & ISTRIP != 0 {
+            c &= 0x7f;
+        }
+        if iflag & IUCLC != 0 && lflag & IEXTEN != 0 {
+            c = c.to_ascii_lowercase();
+        }
+
+        if data.lnext {
+            data.lnext = false;
+            // Over the ^ of the LNEXT echo.
+            if lflag & ECHO != 0 {
+                echo.char(c);
+            }
+            self.store_char(data, echo, c, false);
+            return None;
+        }
+
+        if iflag & IXON != 0 {
+            if termios.is_cc(VSTOP, c) {
+                echo.tty.stop();
+                return None;
+            }
+            if termios.is_cc(VSTART, c) {
+                echo.tty.start();
+                return None;
+            }
+        }
+        if iflag & IXON != 0 && iflag & IXANY != 0 {
+            echo.tty.start();
+        }
+
+        if lflag & ISIG != 0 {
+            let sig = if termios.is_cc(VINTR, c) {
+                Some(SIGINT)
+            } else if termios.is_cc(VQUIT, c) {
+                Some(SIGQUIT)
+            } else if termios.is_cc(VSUSP, c) {
+                Some(SIGTSTP)
+            } else {
+                None
+            };
+            if let Some(sig) = sig {
+                // Refer to linux: n_tty_receive_signal_char
+                if lflag & NOFLSH == 0 {
+                    data.flush();
+                    echo.tty.port_flush_buffer();
+                }
+                echo.tty.start();
+                if lflag & ECHO != 0 {
+                    echo.char(c);
+                }
+                return Some(sig);
+            }
+        }
+
+        if c == b'\r' {
+            if iflag & IGNCR != 0 {
+                return None;
+            }
+            if iflag & ICRNL != 0 {
+                c = b'\n';
+            }
+        } else if c == b'\n' && iflag & INLCR != 0 {
+            c = b'\r';
+        }
+
+        if lflag & ICANON != 0 {
+            if termios.is_cc(VERASE, c)
+                || termios.is_cc(VKILL, c)
+                || (termios.is_cc(VWERASE, c) && lflag & IEXTEN != 0)
+            {
+                self.eraser(data, echo, c);
+                return None;
+            }
+            if termios.is_cc(VLNEXT, c) && lflag & IEXTEN != 0 {
+                data.lnext = true;
+                if lflag & ECHO != 0 && lflag & ECHOCTL != 0 {
+                    echo.raw(b"^\x08");
+                }
+                return None;
+            }
+            if termios.is_cc(VREPRINT, c) && lflag & ECHO != 0 && lflag & IEXTEN != 0 {
+                echo.char(c);
+                echo.output(b'\n');
+                let mut i = data.canon_head;
+                while i != data.read_head {
+                    echo.char(data.at(i));
+                    i = i.wrapping_add(1);
+                }
+                return None;
+            }
+            if c == b'\n' {
+                if lflag & (ECHO | ECHONL) != 0 {
+                    echo.output(b'\n');
+                }
+                self.store_char(data, echo, c, true);
+                return None;
+            }
+            if termios.is_cc(VEOF, c) {
+                // A line end read as nothing.
+                self.store_char(data, echo, DISABLED_CHAR, true);
+                return None;
+            }
+            if termios.is_cc(VEOL, c) || termios.is_cc(VEOL2, c) {
+                if lflag & ECHO != 0 {
+                    echo.char(c);
+                }
+                self.store_char(data, echo, c, true);
+                return None;
+            }
+        }
+
+        if lflag & ECHO != 0 {
+            echo.char(c);
+        }
+        self.store_char(data, echo, c, false);
+        None
+    }
+
+    // Store `c`, a line end makes the line readable. In canonical mode the
+    // last place is kept for a line end.
+    fn store_char(&self, data: &mut NTtyData, echo: &Echo<'_>, c: u8, eol: bool) {
+        let canon = echo.termios.c_lflag & ICANON != 0;
+        let room = N_TTY_BUF_SIZE - data.count();
+        if room == 0 || (canon && !eol && room == 1) {
+            if echo.termios.c_iflag & IMAXBEL != 0 {
+                echo.raw(b"\x07");
+            }
+            return;
+        }
+        let i = data.read_head;
+        data.put(c);
+        if eol {
+            data.set_eol(i, true);
+            data.canon_head = data.read_head;
+        }
+    }
+
+    // ERASE, WERASE and KILL.
+    //
+    // Refer to linux: eraser
+    fn eraser(&self, data: &mut NTtyData, echo: &Echo<'_>, c: u8) {
+        let termios = echo.termios;
+        let lflag = termios.c_lflag;
+        if data.read_head == data.canon_head {
+            return;
+        }
+        let kill = termios.is_cc(VKILL, c);
+        let werase = termios.is_cc(VWERASE, c);
+        if kill {
+            if lflag & ECHO == 0 {
+                data.read_head = data.canon_head;
+                return;
+            }
+            if lflag & (ECHOK | ECHOKE | ECHOE) != ECHOK | ECHOKE | ECHOE {
+                data.read_head = data.canon_head;
+                echo.char(c);
+                if lflag & ECHOK != 0 {
+                    echo.output(b'\n');
+                }
+                return;
+            }
+        }
+
+        let mut seen_alnum = false;
+        while data.read_head != data.canon_head {
+            let prev = data.read_head.wrapping_sub(1);
+            let ch = data.at(prev);
+            if werase {
+                let alnum = ch.is_ascii_alphanumeric() || ch == b'_';
+                if alnum {
+                    seen_alnum = true;
+                } else if seen_alnum {
+                    break;
+                }
+            }
+            data.read_head = prev;
+            if lflag & ECHO != 0 {
+                if lflag & ECHOE != 0 {
+                    echo.erase(ch);
+                } else if !kill && !werase {
+                    echo.char(c);
+                }
+            }
+            if !kill && !werase {
+                break;
+            }
+        }
+    }
+
+    /// Read into `buf`, waiting as `c_lflag` and `c_cc` ask.
+    ///
+    /// In canonical mode one line at most is read, 0 is the end of file.
+    /// Otherwise `VMIN` characters are waited for, `VTIME` tenths of a
+    /// second apart.
+    ///
+    /// Returns `Erestartsys` with nothing read if a signal is pending.
+    ///
+    /// Refer to linux: n_tty_read
+    pub fn read(&self, tty: &Tty, buf: &mut [u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let termios = tty.termios();
+        if termios.c_lflag & ICANON != 0 {
+            self.read_wait.wait_until_interruptible(|| {
+                let data = self.data.lock();
+                data.canon_head != data.read_tail || tty.is_hung_up()
+            })?;
+            return Ok(self.canon_copy(buf));
+        }
+
+        let min = (termios.c_cc[VMIN] as usize).min(buf.len());
+        let time = msecs_to_jiffies(termios.c_cc[VTIME] as u32 * 100);
+        let mut copied = 0;
+        loop {
+            copied += self.copy_from_read_buf(&mut buf[copied..]);
+            if copied >= min.max(1) || copied == buf.len() || tty.is_hung_up() {
+                break;
+            }
+            let readable = || self.data.lock().count() > 0 || tty.is_hung_up();
+            if time != 0 && (copied > 0 || min == 0) {
+                if self.read_wait.wait_until_timeout(readable, time) == 0 {
+                    break;
+                }
+            } else if min == 0 {
+                break;
+            } else if let Err(e) = self.read_wait.wait_until_interruptible(readable) {
+                if copied == 0 {
+                    return Err(e);
+                }
+                break;
+            }
+        }
+        Ok(copied)
+    }
+
+    // Refer to linux: canon_copy_from_read_buf
+    fn canon_copy(&self, buf: &mut [u8]) -> usize {
+        let mut data = self.data.lock();
+        let mut copied = 0;
+        while data.read_tail != data.canon_head && copied < buf.len() {
+            let tail = data.read_tail;
+            let (c, eol) = (data.at(tail), data.is_eol(tail));
+            data.set_eol(tail, false);
+            data.read_tail = tail.wrapping_add(1);
+            if eol && c == DISABLED_CHAR {
+                break;
+            }
+            buf[copied] = c;
+            copied += 1;
+            if eol {
+                break;
+            }
+        }
+        // Do not leave an EOF alone after a full buffer.
+        let tail = data.read_tail;
+        if copied == buf.len()
+            && tail != data.canon_head
+            && data.is_eol(tail)
+            && data.at(tail) == DISABLED_CHAR
+        {
+            data.set_eol(tail, false);
+            data.read_tail = tail.wrapping_add(1);
+        }
+        copied
+    }
+
+    fn copy_from_read_buf(&self, buf: &mut [u8]) -> usize {
+        let mut data = self.data.lock();
+        let n = data.count().min(buf.len());
+        for b in buf[..n].iter_mut() {
+            *b = data.at(data.read_tail);
+            data.read_tail = data.read_tail.wrapping_add(1);
+        }
+        n
+    }
+}
+
+impl Default for NTty {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drivers::tty::tty_io::{
+        set_tty_signal_hook, tty_open, tty_register_driver, tty_release, TtyDriver, TtyPort,
+    };
+    use crate::error::Error;
+    use crate::fs::char_dev::{chrdev_open, mkdev};
+    use crate::schedule::task::set_test_current;
+    use core::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    use std::vec::Vec;
+
+    // Keeps what is written, as a terminal would show it.
+    struct FakePort {
+        out: Mutex<Vec<u8>>,
+        active: AtomicBool,
+        // Most bytes taken by a write, as if the echo filled the buffer.
+        limit: AtomicUsize,
+    }
+
+    impl FakePort {
+        fn take(&self) -> Vec<u8> {
+            core::mem::take(&mut *self.out.lock().unwrap())
+        }
+    }
+
+    impl TtyPort for FakePort {
+        fn activate(&self, _tty: &'static Tty) -> Result {
+            self.active.store(true, Ordering::Relaxed);
+            Ok(())
+        }
+        fn shutdown(&self) {
+            self.active.store(false, Ordering::Relaxed);
+        }
+        fn write(&self, buf: &[u8]) -> usize {
+            let len = buf.len().min(self.limit.load(Ordering::Relaxed));
+            self.out.lock().unwrap().extend_from_slice(&buf[..len]);
+            len
+        }
+        fn write_room(&self) -> usize {
+            4096
+        }
+        fn chars_in_buffer(&self) -> usize {
+            0
+        }
+        fn set_termios(&self, _termios: &Termios) {}
+    }
+
+    struct FakeDriver;
+
+    impl TtyDriver for FakeDriver {
+        fn name(&self) -> &'static str {
+            "ttyFK"
+        }
+        fn major(&self) -> u32 {
+            241
+        }
+        fn minor_start(&self) -> u32 {
+            0
+        }
+        fn num(&self) -> usize {
+            1
+        }
+        fn port(&'static self, index: usize) -> Option<&'static dyn TtyPort> {
+            (index == 0).then_some(&FAKE_PORT as &dyn TtyPort)
+        }
+    }
+
+    static FAKE_PORT: FakePort = FakePort {
+        out: Mutex::new(Vec::new()),
+        active: AtomicBool::new(false),
+        limit: AtomicUsize::new(usize::MAX),
+    };
+    static FAKE_DRIVER: FakeDriver = FakeDriver;
+    static LAST_SIGNAL: AtomicU32 = AtomicU32::new(0);
+
+    fn read(tty: &Tty) -> Vec<u8> {
+        let mut buf = [0; 64];
+        let n = tty.read(&mut buf).unwrap();
+        buf[..n].to_vec()
+    }
+
+    #[test]
+    fn test_n_tty() {
+        set_test_current();
+        set_tty_signal_hook(|_tty, sig| LAST_SIGNAL.store(sig, Ordering::Relaxed));
+        let tty = tty_open(&FAKE_DRIVER, 0).unwrap();
+        assert!(FAKE_PORT.active.load(Ordering::Relaxed));
+        assert!(core::ptr::eq(tty_open(&FAKE_DRIVER, 0).unwrap(), tty));
+        assert!(tty_open(&FAKE_DRIVER, 1).is_err());
+
+        // Canonical mode: erase, CR to NL, echo.
+        tty.receive_buf(b"ab\x7fc\r");
+        assert_eq!(FAKE_PORT.take(), b"ab\x08 \x08c\r\n");
+        assert_eq!(read(tty), b"ac\n");
+
+        // Kill and word erase.
+        tty.receive_buf(b"xyz\x15one two\x17\n");
+        assert_eq!(read(tty), b"one \n");
+        FAKE_PORT.take();
+
+        // EOF ends a line without itself, alone it reads as 0.
+        tty.receive_buf(b"hi\x04\x04");
+        assert_eq!(read(tty), b"hi");
+        assert_eq!(read(tty), b"");
+
+        // Quoted control characters are kept.
+        tty.receive_buf(b"\x16\x03\n");
+        assert_eq!(read(tty), b"\x03\n");
+        FAKE_PORT.take();
+
+        // ^C signals and flushes the line.
+        tty.receive_buf(b"partial\x03");
+        assert_eq!(LAST_SIGNAL.load(Ordering::Relaxed), SIGINT);
+        assert_eq!(FAKE_PORT.take(), b"partial^C");
+        let mut n: i32 = -1;
+        // SAFETY: FIONREAD takes a *mut i32.
+        unsafe { tty.ioctl(FIONREAD, &mut n as *mut i32 as usize).unwrap() };
+        assert_eq!(n, 0);
+        tty.receive_buf(b"\x1a");
+        assert_eq!(LAST_SIGNAL.load(Ordering::Relaxed), SIGTSTP);
+        FAKE_PORT.take();
+
+        // Output processing
+        assert_eq!(tty.write(b"a\nb").unwrap(), 3);
+        assert_eq!(FAKE_PORT.take(), b"a\r\nb");
+        // What the port does not take is sent again, never lost.
+        FAKE_PORT.limit.store(1, Ordering::Relaxed);
+        assert_eq!(tty.write(b"\n\nc").unwrap(), 3);
+        assert_eq!(FAKE_PORT.take(), b"\r\n\r\nc");
+        FAKE_PORT.limit.store(usize::MAX, Ordering::Relaxed);
+
+        // Non canonical mode with VMIN 0 does not wait.
+        let mut termios = Termios::default();
+        // SAFETY: TCGETS takes a *mut Termios.
+        unsafe {
+            tty.ioctl(TCGETS, &mut termios as *mut Termios as usize)
+                .unwrap()
+        };
+        termios.c_lflag &= !(ICANON | ECHO);
+        termios.c_cc[VMIN] = 0;
+        // SAFETY: TCSETS takes a *const Termios.
+        unsafe {
+            tty.ioctl(TCSETS, &termios as *const Termios as usize)
+                .unwrap()
+        };
+        assert_eq!(read(tty), b"");
+        tty.receive_buf(b"q\x7f");
+        assert_eq!(read(tty), b"q\x7f");
+        assert!(FAKE_PORT.take().is_empty());
+        // SAFETY: an unknown ioctl does not use its argument.
+        assert_eq!(unsafe { tty.ioctl(0x5499, 0) }, Err(Error::Enotty));
+
+        // The last close shuts the port down.
+        tty_release(tty);
+        assert!(!tty.is_hung_up());
+        tty_release(tty);
+        assert!(tty.is_hung_up());
+        assert!(!FAKE_PORT.active.load(Ordering::Relaxed));
+
+        // Opened as a character device
+        assert!(tty_register_driver(&FAKE_DRIVER).is_ok());
+        assert_eq!(chrdev_open(mkdev(241, 1)).err(), Some(Error::Enxio));
+        let file = chrdev_open(mkdev(241, 0)).unwrap();
+        assert_eq!(file.write(b"ok\n").unwrap(), 3);
+        assert_eq!(FAKE_PORT.take(), b"ok\r\n");
+        drop(file);
+        assert!(!FAKE_PORT.active.load(Ordering::Relaxed));
+    }
+}
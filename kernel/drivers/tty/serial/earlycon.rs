@@ -1,12 +1,23 @@
 //! earlycon
 //!
+//! Refer to linux: drivers/tty/serial/earlycon.c
+//!
+//! `earlycon=<name>,[mmio|mmio16|mmio32|mmio32be,]<addr>[,options]` sets up
+//! the earlycon driver called `name`, a bare `earlycon` the one matching the
+//! device tree `stdout-path`. The registers are mapped in a fixmap slot, the
+//! driver setup only provides the write callback.
+//!
 //! TODO: support ACPI
 
+use core::sync::atomic::{AtomicU32, Ordering};
+
 use super::uart_port::{UartPort, UartPortIoType};
+use crate::error::{Error, Result};
 use crate::macros::section_init_text;
+use crate::mm::{PhysAddr, VirtAddr};
 use crate::param::obs_param::early_setup_param;
 use crate::param::ParamHandleErr;
-use crate::printk::console::{Console, ConsoleFlags};
+use crate::printk::console::{register_console, Console, ConsoleFlags};
 use crate::{
     fdtree_rs::chosen::Stdout,
     printk::console::{ConsoleNode, GLOBAL_CONSOLE},
@@ -16,61 +27,203 @@ use crate::{
     },
 };
 
+/// uartclk is 16 times this when the firmware does not tell.
+const BASE_BAUD: u32 = 115200;
+
 /// Earlycon device
-#[allow(dead_code)]
 pub struct EarlyConDevice {
     console: Arc<ConsoleNode>,
     port: RawSpinLockNoIrq<UartPort>,
+    write: RawSpinLockNoIrq<Option<fn(&UartPort, &str)>>,
+    baud: AtomicU32,
+}
+
+fn earlycon_write(s: &str) {
+    let write = *EARLYCON_DEV.write.lock();
+    if let Some(write) = write {
+        write(&EARLYCON_DEV.port.lock(), s);
+    }
 }
 
 /// SAFETY: we know what we are doing here.
 /// we use a satic mem to init Arc, if this init Arc refcont to 0, it will panic.
-static EARLYCON_CONSOLE_NODE: ArcInner<ConsoleNode> =
-    ArcInner::new_static(ConsoleNode::new(Console::empty(
+static EARLYCON_CONSOLE_NODE: ArcInner<ConsoleNode> = ArcInner::new_static(ConsoleNode::new(
+    Console::empty(
         "uart",
         ConsoleFlags::from_bits_truncate(
             ConsoleFlags::CON_PRINTBUFFER.bits() | ConsoleFlags::CON_BOOT.bits(),
         ),
         0,
-    )));
+    )
+    .with_write(earlycon_write),
+));
 
 static EARLYCON_DEV: EarlyConDevice = EarlyConDevice {
     // SAFETY: we know what we are doing here.
     // we use a satic mem to init Arc, if this init Arc refcont to 0, it will panic.
     console: unsafe { Arc::from_static(&EARLYCON_CONSOLE_NODE) },
     port: RawSpinLockNoIrq::new(UartPort::new_empty(), None),
+    write: RawSpinLockNoIrq::new(None, None),
+    baud: AtomicU32::new(0),
 };
 
 impl EarlyConDevice {
-    fn init_from_fdt_node(
-        &self,
-        _con_id: &EarlyConId,
-        _node: Stdout<'_, '_>,
-        _options: Option<&str>,
-    ) -> Result<(), ParamHandleErr> {
-        if GLOBAL_CONSOLE.lock().is_register(&self.console) {
+    /// The uart port
+    pub fn port(&self) -> &RawSpinLockNoIrq<UartPort> {
+        &self.port
+    }
+
+    /// Baud rate from the options or the device tree, 0 if unknown.
+    pub fn baud(&self) -> u32 {
+        self.baud.load(Ordering::Relaxed)
+    }
+
+    /// Set the function writing console messages to the port.
+    pub fn set_write(&self, write: fn(&UartPort, &str)) {
+        *self.write.lock() = Some(write);
+    }
+
+    fn is_registered(&self) -> bool {
+        GLOBAL_CONSOLE.lock().is_register(&self.console)
+    }
+
+    // Parse `[<iotype>,]<addr>[,options]` into the port, return the options.
+    fn parse_options<'a>(&self, buf: &'a str) -> Result<Option<&'a str>> {
+        let (iotype, rest) = match buf.split_once(',') {
+            Some(("mmio", rest)) => (UartPortIoType::Mem, rest),
+            Some(("mmio16", rest)) => (UartPortIoType::Mem16, rest),
+            Some(("mmio32", rest)) => (UartPortIoType::Mem32, rest),
+            Some(("mmio32be", rest)) => (UartPortIoType::Mem32Be, rest),
+            _ if buf.starts_with("0x") => (UartPortIoType::Mem, buf),
+            _ => return Err(Error::Einval),
+        };
+        let (addr, options) = match rest.split_once(',') {
+            Some((addr, options)) => (addr, Some(options)),
+            None => (rest, None),
+        };
+        let addr = parse_u64(addr).ok_or(Error::Einval)?;
+
+        let mut port = self.port.lock();
+        port.set_iotype(iotype);
+        port.set_regshift(match iotype {
+            UartPortIoType::Mem16 => 1,
+            UartPortIoType::Mem32 | UartPortIoType::Mem32Be => 2,
+            _ => 0,
+        });
+        port.set_mapbase(addr);
+        if let Some(options) = options {
+            self.baud.store(parse_baud(options), Ordering::Relaxed);
+        }
+        Ok(options)
+    }
+
+    fn init_from_fdt_node(&self, con_id: &EarlyConId, stdout: Stdout<'_, '_>) -> Result {
+        if self.is_registered() {
             return Ok(());
         }
 
-        let mut port = self.port.lock();
-        port.set_iotype(UartPortIoType::Mem);
-        Ok(())
+        let node = stdout.node;
+        let reg = node
+            .reg()
+            .and_then(|mut reg| reg.next())
+            .ok_or(Error::Einval)?;
+        let mapbase = node
+            .translate_address(reg.starting_address as u64)
+            .ok_or(Error::Einval)?;
+        let prop = |name| node.property(name).and_then(|p| p.as_usize());
+        {
+            let mut port = self.port.lock();
+            port.set_iotype(match prop("reg-io-width") {
+                Some(2) => UartPortIoType::Mem16,
+                Some(4) if node.property("big-endian").is_some() => UartPortIoType::Mem32Be,
+                Some(4) => UartPortIoType::Mem32,
+                _ => UartPortIoType::Mem,
+            });
+            port.set_regshift(prop("reg-shift").unwrap_or(0) as u8);
+            port.set_mapbase(mapbase);
+            if let Some(clk) = prop("clock-frequency") {
+                port.set_uartclk(clk as u32);
+            }
+        }
+        if let Some(speed) = prop("current-speed") {
+            self.baud.store(speed as u32, Ordering::Relaxed);
+        }
+        if let Some(options) = stdout.options {
+            self.baud.store(parse_baud(options), Ordering::Relaxed);
+        }
+        self.register(con_id, stdout.options)
+    }
+
+    // Map the registers, let the driver set up and register the console.
+    fn register(&self, con_id: &EarlyConId, options: Option<&str>) -> Result {
+        let mapbase = {
+            let mut port = self.port.lock();
+            if port.uartclk() == 0 {
+                port.set_uartclk(BASE_BAUD * 16);
+            }
+            if port.mapbase() != 0 {
+                let membase =
+                    earlycon_map(PhysAddr::from(port.mapbase() as usize)).ok_or(Error::Enomem)?;
+                port.set_membase(membase.as_usize());
+            }
+            port.mapbase()
+        };
+        self.console.set_device(mapbase as usize);
+
+        (con_id.setup)(self, options)?;
+        if self.write.lock().is_none() {
+            return Err(Error::Enodev);
+        }
+        crate::pr_info!(
+            "earlycon: {} at MMIO {:#x} (options '{}')\n",
+            con_id.name,
+            mapbase,
+            options.unwrap_or("")
+        );
+        register_console(self.console.clone())
+    }
+}
+
+fn earlycon_map(phys: PhysAddr) -> Option<VirtAddr> {
+    cfg_if::cfg_if! {
+        if #[cfg(CONFIG_ARM64)] {
+            Some(crate::arch::arm64::mm::fixmap::FixMap::set_earlycon_map(phys))
+        } else {
+            let _ = phys;
+            None
+        }
     }
 }
 
+// Hex with a 0x prefix, decimal otherwise.
+fn parse_u64(s: &str) -> Option<u64> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+// The baud rate leads the options, as in 115200n8.
+fn parse_baud(options: &str) -> u32 {
+    let end = options
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(options.len());
+    options[..end].parse().unwrap_or(0)
+}
+
 /// Earlycon id, all of them are linked in section __earlycon_table
 ///
 /// Example:
 ///
 /// use crate::drivers::tty::serial::earlycon::earlycon_declare;
 ///
-/// earlycon_declare!(pl011, "arm,pl011", setup_pl011_earlycon);
+/// earlycon_declare!(PL011_EARLYCON, "pl011", "arm,pl011", setup_pl011_earlycon);
 ///
 #[repr(C)]
 pub struct EarlyConId {
     name: &'static str,
     compatible: &'static str,
-    setup: fn(&mut EarlyConDevice, Option<&'static str>),
+    setup: fn(&EarlyConDevice, Option<&str>) -> Result,
 }
 
 impl EarlyConId {
@@ -78,7 +231,7 @@ impl EarlyConId {
     pub const fn new(
         name: &'static str,
         compatible: &'static str,
-        setup: fn(&mut EarlyConDevice, Option<&'static str>),
+        setup: fn(&EarlyConDevice, Option<&str>) -> Result,
     ) -> Self {
         Self {
             name,
@@ -87,22 +240,24 @@ impl EarlyConId {
         }
     }
 
-    fn find(compatible: &str) -> Option<&'static EarlyConId> {
+    fn table() -> &'static [EarlyConId] {
         use crate::global_sym::{__earlycon_table, __earlycon_table_end};
         // SAFETY: __earlycon_table and __earlycon_table_end are defined in link script
         unsafe {
             let start = __earlycon_table as *const EarlyConId;
             let end = __earlycon_table_end as *const EarlyConId;
             let n = (end as usize - start as usize) / core::mem::size_of::<EarlyConId>();
-            let slice = core::slice::from_raw_parts(start, n);
-            for id in slice {
-                if id.compatible == compatible {
-                    return Some(id);
-                }
-            }
-            None
+            core::slice::from_raw_parts(start, n)
         }
     }
+
+    fn find(compatible: &str) -> Option<&'static EarlyConId> {
+        Self::table().iter().find(|id| id.compatible == compatible)
+    }
+
+    fn find_by_name(name: &str) -> Option<&'static EarlyConId> {
+        Self::table().iter().find(|id| id.name == name)
+    }
 }
 
 //use crate::arch::arm64::early_debug::early_uart_put_str;
@@ -111,52 +266,60 @@ impl EarlyConId {
 ///
 /// Example:
 ///
-/// earlycon_declare!(pl011, setup_pl011_earlycon);
+/// earlycon_declare!(PL011_EARLYCON, "pl011", "arm,pl011", setup_pl011_earlycon);
 ///
 #[macro_export]
 macro_rules! earlycon_declare {
-    ($name:ident, $compatible:expr, $fn:ident) => {
+    ($id:ident, $name:expr, $compatible:expr, $fn:ident) => {
         #[unsafe(link_section = "__earlycon_table")]
         #[used]
-        static $name: $crate::drivers::tty::serial::earlycon::EarlyConId =
-            $crate::drivers::tty::serial::earlycon::EarlyConId::new(
-                stringify!($name),
-                $compatible,
-                $fn,
-            );
+        static $id: $crate::drivers::tty::serial::earlycon::EarlyConId =
+            $crate::drivers::tty::serial::earlycon::EarlyConId::new($name, $compatible, $fn);
     };
 }
 
 pub use earlycon_declare;
 
-fn init_earlycon_from_fdt() -> Result<(), ParamHandleErr> {
-    let stdout = crate::drivers::fdt::GLOBAL_FDT.chosen().stdout();
-    match stdout {
-        Some(stdout) => {
-            let compatible = stdout.node.compatible().unwrap().first();
-            let options = stdout.options;
-            let earlycon_id = EarlyConId::find(compatible);
-            match earlycon_id {
-                Some(earlycon_id) => {
-                    EARLYCON_DEV.init_from_fdt_node(earlycon_id, stdout, options)?;
-                }
-                None => return Err(ParamHandleErr::Unknown),
-            }
-        }
-        None => {
-            todo!();
-        }
-    }
+fn init_earlycon_from_fdt() -> Result {
+    // Nothing to set up without a stdout-path.
+    let stdout = crate::drivers::fdt::GLOBAL_FDT
+        .chosen()
+        .and_then(|c| c.stdout())
+        .ok_or(Error::Enoent)?;
+    let earlycon_id = stdout
+        .node
+        .compatible()
+        .and_then(|c| c.all().find_map(EarlyConId::find))
+        .ok_or(Error::Enodev)?;
+    EARLYCON_DEV.init_from_fdt_node(earlycon_id, stdout)
+}
 
-    Ok(())
+fn setup_earlycon(buf: &str) -> Result {
+    if EARLYCON_DEV.is_registered() {
+        return Err(Error::Ebusy);
+    }
+    let (name, options) = match buf.split_once(',') {
+        Some((name, options)) => (name, Some(options)),
+        None => (buf, None),
+    };
+    let earlycon_id = EarlyConId::find_by_name(name).ok_or(Error::Enodev)?;
+    let options = match options {
+        Some(options) => EARLYCON_DEV.parse_options(options)?,
+        None => None,
+    };
+    EARLYCON_DEV.register(earlycon_id, options)
 }
 
 #[section_init_text]
 fn setup_earlycon_param(val: Option<&str>) -> Result<(), ParamHandleErr> {
-    match val {
-        Some(_val) => todo!(),
-        None => return init_earlycon_from_fdt(),
-    }
+    let ret = match val {
+        Some(val) if !val.is_empty() => setup_earlycon(val),
+        _ => init_earlycon_from_fdt(),
+    };
+    ret.map_err(|e| match e {
+        Error::Ebusy => ParamHandleErr::Duplicate,
+        _ => ParamHandleErr::InvalidValue,
+    })
 }
 
 // register earlycon param setup func
@@ -1,4 +1,7 @@
 //! serial driver
 #[cfg(not(test))]
 pub mod earlycon;
+pub mod serial_core;
+#[cfg(all(CONFIG_SERIAL_8250, not(test)))]
+pub mod serial_8250;
 pub mod uart_port;
@@ -0,0 +1,70 @@
+//! 8250 earlycon
+//!
+//! Refer to linux: drivers/tty/serial/8250/8250_early.c
+//!
+//! `earlycon=uart8250,mmio32,0x...` or a `ns16550a`/`snps,dw-apb-uart`
+//! stdout-path. The port is programmed 8n1 only when a baud rate is known.
+
+use super::{
+    BOTH_EMPTY, UART_DLL, UART_DLM, UART_FCR, UART_IER, UART_LCR, UART_LCR_DLAB, UART_LCR_WLEN8,
+    UART_LSR, UART_MCR, UART_MCR_DTR, UART_MCR_RTS, UART_TX,
+};
+use crate::drivers::tty::serial::earlycon::{earlycon_declare, EarlyConDevice};
+use crate::drivers::tty::serial::uart_port::UartPort;
+use crate::error::{Error, Result};
+
+fn serial8250_early_putc(port: &UartPort, c: u8) {
+    while port.read_reg(UART_LSR) & BOTH_EMPTY != BOTH_EMPTY {}
+    port.write_reg(UART_TX, c as u32);
+}
+
+/// Write a console message, for the earlycons of other 8250 ports.
+pub fn early_serial8250_write(port: &UartPort, s: &str) {
+    port.console_write(s, serial8250_early_putc);
+}
+
+// Refer to linux: init_port
+fn init_port(port: &UartPort, baud: u32) {
+    port.write_reg(UART_LCR, UART_LCR_WLEN8);
+    port.write_reg(UART_IER, 0);
+    port.write_reg(UART_FCR, 0);
+    port.write_reg(UART_MCR, UART_MCR_DTR | UART_MCR_RTS);
+
+    let divisor = (port.uartclk() + 8 * baud) / (16 * baud);
+    let lcr = port.read_reg(UART_LCR);
+    port.write_reg(UART_LCR, lcr | UART_LCR_DLAB);
+    port.write_reg(UART_DLL, divisor & 0xff);
+    port.write_reg(UART_DLM, (divisor >> 8) & 0xff);
+    port.write_reg(UART_LCR, lcr & !UART_LCR_DLAB);
+}
+
+/// Set up the earlycon of a 16550 compatible port.
+///
+/// Refer to linux: early_serial8250_setup
+pub fn early_serial8250_setup(dev: &EarlyConDevice, _options: Option<&str>) -> Result {
+    {
+        let port = dev.port().lock();
+        if port.membase() == 0 {
+            return Err(Error::Enodev);
+        }
+        if dev.baud() != 0 {
+            init_port(&port, dev.baud());
+        }
+    }
+    dev.set_write(early_serial8250_write);
+    Ok(())
+}
+
+earlycon_declare!(
+    UART8250_EARLYCON,
+    "uart8250",
+    "ns16550a",
+    early_serial8250_setup
+);
+earlycon_declare!(UART_EARLYCON, "uart", "ns16550", early_serial8250_setup);
+earlycon_declare!(
+    DW_APB_EARLYCON,
+    "uart",
+    "snps,dw-apb-uart",
+    early_serial8250_setup
+);
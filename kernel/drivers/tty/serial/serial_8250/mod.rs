@@ -0,0 +1,478 @@
+//! 8250/16550 serial core
+//!
+//! Refer to linux: drivers/tty/serial/8250/8250_core.c, 8250_port.c
+//!
+//! The bus drivers find the ports and add them with
+//! [`serial8250_register_8250_port`], they are named ttyS in that order. The
+//! registers are read through [`UartPort::read_reg`], so any `reg-shift` and
+//! `reg-io-width` work. The BCM2835 mini UART is a 16550 with less features.
+//! A port is started when its tty is opened.
+
+pub mod early;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::serial_core::{
+    uart_console_write, uart_register_driver, UartDriver, UartOps, UartParity, UartSettings,
+    UartState, UART_NR,
+};
+use super::uart_port::UartPort;
+use crate::drivers::tty::tty_io::TTY_MAJOR;
+use crate::error::{Error, Result};
+use crate::irq::{free_irq, request_irq, IrqReturn};
+use crate::printk::console::{
+    register_console, Console, ConsoleFlags, ConsoleNode, CONSOLE_INDEX_ANY,
+};
+use crate::sync::arc::{Arc, ArcInner};
+use crate::sync::lock::RawSpinLockNoIrq;
+
+/// Receive buffer, DLAB clear
+pub const UART_RX: usize = 0;
+/// Transmit holding, DLAB clear
+pub const UART_TX: usize = 0;
+/// Divisor latch low, DLAB set
+pub const UART_DLL: usize = 0;
+/// Divisor latch high, DLAB set
+pub const UART_DLM: usize = 1;
+/// Interrupt enable
+pub const UART_IER: usize = 1;
+/// Interrupt identification, read
+pub const UART_IIR: usize = 2;
+/// FIFO control, write
+pub const UART_FCR: usize = 2;
+/// Line control
+pub const UART_LCR: usize = 3;
+/// Modem control
+pub const UART_MCR: usize = 4;
+/// Line status
+pub const UART_LSR: usize = 5;
+/// Modem status
+pub const UART_MSR: usize = 6;
+/// DesignWare UART status
+pub const DW_UART_USR: usize = 31;
+
+/// IER: receive data interrupt
+pub const UART_IER_RDI: u32 = 0x01;
+/// IER: transmit holding empty interrupt
+pub const UART_IER_THRI: u32 = 0x02;
+/// IER: receive line status interrupt
+pub const UART_IER_RLSI: u32 = 0x04;
+
+/// IIR: no interrupt pending
+pub const UART_IIR_NO_INT: u32 = 0x01;
+/// IIR: DesignWare busy detect, written LCR while busy
+pub const UART_IIR_BUSY: u32 = 0x07;
+
+/// FCR: enable the FIFOs
+pub const UART_FCR_ENABLE_FIFO: u32 = 0x01;
+/// FCR: clear the receive FIFO
+pub const UART_FCR_CLEAR_RCVR: u32 = 0x02;
+/// FCR: clear the transmit FIFO
+pub const UART_FCR_CLEAR_XMIT: u32 = 0x04;
+/// FCR: receive interrupt at 8 bytes
+pub const UART_FCR_R_TRIG_10: u32 = 0x80;
+
+/// LCR: 8 bit words, the word length is this minus 3 to 5.
+pub const UART_LCR_WLEN8: u32 = 0x03;
+/// LCR: two stop bits
+pub const UART_LCR_STOP: u32 = 0x04;
+/// LCR: parity enable
+pub const UART_LCR_PARITY: u32 = 0x08;
+/// LCR: even parity
+pub const UART_LCR_EPAR: u32 = 0x10;
+/// LCR: divisor latch access
+pub const UART_LCR_DLAB: u32 = 0x80;
+
+/// MCR: data terminal ready
+pub const UART_MCR_DTR: u32 = 0x01;
+/// MCR: request to send
+pub const UART_MCR_RTS: u32 = 0x02;
+/// MCR: out2, gates the interrupt on PC boards
+pub const UART_MCR_OUT2: u32 = 0x08;
+/// MCR: auto flow control
+pub const UART_MCR_AFE: u32 = 0x20;
+
+/// LSR: receiver data ready
+pub const UART_LSR_DR: u32 = 0x01;
+/// LSR: overrun error
+pub const UART_LSR_OE: u32 = 0x02;
+/// LSR: parity error
+pub const UART_LSR_PE: u32 = 0x04;
+/// LSR: framing error
+pub const UART_LSR_FE: u32 = 0x08;
+/// LSR: break interrupt
+pub const UART_LSR_BI: u32 = 0x10;
+/// LSR: transmit holding register empty
+pub const UART_LSR_THRE: u32 = 0x20;
+/// LSR: transmitter empty
+pub const UART_LSR_TEMT: u32 = 0x40;
+/// LSR: both transmit registers empty
+pub const BOTH_EMPTY: u32 = UART_LSR_TEMT | UART_LSR_THRE;
+
+/// Characters read at most per interrupt.
+const RX_BUDGET: usize = 256;
+
+/// 8250 port types
+///
+/// Refer to linux: uart_config
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Serial8250Type {
+    /// 16550A, 16 byte FIFOs
+    Uart16550A,
+    /// Synopsys DesignWare APB UART, a 16550A with a busy detect interrupt
+    DwApb,
+    /// BCM2835 aux mini UART, 8 byte FIFOs, 7 or 8 bits and no parity
+    Bcm2835Aux,
+}
+
+impl Serial8250Type {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Uart16550A => "16550A",
+            Self::DwApb => "16550A (DesignWare)",
+            Self::Bcm2835Aux => "BCM2835 mini UART",
+        }
+    }
+
+    fn fifosize(self) -> u32 {
+        match self {
+            Self::Uart16550A | Self::DwApb => 16,
+            Self::Bcm2835Aux => 8,
+        }
+    }
+}
+
+struct Serial8250Inner {
+    port: UartPort,
+    kind: Serial8250Type,
+    ier: u32,
+}
+
+/// A 8250 port
+pub struct Serial8250Port {
+    inner: RawSpinLockNoIrq<Serial8250Inner>,
+    state: UartState,
+}
+
+static SERIAL8250_PORTS: [Serial8250Port; UART_NR] = [const { Serial8250Port::new() }; UART_NR];
+static SERIAL8250_NR: AtomicUsize = AtomicUsize::new(0);
+
+static SERIAL8250_REG: UartDriver = UartDriver::new("serial", "ttyS", TTY_MAJOR, 64);
+
+impl Serial8250Port {
+    const fn new() -> Self {
+        Self {
+            inner: RawSpinLockNoIrq::new(
+                Serial8250Inner {
+                    port: UartPort::new_empty(),
+                    kind: Serial8250Type::Uart16550A,
+                    ier: 0,
+                },
+                Some("serial8250_port"),
+            ),
+            state: UartState::new(),
+        }
+    }
+
+    fn tx_chars(&self, inner: &mut Serial8250Inner) {
+        for _ in 0..inner.port.fifosize() {
+            match self.state.xmit_pop() {
+                Some(c) => inner.port.write_reg(UART_TX, c as u32),
+                None => break,
+            }
+        }
+        if self.state.chars_pending() == 0 {
+            inner.ier &= !UART_IER_THRI;
+            inner.port.write_reg(UART_IER, inner.ier);
+        }
+    }
+
+    fn rx_chars(&self, inner: &Serial8250Inner, mut lsr: u32) {
+        let icount = self.state.icount();
+        for _ in 0..RX_BUDGET {
+            if lsr & (UART_LSR_DR | UART_LSR_BI) == 0 {
+                break;
+            }
+            let ch = inner.port.read_reg(UART_RX) as u8;
+            if lsr & UART_LSR_OE != 0 {
+                icount.overrun.fetch_add(1, Ordering::Relaxed);
+            }
+            if lsr & UART_LSR_BI != 0 {
+                icount.brk.fetch_add(1, Ordering::Relaxed);
+            } else {
+                if lsr & UART_LSR_PE != 0 {
+                    icount.parity.fetch_add(1, Ordering::Relaxed);
+                }
+                if lsr & UART_LSR_FE != 0 {
+                    icount.frame.fetch_add(1, Ordering::Relaxed);
+                }
+                self.state.insert_char(ch);
+            }
+            lsr = inner.port.read_reg(UART_LSR);
+        }
+    }
+
+    // Refer to linux: serial8250_handle_irq
+    fn handle_irq(&self) -> IrqReturn {
+        let mut inner = self.inner.lock();
+        let iir = inner.port.read_reg(UART_IIR);
+        if inner.kind == Serial8250Type::DwApb && iir & UART_IIR_BUSY == UART_IIR_BUSY {
+            // Reading the status clears the busy detect interrupt.
+            inner.port.read_reg(DW_UART_USR);
+            return IrqReturn::Handled;
+        }
+        if iir & UART_IIR_NO_INT != 0 {
+            return IrqReturn::None;
+        }
+        let lsr = inner.port.read_reg(UART_LSR);
+        self.rx_chars(&inner, lsr);
+        if lsr & UART_LSR_THRE != 0 && inner.ier & UART_IER_THRI != 0 {
+            self.tx_chars(&mut inner);
+        }
+        drop(inner);
+        // The echo starts the transmitter, it takes the port lock.
+        self.state.flip_buffer_push();
+        self.state.write_wakeup();
+        IrqReturn::Handled
+    }
+}
+
+impl UartOps for Serial8250Port {
+    fn state(&self) -> &UartState {
+        &self.state
+    }
+
+    fn tx_empty(&self) -> bool {
+        let inner = self.inner.lock();
+        inner.port.read_reg(UART_LSR) & BOTH_EMPTY == BOTH_EMPTY
+    }
+
+    fn start_tx(&self) {
+        let mut inner = self.inner.lock();
+        if inner.ier & UART_IER_THRI == 0 {
+            inner.ier |= UART_IER_THRI;
+            inner.port.write_reg(UART_IER, inner.ier);
+            // The interrupt only comes once the holding register empties.
+            if inner.port.read_reg(UART_LSR) & UART_LSR_THRE != 0 {
+                self.tx_chars(&mut inner);
+            }
+        }
+        drop(inner);
+        self.state.write_wakeup();
+    }
+
+    fn stop_tx(&self) {
+        let mut inner = self.inner.lock();
+        inner.ier &= !UART_IER_THRI;
+        inner.port.write_reg(UART_IER, inner.ier);
+    }
+
+    fn stop_rx(&self) {
+        let mut inner = self.inner.lock();
+        inner.ier &= !(UART_IER_RDI | UART_IER_RLSI);
+        inner.port.write_reg(UART_IER, inner.ier);
+    }
+
+    fn startup(&self) -> Result {
+        let irq = self.inner.lock().port.irq();
+        request_irq(
+            irq,
+            serial8250_interrupt,
+            "serial",
+            self as *const Self as usize,
+        )?;
+
+        let mut inner = self.inner.lock();
+        let port = &inner.port;
+        port.write_reg(
+            UART_FCR,
+            UART_FCR_ENABLE_FIFO | UART_FCR_CLEAR_RCVR | UART_FCR_CLEAR_XMIT,
+        );
+        // Clear the pending interrupts.
+        port.read_reg(UART_LSR);
+        port.read_reg(UART_RX);
+        port.read_reg(UART_IIR);
+        port.read_reg(UART_MSR);
+        let mcr = port.read_reg(UART_MCR) & UART_MCR_AFE;
+        port.write_reg(UART_MCR, mcr | UART_MCR_DTR | UART_MCR_RTS | UART_MCR_OUT2);
+        inner.ier = UART_IER_RDI | UART_IER_RLSI;
+        inner.port.write_reg(UART_IER, inner.ier);
+        Ok(())
+    }
+
+    fn shutdown(&self) {
+        let irq = {
+            let mut inner = self.inner.lock();
+            inner.ier = 0;
+            inner.port.write_reg(UART_IER, 0);
+            let mcr = inner.port.read_reg(UART_MCR);
+            inner.port.write_reg(UART_MCR, mcr & !UART_MCR_OUT2);
+            inner.port.irq()
+        };
+        free_irq(irq);
+    }
+
+    // Refer to linux: serial8250_do_set_termios
+    fn set_termios(&self, settings: &UartSettings) {
+        let inner = self.inner.lock();
+        let port = &inner.port;
+        let mut lcr = (settings.bits.clamp(5, 8) - 5) as u32;
+        if inner.kind == Serial8250Type::Bcm2835Aux {
+            lcr = if settings.bits == 7 {
+                2
+            } else {
+                UART_LCR_WLEN8
+            };
+        } else {
+            if settings.cstopb {
+                lcr |= UART_LCR_STOP;
+            }
+            match settings.parity {
+                UartParity::None => {}
+                UartParity::Odd => lcr |= UART_LCR_PARITY,
+                UartParity::Even => lcr |= UART_LCR_PARITY | UART_LCR_EPAR,
+            }
+        }
+
+        // Without a clock rate the firmware divisor is kept.
+        let uartclk = port.uartclk();
+        if uartclk != 0 {
+            let baud = settings.baud.clamp(1, uartclk / 16);
+            let quot = (uartclk + 8 * baud) / (16 * baud);
+            port.write_reg(UART_LCR, lcr | UART_LCR_DLAB);
+            port.write_reg(UART_DLL, quot & 0xff);
+            port.write_reg(UART_DLM, (quot >> 8) & 0xff);
+        }
+        port.write_reg(UART_LCR, lcr);
+
+        if inner.kind != Serial8250Type::Bcm2835Aux {
+            let mut mcr = port.read_reg(UART_MCR) & !UART_MCR_AFE;
+            if settings.crtscts {
+                mcr |= UART_MCR_AFE;
+            }
+            port.write_reg(UART_MCR, mcr);
+        }
+        port.write_reg(UART_FCR, UART_FCR_ENABLE_FIFO | UART_FCR_R_TRIG_10);
+    }
+
+    fn port_type(&self) -> &'static str {
+        self.inner.lock().kind.name()
+    }
+
+    fn poll_put_char(&self, c: u8) {
+        serial8250_putc(&self.inner.lock().port, c);
+    }
+
+    fn poll_get_char(&self) -> Option<u8> {
+        let inner = self.inner.lock();
+        if inner.port.read_reg(UART_LSR) & UART_LSR_DR == 0 {
+            return None;
+        }
+        Some(inner.port.read_reg(UART_RX) as u8)
+    }
+}
+
+fn serial8250_interrupt(_irq: u32, data: usize) -> IrqReturn {
+    // SAFETY: data is the static port given to request_irq.
+    let up = unsafe { &*(data as *const Serial8250Port) };
+    up.handle_irq()
+}
+
+/// Write a char once the holding register is empty.
+pub fn serial8250_putc(port: &UartPort, c: u8) {
+    while port.read_reg(UART_LSR) & UART_LSR_THRE == 0 {}
+    port.write_reg(UART_TX, c as u32);
+}
+
+fn serial8250_console_write(s: &str) {
+    let Some(up) = SERIAL8250_PORTS.get(SERIAL8250_CONSOLE.index() as usize) else {
+        return;
+    };
+    let inner = up.inner.lock();
+    if inner.port.membase() == 0 {
+        return;
+    }
+    // Mask the interrupts while printing, and wait for the last char.
+    inner.port.write_reg(UART_IER, 0);
+    uart_console_write(s, |c| serial8250_putc(&inner.port, c));
+    while inner.port.read_reg(UART_LSR) & BOTH_EMPTY != BOTH_EMPTY {}
+    inner.port.write_reg(UART_IER, inner.ier);
+}
+
+/// Without options the probe settings are kept.
+fn serial8250_console_setup(con: &Console, options: Option<&str>) -> Result {
+    let up = SERIAL8250_PORTS
+        .get(con.index() as usize)
+        .ok_or(Error::Enodev)?;
+    let mapbase = up.inner.lock().port.mapbase();
+    if mapbase == 0 {
+        return Err(Error::Enodev);
+    }
+    if let Some(options) = options {
+        up.set_termios(&UartSettings::parse_options(options));
+    }
+    // Takes over from the earlycon at the same address.
+    con.set_device(mapbase as usize);
+    Ok(())
+}
+
+/// SAFETY: we know what we are doing here.
+/// we use a satic mem to init Arc, if this init Arc refcont to 0, it will panic.
+static SERIAL8250_CONSOLE_NODE: ArcInner<ConsoleNode> = ArcInner::new_static(ConsoleNode::new(
+    Console::empty("ttyS", ConsoleFlags::CON_PRINTBUFFER, CONSOLE_INDEX_ANY)
+        .with_write(serial8250_console_write)
+        .with_setup(serial8250_console_setup)
+        .with_tty_driver(&SERIAL8250_REG),
+));
+
+// SAFETY: we know what we are doing here.
+// we use a satic mem to init Arc, if this init Arc refcont to 0, it will panic.
+static SERIAL8250_CONSOLE: Arc<ConsoleNode> = unsafe { Arc::from_static(&SERIAL8250_CONSOLE_NODE) };
+
+/// Add a port found by a bus driver, return its line.
+///
+/// Refer to linux: serial8250_register_8250_port
+///
+/// The registers of `port` are mapped, the line is set to `baud` 8n1 if
+/// `port` has a clock rate. On error the caller still owns the mapping.
+pub fn serial8250_register_8250_port(
+    mut port: UartPort,
+    kind: Serial8250Type,
+    baud: u32,
+) -> Result<usize> {
+    let line = SERIAL8250_NR
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |line| {
+            (line < SERIAL8250_PORTS.len()).then_some(line + 1)
+        })
+        .map_err(|_| Error::Enospc)?;
+    if line == 0 {
+        if let Err(e) = uart_register_driver(&SERIAL8250_REG) {
+            // Let the next port try again.
+            let _ = SERIAL8250_NR.compare_exchange(1, 0, Ordering::Relaxed, Ordering::Relaxed);
+            return Err(e);
+        }
+    }
+    let up = &SERIAL8250_PORTS[line];
+    if port.fifosize() == 0 {
+        port.set_fifosize(kind.fifosize());
+    }
+    let (mapbase, irq, uartclk) = (port.mapbase(), port.irq(), port.uartclk());
+    *up.inner.lock() = Serial8250Inner { port, kind, ier: 0 };
+    up.set_termios(&UartSettings::new(baud));
+    SERIAL8250_REG.add_one_port(line, up)?;
+    crate::pr_info!(
+        "ttyS{} at MMIO {:#x} (irq = {}, base_baud = {}) is a {}\n",
+        line,
+        mapbase,
+        irq,
+        uartclk / 16,
+        kind.name()
+    );
+
+    // The console may ask for a line that is not added yet.
+    match register_console(SERIAL8250_CONSOLE.clone()) {
+        Ok(()) | Err(Error::Eexist | Error::Enoent | Error::Enodev) => {}
+        Err(e) => crate::pr_err!("ttyS{}: console: {:?}\n", line, e),
+    }
+    Ok(line)
+}
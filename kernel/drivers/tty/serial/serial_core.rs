@@ -0,0 +1,589 @@
+//! Serial core
+//!
+//! Refer to linux: drivers/tty/serial/serial_core.c
+//!
+//! A UART driver implements [`UartOps`] for each of its ports and adds them
+//! to its [`UartDriver`]. The port [`UartState`] holds the characters to
+//! transmit and the ones received: writers queue characters and start the
+//! transmitter, the interrupt handler moves them to and from the FIFOs.
+//!
+//! A registered [`UartDriver`] is a tty driver. Once the tty of a port is
+//! open, the received characters go to its line discipline.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crate::drivers::tty::termios::{Termios, CLOCAL, CRTSCTS, CSIZE, CSTOPB, PARENB, PARODD};
+use crate::drivers::tty::tty_io::{tty_register_driver, Tty, TtyDriver, TtyPort};
+use crate::error::{Error, Result};
+use crate::klib::circ_buf::CircBuf;
+use crate::schedule::WaitQueue;
+use crate::sync::lock::RawSpinLockNoIrq;
+
+/// Size of the transmit buffer
+pub const UART_XMIT_SIZE: usize = 4096;
+/// Size of the receive buffer
+pub const UART_RECV_SIZE: usize = 4096;
+/// Writers are woken up when fewer characters are left to transmit.
+pub const WAKEUP_CHARS: usize = 256;
+/// Most ports of a driver
+pub const UART_NR: usize = 8;
+/// Baud rate when nothing tells.
+pub const DEFAULT_BAUD: u32 = 115200;
+
+/// Parity
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum UartParity {
+    /// No parity bit
+    None,
+    /// Odd parity
+    Odd,
+    /// Even parity
+    Even,
+}
+
+/// Line settings of a port
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct UartSettings {
+    /// Baud rate
+    pub baud: u32,
+    /// Bits per character, 5 to 8.
+    pub bits: u8,
+    /// Parity
+    pub parity: UartParity,
+    /// Two stop bits instead of one.
+    pub cstopb: bool,
+    /// RTS/CTS flow control
+    pub crtscts: bool,
+}
+
+impl UartSettings {
+    /// 8 bits, no parity, 1 stop bit.
+    pub const fn new(baud: u32) -> Self {
+        Self {
+            baud,
+            bits: 8,
+            parity: UartParity::None,
+            cstopb: false,
+            crtscts: false,
+        }
+    }
+
+    /// Parse console options, `<baud><parity><bits><flow>` as in `115200n8r`.
+    ///
+    /// Refer to linux: uart_parse_options
+    pub fn parse_options(options: &str) -> Self {
+        let end = options
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(options.len());
+        let mut settings = Self::new(options[..end].parse().unwrap_or(DEFAULT_BAUD));
+        let mut rest = options[end..].bytes();
+        settings.parity = match rest.next() {
+            Some(b'o') => UartParity::Odd,
+            Some(b'e') => UartParity::Even,
+            _ => UartParity::None,
+        };
+        if let Some(bits @ b'5'..=b'8') = rest.next() {
+            settings.bits = bits - b'0';
+        }
+        settings.crtscts = rest.next() == Some(b'r');
+        settings
+    }
+
+    /// The line settings of `termios`, None for baud rate 0.
+    pub fn from_termios(termios: &Termios) -> Option<Self> {
+        let baud = termios.baud_rate();
+        if baud == 0 {
+            return None;
+        }
+        let cflag = termios.c_cflag;
+        let mut settings = Self::new(baud);
+        settings.bits = 5 + ((cflag & CSIZE) >> 4) as u8;
+        settings.parity = match (cflag & PARENB != 0, cflag & PARODD != 0) {
+            (false, _) => UartParity::None,
+            (true, true) => UartParity::Odd,
+            (true, false) => UartParity::Even,
+        };
+        settings.cstopb = cflag & CSTOPB != 0;
+        settings.crtscts = cflag & CRTSCTS != 0;
+        Some(settings)
+    }
+}
+
+/// UART driver operations on a port, called by the serial core.
+///
+/// Refer to linux: struct uart_ops
+pub trait UartOps: Sync {
+    /// The port state
+    fn state(&self) -> &UartState;
+    /// Whether the transmitter is empty.
+    fn tx_empty(&self) -> bool;
+    /// Start transmitting the characters of the transmit buffer.
+    fn start_tx(&self);
+    /// Stop transmitting.
+    fn stop_tx(&self);
+    /// Stop receiving, the port is being closed.
+    fn stop_rx(&self);
+    /// Enable the port and its interrupts.
+    fn startup(&self) -> Result;
+    /// Disable the port and its interrupts.
+    fn shutdown(&self);
+    /// Program the line settings.
+    fn set_termios(&self, settings: &UartSettings);
+    /// Port type, for messages.
+    fn port_type(&self) -> &'static str;
+    /// Write a character, waiting for room in the FIFO, for consoles.
+    fn poll_put_char(&self, c: u8);
+    /// Read a received character without waiting.
+    fn poll_get_char(&self) -> Option<u8>;
+}
+
+/// Interrupt counters of a port
+///
+/// Refer to linux: struct uart_icount
+#[derive(Default)]
+pub struct UartIcount {
+    /// Characters received
+    pub rx: AtomicU32,
+    /// Characters transmitted
+    pub tx: AtomicU32,
+    /// Framing errors
+    pub frame: AtomicU32,
+    /// Parity errors
+    pub parity: AtomicU32,
+    /// Breaks
+    pub brk: AtomicU32,
+    /// Characters lost in the FIFO
+    pub overrun: AtomicU32,
+    /// Characters lost because the receive buffer was full.
+    pub buf_overrun: AtomicU32,
+}
+
+impl UartIcount {
+    const fn new() -> Self {
+        Self {
+            rx: AtomicU32::new(0),
+            tx: AtomicU32::new(0),
+            frame: AtomicU32::new(0),
+            parity: AtomicU32::new(0),
+            brk: AtomicU32::new(0),
+            overrun: AtomicU32::new(0),
+            buf_overrun: AtomicU32::new(0),
+        }
+    }
+}
+
+/// Buffers of a port shared by the core and the driver.
+pub struct UartState {
+    xmit: RawSpinLockNoIrq<CircBuf<UART_XMIT_SIZE>>,
+    recv: RawSpinLockNoIrq<CircBuf<UART_RECV_SIZE>>,
+    read_wait: WaitQueue,
+    write_wait: WaitQueue,
+    icount: UartIcount,
+    // the open tty, it takes the received characters
+    tty: RawSpinLockNoIrq<Option<&'static Tty>>,
+}
+
+impl UartState {
+    /// Create an empty state
+    pub const fn new() -> Self {
+        Self {
+            xmit: RawSpinLockNoIrq::new(CircBuf::new(), Some("uart_xmit")),
+            recv: RawSpinLockNoIrq::new(CircBuf::new(), Some("uart_recv")),
+            read_wait: WaitQueue::new(),
+            write_wait: WaitQueue::new(),
+            icount: UartIcount::new(),
+            tty: RawSpinLockNoIrq::new(None, Some("uart_tty")),
+        }
+    }
+
+    /// Interrupt counters
+    pub fn icount(&self) -> &UartIcount {
+        &self.icount
+    }
+
+    /// Characters left to transmit.
+    pub fn chars_pending(&self) -> usize {
+        self.xmit.lock().len()
+    }
+
+    /// Room left in the transmit buffer.
+    pub fn write_room(&self) -> usize {
+        self.xmit.lock().space()
+    }
+
+    /// Take the next character to transmit, for the driver.
+    pub fn xmit_pop(&self) -> Option<u8> {
+        let c = self.xmit.lock().pop()?;
+        self.icount.tx.fetch_add(1, Ordering::Relaxed);
+        Some(c)
+    }
+
+    /// Wake up writers once the transmit buffer drained enough.
+    ///
+    /// Refer to linux: uart_write_wakeup
+    pub fn write_wakeup(&self) {
+        if self.chars_pending() < WAKEUP_CHARS {
+            self.write_wait.notify_all();
+            if let Some(tty) = *self.tty.lock() {
+                tty.write_wakeup();
+            }
+        }
+    }
+
+    /// Store a received character, for the driver.
+    ///
+    /// Refer to linux: uart_insert_char
+    pub fn insert_char(&self, c: u8) {
+        self.icount.rx.fetch_add(1, Ordering::Relaxed);
+        if !self.recv.lock().push(c) {
+            self.icount.buf_overrun.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Hand the received characters to the open tty, or wake up readers.
+    ///
+    /// The driver calls it without its locks held, the echo starts the
+    /// transmitter.
+    ///
+    /// Refer to linux: tty_flip_buffer_push
+    pub fn flip_buffer_push(&self) {
+        if let Some(tty) = *self.tty.lock() {
+            let mut buf = [0; 64];
+            loop {
+                let n = self.recv.lock().read(&mut buf);
+                if n == 0 {
+                    break;
+                }
+                tty.receive_buf(&buf[..n]);
+            }
+        }
+        self.read_wait.notify_all();
+    }
+
+    /// Drop the characters not transmitted or not read yet.
+    pub fn flush_buffers(&self) {
+        self.xmit.lock().clear();
+        self.recv.lock().clear();
+    }
+}
+
+impl Default for UartState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Queue as much of `buf` as fits for transmission, return how much.
+pub fn uart_write(port: &dyn UartOps, buf: &[u8]) -> usize {
+    let n = port.state().xmit.lock().write(buf);
+    if n > 0 {
+        port.start_tx();
+    }
+    n
+}
+
+/// Queue all of `buf` for transmission, waiting for room.
+///
+/// Returns `Erestartsys` with nothing more queued if a signal is pending.
+pub fn uart_write_all(port: &dyn UartOps, mut buf: &[u8]) -> Result {
+    let state = port.state();
+    while !buf.is_empty() {
+        state
+            .write_wait
+            .wait_until_interruptible(|| state.xmit.lock().space() > 0)?;
+        buf = &buf[uart_write(port, buf)..];
+    }
+    Ok(())
+}
+
+/// Take received characters into `buf`, return how many.
+pub fn uart_read(port: &dyn UartOps, buf: &mut [u8]) -> usize {
+    port.state().recv.lock().read(buf)
+}
+
+/// Wait until a character is received.
+///
+/// Returns `Erestartsys` if a signal is pending.
+pub fn uart_wait_read(port: &dyn UartOps) -> Result {
+    let state = port.state();
+    state
+        .read_wait
+        .wait_until_interruptible(|| !state.recv.lock().is_empty())
+}
+
+/// Write a console message with `putchar`, turning `\n` into `\r\n`.
+///
+/// Refer to linux: uart_console_write
+pub fn uart_console_write(s: &str, mut putchar: impl FnMut(u8)) {
+    for c in s.bytes() {
+        if c == b'\n' {
+            putchar(b'\r');
+        }
+        putchar(c);
+    }
+}
+
+/// The tty port of a UART line
+///
+/// Refer to linux: uart_port_activate, uart_port_shutdown
+struct UartLine {
+    port: RawSpinLockNoIrq<Option<&'static dyn UartOps>>,
+}
+
+impl UartLine {
+    const fn new() -> Self {
+        Self {
+            port: RawSpinLockNoIrq::new(None, Some("uart_line")),
+        }
+    }
+
+    fn uart(&self) -> Option<&'static dyn UartOps> {
+        *self.port.lock()
+    }
+}
+
+impl TtyPort for UartLine {
+    fn activate(&self, tty: &'static Tty) -> Result {
+        let uart = self.uart().ok_or(Error::Enodev)?;
+        *uart.state().tty.lock() = Some(tty);
+        uart.startup()
+            .inspect_err(|_| *uart.state().tty.lock() = None)
+    }
+
+    fn shutdown(&self) {
+        let Some(uart) = self.uart() else {
+            return;
+        };
+        uart.stop_rx();
+        uart.shutdown();
+        *uart.state().tty.lock() = None;
+        uart.state().flush_buffers();
+    }
+
+    fn write(&self, buf: &[u8]) -> usize {
+        self.uart().map_or(0, |uart| uart_write(uart, buf))
+    }
+
+    fn write_room(&self) -> usize {
+        self.uart().map_or(0, |uart| uart.state().write_room())
+    }
+
+    fn chars_in_buffer(&self) -> usize {
+        self.uart().map_or(0, |uart| uart.state().chars_pending())
+    }
+
+    fn set_termios(&self, termios: &Termios) {
+        let Some(uart) = self.uart() else {
+            return;
+        };
+        // TODO: baud rate 0 should drop DTR, there is no modem control.
+        if let Some(settings) = UartSettings::from_termios(termios) {
+            uart.set_termios(&settings);
+        }
+    }
+
+    fn flush_buffer(&self) {
+        if let Some(uart) = self.uart() {
+            uart.state().xmit.lock().clear();
+        }
+    }
+
+    fn stop(&self) {
+        if let Some(uart) = self.uart() {
+            uart.stop_tx();
+        }
+    }
+
+    fn start(&self) {
+        if let Some(uart) = self.uart() {
+            uart.start_tx();
+        }
+    }
+}
+
+/// A UART driver and its ports, port `line` is named `<dev_name><line>`.
+///
+/// Refer to linux: struct uart_driver
+pub struct UartDriver {
+    driver_name: &'static str,
+    dev_name: &'static str,
+    major: u32,
+    minor: u32,
+    lines: [UartLine; UART_NR],
+}
+
+impl UartDriver {
+    /// Create a driver without ports, its ttys are numbered from
+    /// `major`:`minor`.
+    pub const fn new(
+        driver_name: &'static str,
+        dev_name: &'static str,
+        major: u32,
+        minor: u32,
+    ) -> Self {
+        Self {
+            driver_name,
+            dev_name,
+            major,
+            minor,
+            lines: [const { UartLine::new() }; UART_NR],
+        }
+    }
+
+    /// Driver name
+    pub fn driver_name(&self) -> &'static str {
+        self.driver_name
+    }
+
+    /// Device name prefix, as ttyAMA.
+    pub fn dev_name(&self) -> &'static str {
+        self.dev_name
+    }
+
+    /// Add `port` as `line`.
+    pub fn add_one_port(&self, line: usize, port: &'static dyn UartOps) -> Result {
+        let mut slot = self.lines.get(line).ok_or(Error::Einval)?.port.lock();
+        if slot.is_some() {
+            return Err(Error::Ebusy);
+        }
+        *slot = Some(port);
+        Ok(())
+    }
+
+    /// Remove port `line`, return it.
+    pub fn remove_one_port(&self, line: usize) -> Option<&'static dyn UartOps> {
+        self.lines.get(line)?.port.lock().take()
+    }
+
+    /// Port `line`, if added.
+    pub fn port(&self, line: usize) -> Option<&'static dyn UartOps> {
+        self.lines.get(line)?.uart()
+    }
+}
+
+impl TtyDriver for UartDriver {
+    fn name(&self) -> &'static str {
+        self.dev_name
+    }
+
+    fn major(&self) -> u32 {
+        self.major
+    }
+
+    fn minor_start(&self) -> u32 {
+        self.minor
+    }
+
+    fn num(&self) -> usize {
+        UART_NR
+    }
+
+    fn port(&'static self, index: usize) -> Option<&'static dyn TtyPort> {
+        let line = self.lines.get(index)?;
+        line.uart()?;
+        Some(line)
+    }
+
+    fn init_termios(&self) -> Termios {
+        let mut termios = Termios::new(DEFAULT_BAUD);
+        termios.c_cflag |= CLOCAL;
+        termios
+    }
+}
+
+/// Register the ttys of `drv`.
+///
+/// Refer to linux: uart_register_driver
+pub fn uart_register_driver(drv: &'static UartDriver) -> Result {
+    tty_register_driver(drv)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schedule::task::set_test_current;
+
+    // Loops transmitted characters back, as if the interrupt came at once.
+    struct LoopbackPort {
+        state: UartState,
+    }
+
+    impl UartOps for LoopbackPort {
+        fn state(&self) -> &UartState {
+            &self.state
+        }
+        fn tx_empty(&self) -> bool {
+            true
+        }
+        fn start_tx(&self) {
+            while let Some(c) = self.state.xmit_pop() {
+                self.state.insert_char(c);
+            }
+            self.state.flip_buffer_push();
+            self.state.write_wakeup();
+        }
+        fn stop_tx(&self) {}
+        fn stop_rx(&self) {}
+        fn startup(&self) -> Result {
+            Ok(())
+        }
+        fn shutdown(&self) {}
+        fn set_termios(&self, _settings: &UartSettings) {}
+        fn port_type(&self) -> &'static str {
+            "loopback"
+        }
+        fn poll_put_char(&self, _c: u8) {}
+        fn poll_get_char(&self) -> Option<u8> {
+            None
+        }
+    }
+
+    static LOOPBACK: LoopbackPort = LoopbackPort {
+        state: UartState::new(),
+    };
+    static LOOPBACK_DRIVER: UartDriver = UartDriver::new("loopback", "ttyLB", 240, 0);
+
+    #[test]
+    fn test_serial_core() {
+        set_test_current();
+        assert!(LOOPBACK_DRIVER.add_one_port(0, &LOOPBACK).is_ok());
+        assert_eq!(
+            LOOPBACK_DRIVER.add_one_port(0, &LOOPBACK),
+            Err(Error::Ebusy)
+        );
+        assert_eq!(
+            LOOPBACK_DRIVER.add_one_port(UART_NR, &LOOPBACK),
+            Err(Error::Einval)
+        );
+        let port = LOOPBACK_DRIVER.port(0).unwrap();
+
+        assert!(uart_write_all(port, b"hello").is_ok());
+        assert!(uart_wait_read(port).is_ok());
+        let mut buf = [0; 16];
+        assert_eq!(uart_read(port, &mut buf), 5);
+        assert_eq!(&buf[..5], b"hello");
+        assert_eq!(port.state().icount().tx.load(Ordering::Relaxed), 5);
+
+        // The receive buffer overflows, the oldest characters are kept.
+        let big = [b'x'; UART_RECV_SIZE + 1];
+        assert!(uart_write_all(port, &big).is_ok());
+        assert_eq!(port.state().icount().buf_overrun.load(Ordering::Relaxed), 1);
+        port.state().flush_buffers();
+        assert_eq!(uart_read(port, &mut buf), 0);
+        assert!(LOOPBACK_DRIVER.remove_one_port(0).is_some());
+
+        let mut out = std::vec::Vec::new();
+        uart_console_write("a\nb", |c| out.push(c));
+        assert_eq!(out, b"a\r\nb");
+
+        let settings = UartSettings::parse_options("9600e7r");
+        assert_eq!(settings.baud, 9600);
+        assert_eq!(settings.parity, UartParity::Even);
+        assert_eq!(settings.bits, 7);
+        assert!(settings.crtscts);
+        assert_eq!(
+            UartSettings::parse_options(""),
+            UartSettings::new(DEFAULT_BAUD)
+        );
+    }
+}
@@ -1,6 +1,11 @@
 //! uart port define
 
+use core::ptr::{read_volatile, write_volatile};
+
+use super::serial_core::uart_console_write;
+
 /// uart port io type
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum UartPortIoType {
     /// unknown
     Unknown,
@@ -26,7 +31,10 @@ pub enum UartPortIoType {
 #[allow(dead_code)]
 pub struct UartPort {
     iobase: u64,
+    mapbase: u64,
+    membase: usize,
     irq: u32,
+    regshift: u8,
     uartclk: u32,
     fifosize: u32,
     flags: u32,
@@ -42,7 +50,10 @@ impl UartPort {
     pub const fn new_empty() -> Self {
         Self {
             iobase: 0,
+            mapbase: 0,
+            membase: 0,
             irq: 0,
+            regshift: 0,
             uartclk: 0,
             fifosize: 0,
             flags: 0,
@@ -58,4 +69,110 @@ impl UartPort {
     pub fn set_iotype(&mut self, iotype: UartPortIoType) {
         self.iotype = iotype;
     }
+
+    /// io type
+    pub fn iotype(&self) -> UartPortIoType {
+        self.iotype
+    }
+
+    /// Physical address of the registers.
+    pub fn mapbase(&self) -> u64 {
+        self.mapbase
+    }
+
+    /// set mapbase
+    pub fn set_mapbase(&mut self, mapbase: u64) {
+        self.mapbase = mapbase;
+    }
+
+    /// Virtual address of the registers, 0 until mapped.
+    pub fn membase(&self) -> usize {
+        self.membase
+    }
+
+    /// set membase
+    pub fn set_membase(&mut self, membase: usize) {
+        self.membase = membase;
+    }
+
+    /// Base clock, in Hz.
+    pub fn uartclk(&self) -> u32 {
+        self.uartclk
+    }
+
+    /// set uartclk
+    pub fn set_uartclk(&mut self, uartclk: u32) {
+        self.uartclk = uartclk;
+    }
+
+    /// Interrupt number, 0 if none.
+    pub fn irq(&self) -> u32 {
+        self.irq
+    }
+
+    /// set irq
+    pub fn set_irq(&mut self, irq: u32) {
+        self.irq = irq;
+    }
+
+    /// Registers are `1 << regshift` bytes apart.
+    pub fn regshift(&self) -> u8 {
+        self.regshift
+    }
+
+    /// set regshift
+    pub fn set_regshift(&mut self, regshift: u8) {
+        self.regshift = regshift;
+    }
+
+    /// Read register `offset`, with the width and endianness of the io type.
+    ///
+    /// Refer to linux: serial_port_in
+    pub fn read_reg(&self, offset: usize) -> u32 {
+        let addr = self.membase + (offset << self.regshift);
+        // SAFETY: the driver maps the registers at membase.
+        unsafe {
+            match self.iotype {
+                UartPortIoType::Mem => read_volatile(addr as *const u8) as u32,
+                UartPortIoType::Mem16 => read_volatile(addr as *const u16) as u32,
+                UartPortIoType::Mem32 => u32::from_le(read_volatile(addr as *const u32)),
+                UartPortIoType::Mem32Be => u32::from_be(read_volatile(addr as *const u32)),
+                _ => 0,
+            }
+        }
+    }
+
+    /// Write register `offset`, with the width and endianness of the io type.
+    ///
+    /// Refer to linux: serial_port_out
+    pub fn write_reg(&self, offset: usize, value: u32) {
+        let addr = self.membase + (offset << self.regshift);
+        // SAFETY: the driver maps the registers at membase.
+        unsafe {
+            match self.iotype {
+                UartPortIoType::Mem => write_volatile(addr as *mut u8, value as u8),
+                UartPortIoType::Mem16 => write_volatile(addr as *mut u16, value as u16),
+                UartPortIoType::Mem32 => write_volatile(addr as *mut u32, value.to_le()),
+                UartPortIoType::Mem32Be => write_volatile(addr as *mut u32, value.to_be()),
+                _ => {}
+            }
+        }
+    }
+
+    /// Size of the transmit FIFO
+    pub fn fifosize(&self) -> u32 {
+        self.fifosize
+    }
+
+    /// set fifosize
+    pub fn set_fifosize(&mut self, fifosize: u32) {
+        self.fifosize = fifosize;
+    }
+
+    /// Write a console message with `putchar`, turning `\n` into `\r\n`.
+    ///
+    /// Refer to linux: uart_console_write
+    pub fn console_write(&self, s: &str, putchar: fn(&UartPort, u8)) {
+        uart_console_write(s, |c| putchar(self, c));
+    }
 }
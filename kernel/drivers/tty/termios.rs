@@ -0,0 +1,291 @@
+//! Terminal settings
+//!
+//! Refer to linux: include/uapi/asm-generic/termbits.h, ioctls.h
+//!
+//! The layout and the flag values are the ones of the generic `struct
+//! termios`, so the ioctls can copy it as is. The baud rate only comes from
+//! the `CBAUD` bits of `c_cflag`.
+
+/// Control characters in `c_cc`
+pub const NCCS: usize = 19;
+
+/// c_cc: interrupt, sends SIGINT
+pub const VINTR: usize = 0;
+/// c_cc: quit, sends SIGQUIT
+pub const VQUIT: usize = 1;
+/// c_cc: erase a character
+pub const VERASE: usize = 2;
+/// c_cc: erase the line
+pub const VKILL: usize = 3;
+/// c_cc: end of file
+pub const VEOF: usize = 4;
+/// c_cc: non canonical read timeout, in tenths of a second
+pub const VTIME: usize = 5;
+/// c_cc: non canonical read minimum
+pub const VMIN: usize = 6;
+/// c_cc: switch character, unused
+pub const VSWTC: usize = 7;
+/// c_cc: restart the output
+pub const VSTART: usize = 8;
+/// c_cc: stop the output
+pub const VSTOP: usize = 9;
+/// c_cc: suspend, sends SIGTSTP
+pub const VSUSP: usize = 10;
+/// c_cc: additional end of line
+pub const VEOL: usize = 11;
+/// c_cc: reprint the line
+pub const VREPRINT: usize = 12;
+/// c_cc: discard the output
+pub const VDISCARD: usize = 13;
+/// c_cc: erase a word
+pub const VWERASE: usize = 14;
+/// c_cc: quote the next character
+pub const VLNEXT: usize = 15;
+/// c_cc: additional end of line
+pub const VEOL2: usize = 16;
+
+/// A control character set to this is disabled.
+pub const DISABLED_CHAR: u8 = 0;
+
+/// c_iflag: ignore breaks
+pub const IGNBRK: u32 = 0x0001;
+/// c_iflag: a break flushes the queues and sends SIGINT
+pub const BRKINT: u32 = 0x0002;
+/// c_iflag: ignore parity errors
+pub const IGNPAR: u32 = 0x0004;
+/// c_iflag: mark parity errors
+pub const PARMRK: u32 = 0x0008;
+/// c_iflag: check the parity
+pub const INPCK: u32 = 0x0010;
+/// c_iflag: strip the eighth bit
+pub const ISTRIP: u32 = 0x0020;
+/// c_iflag: translate NL to CR
+pub const INLCR: u32 = 0x0040;
+/// c_iflag: ignore CR
+pub const IGNCR: u32 = 0x0080;
+/// c_iflag: translate CR to NL
+pub const ICRNL: u32 = 0x0100;
+/// c_iflag: map upper case to lower case
+pub const IUCLC: u32 = 0x0200;
+/// c_iflag: START/STOP output control
+pub const IXON: u32 = 0x0400;
+/// c_iflag: any character restarts the output
+pub const IXANY: u32 = 0x0800;
+/// c_iflag: START/STOP input control
+pub const IXOFF: u32 = 0x1000;
+/// c_iflag: ring the bell when the input queue is full
+pub const IMAXBEL: u32 = 0x2000;
+/// c_iflag: input is UTF-8
+pub const IUTF8: u32 = 0x4000;
+
+/// c_oflag: process the output
+pub const OPOST: u32 = 0x01;
+/// c_oflag: map lower case to upper case
+pub const OLCUC: u32 = 0x02;
+/// c_oflag: translate NL to CR-NL
+pub const ONLCR: u32 = 0x04;
+/// c_oflag: translate CR to NL
+pub const OCRNL: u32 = 0x08;
+/// c_oflag: no CR at column 0
+pub const ONOCR: u32 = 0x10;
+/// c_oflag: NL does the CR function
+pub const ONLRET: u32 = 0x20;
+
+/// c_cflag: baud rate
+pub const CBAUD: u32 = 0x0000_100f;
+/// c_cflag: extended baud rates
+pub const CBAUDEX: u32 = 0x0000_1000;
+/// c_cflag: character size
+pub const CSIZE: u32 = 0x0000_0030;
+/// c_cflag: 5 bits
+pub const CS5: u32 = 0x0000_0000;
+/// c_cflag: 6 bits
+pub const CS6: u32 = 0x0000_0010;
+/// c_cflag: 7 bits
+pub const CS7: u32 = 0x0000_0020;
+/// c_cflag: 8 bits
+pub const CS8: u32 = 0x0000_0030;
+/// c_cflag: two stop bits
+pub const CSTOPB: u32 = 0x0000_0040;
+/// c_cflag: enable the receiver
+pub const CREAD: u32 = 0x0000_0080;
+/// c_cflag: parity enable
+pub const PARENB: u32 = 0x0000_0100;
+/// c_cflag: odd parity
+pub const PARODD: u32 = 0x0000_0200;
+/// c_cflag: hang up on last close
+pub const HUPCL: u32 = 0x0000_0400;
+/// c_cflag: ignore the modem lines
+pub const CLOCAL: u32 = 0x0000_0800;
+/// c_cflag: RTS/CTS flow control
+pub const CRTSCTS: u32 = 0x8000_0000;
+
+/// c_lflag: INTR, QUIT and SUSP send signals
+pub const ISIG: u32 = 0x00001;
+/// c_lflag: canonical mode, the input is read by lines
+pub const ICANON: u32 = 0x00002;
+/// c_lflag: echo the input
+pub const ECHO: u32 = 0x00008;
+/// c_lflag: ERASE and WERASE erase on screen
+pub const ECHOE: u32 = 0x00010;
+/// c_lflag: KILL echoes a NL
+pub const ECHOK: u32 = 0x00020;
+/// c_lflag: echo NL even without ECHO
+pub const ECHONL: u32 = 0x00040;
+/// c_lflag: no flush after a signal character
+pub const NOFLSH: u32 = 0x00080;
+/// c_lflag: SIGTTOU for background writes
+pub const TOSTOP: u32 = 0x00100;
+/// c_lflag: echo control characters as ^X
+pub const ECHOCTL: u32 = 0x00200;
+/// c_lflag: print erased characters
+pub const ECHOPRT: u32 = 0x00400;
+/// c_lflag: KILL erases the line on screen
+pub const ECHOKE: u32 = 0x00800;
+/// c_lflag: discard the output
+pub const FLUSHO: u32 = 0x01000;
+/// c_lflag: reprint the input at the next read
+pub const PENDIN: u32 = 0x04000;
+/// c_lflag: WERASE, LNEXT, REPRINT and DISCARD
+pub const IEXTEN: u32 = 0x08000;
+
+/// Get the settings, arg is a `*mut Termios`.
+pub const TCGETS: u32 = 0x5401;
+/// Set the settings now, arg is a `*const Termios`.
+pub const TCSETS: u32 = 0x5402;
+/// Set the settings once the output is sent.
+pub const TCSETSW: u32 = 0x5403;
+/// Set the settings once the output is sent, and flush the input.
+pub const TCSETSF: u32 = 0x5404;
+/// Flush the queues, arg is [`TCIFLUSH`], [`TCOFLUSH`] or [`TCIOFLUSH`].
+pub const TCFLSH: u32 = 0x540B;
+/// Make the terminal the controlling terminal.
+pub const TIOCSCTTY: u32 = 0x540E;
+/// Get the foreground process group, arg is a `*mut i32`.
+pub const TIOCGPGRP: u32 = 0x540F;
+/// Set the foreground process group, arg is a `*const i32`.
+pub const TIOCSPGRP: u32 = 0x5410;
+/// Characters left to send, arg is a `*mut i32`.
+pub const TIOCOUTQ: u32 = 0x5411;
+/// Get the window size, arg is a `*mut Winsize`.
+pub const TIOCGWINSZ: u32 = 0x5413;
+/// Set the window size, arg is a `*const Winsize`.
+pub const TIOCSWINSZ: u32 = 0x5414;
+/// Characters ready to read, arg is a `*mut i32`.
+pub const FIONREAD: u32 = 0x541B;
+
+/// TCFLSH: flush the input
+pub const TCIFLUSH: usize = 0;
+/// TCFLSH: flush the output
+pub const TCOFLUSH: usize = 1;
+/// TCFLSH: flush both
+pub const TCIOFLUSH: usize = 2;
+
+// The CBAUD values, B0 to B38400 then B57600 to B4000000 with CBAUDEX.
+const BAUD_TABLE: [u32; 31] = [
+    0, 50, 75, 110, 134, 150, 200, 300, 600, 1200, 1800, 2400, 4800, 9600, 19200, 38400, 57600,
+    115200, 230400, 460800, 500000, 576000, 921600, 1000000, 1152000, 1500000, 2000000, 2500000,
+    3000000, 3500000, 4000000,
+];
+
+/// Terminal settings
+///
+/// Refer to linux: struct termios
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Termios {
+    /// Input modes
+    pub c_iflag: u32,
+    /// Output modes
+    pub c_oflag: u32,
+    /// Control modes
+    pub c_cflag: u32,
+    /// Local modes
+    pub c_lflag: u32,
+    /// Line discipline
+    pub c_line: u8,
+    /// Control characters
+    pub c_cc: [u8; NCCS],
+}
+
+impl Termios {
+    /// The settings of a new terminal, in canonical mode with echo.
+    ///
+    /// Refer to linux: tty_std_termios
+    pub const fn new(baud: u32) -> Self {
+        let mut termios = Self {
+            c_iflag: ICRNL | IXON,
+            c_oflag: OPOST | ONLCR,
+            c_cflag: CS8 | CREAD | HUPCL,
+            c_lflag: ISIG | ICANON | ECHO | ECHOE | ECHOK | ECHOCTL | ECHOKE | IEXTEN,
+            c_line: 0,
+            // ^C ^\ DEL ^U ^D, VTIME 0, VMIN 1, ^Q ^S ^Z, ^R ^O ^W ^V
+            c_cc: [
+                0x03, 0x1c, 0x7f, 0x15, 0x04, 0, 1, 0, 0x11, 0x13, 0x1a, 0, 0x12, 0x0f, 0x17, 0x16,
+                0, 0, 0,
+            ],
+        };
+        termios.set_baud_rate(baud);
+        termios
+    }
+
+    /// Baud rate, 0 hangs up.
+    ///
+    /// Refer to linux: tty_termios_baud_rate
+    pub const fn baud_rate(&self) -> u32 {
+        let mut cbaud = (self.c_cflag & CBAUD) as usize;
+        if cbaud & CBAUDEX as usize != 0 {
+            cbaud = (cbaud & !(CBAUDEX as usize)) + 15;
+        }
+        if cbaud < BAUD_TABLE.len() {
+            BAUD_TABLE[cbaud]
+        } else {
+            0
+        }
+    }
+
+    /// Set the baud rate, to the closest rate `CBAUD` can tell.
+    pub const fn set_baud_rate(&mut self, baud: u32) {
+        let mut best = 0;
+        let mut i = 1;
+        while i < BAUD_TABLE.len() {
+            if BAUD_TABLE[i].abs_diff(baud) < BAUD_TABLE[best].abs_diff(baud) {
+                best = i;
+            }
+            i += 1;
+        }
+        let cbaud = if best > 15 {
+            (best - 15) as u32 | CBAUDEX
+        } else {
+            best as u32
+        };
+        self.c_cflag = (self.c_cflag & !CBAUD) | cbaud;
+    }
+
+    /// Whether `c` is the enabled control character `index`.
+    pub fn is_cc(&self, index: usize, c: u8) -> bool {
+        self.c_cc[index] != DISABLED_CHAR && self.c_cc[index] == c
+    }
+}
+
+impl Default for Termios {
+    fn default() -> Self {
+        Self::new(38400)
+    }
+}
+
+/// Window size
+///
+/// Refer to linux: struct winsize
+#[repr(C)]
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug)]
+pub struct Winsize {
+    /// Rows, in characters
+    pub ws_row: u16,
+    /// Columns, in characters
+    pub ws_col: u16,
+    /// Width, in pixels
+    pub ws_xpixel: u16,
+    /// Height, in pixels
+    pub ws_ypixel: u16,
+}
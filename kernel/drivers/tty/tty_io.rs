@@ -0,0 +1,578 @@
+//! TTY core
+//!
+//! Refer to linux: drivers/tty/tty_io.c, tty_ioctl.c, tty_port.c
+//!
+//! A [`TtyDriver`] has numbered [`TtyPort`]s, the ones of a registered
+//! driver are character devices. Opening a port gets its [`Tty`], with the
+//! settings, the window size and the N_TTY line discipline. The port is
+//! activated on the first open and shut down on the last close. Opening
+//! `/dev/console` opens the tty of the preferred console.
+//!
+//! TODO: there are no processes, the signal characters call the hook set
+//! by [`set_tty_signal_hook`] with the foreground process group.
+
+use core::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+
+use super::n_tty::{process_output, NTty};
+use super::termios::*;
+use crate::error::{Error, Result};
+use crate::fs::char_dev::{mkdev, register_chrdev_region, DevT, File, FileOperations};
+use crate::macros::initcall;
+use crate::printk::console::console_device;
+use crate::schedule::WaitQueue;
+use crate::sync::lock::{Mutex, RawSpinLockNoIrq};
+
+/// Interrupt, sent by `VINTR`
+pub const SIGINT: u32 = 2;
+/// Quit, sent by `VQUIT`
+pub const SIGQUIT: u32 = 3;
+/// Stop, sent by `VSUSP`
+pub const SIGTSTP: u32 = 20;
+/// The window size changed.
+pub const SIGWINCH: u32 = 28;
+
+/// Major number of the serial ttys
+pub const TTY_MAJOR: u32 = 4;
+/// Major number of `/dev/tty` and `/dev/console`
+pub const TTYAUX_MAJOR: u32 = 5;
+
+/// Most open ttys
+pub const NR_TTYS: usize = 16;
+/// Most registered drivers
+const NR_TTY_DRIVERS: usize = 8;
+
+/// A port of a tty driver
+///
+/// Refer to linux: struct tty_port_operations, struct tty_operations
+pub trait TtyPort: Sync {
+    /// First open, received characters go to `tty` from now on.
+    fn activate(&self, tty: &'static Tty) -> Result;
+    /// Last close
+    fn shutdown(&self);
+    /// Queue as much of `buf` as fits, return how much.
+    fn write(&self, buf: &[u8]) -> usize;
+    /// Room to write
+    fn write_room(&self) -> usize;
+    /// Characters left to send
+    fn chars_in_buffer(&self) -> usize;
+    /// Program the settings.
+    fn set_termios(&self, termios: &Termios);
+    /// Drop the characters left to send.
+    fn flush_buffer(&self) {}
+    /// Stop sending, for `VSTOP`.
+    fn stop(&self) {}
+    /// Restart sending, for `VSTART`.
+    fn start(&self) {}
+}
+
+/// A tty driver, port `index` is named `<name><index>`, device number
+/// `minor_start + index`.
+///
+/// Refer to linux: struct tty_driver
+pub trait TtyDriver: Sync {
+    /// Device name prefix, as ttyAMA.
+    fn name(&self) -> &'static str;
+    /// Major number
+    fn major(&self) -> u32;
+    /// Minor number of port 0
+    fn minor_start(&self) -> u32;
+    /// Number of ports
+    fn num(&self) -> usize;
+    /// Port `index`, if present.
+    fn port(&'static self, index: usize) -> Option<&'static dyn TtyPort>;
+    /// Settings of a newly opened port
+    fn init_termios(&self) -> Termios {
+        Termios::default()
+    }
+}
+
+#[derive(Copy, Clone)]
+struct TtyLink {
+    driver: &'static dyn TtyDriver,
+    index: usize,
+    port: &'static dyn TtyPort,
+    count: usize,
+}
+
+/// An open tty
+///
+/// Refer to linux: struct tty_struct
+pub struct Tty {
+    link: RawSpinLockNoIrq<Option<TtyLink>>,
+    termios: RawSpinLockNoIrq<Termios>,
+    winsize: RawSpinLockNoIrq<Winsize>,
+    pgrp: AtomicI32,
+    stopped: AtomicBool,
+    ldisc: NTty,
+    write_wait: WaitQueue,
+}
+
+impl Tty {
+    const fn new() -> Self {
+        Self {
+            link: RawSpinLockNoIrq::new(None, Some("tty_link")),
+            termios: RawSpinLockNoIrq::new(Termios::new(38400), Some("tty_termios")),
+            winsize: RawSpinLockNoIrq::new(
+                Winsize {
+                    ws_row: 0,
+                    ws_col: 0,
+                    ws_xpixel: 0,
+                    ws_ypixel: 0,
+                },
+                Some("tty_winsize"),
+            ),
+            pgrp: AtomicI32::new(0),
+            stopped: AtomicBool::new(false),
+            ldisc: NTty::new(),
+            write_wait: WaitQueue::new(),
+        }
+    }
+
+    /// The driver and the port index, None once closed.
+    pub fn driver(&self) -> Option<(&'static dyn TtyDriver, usize)> {
+        self.link.lock().map(|link| (link.driver, link.index))
+    }
+
+    /// The port, None once closed.
+    pub fn port(&self) -> Option<&'static dyn TtyPort> {
+        self.link.lock().map(|link| link.port)
+    }
+
+    /// Whether the tty is closed, readers and writers give up.
+    pub fn is_hung_up(&self) -> bool {
+        self.link.lock().is_none()
+    }
+
+    /// Current settings
+    pub fn termios(&self) -> Termios {
+        *self.termios.lock()
+    }
+
+    /// Change the settings.
+    ///
+    /// Refer to linux: tty_set_termios
+    pub fn set_termios(&self, termios: &Termios) {
+        let old = core::mem::replace(&mut *self.termios.lock(), *termios);
+        if let Some(port) = self.port() {
+            port.set_termios(termios);
+        }
+        self.ldisc.set_termios(&old, termios);
+        if old.c_iflag & IXON != 0 && termios.c_iflag & IXON == 0 {
+            self.start();
+        }
+    }
+
+    /// Window size
+    pub fn winsize(&self) -> Winsize {
+        *self.winsize.lock()
+    }
+
+    /// Foreground process group, 0 if none.
+    pub fn pgrp(&self) -> i32 {
+        self.pgrp.load(Ordering::Relaxed)
+    }
+
+    /// Whether the output is stopped by `VSTOP`.
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Relaxed)
+    }
+
+    /// Stop the output.
+    ///
+    /// Refer to linux: stop_tty
+    pub fn stop(&self) {
+        if self.stopped.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        if let Some(port) = self.port() {
+            port.stop();
+        }
+    }
+
+    /// Restart the output.
+    ///
+    /// Refer to linux: start_tty
+    pub fn start(&self) {
+        if !self.stopped.swap(false, Ordering::Relaxed) {
+            return;
+        }
+        if let Some(port) = self.port() {
+            port.start();
+        }
+        self.write_wait.notify_all();
+    }
+
+    /// Write to the port without output processing, for echo.
+    pub fn port_write(&self, buf: &[u8]) -> usize {
+        self.port().map_or(0, |port| port.write(buf))
+    }
+
+    /// Drop the characters left to send.
+    pub fn port_flush_buffer(&self) {
+        if let Some(port) = self.port() {
+            port.flush_buffer();
+        }
+    }
+
+    /// Send `sig` to the foreground process group.
+    pub fn signal(&self, sig: u32) {
+        let hook = *TTY_SIGNAL_HOOK.lock();
+        if let Some(hook) = hook {
+            hook(self, sig);
+        }
+    }
+
+    /// Characters received by the port, for the driver.
+    pub fn receive_buf(&self, buf: &[u8]) {
+        self.ldisc.receive_buf(self, buf);
+    }
+
+    /// The port has room to write again, for the driver.
+    pub fn write_wakeup(&self) {
+        self.write_wait.notify_all();
+    }
+
+    /// Read into `buf` through the line discipline.
+    ///
+    /// Returns `Eio` once the tty is closed, `Erestartsys` if a signal is
+    /// pending.
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        if self.is_hung_up() {
+            return Err(Error::Eio);
+        }
+        self.ldisc.read(self, buf)
+    }
+
+    /// Write `buf` with the output processing of `c_oflag`, waiting for
+    /// room and while the output is stopped.
+    ///
+    /// Returns `Eio` once the tty is closed, `Erestartsys` if a signal is
+    /// pending, unless part of `buf` is written.
+    ///
+    /// Refer to linux: n_tty_write
+    pub fn write(&self, buf: &[u8]) -> Result<usize> {
+        let mut written = 0;
+        let mut chunk = [0; 64];
+        // The output of `buf[written]` the port did not take, at the start
+        // of `chunk`.
+        let mut tail = 0;
+        while written < buf.len() {
+            let ready = self.write_wait.wait_until_interruptible(|| {
+                self.is_hung_up()
+                    || (!self.is_stopped() && self.port().is_some_and(|p| p.write_room() >= 2))
+            });
+            let port = match (ready, self.port()) {
+                (Ok(()), Some(port)) => port,
+                (Err(_), _) | (_, None) if written > 0 => break,
+                (Err(e), _) => return Err(e),
+                (_, None) => return Err(Error::Eio),
+            };
+
+            let termios = self.termios();
+            let room = port.write_room().min(chunk.len());
+            // The end in `chunk` of the output of each character.
+            let mut ends = [0; 64];
+            let mut count = 0;
+            let mut len = tail;
+            if tail > 0 {
+                ends[0] = tail;
+                count = 1;
+            }
+            for &c in &buf[written + count..] {
+                let mut out = [0; 2];
+                let n = process_output(&termios, c, &mut out);
+                if len + n > room {
+                    break;
+                }
+                chunk[len..len + n].copy_from_slice(&out[..n]);
+                len += n;
+                ends[count] = len;
+                count += 1;
+            }
+
+            // The echo of the receive interrupt may have taken the room, the
+            // characters not sent are processed again.
+            let sent = port.write(&chunk[..len]);
+            let done = ends[..count].iter().take_while(|&&end| end <= sent).count();
+            written += done;
+            tail = 0;
+            if done < count && sent > done.checked_sub(1).map_or(0, |i| ends[i]) {
+                tail = ends[done] - sent;
+                chunk.copy_within(sent..ends[done], 0);
+            }
+        }
+        Ok(written)
+    }
+
+    /// Wait until the characters left are sent.
+    ///
+    /// Refer to linux: tty_wait_until_sent
+    pub fn wait_until_sent(&self) -> Result {
+        self.write_wait
+            .wait_until_interruptible(|| self.port().is_none_or(|port| port.chars_in_buffer() == 0))
+    }
+
+    /// Terminal request, see the ioctls of [`super::termios`].
+    ///
+    /// Returns `Enotty` for an unknown `cmd`, `Efault` for a null pointer.
+    ///
+    /// # Safety
+    ///
+    /// `arg` must be what `cmd` expects, a pointer valid for it or a value.
+    ///
+    /// Refer to linux: tty_ioctl, tty_mode_ioctl
+    pub unsafe fn ioctl(&self, cmd: u32, arg: usize) -> Result<usize> {
+        match cmd {
+            TCGETS => {
+                // SAFETY: the caller passes a `*mut Termios`.
+                unsafe { put_user(arg, self.termios())? };
+            }
+            TCSETS | TCSETSW | TCSETSF => {
+                // SAFETY: the caller passes a `*const Termios`.
+                let termios: Termios = unsafe { get_user(arg)? };
+                if cmd != TCSETS {
+                    self.wait_until_sent()?;
+                }
+                if cmd == TCSETSF {
+                    self.ldisc.flush_buffer();
+                }
+                self.set_termios(&termios);
+            }
+            TCFLSH => match arg {
+                TCIFLUSH => self.ldisc.flush_buffer(),
+                TCOFLUSH => self.port_flush_buffer(),
+                TCIOFLUSH => {
+                    self.ldisc.flush_buffer();
+                    self.port_flush_buffer();
+                }
+                _ => return Err(Error::Einval),
+            },
+            // TODO: there are no sessions, every tty is controlling.
+            TIOCSCTTY => {}
+            TIOCGPGRP => {
+                // SAFETY: the caller passes a `*mut i32`.
+                unsafe { put_user(arg, self.pgrp())? };
+            }
+            TIOCSPGRP => {
+                // SAFETY: the caller passes a `*const i32`.
+                let pgrp: i32 = unsafe { get_user(arg)? };
+                if pgrp < 0 {
+                    return Err(Error::Einval);
+                }
+                self.pgrp.store(pgrp, Ordering::Relaxed);
+            }
+            TIOCOUTQ => {
+                let n = self.port().map_or(0, |port| port.chars_in_buffer());
+                // SAFETY: the caller passes a `*mut i32`.
+                unsafe { put_user(arg, n as i32)? };
+            }
+            FIONREAD => {
+                let n = self.ldisc.chars_readable(&self.termios());
+                // SAFETY: the caller passes a `*mut i32`.
+                unsafe { put_user(arg, n as i32)? };
+            }
+            TIOCGWINSZ => {
+                // SAFETY: the caller passes a `*mut Winsize`.
+                unsafe { put_user(arg, self.winsize())? };
+            }
+            TIOCSWINSZ => {
+                // SAFETY: the caller passes a `*const Winsize`.
+                let winsize: Winsize = unsafe { get_user(arg)? };
+                let old = core::mem::replace(&mut *self.winsize.lock(), winsize);
+                if old != winsize {
+                    self.signal(SIGWINCH);
+                }
+            }
+            _ => return Err(Error::Enotty),
+        }
+        Ok(0)
+    }
+}
+
+// TODO: there is no user space, the ioctl arguments are kernel pointers.
+unsafe fn put_user<T>(arg: usize, value: T) -> Result {
+    let ptr = arg as *mut T;
+    if ptr.is_null() {
+        return Err(Error::Efault);
+    }
+    // SAFETY: the caller passes a pointer valid for T.
+    unsafe { ptr.write_unaligned(value) };
+    Ok(())
+}
+
+unsafe fn get_user<T>(arg: usize) -> Result<T> {
+    let ptr = arg as *const T;
+    if ptr.is_null() {
+        return Err(Error::Efault);
+    }
+    // SAFETY: the caller passes a pointer valid for T.
+    Ok(unsafe { ptr.read_unaligned() })
+}
+
+static TTY_TABLE: [Tty; NR_TTYS] = [const { Tty::new() }; NR_TTYS];
+
+// Serializes the opens and the closes, the ports activate and shut down
+// under it.
+static TTY_MUTEX: Mutex<()> = Mutex::new((), Some("tty_mutex"));
+
+static TTY_SIGNAL_HOOK: RawSpinLockNoIrq<Option<fn(&Tty, u32)>> =
+    RawSpinLockNoIrq::new(None, Some("tty_signal_hook"));
+
+/// Call `hook` with the tty and the signal for the signal characters.
+///
+/// The characters are received in the interrupt handler of the port, so
+/// `hook` runs in interrupt context and must not sleep.
+pub fn set_tty_signal_hook(hook: fn(&Tty, u32)) {
+    *TTY_SIGNAL_HOOK.lock() = Some(hook);
+}
+
+/// Open port `index` of `driver`, activate it on the first open.
+///
+/// Returns `Enodev` if there is no such port, `Enfile` if too many ttys
+/// are open.
+///
+/// Refer to linux: tty_open
+pub fn tty_open(driver: &'static dyn TtyDriver, index: usize) -> Result<&'static Tty> {
+    let _guard = TTY_MUTEX.lock();
+    for tty in TTY_TABLE.iter() {
+        let mut link = tty.link.lock();
+        if let Some(link) = link
+            .as_mut()
+            .filter(|link| core::ptr::addr_eq(link.driver, driver) && link.index == index)
+        {
+            link.count += 1;
+            return Ok(tty);
+        }
+    }
+
+    let port = driver.port(index).ok_or(Error::Enodev)?;
+    let tty = TTY_TABLE
+        .iter()
+        .find(|tty| tty.link.lock().is_none())
+        .ok_or(Error::Enfile)?;
+    let termios = driver.init_termios();
+    *tty.termios.lock() = termios;
+    *tty.winsize.lock() = Winsize::default();
+    tty.pgrp.store(0, Ordering::Relaxed);
+    tty.stopped.store(false, Ordering::Relaxed);
+    tty.ldisc.flush_buffer();
+    *tty.link.lock() = Some(TtyLink {
+        driver,
+        index,
+        port,
+        count: 1,
+    });
+
+    if let Err(e) = port.activate(tty) {
+        *tty.link.lock() = None;
+        return Err(e);
+    }
+    port.set_termios(&termios);
+    Ok(tty)
+}
+
+/// Close `tty`, shut its port down on the last close.
+///
+/// Refer to linux: tty_release
+pub fn tty_release(tty: &'static Tty) {
+    let _guard = TTY_MUTEX.lock();
+    let port = {
+        let mut link = tty.link.lock();
+        let Some(l) = link.as_mut() else {
+            return;
+        };
+        l.count -= 1;
+        if l.count > 0 {
+            return;
+        }
+        let port = l.port;
+        *link = None;
+        port
+    };
+    port.shutdown();
+    // Readers and writers see the hang up.
+    tty.ldisc.wakeup_readers();
+    tty.write_wait.notify_all();
+}
+
+static TTY_DRIVERS: RawSpinLockNoIrq<[Option<&'static dyn TtyDriver>; NR_TTY_DRIVERS]> =
+    RawSpinLockNoIrq::new([None; NR_TTY_DRIVERS], Some("tty_drivers"));
+
+/// Register the character devices of `driver`.
+///
+/// Refer to linux: tty_register_driver
+pub fn tty_register_driver(driver: &'static dyn TtyDriver) -> Result {
+    let mut drivers = TTY_DRIVERS.lock();
+    let slot = drivers
+        .iter_mut()
+        .find(|d| d.is_none())
+        .ok_or(Error::Enospc)?;
+    register_chrdev_region(
+        mkdev(driver.major(), driver.minor_start()),
+        driver.num() as u32,
+        driver.name(),
+        &TTY_FOPS,
+    )?;
+    *slot = Some(driver);
+    Ok(())
+}
+
+// Refer to linux: tty_lookup_driver
+fn tty_lookup_driver(dev: DevT) -> Result<(&'static dyn TtyDriver, usize)> {
+    if dev == mkdev(TTYAUX_MAJOR, 1) {
+        return console_device().ok_or(Error::Enodev);
+    }
+    let drivers = TTY_DRIVERS.lock();
+    drivers
+        .iter()
+        .flatten()
+        .find_map(|driver| {
+            let base = mkdev(driver.major(), driver.minor_start());
+            let index = dev.wrapping_sub(base) as usize;
+            (dev >= base && index < driver.num()).then_some((*driver, index))
+        })
+        .ok_or(Error::Enodev)
+}
+
+struct TtyFops;
+
+static TTY_FOPS: TtyFops = TtyFops;
+
+fn file_tty(file: &File) -> &'static Tty {
+    // SAFETY: open set it to a tty of the table.
+    unsafe { &*(file.private_data() as *const Tty) }
+}
+
+impl FileOperations for TtyFops {
+    fn open(&self, file: &mut File) -> Result {
+        let (driver, index) = tty_lookup_driver(file.dev())?;
+        let tty = tty_open(driver, index)?;
+        file.set_private_data(tty as *const Tty as usize);
+        Ok(())
+    }
+
+    fn release(&self, file: &File) {
+        tty_release(file_tty(file));
+    }
+
+    fn read(&self, file: &File, buf: &mut [u8]) -> Result<usize> {
+        file_tty(file).read(buf)
+    }
+
+    fn write(&self, file: &File, buf: &[u8]) -> Result<usize> {
+        file_tty(file).write(buf)
+    }
+
+    unsafe fn ioctl(&self, file: &File, cmd: u32, arg: usize) -> Result<usize> {
+        // SAFETY: the caller passes what cmd expects.
+        unsafe { file_tty(file).ioctl(cmd, arg) }
+    }
+}
+
+/// Register `/dev/console`, it opens the tty of the preferred console.
+///
+/// Refer to linux: tty_init
+#[initcall(fs)]
+pub fn tty_init() -> Result {
+    register_chrdev_region(mkdev(TTYAUX_MAJOR, 1), 1, "/dev/console", &TTY_FOPS)
+}
@@ -170,6 +170,12 @@ pub enum Error {
     Elibbad = 80,
     /// .lib section in a.out corrupted.
     Elibscn = 81,
+
+    // Kernel internal, never seen by user space.
+    /// Restart the interrupted system call.
+    Erestartsys = 512,
+    /// Driver requests probe retry.
+    Eprobedefer = 517,
 }
 
 impl Error {
@@ -200,6 +206,7 @@ impl Error {
             Error::Einval => "Einval",
             Error::Enfile => "Enfile",
             Error::Emfile => "Emfile",
+            Error::Eprobedefer => "Eprobedefer",
             _ => "Unknown",
         }
     }
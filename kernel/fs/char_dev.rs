@@ -0,0 +1,211 @@
+//! Character devices
+//!
+//! Refer to linux: fs/char_dev.c
+//!
+//! A driver registers the [`FileOperations`] of a range of device numbers,
+//! [`chrdev_open`] finds them and opens a [`File`]. The file is released
+//! when dropped.
+
+use crate::error::{Error, Result};
+use crate::sync::lock::RawSpinLockNoIrq;
+
+/// A device number, the major number in the high 12 bits.
+pub type DevT = u32;
+
+/// Bits of the minor number
+pub const MINORBITS: u32 = 20;
+/// Mask of the minor number
+pub const MINORMASK: u32 = (1 << MINORBITS) - 1;
+
+/// Make a device number.
+pub const fn mkdev(major: u32, minor: u32) -> DevT {
+    (major << MINORBITS) | minor
+}
+
+/// Major number of `dev`
+pub const fn major(dev: DevT) -> u32 {
+    dev >> MINORBITS
+}
+
+/// Minor number of `dev`
+pub const fn minor(dev: DevT) -> u32 {
+    dev & MINORMASK
+}
+
+/// File operations of a character device
+///
+/// Refer to linux: struct file_operations
+pub trait FileOperations: Sync {
+    /// Open the device, the driver may keep its data in the file.
+    fn open(&self, file: &mut File) -> Result;
+    /// The last reference to the file is gone.
+    fn release(&self, _file: &File) {}
+    /// Read into `buf`, return how much.
+    fn read(&self, _file: &File, _buf: &mut [u8]) -> Result<usize> {
+        Err(Error::Einval)
+    }
+    /// Write `buf`, return how much.
+    fn write(&self, _file: &File, _buf: &[u8]) -> Result<usize> {
+        Err(Error::Einval)
+    }
+    /// Device specific request
+    ///
+    /// # Safety
+    ///
+    /// `arg` must be what `cmd` expects, usually a pointer valid for it.
+    unsafe fn ioctl(&self, _file: &File, _cmd: u32, _arg: usize) -> Result<usize> {
+        Err(Error::Enotty)
+    }
+}
+
+/// An open character device
+///
+/// Refer to linux: struct file
+pub struct File {
+    dev: DevT,
+    ops: &'static dyn FileOperations,
+    private_data: usize,
+}
+
+impl File {
+    /// Device number
+    pub fn dev(&self) -> DevT {
+        self.dev
+    }
+
+    /// Driver data, 0 until the driver sets it.
+    pub fn private_data(&self) -> usize {
+        self.private_data
+    }
+
+    /// Set the driver data, on open.
+    pub fn set_private_data(&mut self, data: usize) {
+        self.private_data = data;
+    }
+
+    /// Read into `buf`, return how much.
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        self.ops.read(self, buf)
+    }
+
+    /// Write `buf`, return how much.
+    pub fn write(&self, buf: &[u8]) -> Result<usize> {
+        self.ops.write(self, buf)
+    }
+
+    /// Device specific request
+    ///
+    /// # Safety
+    ///
+    /// `arg` must be what `cmd` expects, usually a pointer valid for it.
+    pub unsafe fn ioctl(&self, cmd: u32, arg: usize) -> Result<usize> {
+        // SAFETY: the caller passes what cmd expects.
+        unsafe { self.ops.ioctl(self, cmd, arg) }
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        self.ops.release(self);
+    }
+}
+
+/// Most registered device number ranges
+const MAX_CHRDEV: usize = 32;
+
+#[derive(Copy, Clone)]
+struct CharDeviceStruct {
+    dev: DevT,
+    count: u32,
+    name: &'static str,
+    ops: &'static dyn FileOperations,
+}
+
+impl CharDeviceStruct {
+    fn contains(&self, dev: DevT) -> bool {
+        dev >= self.dev && dev - self.dev < self.count
+    }
+
+    fn overlaps(&self, dev: DevT, count: u32) -> bool {
+        dev < self.dev + self.count && self.dev < dev + count
+    }
+}
+
+static CHRDEVS: RawSpinLockNoIrq<[Option<CharDeviceStruct>; MAX_CHRDEV]> =
+    RawSpinLockNoIrq::new([None; MAX_CHRDEV], Some("chrdevs"));
+
+/// Register `count` device numbers from `dev` to `ops`.
+///
+/// Returns `Ebusy` if some of them are already registered.
+pub fn register_chrdev_region(
+    dev: DevT,
+    count: u32,
+    name: &'static str,
+    ops: &'static dyn FileOperations,
+) -> Result {
+    if count == 0 || minor(dev) + count - 1 > MINORMASK {
+        return Err(Error::Einval);
+    }
+    let mut chrdevs = CHRDEVS.lock();
+    if chrdevs.iter().flatten().any(|cd| cd.overlaps(dev, count)) {
+        return Err(Error::Ebusy);
+    }
+    let slot = chrdevs
+        .iter_mut()
+        .find(|cd| cd.is_none())
+        .ok_or(Error::Enospc)?;
+    *slot = Some(CharDeviceStruct {
+        dev,
+        count,
+        name,
+        ops,
+    });
+    Ok(())
+}
+
+/// Unregister the device numbers registered from `dev`.
+pub fn unregister_chrdev_region(dev: DevT) {
+    let mut chrdevs = CHRDEVS.lock();
+    if let Some(slot) = chrdevs
+        .iter_mut()
+        .find(|cd| cd.is_some_and(|cd| cd.dev == dev))
+    {
+        *slot = None;
+    }
+}
+
+/// Name of the driver of `dev`
+pub fn chrdev_name(dev: DevT) -> Option<&'static str> {
+    let chrdevs = CHRDEVS.lock();
+    chrdevs
+        .iter()
+        .flatten()
+        .find(|cd| cd.contains(dev))
+        .map(|cd| cd.name)
+}
+
+/// Open device `dev`.
+///
+/// Returns `Enxio` if no driver registered it.
+pub fn chrdev_open(dev: DevT) -> Result<File> {
+    let ops = {
+        let chrdevs = CHRDEVS.lock();
+        chrdevs
+            .iter()
+            .flatten()
+            .find(|cd| cd.contains(dev))
+            .ok_or(Error::Enxio)?
+            .ops
+    };
+    let mut file = File {
+        dev,
+        ops,
+        private_data: 0,
+    };
+    if let Err(e) = ops.open(&mut file) {
+        // Not opened, nothing to release.
+        core::mem::forget(file);
+        return Err(e);
+    }
+    Ok(file)
+}
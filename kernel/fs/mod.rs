@@ -0,0 +1,8 @@
+//! File systems
+//!
+//! Refer to linux: fs/
+//!
+//! TODO: there is no VFS yet, character devices are opened by number with
+//! [`char_dev::chrdev_open`].
+
+pub mod char_dev;
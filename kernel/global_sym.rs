@@ -52,10 +52,34 @@ unsafe extern "C" {
     pub fn __setup_start();
     /// obs_kernel_param end address
     pub fn __setup_end();
+    /// kernel_param start address
+    pub fn __start___param();
+    /// kernel_param end address
+    pub fn __stop___param();
     /// early con table
     pub fn __earlycon_table();
     /// early con table end
     pub fn __earlycon_table_end();
+    /// early initcalls start
+    pub fn __initcall_start();
+    /// level 0 initcalls start
+    pub fn __initcall0_start();
+    /// level 1 initcalls start
+    pub fn __initcall1_start();
+    /// level 2 initcalls start
+    pub fn __initcall2_start();
+    /// level 3 initcalls start
+    pub fn __initcall3_start();
+    /// level 4 initcalls start
+    pub fn __initcall4_start();
+    /// level 5 initcalls start
+    pub fn __initcall5_start();
+    /// level 6 initcalls start
+    pub fn __initcall6_start();
+    /// level 7 initcalls start
+    pub fn __initcall7_start();
+    /// initcalls end
+    pub fn __initcall_end();
     /// init_stack define in vmrynux.rs
     pub fn init_stack();
 }
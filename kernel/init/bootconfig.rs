@@ -0,0 +1,92 @@
+//! Load the bootconfig appended to the initrd
+//!
+//! Refer to linux: init/main.c
+
+use crate::drivers::fdt::GLOBAL_FDT;
+use crate::early_setup_param;
+use crate::init::command_line::split_init_args;
+use crate::init::GLOBAL_COMMAND_LINE;
+use crate::klib::bootconfig::{
+    find_bootconfig, Xbc, XbcError, BOOTCONFIG_ALIGN, BOOTCONFIG_MAGIC, XBC_DATA_MAX,
+};
+use crate::macros::section_init_text;
+use crate::mm::early_ioremap::{early_memremap_ro, early_memunmap};
+use crate::mm::PhysAddr;
+use crate::param::{ParamHandleErr, ParamParser};
+use crate::sync::lock::RawSpinLockNoIrq;
+
+// The most of the initrd end to map: the data, its size and checksum, the
+// magic and the padding.
+const BOOTCONFIG_TAIL_MAX: usize = XBC_DATA_MAX + 8 + BOOTCONFIG_MAGIC.len() + BOOTCONFIG_ALIGN - 1;
+
+/// The bootconfig, empty if none is loaded.
+pub static BOOT_CONFIG: RawSpinLockNoIrq<Xbc> =
+    RawSpinLockNoIrq::new(Xbc::new(), Some("BOOT_CONFIG"));
+
+// Done by setup_boot_config, keep it away from init.
+fn bootconfig_setup(_val: Option<&str>) -> Result<(), ParamHandleErr> {
+    Ok(())
+}
+
+early_setup_param!(BOOTCONFIG_PARAM, "bootconfig", bootconfig_setup);
+
+enum LoadErr {
+    NotFound,
+    Initrd(&'static str),
+    Init(XbcError),
+}
+
+// Refer to linux: get_boot_config_from_initrd
+#[section_init_text]
+fn load_from_initrd(xbc: &mut Xbc) -> Result<usize, LoadErr> {
+    let (start, end) = GLOBAL_FDT
+        .chosen()
+        .and_then(|chosen| chosen.initrd())
+        .ok_or(LoadErr::NotFound)?;
+    let size = (end - start).min(BOOTCONFIG_TAIL_MAX);
+    let virt = early_memremap_ro(PhysAddr::from(end - size), size).ok_or(LoadErr::NotFound)?;
+    // SAFETY: the end of the initrd is mapped until early_memunmap.
+    let tail = unsafe { core::slice::from_raw_parts(virt.as_usize() as *const u8, size) };
+    let ret = match find_bootconfig(tail) {
+        None => Err(LoadErr::NotFound),
+        Some(Err(msg)) => Err(LoadErr::Initrd(msg)),
+        Some(Ok(data)) => xbc.init(data).map_err(LoadErr::Init),
+    };
+    early_memunmap(virt, size);
+    ret
+}
+
+/// Load the bootconfig at the end of the initrd if `bootconfig` is given
+/// or Kconfig BOOT_CONFIG_FORCE is set, and merge it into the command line.
+///
+/// Refer to linux: setup_boot_config
+#[section_init_text]
+pub(crate) fn setup_boot_config() {
+    let found = {
+        let cmdline = GLOBAL_COMMAND_LINE.lock();
+        let (kernel_args, _) = split_init_args(cmdline.as_str());
+        ParamParser::new(kernel_args).any(|(param, _)| param == "bootconfig")
+    };
+    if !found && !cfg!(CONFIG_BOOT_CONFIG_FORCE) {
+        return;
+    }
+
+    let mut xbc = BOOT_CONFIG.lock();
+    match load_from_initrd(&mut xbc) {
+        Ok(nodes) => {
+            crate::pr_info!("Load bootconfig: {} bytes {} nodes\n", xbc.size(), nodes);
+            GLOBAL_COMMAND_LINE.lock().merge_bootconfig(&xbc);
+        }
+        Err(LoadErr::NotFound) if found => {
+            crate::pr_err!("'bootconfig' found on command line, but no bootconfig found\n");
+        }
+        Err(LoadErr::NotFound) => {
+            crate::pr_info!("No bootconfig data provided, so skipping bootconfig\n");
+        }
+        Err(LoadErr::Initrd(msg)) => crate::pr_err!("{}\n", msg),
+        Err(LoadErr::Init(e)) if e.pos.is_none() => {
+            crate::pr_err!("Failed to init bootconfig: {}.\n", e.msg);
+        }
+        Err(LoadErr::Init(e)) => crate::pr_err!("Failed to parse bootconfig: {}.\n", e),
+    }
+}
@@ -1,14 +1,54 @@
 //! Command line parsing
 
+use core::fmt;
+
+#[cfg(not(test))]
 use crate::arch::arm64::early_debug::early_uart_put_str;
+#[cfg(not(test))]
 use crate::drivers::fdt::GLOBAL_FDT;
+#[cfg(CONFIG_BOOT_CONFIG)]
+use crate::klib::bootconfig::Xbc;
 use crate::macros::section_init_text;
+use crate::param::kernel_param::{kernel_params, parse_args};
 use crate::param::obs_param::for_each_setup_param;
-use crate::param::ParamParser;
+use crate::param::{report_param_err, ParamHandleErr, ParamParser};
 use crate::sync::lock::RawSpinLockNoIrq;
 use crate::types::OnceCell;
 
 const COMMAND_LINE_SIZE: usize = 2048;
+/// The built-in command line, Kconfig CMDLINE
+const BUILTIN_CMDLINE: &str = match option_env!("CONFIG_CMDLINE") {
+    Some(cmdline) => cmdline,
+    None => "",
+};
+const _: () = assert!(
+    BUILTIN_CMDLINE.len() < COMMAND_LINE_SIZE,
+    "CONFIG_CMDLINE is too long"
+);
+
+/// Where the command line comes from, Kconfig CMDLINE_FROM_BOOTLOADER,
+/// CMDLINE_EXTEND or CMDLINE_FORCE
+#[derive(Clone, Copy)]
+enum CmdlineSource {
+    /// The bootloader arguments, the built-in ones without them
+    FromBootloader,
+    /// The bootloader arguments followed by the built-in ones
+    Extend,
+    /// The built-in arguments only
+    Force,
+}
+
+#[cfg(not(test))]
+const CMDLINE_SOURCE: CmdlineSource = if cfg!(CONFIG_CMDLINE_FORCE) {
+    CmdlineSource::Force
+} else if cfg!(CONFIG_CMDLINE_EXTEND) {
+    CmdlineSource::Extend
+} else {
+    CmdlineSource::FromBootloader
+};
+
+const MAX_INIT_ARGS: usize = 32;
+const MAX_INIT_ENVS: usize = 32;
 
 /// Command line Parser
 pub struct CommandLine {
@@ -18,6 +58,104 @@ pub struct CommandLine {
 }
 
 impl CommandLine {
+    const fn empty() -> Self {
+        Self {
+            arch_boot_cmdline: [0; COMMAND_LINE_SIZE],
+            parsed_early_options: false,
+            used: 0,
+        }
+    }
+
+    /// Merge the bootloader arguments and the built-in command line as
+    /// `source` tells.
+    ///
+    /// Refer to linux: early_init_dt_scan_chosen
+    fn merge(bootargs: Option<&str>, builtin: &str, source: CmdlineSource) -> Self {
+        let mut cmdline = Self::empty();
+        let fits = match source {
+            CmdlineSource::Force => cmdline.append(builtin),
+            CmdlineSource::Extend => {
+                cmdline.append(bootargs.unwrap_or("")) && cmdline.append(builtin)
+            }
+            // No arguments from the bootloader, use the built-in ones.
+            CmdlineSource::FromBootloader => {
+                match bootargs.filter(|args| !args.trim().is_empty()) {
+                    Some(args) => cmdline.append(args),
+                    None => cmdline.append(builtin),
+                }
+            }
+        };
+        if !fits {
+            cmdline.warn_truncated();
+        }
+        cmdline
+    }
+
+    /// Put the `kernel` keys of the bootconfig before the command line,
+    /// and its `init` keys first after `--`.
+    ///
+    /// Refer to linux: setup_command_line
+    #[cfg(CONFIG_BOOT_CONFIG)]
+    #[section_init_text]
+    pub fn merge_bootconfig(&mut self, xbc: &Xbc) {
+        let mut merged = Self::empty();
+        merged.parsed_early_options = self.parsed_early_options;
+        let (kernel_args, init_args) = split_init_args(self.as_str());
+
+        // A full buffer fails to append too, and is cut at a space.
+        let mut buf = ArgsBuf::new();
+        let _ = xbc.make_cmdline("kernel", &mut buf);
+        let mut fits = merged.append(buf.as_str()) & merged.append(kernel_args);
+        buf.clear();
+        let _ = xbc.make_cmdline("init", &mut buf);
+        if init_args.is_some() || !buf.as_str().is_empty() {
+            fits &= merged.append("--")
+                & merged.append(buf.as_str())
+                & merged.append(init_args.unwrap_or(""));
+        }
+        if !fits {
+            merged.warn_truncated();
+        }
+        *self = merged;
+    }
+
+    fn warn_truncated(&self) {
+        #[cfg(not(test))]
+        early_uart_put_str("command line truncated\n");
+        crate::pr_warn!("Command line too long, truncated to {} bytes\n", self.used);
+    }
+
+    // Append `args` after a space. If it does not fit, the parameters that
+    // fit are kept and false is returned.
+    fn append(&mut self, args: &str) -> bool {
+        let args = args.trim();
+        if args.is_empty() {
+            return true;
+        }
+        let sep = usize::from(self.used > 0);
+        // Keep room for a NUL, as linux.
+        let room = (COMMAND_LINE_SIZE - 1 - self.used).saturating_sub(sep);
+        let (len, fits) = match args.len() <= room {
+            true => (args.len(), true),
+            false => {
+                let cut = args.as_bytes()[..=room]
+                    .iter()
+                    .rposition(|b| b.is_ascii_whitespace())
+                    .unwrap_or(0);
+                (args[..cut].trim_end().len(), false)
+            }
+        };
+        if len > 0 {
+            if sep == 1 {
+                self.arch_boot_cmdline[self.used] = b' ';
+            }
+            let start = self.used + sep;
+            self.arch_boot_cmdline[start..start + len].copy_from_slice(&args.as_bytes()[..len]);
+            self.used = start + len;
+        }
+        fits
+    }
+
     /// Get the command line as a string
     pub fn as_str(&self) -> &str {
         core::str::from_utf8(&self.arch_boot_cmdline[..self.used]).unwrap()
@@ -33,39 +171,390 @@ impl CommandLine {
         let tmp_cmdline = self.as_str();
         let parser = ParamParser::new(tmp_cmdline);
 
+        // Malformed parameters are skipped, the late pass reports them.
         for (param, val) in parser {
             for_each_setup_param(|p| {
-                if p.name == param {
-                    let _ = (p.func)(val);
+                if !p.early || p.name != param {
+                    return;
+                }
+                if let Err(e) = (p.func)(val) {
+                    report_param_err("Early options", param, val, e);
                 }
             });
         }
 
         self.parsed_early_options = true;
     }
+
+    #[section_init_text]
+    /// Parse the typed and the late setup parameters, once the allocators
+    /// are up. The unknown ones go to init.
+    ///
+    /// Refer to linux: start_kernel
+    pub fn parse_late_options(&mut self) {
+        let after_dashes = parse_args(
+            "Booting kernel",
+            self.as_str(),
+            kernel_params(),
+            unknown_bootoption,
+        );
+
+        let init_args = INIT_ARGS.lock();
+        if init_args.has_unknown() {
+            crate::pr_notice!(
+                "Unknown kernel command line parameters \"{}\", will be passed to user space.\n",
+                UnknownOptions(&init_args)
+            );
+        }
+        drop(init_args);
+
+        if let Some(args) = after_dashes {
+            let _ = parse_args("Setting init args", args, &[], |param, val| {
+                INIT_ARGS.lock().push_arg(param, val);
+                Ok(())
+            });
+        }
+    }
+}
+
+/// Split `args` at the `--` before the init arguments.
+pub(crate) fn split_init_args(args: &str) -> (&str, Option<&str>) {
+    let mut parser = ParamParser::new(args);
+    while let Some(ret) = parser.next_param() {
+        if let Ok((offset, "--", None)) = ret {
+            return (&args[..offset], Some(parser.remaining()));
+        }
+    }
+    (args, None)
+}
+
+// Arguments made from the bootconfig, cut short when full.
+#[cfg(CONFIG_BOOT_CONFIG)]
+struct ArgsBuf {
+    buf: [u8; COMMAND_LINE_SIZE],
+    used: usize,
 }
 
+#[cfg(CONFIG_BOOT_CONFIG)]
+impl ArgsBuf {
+    const fn new() -> Self {
+        Self {
+            buf: [0; COMMAND_LINE_SIZE],
+            used: 0,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.used = 0;
+    }
+
+    fn as_str(&self) -> &str {
+        // It may be cut in a character when full.
+        match core::str::from_utf8(&self.buf[..self.used]) {
+            Ok(s) => s,
+            Err(e) => core::str::from_utf8(&self.buf[..e.valid_up_to()]).unwrap(),
+        }
+    }
+}
+
+#[cfg(CONFIG_BOOT_CONFIG)]
+impl fmt::Write for ArgsBuf {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let len = s.len().min(COMMAND_LINE_SIZE - self.used);
+        self.buf[self.used..self.used + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.used += len;
+        match len == s.len() {
+            true => Ok(()),
+            false => Err(fmt::Error),
+        }
+    }
+}
+
+// Refer to linux: obsolete_checksetup
+fn obsolete_checksetup(param: &str, val: Option<&str>) -> bool {
+    let mut had_early_param = false;
+    let mut handled = false;
+    for_each_setup_param(|p| {
+        if handled || p.name != param {
+            return;
+        }
+        if p.early {
+            // Already done in parse_early_options
+            had_early_param = true;
+        } else if (p.func)(val).is_ok() {
+            handled = true;
+        }
+    });
+    had_early_param || handled
+}
+
+// Refer to linux: unknown_bootoption
+fn unknown_bootoption(param: &str, val: Option<&str>) -> Result<(), ParamHandleErr> {
+    if obsolete_checksetup(param, val) {
+        return Ok(());
+    }
+
+    // Unused module parameter
+    if param.contains('.') {
+        return Ok(());
+    }
+
+    let mut init_args = INIT_ARGS.lock();
+    match val {
+        Some(val) => init_args.push_env(param, val),
+        None => init_args.push_arg(param, None),
+    }
+    Ok(())
+}
+
+/// The arguments and the environment passed to init
+///
+/// Refer to linux: argv_init, envp_init
+pub struct InitArgs {
+    buf: [u8; COMMAND_LINE_SIZE],
+    used: usize,
+    argv: [(usize, usize); MAX_INIT_ARGS],
+    argc: usize,
+    envp: [(usize, usize); MAX_INIT_ENVS],
+    envc: usize,
+}
+
+impl InitArgs {
+    const DEFAULT_ARGV: [&'static str; 1] = ["init"];
+    const DEFAULT_ENVP: [&'static str; 2] = ["HOME=/", "TERM=linux"];
+
+    const fn new() -> Self {
+        let mut args = Self {
+            buf: [0; COMMAND_LINE_SIZE],
+            used: 0,
+            argv: [(0, 0); MAX_INIT_ARGS],
+            argc: 0,
+            envp: [(0, 0); MAX_INIT_ENVS],
+            envc: 0,
+        };
+        // The defaults always fit.
+        while args.argc < Self::DEFAULT_ARGV.len() {
+            if let Some(arg) = args.store(Self::DEFAULT_ARGV[args.argc], None) {
+                args.argv[args.argc] = arg;
+            }
+            args.argc += 1;
+        }
+        while args.envc < Self::DEFAULT_ENVP.len() {
+            if let Some(env) = args.store(Self::DEFAULT_ENVP[args.envc], None) {
+                args.envp[args.envc] = env;
+            }
+            args.envc += 1;
+        }
+        args
+    }
+
+    // Copy `param` or `param=val` to buf, None if it is full.
+    const fn store(&mut self, param: &str, val: Option<&str>) -> Option<(usize, usize)> {
+        let param = param.as_bytes();
+        let len = match val {
+            Some(val) => param.len() + 1 + val.len(),
+            None => param.len(),
+        };
+        if self.used + len > COMMAND_LINE_SIZE {
+            return None;
+        }
+        let start = self.used;
+        let mut i = 0;
+        while i < param.len() {
+            self.buf[start + i] = param[i];
+            i += 1;
+        }
+        if let Some(val) = val {
+            let val = val.as_bytes();
+            self.buf[start + i] = b'=';
+            i += 1;
+            let mut j = 0;
+            while j < val.len() {
+                self.buf[start + i + j] = val[j];
+                j += 1;
+            }
+        }
+        self.used += len;
+        Some((start, len))
+    }
+
+    fn get(&self, (start, len): (usize, usize)) -> &str {
+        core::str::from_utf8(&self.buf[start..start + len]).unwrap_or("")
+    }
+
+    fn push_arg(&mut self, param: &str, val: Option<&str>) {
+        if self.argc == MAX_INIT_ARGS {
+            crate::pr_warn!("Too many boot init vars at `{}'\n", param);
+            return;
+        }
+        let Some(arg) = self.store(param, val) else {
+            crate::pr_warn!("Too long boot init vars at `{}'\n", param);
+            return;
+        };
+        self.argv[self.argc] = arg;
+        self.argc += 1;
+    }
+
+    fn push_env(&mut self, param: &str, val: &str) {
+        // Env var already there? Replace it.
+        let index = (0..self.envc)
+            .find(|&i| self.get(self.envp[i]).split_once('=').map(|(name, _)| name) == Some(param));
+        if index.is_none() && self.envc == MAX_INIT_ENVS {
+            crate::pr_warn!("Too many boot env vars at `{}'\n", param);
+            return;
+        }
+        let Some(env) = self.store(param, Some(val)) else {
+            crate::pr_warn!("Too long boot env vars at `{}'\n", param);
+            return;
+        };
+        let index = index.unwrap_or_else(|| {
+            self.envc += 1;
+            self.envc - 1
+        });
+        self.envp[index] = env;
+    }
+
+    fn has_unknown(&self) -> bool {
+        self.argc > Self::DEFAULT_ARGV.len() || self.envc > Self::DEFAULT_ENVP.len()
+    }
+
+    /// Arguments of init, `init` first
+    pub fn argv(&self) -> impl Iterator<Item = &str> {
+        self.argv[..self.argc].iter().map(|&arg| self.get(arg))
+    }
+
+    /// Environment of init, as `name=value`
+    pub fn envp(&self) -> impl Iterator<Item = &str> {
+        self.envp[..self.envc].iter().map(|&env| self.get(env))
+    }
+}
+
+// Refer to linux: print_unknown_bootoptions
+struct UnknownOptions<'a>(&'a InitArgs);
+
+impl fmt::Display for UnknownOptions<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let args = self.0.argv().skip(InitArgs::DEFAULT_ARGV.len());
+        let envs = self.0.envp().skip(InitArgs::DEFAULT_ENVP.len());
+        for (i, arg) in args.chain(envs).enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            f.write_str(arg)?;
+        }
+        Ok(())
+    }
+}
+
+/// The arguments and the environment of init.
+pub static INIT_ARGS: RawSpinLockNoIrq<InitArgs> =
+    RawSpinLockNoIrq::new(InitArgs::new(), Some("INIT_ARGS"));
+
 /// A static instance of the command line.
 pub static GLOBAL_COMMAND_LINE: RawSpinLockNoIrq<OnceCell<CommandLine>> =
     RawSpinLockNoIrq::new(OnceCell::new(), Some("GLOBAL_COMMAND_LINE"));
 
+#[cfg(not(test))]
 #[section_init_text]
 pub(crate) fn setup_from_fdt() {
-    let chosen = GLOBAL_FDT.chosen();
-    let bootargs = chosen.bootargs().unwrap().as_bytes();
-    if bootargs.len() >= COMMAND_LINE_SIZE {
-        panic!("bootargs too long");
-    }
-
-    let mut buf = [0; COMMAND_LINE_SIZE];
-    buf[..bootargs.len()].copy_from_slice(bootargs);
+    // Without /chosen or its bootargs, the built-in command line is used.
+    let bootargs = GLOBAL_FDT.chosen().and_then(|chosen| chosen.bootargs());
+    let cmdline = CommandLine::merge(bootargs, BUILTIN_CMDLINE, CMDLINE_SOURCE);
 
     early_uart_put_str("bootargs: ");
-    early_uart_put_str(core::str::from_utf8(bootargs).unwrap());
+    early_uart_put_str(cmdline.as_str());
     early_uart_put_str("\n");
-    GLOBAL_COMMAND_LINE.lock().set(CommandLine {
-        arch_boot_cmdline: buf,
-        used: bootargs.len(),
-        parsed_early_options: false,
-    });
+    GLOBAL_COMMAND_LINE.lock().set(cmdline);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schedule::task::set_test_current;
+    use std::string::String;
+    use std::vec::Vec;
+
+    #[test]
+    fn test_append() {
+        let mut cmdline = CommandLine::empty();
+        assert!(cmdline.append("  a=1  "));
+        assert!(cmdline.append(" "));
+        assert!(cmdline.append("b"));
+        assert_eq!(cmdline.as_str(), "a=1 b");
+
+        // Cut at the last space that fits, a NUL is left room for.
+        let mut cmdline = CommandLine::empty();
+        let long = "y".repeat(COMMAND_LINE_SIZE - 10);
+        assert!(cmdline.append(&long));
+        assert!(!cmdline.append("abc defgh ij"));
+        assert_eq!(&cmdline.as_str()[long.len()..], " abc");
+        assert!(!cmdline.append("0123456789"));
+        assert_eq!(cmdline.as_str().len(), COMMAND_LINE_SIZE - 6);
+        assert!(cmdline.append("z"));
+        assert_eq!(cmdline.as_str().len(), COMMAND_LINE_SIZE - 4);
+    }
+
+    #[test]
+    fn test_merge() {
+        set_test_current();
+        let merge = |bootargs, source| {
+            String::from(CommandLine::merge(bootargs, "console=ttyS0", source).as_str())
+        };
+        let bootargs = Some("root=/dev/vda");
+
+        let source = CmdlineSource::FromBootloader;
+        assert_eq!(merge(bootargs, source), "root=/dev/vda");
+        assert_eq!(merge(Some("  "), source), "console=ttyS0");
+        assert_eq!(merge(None, source), "console=ttyS0");
+
+        let source = CmdlineSource::Extend;
+        assert_eq!(merge(bootargs, source), "root=/dev/vda console=ttyS0");
+        assert_eq!(merge(None, source), "console=ttyS0");
+
+        let source = CmdlineSource::Force;
+        assert_eq!(merge(bootargs, source), "console=ttyS0");
+        assert_eq!(merge(None, source), "console=ttyS0");
+
+        // The built-in arguments do not fit after the bootloader ones.
+        let long = "a".repeat(COMMAND_LINE_SIZE - 8);
+        assert_eq!(merge(Some(&long), CmdlineSource::Extend), long);
+    }
+
+    #[test]
+    fn test_split_init_args() {
+        assert_eq!(split_init_args("a=1 -- b c"), ("a=1 ", Some("b c")));
+        assert_eq!(split_init_args("a=\"x -- y\" b"), ("a=\"x -- y\" b", None));
+    }
+
+    #[test]
+    fn test_init_args() {
+        set_test_current();
+        let mut args = InitArgs::new();
+        assert!(!args.has_unknown());
+        args.push_arg("single", None);
+        args.push_env("TERM", "vt100");
+        args.push_env("LANG", "C");
+        assert!(args.has_unknown());
+        assert_eq!(args.argv().collect::<Vec<_>>(), ["init", "single"]);
+        assert_eq!(
+            args.envp().collect::<Vec<_>>(),
+            ["HOME=/", "TERM=vt100", "LANG=C"]
+        );
+
+        // Nothing is added once the buffer is full.
+        let long = "x".repeat(COMMAND_LINE_SIZE);
+        args.push_arg(&long, None);
+        args.push_env(&long, "1");
+        args.push_env("TERM", &long);
+        assert_eq!(args.argv().count(), 2);
+        assert_eq!(
+            args.envp().collect::<Vec<_>>(),
+            ["HOME=/", "TERM=vt100", "LANG=C"]
+        );
+
+        for _ in args.argc..MAX_INIT_ARGS + 1 {
+            args.push_arg("a", None);
+        }
+        assert_eq!(args.argv().count(), MAX_INIT_ARGS);
+    }
 }
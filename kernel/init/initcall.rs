@@ -0,0 +1,113 @@
+//! Initcalls
+//!
+//! Refer to linux: include/linux/init.h, init/main.c
+//!
+//! `#[initcall(level)]` puts an [`InitCall`] in `.initcall<N>.init`, the link
+//! script keeps the levels in order. The `early` ones run from
+//! [`do_pre_smp_initcalls`], the others level by level from [`do_initcalls`].
+
+use crate::core_param;
+use crate::error::{Error, Result};
+use crate::global_sym::{
+    __initcall0_start, __initcall1_start, __initcall2_start, __initcall3_start, __initcall4_start,
+    __initcall5_start, __initcall6_start, __initcall7_start, __initcall_end, __initcall_start,
+};
+use crate::macros::section_init_text;
+use crate::time::{ktime_get, NSEC_PER_USEC};
+
+/// A function run at boot, made by `#[initcall(level)]`
+#[repr(C)]
+pub struct InitCall {
+    func: fn() -> Result,
+    name: &'static str,
+}
+
+impl InitCall {
+    /// Create an initcall, `name` is printed with `initcall_debug`
+    pub const fn new(func: fn() -> Result, name: &'static str) -> Self {
+        Self { func, name }
+    }
+}
+
+core_param!(INITCALL_DEBUG, "initcall_debug", bool, false);
+
+// The initcalls from `start` to `end` in the link script.
+fn initcall_table(
+    start: unsafe extern "C" fn(),
+    end: unsafe extern "C" fn(),
+) -> &'static [InitCall] {
+    let start = start as *const InitCall;
+    let n = (end as usize - start as usize) / core::mem::size_of::<InitCall>();
+    // SAFETY: the link script puts only InitCall between the symbols.
+    unsafe { core::slice::from_raw_parts(start, n) }
+}
+
+/// Run one initcall, a failure is reported but does not stop the boot.
+///
+/// Refer to linux: do_one_initcall
+#[section_init_text]
+pub fn do_one_initcall(call: &InitCall) -> Result {
+    let debug = INITCALL_DEBUG.get();
+    let calltime = match debug {
+        true => {
+            crate::pr_info!("calling  {}\n", call.name);
+            ktime_get()
+        }
+        false => 0,
+    };
+
+    let ret = (call.func)();
+
+    if debug {
+        let duration = (ktime_get() - calltime) / NSEC_PER_USEC;
+        let errno = ret.err().map_or(0, |e| -(e as i32));
+        crate::pr_info!(
+            "initcall {} returned {} after {} usecs\n",
+            call.name,
+            errno,
+            duration
+        );
+    }
+    // No device is not an error, the driver has nothing to do.
+    match ret {
+        Err(e) if e != Error::Enodev => {
+            crate::pr_warn!("initcall {} failed with error {:?}\n", call.name, e);
+        }
+        _ => {}
+    }
+    ret
+}
+
+/// Run the `early` initcalls.
+///
+/// Refer to linux: do_pre_smp_initcalls
+#[section_init_text]
+pub fn do_pre_smp_initcalls() {
+    for call in initcall_table(__initcall_start, __initcall0_start) {
+        let _ = do_one_initcall(call);
+    }
+}
+
+/// Run the initcalls from `pure` to `late`, level by level.
+///
+/// Refer to linux: do_initcalls
+#[section_init_text]
+pub fn do_initcalls() {
+    // pure, core, postcore, arch, subsys, fs, device and late
+    let levels: [unsafe extern "C" fn(); 9] = [
+        __initcall0_start,
+        __initcall1_start,
+        __initcall2_start,
+        __initcall3_start,
+        __initcall4_start,
+        __initcall5_start,
+        __initcall6_start,
+        __initcall7_start,
+        __initcall_end,
+    ];
+    for level in levels.windows(2) {
+        for call in initcall_table(level[0], level[1]) {
+            let _ = do_one_initcall(call);
+        }
+    }
+}
@@ -1,6 +1,13 @@
 //! Rynux init module
 
+#[cfg(all(CONFIG_BOOT_CONFIG, not(test)))]
+pub mod bootconfig;
 pub mod command_line;
+#[cfg(not(test))]
 pub(crate) use command_line::GLOBAL_COMMAND_LINE;
 
+// The rest only in the real image, the command line is tested on the host.
+#[cfg(not(test))]
 pub mod init_task;
+#[cfg(not(test))]
+pub mod initcall;
@@ -0,0 +1,379 @@
+//! Interrupt handling
+//!
+//! Refer to linux: kernel/irq/manage.c, kernel/irq/irqdesc.c
+//!
+//! Drivers attach a handler to an interrupt number with [`request_irq`], the
+//! interrupt controller driver registered with [`irq_set_chip`] unmasks it
+//! and calls [`generic_handle_irq`] for every interrupt it takes. The
+//! exception entry of the arch calls [`handle_arch_irq`].
+//!
+//! Device tree interrupts are translated by the controller whose node is
+//! their interrupt parent, see [`irq_of_parse_and_map`].
+//!
+//! TODO: there are no irq domains, interrupt numbers are the ones of the
+//! controller registered, the GIC interrupt ids.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::error::{Error, Result};
+use crate::fdtree_rs::{FdtNode, PhandleArgs};
+use crate::schedule::preempt::{irq_enter, irq_exit};
+use crate::sync::lock::RawSpinLockNoIrq;
+
+/// Number of interrupts
+pub const NR_IRQS: usize = 1024;
+
+/// What an interrupt handler did.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum IrqReturn {
+    /// The interrupt was not from this device.
+    None,
+    /// The interrupt was handled.
+    Handled,
+}
+
+/// Trigger types, of the flags cell of the interrupt specifiers
+///
+/// Refer to linux: include/dt-bindings/interrupt-controller/irq.h
+pub const IRQ_TYPE_NONE: u32 = 0;
+/// Rising edge triggered
+pub const IRQ_TYPE_EDGE_RISING: u32 = 1;
+/// Falling edge triggered
+pub const IRQ_TYPE_EDGE_FALLING: u32 = 2;
+/// Active high level triggered
+pub const IRQ_TYPE_LEVEL_HIGH: u32 = 4;
+/// Active low level triggered
+pub const IRQ_TYPE_LEVEL_LOW: u32 = 8;
+/// The trigger type bits
+pub const IRQ_TYPE_SENSE_MASK: u32 = 0xf;
+
+/// An interrupt handler, called with the interrupt number and its data.
+pub type IrqHandler = fn(irq: u32, data: usize) -> IrqReturn;
+
+#[derive(Copy, Clone)]
+struct IrqAction {
+    handler: IrqHandler,
+    data: usize,
+    name: &'static str,
+}
+
+struct IrqDesc {
+    action: RawSpinLockNoIrq<Option<IrqAction>>,
+    count: AtomicUsize,
+}
+
+impl IrqDesc {
+    const fn new() -> Self {
+        Self {
+            action: RawSpinLockNoIrq::new(None, Some("irq_desc")),
+            count: AtomicUsize::new(0),
+        }
+    }
+}
+
+static IRQ_DESC: [IrqDesc; NR_IRQS] = [const { IrqDesc::new() }; NR_IRQS];
+
+/// The interrupt controller of the cpus
+///
+/// Refer to linux: struct irq_chip
+pub trait IrqChip: Sync {
+    /// Controller name
+    fn name(&self) -> &'static str;
+
+    /// Let interrupt `irq` through to the cpus.
+    fn irq_unmask(&self, irq: u32);
+
+    /// Hold interrupt `irq` back.
+    fn irq_mask(&self, irq: u32);
+
+    /// The device tree node of the controller, the interrupt parent of the
+    /// interrupts it translates.
+    fn of_node(&self) -> Option<FdtNode<'static, 'static>> {
+        None
+    }
+
+    /// Translate the specifier of a device tree interrupt to the interrupt
+    /// number and the `IRQ_TYPE_*` trigger type.
+    ///
+    /// Refer to linux: struct irq_domain_ops, translate
+    fn irq_xlate(&self, _spec: PhandleArgs<'_, '_>) -> Result<(u32, u32)> {
+        Err(Error::Einval)
+    }
+
+    /// Set the trigger type of interrupt `irq`, one of the `IRQ_TYPE_*`.
+    ///
+    /// Controllers with a fixed trigger type keep the default, which
+    /// accepts any.
+    fn irq_set_type(&self, _irq: u32, _flow: u32) -> Result {
+        Ok(())
+    }
+
+    /// Take the pending interrupts of the current cpu, and call
+    /// [`generic_handle_irq`] for each.
+    fn handle_irq(&self);
+}
+
+static IRQ_CHIP: RawSpinLockNoIrq<Option<&'static dyn IrqChip>> =
+    RawSpinLockNoIrq::new(None, Some("irq_chip"));
+
+/// Register the interrupt controller, which takes the interrupts of the
+/// cpus from now on.
+///
+/// Refer to linux: set_handle_irq
+pub fn irq_set_chip(chip: &'static dyn IrqChip) -> Result {
+    let mut slot = IRQ_CHIP.lock();
+    if slot.is_some() {
+        return Err(Error::Ebusy);
+    }
+    *slot = Some(chip);
+    Ok(())
+}
+
+fn irq_chip() -> Option<&'static dyn IrqChip> {
+    *IRQ_CHIP.lock()
+}
+
+/// Handle an interrupt exception of the current cpu.
+///
+/// Refer to linux: handle_arch_irq
+pub fn handle_arch_irq() {
+    let Some(chip) = irq_chip() else {
+        panic!("interrupt taken without an interrupt controller");
+    };
+    irq_enter();
+    chip.handle_irq();
+    irq_exit();
+}
+
+fn irq_to_desc(irq: u32) -> Result<&'static IrqDesc> {
+    IRQ_DESC.get(irq as usize).ok_or(Error::Einval)
+}
+
+/// Set the trigger type of interrupt `irq`, `IRQ_TYPE_NONE` keeps it.
+///
+/// Returns `Enodev` without an interrupt controller, `Einval` if it does
+/// not support the type.
+///
+/// Refer to linux: irq_set_irq_type
+pub fn irq_set_irq_type(irq: u32, flow: u32) -> Result {
+    irq_to_desc(irq)?;
+    let chip = irq_chip().ok_or(Error::Enodev)?;
+    match flow & IRQ_TYPE_SENSE_MASK {
+        IRQ_TYPE_NONE => Ok(()),
+        flow => chip.irq_set_type(irq, flow),
+    }
+}
+
+/// Attach `handler` to interrupt `irq`, it is called with `data`, and
+/// unmask the interrupt.
+///
+/// Interrupts are not shared, returns `Ebusy` if `irq` already has a
+/// handler.
+pub fn request_irq(irq: u32, handler: IrqHandler, name: &'static str, data: usize) -> Result {
+    {
+        let mut action = irq_to_desc(irq)?.action.lock();
+        if action.is_some() {
+            return Err(Error::Ebusy);
+        }
+        *action = Some(IrqAction {
+            handler,
+            data,
+            name,
+        });
+    }
+    if let Some(chip) = irq_chip() {
+        chip.irq_unmask(irq);
+    }
+    Ok(())
+}
+
+/// Mask interrupt `irq` and detach its handler.
+pub fn free_irq(irq: u32) {
+    if let Ok(desc) = irq_to_desc(irq) {
+        if let Some(chip) = irq_chip() {
+            chip.irq_mask(irq);
+        }
+        *desc.action.lock() = None;
+    }
+}
+
+/// Name the handler of interrupt `irq` was requested with.
+pub fn irq_name(irq: u32) -> Option<&'static str> {
+    let action = *irq_to_desc(irq).ok()?.action.lock();
+    action.map(|a| a.name)
+}
+
+/// How many times interrupt `irq` was handled.
+pub fn kstat_irqs(irq: u32) -> usize {
+    irq_to_desc(irq).map_or(0, |desc| desc.count.load(Ordering::Relaxed))
+}
+
+/// Run the handler of interrupt `irq`.
+///
+/// Returns `Einval` if it has no handler or the handler did not handle it.
+pub fn generic_handle_irq(irq: u32) -> Result {
+    let desc = irq_to_desc(irq)?;
+    // Copied out, the handler may take other locks.
+    let action = (*desc.action.lock()).ok_or(Error::Einval)?;
+    match (action.handler)(irq, action.data) {
+        IrqReturn::Handled => {
+            desc.count.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+        IrqReturn::None => Err(Error::Einval),
+    }
+}
+
+/// Interrupt number of the `index`th interrupt of a device tree node, the
+/// trigger type of its specifier is set.
+///
+/// Returns `Enxio` if the node has no such interrupt, `Eprobedefer` if its
+/// interrupt parent is not the controller registered, at least not yet,
+/// `Einval` if the controller does not understand the specifier.
+///
+/// Refer to linux: irq_of_parse_and_map, of_irq_get
+pub fn irq_of_parse_and_map(node: FdtNode<'_, '_>, index: usize) -> Result<u32> {
+    let spec = node.interrupt(index).ok_or(Error::Enxio)?;
+    // Nodes are compared by their name, it points in the blob.
+    let chip = irq_chip()
+        .filter(|chip| {
+            chip.of_node()
+                .is_some_and(|n| core::ptr::eq(n.name, spec.node.name))
+        })
+        .ok_or(Error::Eprobedefer)?;
+    let (irq, flow) = chip.irq_xlate(spec)?;
+    irq_to_desc(irq)?;
+    if let Err(e) = irq_set_irq_type(irq, flow) {
+        crate::pr_warn!(
+            "{}: trigger type {:#x} of irq {} not set: {:?}\n",
+            node.name,
+            flow,
+            irq,
+            e
+        );
+    }
+    Ok(irq)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fdtree_rs::LinuxFdt;
+    use crate::schedule::task::set_test_current;
+    use core::sync::atomic::AtomicU32;
+    use std::sync::OnceLock;
+
+    fn test_handler(irq: u32, data: usize) -> IrqReturn {
+        let seen = unsafe { &*(data as *const AtomicU32) };
+        seen.store(irq, Ordering::Relaxed);
+        IrqReturn::Handled
+    }
+
+    #[test]
+    fn test_request_irq() {
+        set_test_current();
+        static SEEN: AtomicU32 = AtomicU32::new(0);
+        let data = &SEEN as *const AtomicU32 as usize;
+
+        assert!(generic_handle_irq(40).is_err());
+        assert!(request_irq(40, test_handler, "test", data).is_ok());
+        assert_eq!(
+            request_irq(40, test_handler, "test", data),
+            Err(Error::Ebusy)
+        );
+        assert_eq!(irq_name(40), Some("test"));
+        assert!(generic_handle_irq(40).is_ok());
+        assert_eq!(SEEN.load(Ordering::Relaxed), 40);
+        assert_eq!(kstat_irqs(40), 1);
+
+        free_irq(40);
+        assert!(generic_handle_irq(40).is_err());
+        assert!(request_irq(NR_IRQS as u32, test_handler, "test", data).is_err());
+    }
+
+    static BINDINGS: &[u8] = include_bytes!("../../third_lib/fdtree-rs/dtb/bindings.dtb");
+    static FDT: OnceLock<LinuxFdt<'static>> = OnceLock::new();
+
+    // Interrupt 41 is pending until taken. It is the interrupt parent `/intc`
+    // of the bindings test tree, which has GIC like SPI specifiers.
+    struct TestChip {
+        unmasked: AtomicU32,
+        pending: AtomicU32,
+        flow: AtomicU32,
+    }
+
+    impl IrqChip for TestChip {
+        fn name(&self) -> &'static str {
+            "test"
+        }
+        fn irq_unmask(&self, irq: u32) {
+            if irq == 41 {
+                self.unmasked.store(1, Ordering::Relaxed);
+            }
+        }
+        fn irq_mask(&self, irq: u32) {
+            if irq == 41 {
+                self.unmasked.store(0, Ordering::Relaxed);
+            }
+        }
+        fn of_node(&self) -> Option<FdtNode<'static, 'static>> {
+            FDT.get()?.find_node("/intc")
+        }
+        fn irq_xlate(&self, spec: PhandleArgs<'_, '_>) -> Result<(u32, u32)> {
+            match (spec.arg(0), spec.arg(1), spec.arg(2)) {
+                (Some(0), Some(number), Some(flags)) => Ok((number + 32, flags)),
+                _ => Err(Error::Einval),
+            }
+        }
+        fn irq_set_type(&self, _irq: u32, flow: u32) -> Result {
+            self.flow.store(flow, Ordering::Relaxed);
+            Ok(())
+        }
+        fn handle_irq(&self) {
+            let irq = self.pending.swap(0, Ordering::Relaxed);
+            if irq != 0 {
+                generic_handle_irq(irq).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_irq_chip() {
+        set_test_current();
+        static CHIP: TestChip = TestChip {
+            unmasked: AtomicU32::new(0),
+            pending: AtomicU32::new(0),
+            flow: AtomicU32::new(0),
+        };
+        static SEEN: AtomicU32 = AtomicU32::new(0);
+        let data = &SEEN as *const AtomicU32 as usize;
+
+        assert!(irq_set_chip(&CHIP).is_ok());
+        assert_eq!(irq_set_chip(&CHIP), Err(Error::Ebusy));
+        assert!(request_irq(41, test_handler, "test", data).is_ok());
+        assert_eq!(CHIP.unmasked.load(Ordering::Relaxed), 1);
+
+        CHIP.pending.store(41, Ordering::Relaxed);
+        handle_arch_irq();
+        assert_eq!(SEEN.load(Ordering::Relaxed), 41);
+        assert_eq!(kstat_irqs(41), 1);
+        assert_eq!(crate::schedule::preempt::preempt_count(), 0);
+
+        free_irq(41);
+        assert_eq!(CHIP.unmasked.load(Ordering::Relaxed), 0);
+
+        // Device tree interrupts are mapped by the controller of their
+        // interrupt parent, with their trigger type.
+        let fdt = FDT.get_or_init(|| LinuxFdt::new(BINDINGS).unwrap());
+        let uart = fdt.find_node("/soc/uart").unwrap();
+        assert_eq!(irq_of_parse_and_map(uart, 0), Ok(89));
+        assert_eq!(CHIP.flow.load(Ordering::Relaxed), IRQ_TYPE_LEVEL_HIGH);
+        assert_eq!(irq_of_parse_and_map(uart, 1), Err(Error::Enxio));
+        let mmc = fdt.find_node("/mmc").unwrap();
+        assert_eq!(irq_of_parse_and_map(mmc, 0), Ok(62));
+        // Other controllers have no driver, yet.
+        assert_eq!(irq_of_parse_and_map(mmc, 1), Err(Error::Eprobedefer));
+        let timer = fdt.find_node("/soc/timer").unwrap();
+        assert_eq!(irq_of_parse_and_map(timer, 0), Err(Error::Eprobedefer));
+    }
+}
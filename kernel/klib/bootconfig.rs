@@ -0,0 +1,778 @@
+//! Extra boot config
+//!
+//! Refer to linux: lib/bootconfig.c, include/linux/bootconfig.h
+//!
+//! A bootconfig is a tree of `key.subkey = value` entries, as
+//!
+//! ```text
+//! kernel {
+//!     loglevel = 7
+//!     console = ttyAMA0, "tty1"   # an array
+//! }
+//! init.home := /root
+//! ```
+//!
+//! `=` sets a value once, `+=` appends to an array and `:=` overrides. It
+//! is appended to the initrd, followed by its size, its checksum and
+//! [`BOOTCONFIG_MAGIC`].
+//!
+//! [`Xbc`] holds a copy of the data and the nodes in fixed arrays, so it
+//! can be built before any allocator.
+
+use core::fmt;
+
+/// Magic at the end of the initrd
+pub const BOOTCONFIG_MAGIC: &[u8] = b"#BOOTCONFIG\n";
+/// The size and checksum before the magic are aligned to this
+pub const BOOTCONFIG_ALIGN: usize = 4;
+/// Maximum size of the bootconfig data
+pub const XBC_DATA_MAX: usize = 32767;
+/// Maximum number of keys and values
+pub const XBC_NODE_MAX: usize = 1024;
+/// Maximum depth of the keys
+pub const XBC_DEPTH_MAX: usize = 16;
+
+const XBC_NONE: u16 = u16::MAX;
+
+/// The bootconfig checksum, the sum of the bytes.
+///
+/// Refer to linux: xbc_calc_checksum
+pub fn xbc_calc_checksum(data: &[u8]) -> u32 {
+    data.iter().fold(0u32, |sum, &b| sum.wrapping_add(b as u32))
+}
+
+/// Find the bootconfig at the end of `initrd`.
+///
+/// Returns None without magic, otherwise the data or why it is invalid.
+/// `initrd` can be only the end of the initrd, if it holds the bootconfig.
+///
+/// Refer to linux: get_boot_config_from_initrd
+pub fn find_bootconfig(initrd: &[u8]) -> Option<Result<&[u8], &'static str>> {
+    // The initrd size may be aligned to 4 by the bootloader.
+    let end = (0..BOOTCONFIG_ALIGN).find_map(|pad| {
+        let end = initrd.len().checked_sub(pad + BOOTCONFIG_MAGIC.len())?;
+        (&initrd[end..end + BOOTCONFIG_MAGIC.len()] == BOOTCONFIG_MAGIC).then_some(end)
+    })?;
+    let Some(hdr) = end.checked_sub(8) else {
+        return Some(Err("bootconfig size is greater than initrd size"));
+    };
+    let size = u32::from_le_bytes(initrd[hdr..hdr + 4].try_into().unwrap()) as usize;
+    let csum = u32::from_le_bytes(initrd[hdr + 4..hdr + 8].try_into().unwrap());
+    if size > XBC_DATA_MAX {
+        return Some(Err("bootconfig size is greater than max size"));
+    }
+    let Some(start) = hdr.checked_sub(size) else {
+        return Some(Err("bootconfig size is greater than initrd size"));
+    };
+    let data = &initrd[start..hdr];
+    if xbc_calc_checksum(data) != csum {
+        return Some(Err("bootconfig checksum failed"));
+    }
+    Some(Ok(data))
+}
+
+/// A bootconfig parse error
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct XbcError {
+    /// What is wrong
+    pub msg: &'static str,
+    /// Byte offset in the data, None if it is not about a position
+    pub pos: Option<usize>,
+}
+
+impl XbcError {
+    const fn at(msg: &'static str, pos: usize) -> Self {
+        Self {
+            msg,
+            pos: Some(pos),
+        }
+    }
+}
+
+impl fmt::Display for XbcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.pos {
+            Some(pos) => write!(f, "{} at {}", self.msg, pos),
+            None => f.write_str(self.msg),
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+struct RawNode {
+    next: u16,
+    child: u16,
+    parent: u16,
+    start: u16,
+    len: u16,
+    value: bool,
+}
+
+impl RawNode {
+    // Only the first `nr_nodes` are used, all zero keeps `Xbc` in bss.
+    const EMPTY: Self = Self {
+        next: 0,
+        child: 0,
+        parent: 0,
+        start: 0,
+        len: 0,
+        value: false,
+    };
+}
+
+/// A parsed bootconfig
+///
+/// Refer to linux: struct xbc_node, xbc_nodes
+pub struct Xbc {
+    data: [u8; XBC_DATA_MAX],
+    size: usize,
+    nodes: [RawNode; XBC_NODE_MAX],
+    nr_nodes: usize,
+}
+
+impl Xbc {
+    /// Create an empty bootconfig
+    pub const fn new() -> Self {
+        Self {
+            data: [0; XBC_DATA_MAX],
+            size: 0,
+            nodes: [RawNode::EMPTY; XBC_NODE_MAX],
+            nr_nodes: 0,
+        }
+    }
+
+    /// Parse `data`, it is copied up to the first NUL. Returns the number
+    /// of nodes, the bootconfig is left empty on error.
+    ///
+    /// Refer to linux: xbc_init
+    pub fn init(&mut self, data: &[u8]) -> Result<usize, XbcError> {
+        self.clear();
+        let size = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+        if size == 0 {
+            return Err(XbcError {
+                msg: "Config data is empty",
+                pos: None,
+            });
+        }
+        if size > XBC_DATA_MAX {
+            return Err(XbcError {
+                msg: "Config data is too big",
+                pos: None,
+            });
+        }
+        if let Err(e) = core::str::from_utf8(&data[..size]) {
+            return Err(XbcError::at("Config data is not UTF-8", e.valid_up_to()));
+        }
+        self.data[..size].copy_from_slice(&data[..size]);
+        self.size = size;
+
+        let ret = XbcParser {
+            xbc: self,
+            pos: 0,
+            stack: [XBC_NONE; XBC_DEPTH_MAX],
+            brace_pos: [0; XBC_DEPTH_MAX],
+            depth: 0,
+        }
+        .parse();
+        match ret {
+            Ok(()) => Ok(self.nr_nodes),
+            Err(e) => {
+                self.clear();
+                Err(e)
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        self.size = 0;
+        self.nr_nodes = 0;
+    }
+
+    /// Whether there is no key
+    pub fn is_empty(&self) -> bool {
+        self.nr_nodes == 0
+    }
+
+    /// Number of keys and values
+    pub fn nr_nodes(&self) -> usize {
+        self.nr_nodes
+    }
+
+    /// Size of the data
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    fn node(&self, index: u16) -> XbcNode<'_> {
+        XbcNode { xbc: self, index }
+    }
+
+    fn str(&self, node: &RawNode) -> &str {
+        let start = node.start as usize;
+        // SAFETY: data is checked to be UTF-8, and nodes are split at ASCII
+        // characters.
+        unsafe { core::str::from_utf8_unchecked(&self.data[start..start + node.len as usize]) }
+    }
+
+    fn first_child(&self, parent: u16) -> u16 {
+        match parent {
+            // The first node is always the first top level key.
+            XBC_NONE if self.nr_nodes > 0 => 0,
+            XBC_NONE => XBC_NONE,
+            parent => self.nodes[parent as usize].child,
+        }
+    }
+
+    /// The top level keys
+    pub fn root_keys(&self) -> XbcNodeIter<'_> {
+        XbcNodeIter {
+            xbc: self,
+            index: self.first_child(XBC_NONE),
+            value: false,
+        }
+    }
+
+    /// Find the key `key`, as `foo.bar`.
+    ///
+    /// Refer to linux: xbc_find_node
+    pub fn find_node(&self, key: &str) -> Option<XbcNode<'_>> {
+        let mut parent = XBC_NONE;
+        for name in key.split('.') {
+            let mut index = self.first_child(parent);
+            loop {
+                if index == XBC_NONE {
+                    return None;
+                }
+                let node = &self.nodes[index as usize];
+                if !node.value && self.str(node) == name {
+                    break;
+                }
+                index = node.next;
+            }
+            parent = index;
+        }
+        (parent != XBC_NONE).then(|| self.node(parent))
+    }
+
+    /// The first value of `key`, "" if it has no value, None if it does not
+    /// exist or only has subkeys.
+    ///
+    /// Refer to linux: xbc_find_value
+    pub fn find_value(&self, key: &str) -> Option<&str> {
+        let node = self.find_node(key)?;
+        match node.values().next() {
+            Some(value) => Some(value),
+            None if node.subkeys().next().is_none() => Some(""),
+            None => None,
+        }
+    }
+
+    /// The values of `key`, none if it does not exist.
+    pub fn values(&self, key: &str) -> impl Iterator<Item = &str> {
+        self.find_node(key)
+            .into_iter()
+            .flat_map(|node| node.values())
+    }
+
+    /// Write the keys under `root` as a command line, `key=value` with the
+    /// key after `root`, one per array value.
+    ///
+    /// Refer to linux: xbc_snprint_cmdline
+    pub fn make_cmdline(&self, root: &str, w: &mut dyn fmt::Write) -> fmt::Result {
+        let Some(root) = self.find_node(root) else {
+            return Ok(());
+        };
+        let mut path = [XBC_NONE; XBC_DEPTH_MAX];
+        for key in root.subkeys() {
+            self.write_leaves(key, &mut path, 0, w)?;
+        }
+        Ok(())
+    }
+
+    fn write_leaves(
+        &self,
+        key: XbcNode<'_>,
+        path: &mut [u16; XBC_DEPTH_MAX],
+        depth: usize,
+        w: &mut dyn fmt::Write,
+    ) -> fmt::Result {
+        path[depth] = key.index;
+        let path_str = |w: &mut dyn fmt::Write| -> fmt::Result {
+            for (i, &index) in path[..=depth].iter().enumerate() {
+                if i > 0 {
+                    w.write_char('.')?;
+                }
+                w.write_str(self.node(index).data())?;
+            }
+            Ok(())
+        };
+        let mut values = key.values().peekable();
+        if values.peek().is_some() {
+            for value in values {
+                path_str(w)?;
+                // Only quote the value when it has spaces.
+                let q = if value.contains([' ', '\t', '\r', '\n']) {
+                    "\""
+                } else {
+                    ""
+                };
+                write!(w, "={}{}{} ", q, value, q)?;
+            }
+        } else if key.subkeys().next().is_none() {
+            path_str(w)?;
+            w.write_char(' ')?;
+        }
+        for subkey in key.subkeys() {
+            self.write_leaves(subkey, path, depth + 1, w)?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for Xbc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A key or a value of a bootconfig
+#[derive(Copy, Clone)]
+pub struct XbcNode<'a> {
+    xbc: &'a Xbc,
+    index: u16,
+}
+
+impl<'a> XbcNode<'a> {
+    fn raw(&self) -> &'a RawNode {
+        &self.xbc.nodes[self.index as usize]
+    }
+
+    /// The key name, or the value
+    pub fn data(&self) -> &'a str {
+        self.xbc.str(self.raw())
+    }
+
+    /// Whether this is a value
+    pub fn is_value(&self) -> bool {
+        self.raw().value
+    }
+
+    /// The parent key, None at the top level
+    pub fn parent(&self) -> Option<XbcNode<'a>> {
+        let parent = self.raw().parent;
+        (parent != XBC_NONE).then(|| self.xbc.node(parent))
+    }
+
+    /// The subkeys
+    pub fn subkeys(&self) -> XbcNodeIter<'a> {
+        XbcNodeIter {
+            xbc: self.xbc,
+            index: self.raw().child,
+            value: false,
+        }
+    }
+
+    /// The values, more than one for an array
+    pub fn values(&self) -> impl Iterator<Item = &'a str> + use<'a> {
+        XbcNodeIter {
+            xbc: self.xbc,
+            index: self.raw().child,
+            value: true,
+        }
+        .map(|node| node.data())
+    }
+}
+
+/// Iterator over the keys or the values of a node
+pub struct XbcNodeIter<'a> {
+    xbc: &'a Xbc,
+    index: u16,
+    value: bool,
+}
+
+impl<'a> Iterator for XbcNodeIter<'a> {
+    type Item = XbcNode<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.index != XBC_NONE {
+            let node = self.xbc.node(self.index);
+            self.index = node.raw().next;
+            if node.is_value() == self.value {
+                return Some(node);
+            }
+        }
+        None
+    }
+}
+
+// Refer to linux: xbc_parse_tree
+struct XbcParser<'a> {
+    xbc: &'a mut Xbc,
+    pos: usize,
+    stack: [u16; XBC_DEPTH_MAX],
+    brace_pos: [usize; XBC_DEPTH_MAX],
+    depth: usize,
+}
+
+impl XbcParser<'_> {
+    fn peek(&self) -> Option<u8> {
+        (self.pos < self.xbc.size).then(|| self.xbc.data[self.pos])
+    }
+
+    fn parent(&self) -> u16 {
+        match self.depth {
+            0 => XBC_NONE,
+            depth => self.stack[depth - 1],
+        }
+    }
+
+    // Skip blanks, and with `lines` newlines, `;` and comments too.
+    fn skip(&mut self, lines: bool) {
+        while let Some(c) = self.peek() {
+            match c {
+                b' ' | b'\t' | b'\r' => {}
+                b'\n' | b';' if lines => {}
+                b'#' if lines => {
+                    while self.peek().is_some_and(|c| c != b'\n') {
+                        self.pos += 1;
+                    }
+                    continue;
+                }
+                _ => return,
+            }
+            self.pos += 1;
+        }
+    }
+
+    fn parse(mut self) -> Result<(), XbcError> {
+        loop {
+            self.skip(true);
+            let Some(c) = self.peek() else {
+                break;
+            };
+            if c == b'}' {
+                if self.depth == 0 {
+                    return Err(XbcError::at("Unexpected closing brace", self.pos));
+                }
+                self.depth -= 1;
+                self.pos += 1;
+                continue;
+            }
+            let key_pos = self.pos;
+            let key = self.parse_key()?;
+            self.skip(false);
+            match self.peek() {
+                Some(b'{') => {
+                    if self.depth == XBC_DEPTH_MAX {
+                        return Err(XbcError::at("Too much nested", self.pos));
+                    }
+                    self.stack[self.depth] = key;
+                    self.brace_pos[self.depth] = self.pos;
+                    self.depth += 1;
+                    self.pos += 1;
+                }
+                Some(b'=') => {
+                    self.pos += 1;
+                    self.parse_values(key, key_pos, b'=')?;
+                }
+                Some(op @ (b'+' | b':')) => {
+                    self.pos += 1;
+                    if self.peek() != Some(b'=') {
+                        return Err(XbcError::at("Wrong '+' or ':' operator", self.pos - 1));
+                    }
+                    self.pos += 1;
+                    self.parse_values(key, key_pos, op)?;
+                }
+                None | Some(b'\n' | b';' | b'#' | b'}') => {}
+                Some(_) => return Err(XbcError::at("Invalid keyword", self.pos)),
+            }
+        }
+        if self.depth > 0 {
+            return Err(XbcError::at(
+                "Brace is not closed",
+                self.brace_pos[self.depth - 1],
+            ));
+        }
+        Ok(())
+    }
+
+    // Parse `foo.bar`, adding the missing keys. Returns the last one.
+    fn parse_key(&mut self) -> Result<u16, XbcError> {
+        let mut parent = self.parent();
+        let mut depth = self.depth;
+        loop {
+            let start = self.pos;
+            while self
+                .peek()
+                .is_some_and(|c| c.is_ascii_alphanumeric() || c == b'-' || c == b'_')
+            {
+                self.pos += 1;
+            }
+            if self.pos == start {
+                return Err(XbcError::at("Invalid keyword", start));
+            }
+            depth += 1;
+            if depth > XBC_DEPTH_MAX {
+                return Err(XbcError::at("Too much nested", start));
+            }
+            parent = self.find_or_add_key(parent, start, self.pos - start)?;
+            if self.peek() != Some(b'.') {
+                return Ok(parent);
+            }
+            self.pos += 1;
+        }
+    }
+
+    fn find_or_add_key(&mut self, parent: u16, start: usize, len: usize) -> Result<u16, XbcError> {
+        let name = &self.xbc.data[start..start + len];
+        let mut index = self.xbc.first_child(parent);
+        while index != XBC_NONE {
+            let node = &self.xbc.nodes[index as usize];
+            if !node.value && &self.xbc.data[node.start as usize..][..node.len as usize] == name {
+                return Ok(index);
+            }
+            index = node.next;
+        }
+        self.add_node(parent, start, len, false)
+    }
+
+    // Add a node as the last child of `parent`, values go before the keys.
+    fn add_node(
+        &mut self,
+        parent: u16,
+        start: usize,
+        len: usize,
+        value: bool,
+    ) -> Result<u16, XbcError> {
+        if self.xbc.nr_nodes == XBC_NODE_MAX {
+            return Err(XbcError::at("Too many nodes", start));
+        }
+        let index = self.xbc.nr_nodes as u16;
+        self.xbc.nr_nodes += 1;
+        self.xbc.nodes[index as usize] = RawNode {
+            next: XBC_NONE,
+            child: XBC_NONE,
+            parent,
+            start: start as u16,
+            len: len as u16,
+            value,
+        };
+
+        let mut prev = XBC_NONE;
+        let mut next = match index {
+            0 => XBC_NONE,
+            _ => self.xbc.first_child(parent),
+        };
+        while next != XBC_NONE && (!value || self.xbc.nodes[next as usize].value) {
+            prev = next;
+            next = self.xbc.nodes[next as usize].next;
+        }
+        self.xbc.nodes[index as usize].next = next;
+        match (prev, parent) {
+            (XBC_NONE, XBC_NONE) => {}
+            (XBC_NONE, parent) => self.xbc.nodes[parent as usize].child = index,
+            (prev, _) => self.xbc.nodes[prev as usize].next = index,
+        }
+        Ok(index)
+    }
+
+    // Parse the values of `key` after `=`, `+=` or `:=`.
+    fn parse_values(&mut self, key: u16, key_pos: usize, op: u8) -> Result<(), XbcError> {
+        let first = self.xbc.nodes[key as usize].child;
+        let has_value = first != XBC_NONE && self.xbc.nodes[first as usize].value;
+        match op {
+            b'=' if has_value => return Err(XbcError::at("Value is redefined", key_pos)),
+            b':' => {
+                // Drop the old values.
+                let mut next = first;
+                while next != XBC_NONE && self.xbc.nodes[next as usize].value {
+                    next = self.xbc.nodes[next as usize].next;
+                }
+                self.xbc.nodes[key as usize].child = next;
+            }
+            _ => {}
+        }
+
+        loop {
+            self.skip(false);
+            let (start, len) = match self.peek() {
+                Some(q @ (b'"' | b'\'')) => {
+                    let start = self.pos + 1;
+                    let Some(len) = self.xbc.data[start..self.xbc.size]
+                        .iter()
+                        .position(|&c| c == q)
+                    else {
+                        return Err(XbcError::at("No closing quotes", self.pos));
+                    };
+                    self.pos = start + len + 1;
+                    self.skip(false);
+                    if !matches!(self.peek(), None | Some(b',' | b';' | b'\n' | b'#' | b'}')) {
+                        return Err(XbcError::at("No delimiter after closing quotes", self.pos));
+                    }
+                    (start, len)
+                }
+                _ => {
+                    let start = self.pos;
+                    while !matches!(self.peek(), None | Some(b',' | b';' | b'\n' | b'#' | b'}')) {
+                        self.pos += 1;
+                    }
+                    let value = &self.xbc.data[start..self.pos];
+                    let len = value.len()
+                        - value
+                            .iter()
+                            .rev()
+                            .take_while(|c| c.is_ascii_whitespace())
+                            .count();
+                    (start, len)
+                }
+            };
+            self.add_node(key, start, len, true)?;
+            if self.peek() != Some(b',') {
+                return Ok(());
+            }
+            // An array can go on the next lines.
+            self.pos += 1;
+            self.skip_array_space();
+        }
+    }
+
+    fn skip_array_space(&mut self) {
+        loop {
+            self.skip(false);
+            match self.peek() {
+                Some(b'\n') => self.pos += 1,
+                Some(b'#') => {
+                    while self.peek().is_some_and(|c| c != b'\n') {
+                        self.pos += 1;
+                    }
+                }
+                _ => return,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::string::String;
+    use std::vec::Vec;
+
+    fn bootconfig(data: &[u8]) -> Vec<u8> {
+        let mut initrd = b"initrd".to_vec();
+        initrd.extend_from_slice(data);
+        initrd.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        initrd.extend_from_slice(&xbc_calc_checksum(data).to_le_bytes());
+        initrd.extend_from_slice(BOOTCONFIG_MAGIC);
+        initrd
+    }
+
+    #[test]
+    fn test_find_bootconfig() {
+        let mut initrd = bootconfig(b"foo = bar\n\0\0");
+        assert_eq!(find_bootconfig(&initrd), Some(Ok(&b"foo = bar\n\0\0"[..])));
+        // Padded by the bootloader
+        initrd.extend_from_slice(&[0; 3]);
+        assert_eq!(find_bootconfig(&initrd), Some(Ok(&b"foo = bar\n\0\0"[..])));
+        assert_eq!(find_bootconfig(b"initrd only"), None);
+
+        let mut bad = bootconfig(b"foo = bar\n");
+        bad[6] = b'g';
+        assert_eq!(
+            find_bootconfig(&bad),
+            Some(Err("bootconfig checksum failed"))
+        );
+        let mut big = bootconfig(b"foo");
+        big[9] = 0xff;
+        assert_eq!(
+            find_bootconfig(&big),
+            Some(Err("bootconfig size is greater than initrd size"))
+        );
+        big[11] = 0x01;
+        assert_eq!(
+            find_bootconfig(&big),
+            Some(Err("bootconfig size is greater than max size"))
+        );
+    }
+
+    #[test]
+    fn test_xbc_parse() {
+        let mut xbc = Xbc::new();
+        let data = b"# comment\n\
+            kernel {\n\
+                loglevel = 7 # debug\n\
+                console = ttyAMA0, \"tty1\",\n\
+                    'ttyS0,115200n8'\n\
+                quiet; printk.time\n\
+            }\n\
+            kernel.console += hvc0\n\
+            init.home := /root\n\
+            init.home := \"/home/user one\"\n\
+            init.single\n\0\0";
+        assert_eq!(xbc.init(data), Ok(16));
+
+        assert_eq!(xbc.find_value("kernel.loglevel"), Some("7"));
+        assert_eq!(
+            xbc.values("kernel.console").collect::<Vec<_>>(),
+            ["ttyAMA0", "tty1", "ttyS0,115200n8", "hvc0"]
+        );
+        assert_eq!(xbc.find_value("kernel.quiet"), Some(""));
+        assert_eq!(xbc.find_value("kernel.printk"), None);
+        assert_eq!(xbc.find_value("kernel.printk.time"), Some(""));
+        assert_eq!(xbc.find_value("init.home"), Some("/home/user one"));
+        assert_eq!(xbc.find_value("kernel.nothing"), None);
+        assert_eq!(
+            xbc.root_keys().map(|k| k.data()).collect::<Vec<_>>(),
+            ["kernel", "init"]
+        );
+        let time = xbc.find_node("kernel.printk.time").unwrap();
+        assert_eq!(time.parent().unwrap().data(), "printk");
+
+        let mut cmdline = String::new();
+        xbc.make_cmdline("kernel", &mut cmdline).unwrap();
+        assert_eq!(
+            cmdline,
+            "loglevel=7 console=ttyAMA0 console=tty1 console=ttyS0,115200n8 \
+             console=hvc0 quiet printk.time "
+        );
+        cmdline.clear();
+        xbc.make_cmdline("init", &mut cmdline).unwrap();
+        assert_eq!(cmdline, "home=\"/home/user one\" single ");
+    }
+
+    #[test]
+    fn test_xbc_errors() {
+        let mut xbc = Xbc::new();
+        let err = |xbc: &mut Xbc, data: &[u8]| xbc.init(data).unwrap_err();
+        assert_eq!(
+            err(&mut xbc, b"foo = 1\nfoo = 2"),
+            XbcError::at("Value is redefined", 8)
+        );
+        assert_eq!(
+            err(&mut xbc, b"foo {\n bar\n"),
+            XbcError::at("Brace is not closed", 4)
+        );
+        assert_eq!(
+            err(&mut xbc, b"foo }"),
+            XbcError::at("Unexpected closing brace", 4)
+        );
+        assert_eq!(
+            err(&mut xbc, b"foo..bar"),
+            XbcError::at("Invalid keyword", 4)
+        );
+        assert_eq!(
+            err(&mut xbc, b"foo = \"bar"),
+            XbcError::at("No closing quotes", 6)
+        );
+        assert_eq!(
+            err(&mut xbc, b"foo = \"bar\" baz"),
+            XbcError::at("No delimiter after closing quotes", 12)
+        );
+        assert_eq!(
+            err(&mut xbc, b"foo ! bar"),
+            XbcError::at("Invalid keyword", 4)
+        );
+        assert_eq!(err(&mut xbc, b"\0").pos, None);
+        assert!(xbc.is_empty());
+    }
+}
@@ -0,0 +1,95 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Circular byte buffer
+//!
+//! Refer to linux: include/linux/circ_buf.h
+
+/// A circular buffer of `N` bytes, `N` must be a power of two.
+///
+/// The indexes run freely and are masked on access, so the buffer holds
+/// all `N` bytes when full.
+pub struct CircBuf<const N: usize> {
+    buf: [u8; N],
+    head: usize,
+    tail: usize,
+}
+
+impl<const N: usize> CircBuf<N> {
+    const MASK: usize = {
+        assert!(N.is_power_of_two());
+        N - 1
+    };
+
+    /// Create an empty buffer
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; N],
+            head: 0,
+            tail: 0,
+        }
+    }
+
+    /// Bytes in the buffer
+    pub fn len(&self) -> usize {
+        self.head.wrapping_sub(self.tail)
+    }
+
+    /// Whether the buffer is empty
+    pub fn is_empty(&self) -> bool {
+        self.head == self.tail
+    }
+
+    /// Free space, in bytes
+    pub fn space(&self) -> usize {
+        N - self.len()
+    }
+
+    /// Append a byte, return false if the buffer is full.
+    pub fn push(&mut self, c: u8) -> bool {
+        if self.space() == 0 {
+            return false;
+        }
+        self.buf[self.head & Self::MASK] = c;
+        self.head = self.head.wrapping_add(1);
+        true
+    }
+
+    /// Append as many bytes of `data` as fit, return how many.
+    pub fn write(&mut self, data: &[u8]) -> usize {
+        let n = data.len().min(self.space());
+        for &c in &data[..n] {
+            self.push(c);
+        }
+        n
+    }
+
+    /// Take the oldest byte.
+    pub fn pop(&mut self) -> Option<u8> {
+        if self.is_empty() {
+            return None;
+        }
+        let c = self.buf[self.tail & Self::MASK];
+        self.tail = self.tail.wrapping_add(1);
+        Some(c)
+    }
+
+    /// Take the oldest bytes into `buf`, return how many.
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        let n = buf.len().min(self.len());
+        for c in &mut buf[..n] {
+            *c = self.pop().unwrap();
+        }
+        n
+    }
+
+    /// Drop all bytes.
+    pub fn clear(&mut self) {
+        self.tail = self.head;
+    }
+}
+
+impl<const N: usize> Default for CircBuf<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
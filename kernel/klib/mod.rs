@@ -3,5 +3,8 @@
 //! Kernel basic general library code.
 
 pub mod bits;
+pub mod bootconfig;
+pub mod circ_buf;
 pub mod math;
+pub mod rbtree;
 pub mod string;
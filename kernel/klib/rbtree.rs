@@ -0,0 +1,572 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Intrusive red-black tree
+//!
+//! Refer to linux: lib/rbtree.c
+//!
+//! Like [`RawList`], entries embed their [`RbLinks`] and are owned by the
+//! caller, the tree only keeps pointers to them. The leftmost entry is cached
+//! so the smallest one is found in O(1), which is what timer queues need.
+//!
+//! [`RawList`]: crate::list::RawList
+
+use core::cell::UnsafeCell;
+use core::ptr::NonNull;
+
+/// A descriptor of tree entries, see [`crate::list::GetLinks`].
+pub trait GetRbLinks {
+    /// The type of the entries in the tree.
+    type EntryType;
+
+    /// Returns the links to be used when linking an entry within a tree.
+    fn get_links(data: &Self::EntryType) -> &RbLinks<Self::EntryType>;
+}
+
+struct RbNode<T> {
+    parent: Option<NonNull<T>>,
+    left: Option<NonNull<T>>,
+    right: Option<NonNull<T>>,
+    red: bool,
+    linked: bool,
+}
+
+/// The links used to link an entry on a tree.
+pub struct RbLinks<T> {
+    node: UnsafeCell<RbNode<T>>,
+}
+
+// SAFETY: links are only changed by the tree owning them, which callers
+// serialise like for `Links`.
+unsafe impl<T> Send for RbLinks<T> {}
+
+// SAFETY: see above.
+unsafe impl<T> Sync for RbLinks<T> {}
+
+impl<T> RbLinks<T> {
+    /// Constructs links that are not inserted on any tree yet.
+    pub const fn new() -> Self {
+        Self {
+            node: UnsafeCell::new(RbNode {
+                parent: None,
+                left: None,
+                right: None,
+                red: false,
+                linked: false,
+            }),
+        }
+    }
+
+    /// Is the entry on a tree?
+    #[inline]
+    pub fn is_linked(&self) -> bool {
+        // SAFETY: only read, the tree owner serialises writes.
+        unsafe { (*self.node.get()).linked }
+    }
+}
+
+impl<T> Default for RbLinks<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An intrusive red-black tree with a cached leftmost entry.
+pub struct RawRbTree<G: GetRbLinks> {
+    root: Option<NonNull<G::EntryType>>,
+    leftmost: Option<NonNull<G::EntryType>>,
+}
+
+// SAFETY: the tree only holds pointers to entries, which can be shared with
+// other threads when they are `Sync`.
+unsafe impl<G: GetRbLinks> Send for RawRbTree<G> where G::EntryType: Sync {}
+
+// SAFETY: see above.
+unsafe impl<G: GetRbLinks> Sync for RawRbTree<G> where G::EntryType: Sync {}
+
+type Link<T> = Option<NonNull<T>>;
+
+#[inline]
+fn same<T>(a: Link<T>, b: Link<T>) -> bool {
+    a.map(|p| p.as_ptr()) == b.map(|p| p.as_ptr())
+}
+
+impl<G: GetRbLinks> RawRbTree<G> {
+    /// Constructs an empty tree.
+    pub const fn new() -> Self {
+        Self {
+            root: None,
+            leftmost: None,
+        }
+    }
+
+    /// Is the tree empty?
+    #[inline]
+    pub const fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    /// The smallest entry.
+    #[inline]
+    pub fn first(&self) -> Option<NonNull<G::EntryType>> {
+        self.leftmost
+    }
+
+    // SAFETY (for all node helpers): `p` points to an entry on this tree, and
+    // the caller has exclusive access to the tree.
+    #[allow(clippy::mut_from_ref)]
+    fn node<'a>(p: NonNull<G::EntryType>) -> &'a mut RbNode<G::EntryType> {
+        // SAFETY: see above.
+        unsafe { &mut *G::get_links(p.as_ref()).node.get() }
+    }
+
+    fn parent(p: NonNull<G::EntryType>) -> Link<G::EntryType> {
+        Self::node(p).parent
+    }
+
+    fn left(p: NonNull<G::EntryType>) -> Link<G::EntryType> {
+        Self::node(p).left
+    }
+
+    fn right(p: NonNull<G::EntryType>) -> Link<G::EntryType> {
+        Self::node(p).right
+    }
+
+    fn is_red(p: Link<G::EntryType>) -> bool {
+        p.map(|p| Self::node(p).red).unwrap_or(false)
+    }
+
+    fn set_red(p: NonNull<G::EntryType>, red: bool) {
+        Self::node(p).red = red;
+    }
+
+    fn set_parent(p: Link<G::EntryType>, parent: Link<G::EntryType>) {
+        if let Some(p) = p {
+            Self::node(p).parent = parent;
+        }
+    }
+
+    fn minimum(mut p: NonNull<G::EntryType>) -> NonNull<G::EntryType> {
+        while let Some(l) = Self::left(p) {
+            p = l;
+        }
+        p
+    }
+
+    fn successor(mut p: NonNull<G::EntryType>) -> Link<G::EntryType> {
+        if let Some(r) = Self::right(p) {
+            return Some(Self::minimum(r));
+        }
+        while let Some(parent) = Self::parent(p) {
+            if same(Self::left(parent), Some(p)) {
+                return Some(parent);
+            }
+            p = parent;
+        }
+        None
+    }
+
+    // Replace `old` by `new` in the child slot of `parent`.
+    fn change_child(
+        &mut self,
+        old: NonNull<G::EntryType>,
+        new: Link<G::EntryType>,
+        parent: Link<G::EntryType>,
+    ) {
+        match parent {
+            None => self.root = new,
+            Some(parent) => {
+                let node = Self::node(parent);
+                if same(node.left, Some(old)) {
+                    node.left = new;
+                } else {
+                    node.right = new;
+                }
+            }
+        }
+    }
+
+    fn rotate_left(&mut self, x: NonNull<G::EntryType>) {
+        let y = Self::right(x).expect("rotate left without right child");
+        let y_left = Self::left(y);
+        Self::node(x).right = y_left;
+        Self::set_parent(y_left, Some(x));
+        let parent = Self::parent(x);
+        Self::node(y).parent = parent;
+        self.change_child(x, Some(y), parent);
+        Self::node(y).left = Some(x);
+        Self::node(x).parent = Some(y);
+    }
+
+    fn rotate_right(&mut self, x: NonNull<G::EntryType>) {
+        let y = Self::left(x).expect("rotate right without left child");
+        let y_right = Self::right(y);
+        Self::node(x).left = y_right;
+        Self::set_parent(y_right, Some(x));
+        let parent = Self::parent(x);
+        Self::node(y).parent = parent;
+        self.change_child(x, Some(y), parent);
+        Self::node(y).right = Some(x);
+        Self::node(x).parent = Some(y);
+    }
+
+    /// Inserts an entry, ordered by `less`, returns false if it is already on a tree.
+    ///
+    /// Entries comparing equal are inserted after the existing ones.
+    ///
+    /// # Safety
+    ///
+    /// Callers must ensure that `new` outlives its membership of the tree, and
+    /// that nothing else touches its links meanwhile.
+    pub unsafe fn insert(
+        &mut self,
+        new: &G::EntryType,
+        less: impl Fn(&G::EntryType, &G::EntryType) -> bool,
+    ) -> bool {
+        let links = G::get_links(new);
+        if links.is_linked() {
+            return false;
+        }
+        let new_ptr = NonNull::from(new);
+
+        let mut parent = None;
+        let mut go_left = false;
+        let mut leftmost = true;
+        let mut cur = self.root;
+        while let Some(c) = cur {
+            parent = Some(c);
+            // SAFETY: `c` is on the tree, so it is alive.
+            go_left = less(new, unsafe { c.as_ref() });
+            if go_left {
+                cur = Self::left(c);
+            } else {
+                cur = Self::right(c);
+                leftmost = false;
+            }
+        }
+
+        {
+            let node = Self::node(new_ptr);
+            node.parent = parent;
+            node.left = None;
+            node.right = None;
+            node.red = true;
+            node.linked = true;
+        }
+        match parent {
+            None => self.root = Some(new_ptr),
+            Some(p) if go_left => Self::node(p).left = Some(new_ptr),
+            Some(p) => Self::node(p).right = Some(new_ptr),
+        }
+        if leftmost {
+            self.leftmost = Some(new_ptr);
+        }
+        self.insert_fixup(new_ptr);
+        true
+    }
+
+    fn insert_fixup(&mut self, mut z: NonNull<G::EntryType>) {
+        while let Some(p) = Self::parent(z) {
+            if !Self::is_red(Some(p)) {
+                break;
+            }
+            // A red node is never the root, so the grandparent exists.
+            let g = Self::parent(p).expect("red root");
+            if same(Self::left(g), Some(p)) {
+                let uncle = Self::right(g);
+                if let Some(u) = uncle.filter(|&u| Self::is_red(Some(u))) {
+                    Self::set_red(p, false);
+                    Self::set_red(u, false);
+                    Self::set_red(g, true);
+                    z = g;
+                    continue;
+                }
+                let mut p = p;
+                if same(Self::right(p), Some(z)) {
+                    z = p;
+                    self.rotate_left(z);
+                    p = Self::parent(z).expect("rotated node has parent");
+                }
+                Self::set_red(p, false);
+                Self::set_red(g, true);
+                self.rotate_right(g);
+            } else {
+                let uncle = Self::left(g);
+                if let Some(u) = uncle.filter(|&u| Self::is_red(Some(u))) {
+                    Self::set_red(p, false);
+                    Self::set_red(u, false);
+                    Self::set_red(g, true);
+                    z = g;
+                    continue;
+                }
+                let mut p = p;
+                if same(Self::left(p), Some(z)) {
+                    z = p;
+                    self.rotate_right(z);
+                    p = Self::parent(z).expect("rotated node has parent");
+                }
+                Self::set_red(p, false);
+                Self::set_red(g, true);
+                self.rotate_left(g);
+            }
+        }
+        if let Some(root) = self.root {
+            Self::set_red(root, false);
+        }
+    }
+
+    fn transplant(&mut self, u: NonNull<G::EntryType>, v: Link<G::EntryType>) {
+        let parent = Self::parent(u);
+        self.change_child(u, v, parent);
+        Self::set_parent(v, parent);
+    }
+
+    /// Removes an entry, returns false if it is not on a tree.
+    ///
+    /// # Safety
+    ///
+    /// Callers must ensure that `data` is either not on any tree, or on this one.
+    pub unsafe fn remove(&mut self, data: &G::EntryType) -> bool {
+        if !G::get_links(data).is_linked() {
+            return false;
+        }
+        let z = NonNull::from(data);
+        if same(self.leftmost, Some(z)) {
+            self.leftmost = Self::successor(z);
+        }
+
+        let mut orig_red = Self::is_red(Some(z));
+        let x;
+        let x_parent;
+        match (Self::left(z), Self::right(z)) {
+            (None, right) => {
+                x = right;
+                x_parent = Self::parent(z);
+                self.transplant(z, right);
+            }
+            (left, None) => {
+                x = left;
+                x_parent = Self::parent(z);
+                self.transplant(z, left);
+            }
+            (Some(left), Some(right)) => {
+                let y = Self::minimum(right);
+                orig_red = Self::is_red(Some(y));
+                x = Self::right(y);
+                if same(Self::parent(y), Some(z)) {
+                    x_parent = Some(y);
+                } else {
+                    x_parent = Self::parent(y);
+                    self.transplant(y, x);
+                    Self::node(y).right = Some(right);
+                    Self::node(right).parent = Some(y);
+                }
+                self.transplant(z, Some(y));
+                Self::node(y).left = Some(left);
+                Self::node(left).parent = Some(y);
+                Self::set_red(y, Self::is_red(Some(z)));
+            }
+        }
+        if !orig_red {
+            self.remove_fixup(x, x_parent);
+        }
+
+        let node = Self::node(z);
+        node.parent = None;
+        node.left = None;
+        node.right = None;
+        node.linked = false;
+        true
+    }
+
+    fn remove_fixup(&mut self, mut x: Link<G::EntryType>, mut parent: Link<G::EntryType>) {
+        while !same(x, self.root) && !Self::is_red(x) {
+            // `x` is not the root, so it has a parent.
+            let p = parent.expect("non root without parent");
+            if same(x, Self::left(p)) {
+                // `x` is one black short, so its sibling exists.
+                let mut w = Self::right(p).expect("missing sibling");
+                if Self::is_red(Some(w)) {
+                    Self::set_red(w, false);
+                    Self::set_red(p, true);
+                    self.rotate_left(p);
+                    w = Self::right(p).expect("missing sibling");
+                }
+                if !Self::is_red(Self::left(w)) && !Self::is_red(Self::right(w)) {
+                    Self::set_red(w, true);
+                    x = Some(p);
+                    parent = Self::parent(p);
+                } else {
+                    if !Self::is_red(Self::right(w)) {
+                        Self::set_parent_black(Self::left(w));
+                        Self::set_red(w, true);
+                        self.rotate_right(w);
+                        w = Self::right(p).expect("missing sibling");
+                    }
+                    Self::set_red(w, Self::is_red(Some(p)));
+                    Self::set_red(p, false);
+                    Self::set_parent_black(Self::right(w));
+                    self.rotate_left(p);
+                    x = self.root;
+                    break;
+                }
+            } else {
+                let mut w = Self::left(p).expect("missing sibling");
+                if Self::is_red(Some(w)) {
+                    Self::set_red(w, false);
+                    Self::set_red(p, true);
+                    self.rotate_right(p);
+                    w = Self::left(p).expect("missing sibling");
+                }
+                if !Self::is_red(Self::left(w)) && !Self::is_red(Self::right(w)) {
+                    Self::set_red(w, true);
+                    x = Some(p);
+                    parent = Self::parent(p);
+                } else {
+                    if !Self::is_red(Self::left(w)) {
+                        Self::set_parent_black(Self::right(w));
+                        Self::set_red(w, true);
+                        self.rotate_left(w);
+                        w = Self::left(p).expect("missing sibling");
+                    }
+                    Self::set_red(w, Self::is_red(Some(p)));
+                    Self::set_red(p, false);
+                    Self::set_parent_black(Self::left(w));
+                    self.rotate_right(p);
+                    x = self.root;
+                    break;
+                }
+            }
+        }
+        if let Some(x) = x {
+            Self::set_red(x, false);
+        }
+    }
+
+    fn set_parent_black(p: Link<G::EntryType>) {
+        if let Some(p) = p {
+            Self::set_red(p, false);
+        }
+    }
+
+    /// Iterates the entries in order.
+    pub fn iter(&self) -> Iter<'_, G> {
+        Iter {
+            next: self.leftmost,
+            _tree: self,
+        }
+    }
+}
+
+impl<G: GetRbLinks> Default for RawRbTree<G> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An in order iterator of a [`RawRbTree`].
+pub struct Iter<'a, G: GetRbLinks> {
+    next: Option<NonNull<G::EntryType>>,
+    _tree: &'a RawRbTree<G>,
+}
+
+impl<'a, G: GetRbLinks> Iterator for Iter<'a, G> {
+    type Item = &'a G::EntryType;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let cur = self.next?;
+        self.next = RawRbTree::<G>::successor(cur);
+        // SAFETY: entries on the tree outlive the borrow of the tree.
+        Some(unsafe { &*cur.as_ptr() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    struct Entry {
+        key: u32,
+        links: RbLinks<Entry>,
+    }
+
+    impl GetRbLinks for Entry {
+        type EntryType = Entry;
+        fn get_links(data: &Entry) -> &RbLinks<Entry> {
+            &data.links
+        }
+    }
+
+    fn less(a: &Entry, b: &Entry) -> bool {
+        a.key < b.key
+    }
+
+    // Check the red-black properties, return the black height.
+    fn check(p: Link<Entry>, parent: Link<Entry>) -> usize {
+        let Some(p) = p else {
+            return 1;
+        };
+        assert!(same(RawRbTree::<Entry>::parent(p), parent));
+        let red = RawRbTree::<Entry>::is_red(Some(p));
+        let (l, r) = (RawRbTree::<Entry>::left(p), RawRbTree::<Entry>::right(p));
+        if red {
+            assert!(!RawRbTree::<Entry>::is_red(l) && !RawRbTree::<Entry>::is_red(r));
+        }
+        let lh = check(l, Some(p));
+        assert_eq!(lh, check(r, Some(p)));
+        lh + !red as usize
+    }
+
+    fn keys(tree: &RawRbTree<Entry>) -> Vec<u32> {
+        tree.iter().map(|e| e.key).collect()
+    }
+
+    #[test]
+    fn test_rbtree_random() {
+        let entries: Vec<Entry> = (0..512u32)
+            .map(|i| Entry {
+                key: i.wrapping_mul(2654435761) % 97,
+                links: RbLinks::new(),
+            })
+            .collect();
+        let mut tree = RawRbTree::<Entry>::new();
+        let mut expect = Vec::new();
+
+        for e in &entries {
+            // SAFETY: entries outlive the tree.
+            assert!(unsafe { tree.insert(e, less) });
+            expect.push(e.key);
+        }
+        // SAFETY: see above.
+        assert!(!unsafe { tree.insert(&entries[0], less) });
+        expect.sort();
+        assert_eq!(keys(&tree), expect);
+        assert!(!RawRbTree::<Entry>::is_red(tree.root));
+        check(tree.root, None);
+
+        for (i, e) in entries.iter().enumerate() {
+            if i % 3 == 0 {
+                continue;
+            }
+            // SAFETY: see above.
+            assert!(unsafe { tree.remove(e) });
+            assert!(!e.links.is_linked());
+            let pos = expect.iter().position(|&k| k == e.key).unwrap();
+            expect.remove(pos);
+            check(tree.root, None);
+            // SAFETY: the leftmost entry is alive.
+            let first = tree.first().map(|p| unsafe { p.as_ref() }.key);
+            assert_eq!(first, expect.first().copied());
+        }
+        assert_eq!(keys(&tree), expect);
+        // SAFETY: see above.
+        assert!(!unsafe { tree.remove(&entries[1]) });
+
+        for e in entries.iter().step_by(3) {
+            // SAFETY: see above.
+            assert!(unsafe { tree.remove(e) });
+        }
+        assert!(tree.is_empty());
+        assert!(tree.first().is_none());
+    }
+}
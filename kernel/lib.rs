@@ -21,7 +21,9 @@
 compile_error!("This crate only supports little endian platforms!");
 
 // Allow proc-macros to refer to `::kernel` inside the `kernel` crate (this crate).
-//extern crate self as kernel;
+// The initcall tables using it are not built in tests.
+#[cfg_attr(test, allow(unused_extern_crates))]
+extern crate self as kernel;
 
 pub use bitflags;
 pub use cfg_if;
@@ -37,8 +39,6 @@ cfg_if::cfg_if! {
     if #[cfg(not(test))] {
         // vmrynux and global_sym only used in real image
         pub mod vmrynux;
-        // init only avaliable in real image
-        pub mod init;
         pub mod global_sym;
     }
 }
@@ -48,6 +48,9 @@ pub mod compiler;
 pub mod cpu;
 pub mod drivers;
 pub mod error;
+pub mod fs;
+pub mod init;
+pub mod irq;
 pub mod klib;
 pub mod linkage;
 pub mod list;
@@ -58,6 +61,7 @@ pub mod printk;
 pub mod schedule;
 pub mod size;
 pub mod sync;
+pub mod time;
 pub mod types;
 
 #[cfg(not(any(testlib, test)))]
@@ -313,7 +313,8 @@ impl<G: GetLinksWrapped> iter::DoubleEndedIterator for Iterator<'_, G> {
 #[cfg(test)]
 mod tests {
     use super::{GetLinks, Links, List};
-    use std::sync::Arc;
+    use crate::alloc::AllocFlags;
+    use crate::sync::arc::Arc;
 
     struct Example {
         inner: usize,
@@ -353,10 +354,16 @@ mod tests {
         let mut list = List::<Arc<Example>>::new();
 
         for n in 1..=MAX {
-            list.push_back(Arc::new(Example {
-                inner: n,
-                links: Links::new(),
-            }));
+            list.push_back(
+                Arc::new(
+                    Example {
+                        inner: n,
+                        links: Links::new(),
+                    },
+                    AllocFlags::GFP_KERNEL,
+                )
+                .unwrap(),
+            );
         }
         assert_list_contents(&list, MAX);
     }
@@ -367,10 +374,16 @@ mod tests {
         let mut list = List::<Arc<Example>>::new();
 
         for n in (1..=MAX).rev() {
-            list.push_front(Arc::new(Example {
-                inner: n,
-                links: Links::new(),
-            }));
+            list.push_front(
+                Arc::new(
+                    Example {
+                        inner: n,
+                        links: Links::new(),
+                    },
+                    AllocFlags::GFP_KERNEL,
+                )
+                .unwrap(),
+            );
         }
         assert_list_contents(&list, MAX);
     }
@@ -381,10 +394,16 @@ mod tests {
         let mut list = List::<Arc<Example>>::new();
 
         for n in 1..=MAX {
-            list.push_front(Arc::new(Example {
-                inner: n,
-                links: Links::new(),
-            }));
+            list.push_front(
+                Arc::new(
+                    Example {
+                        inner: n,
+                        links: Links::new(),
+                    },
+                    AllocFlags::GFP_KERNEL,
+                )
+                .unwrap(),
+            );
         }
         assert_eq!(list.front().unwrap().inner, MAX);
     }
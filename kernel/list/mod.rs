@@ -123,6 +123,7 @@ pub(crate) use __def_node_internal;
 /// # Example
 ///
 /// ```rust
+/// use crate::alloc::AllocFlags;
 /// use crate::list::{def_node, List};
 /// use crate::sync::arc::Arc;
 ///
@@ -135,8 +136,8 @@ pub(crate) use __def_node_internal;
 ///     pub struct GenericNode<T>(T);
 /// }
 ///
-/// let node1 = Arc::new(ExampleNode::new(0));
-/// let node2 = Arc::new(ExampleNode::new(1));
+/// let node1 = Arc::new(ExampleNode::new(0), AllocFlags::GFP_KERNEL).unwrap();
+/// let node2 = Arc::new(ExampleNode::new(1), AllocFlags::GFP_KERNEL).unwrap();
 /// let mut list =  List::<Arc<ExampleNode>>::new();
 ///
 /// list.push_back(node1);
@@ -153,8 +154,8 @@ pub(crate) use __def_node_internal;
 /// assert!(node2.into_inner() == 1);
 /// assert!(list.pop_front().is_none());
 ///
-/// let node1 = Arc::new(GenericNode::new(0));
-/// let node2 = Arc::new(GenericNode::new(1));
+/// let node1 = Arc::new(GenericNode::new(0), AllocFlags::GFP_KERNEL).unwrap();
+/// let node2 = Arc::new(GenericNode::new(1), AllocFlags::GFP_KERNEL).unwrap();
 ///
 /// let mut list =  List::<Arc<GenericNode<usize>>>::new();
 ///
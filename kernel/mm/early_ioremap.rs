@@ -0,0 +1,30 @@
+//! Map memory before paging is fully up
+//!
+//! Refer to linux: mm/early_ioremap.c
+
+use super::{PhysAddr, VirtAddr};
+
+/// Temporarily map `size` bytes of memory at `phys` read only, return None
+/// on failure. Only one mapping can be used at a time.
+pub fn early_memremap_ro(phys: PhysAddr, size: usize) -> Option<VirtAddr> {
+    cfg_if::cfg_if! {
+        if #[cfg(CONFIG_ARM64)] {
+            use crate::arch::arm64::{mm::fixmap::FixMap, pgtable::PtePgProt};
+            FixMap::early_memremap(phys, size, PtePgProt::PAGE_KERNEL_RO)
+        } else {
+            let _ = (phys, size);
+            None
+        }
+    }
+}
+
+/// Unmap a mapping from [`early_memremap_ro`].
+pub fn early_memunmap(virt: VirtAddr, size: usize) {
+    cfg_if::cfg_if! {
+        if #[cfg(CONFIG_ARM64)] {
+            crate::arch::arm64::mm::fixmap::FixMap::early_memunmap(virt, size)
+        } else {
+            let _ = (virt, size);
+        }
+    }
+}
@@ -0,0 +1,33 @@
+//! Map device memory
+//!
+//! Refer to linux: mm/ioremap.c
+
+use super::{PhysAddr, VirtAddr};
+
+/// Map `size` bytes of device registers at `phys`, return None on failure.
+///
+/// The mapping is permanent unless it is undone with [`iounmap`].
+pub fn ioremap(phys: PhysAddr, size: usize) -> Option<VirtAddr> {
+    cfg_if::cfg_if! {
+        if #[cfg(CONFIG_ARM64)] {
+            crate::arch::arm64::mm::fixmap::FixMap::ioremap(phys, size)
+        } else {
+            let _ = (phys, size);
+            None
+        }
+    }
+}
+
+/// Unmap a mapping of `size` bytes at `virt` from [`ioremap`].
+///
+/// Only the last mapping gives its space back, use it to undo a mapping
+/// on an error path.
+pub fn iounmap(virt: VirtAddr, size: usize) {
+    cfg_if::cfg_if! {
+        if #[cfg(CONFIG_ARM64)] {
+            crate::arch::arm64::mm::fixmap::FixMap::iounmap(virt, size)
+        } else {
+            let _ = (virt, size);
+        }
+    }
+}
@@ -501,7 +501,7 @@ impl MemBlock {
         }
 
         // Handle linux,usable-memory-range property
-        if let Some(regions) = fdt.chosen().usable_mem_region() {
+        if let Some(regions) = fdt.chosen().and_then(|c| c.usable_mem_region()) {
             for r in regions {
                 let start = PhysAddr::from(r.starting_address as usize);
                 self.cap_memory(start, r.size);
@@ -1,8 +1,11 @@
 //! Memory management code.
 
 pub mod addr;
+pub mod early_ioremap;
+pub mod ioremap;
 pub mod memblock;
 pub mod page;
 pub mod percpu;
 
 pub use addr::{PhysAddr, VirtAddr};
+pub use ioremap::{ioremap, iounmap};
@@ -0,0 +1,509 @@
+//! Typed kernel parameters
+//!
+//! Refer to linux: kernel/params.c, include/linux/moduleparam.h
+//!
+//! [`core_param!`] and [`module_param!`] declare a parameter: a static
+//! [`ParamCell`] holding its value, and a [`KernelParam`] in the `__param`
+//! section that parses it from the command line after the early
+//! parameters. `module_param!` names it `<module>.<name>`, the module being
+//! the last part of the module path, as `printk.time`.
+//!
+//! Values are parsed by [`ParamValue`]: booleans, integers with an optional
+//! `K`, `M`, `G`, `T`, `P` or `E` suffix, [`ParamStr`] strings, enums
+//! declared with [`param_enum!`] and comma separated [`ParamArray`]s.
+//!
+//! Example:
+//!
+//! ```ignore
+//! use kernel::param::kernel_param::{core_param, module_param};
+//!
+//! core_param!(pub PANIC_TIMEOUT, "panic", i32, 0);
+//! module_param!(RX_RING, "rx_ring", usize, 256);
+//! ```
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use super::{report_param_err, report_parse_err, ParamHandleErr, ParamParser};
+use crate::sync::lock::RawSpinLockNoIrq;
+
+/// A parameter in section `__param`
+///
+/// Refer to linux: struct kernel_param
+#[repr(C)]
+pub struct KernelParam {
+    name: &'static str,
+    set: fn(Option<&str>) -> Result<(), ParamHandleErr>,
+}
+
+impl KernelParam {
+    /// Create a parameter, `set` parses and stores its value.
+    pub const fn new(
+        name: &'static str,
+        set: fn(Option<&str>) -> Result<(), ParamHandleErr>,
+    ) -> Self {
+        Self { name, set }
+    }
+
+    /// Parameter name
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Parse and store `val`.
+    pub fn set(&self, val: Option<&str>) -> Result<(), ParamHandleErr> {
+        (self.set)(val)
+    }
+}
+
+/// A value parsed from the command line
+pub trait ParamValue: Sized {
+    /// Parse `val`, None if the parameter has no `=`.
+    fn parse_param(val: Option<&str>) -> Result<Self, ParamHandleErr>;
+}
+
+/// Parse a boolean, `y`, `n`, `1`, `0`, `on` or `off`.
+///
+/// Refer to linux: kstrtobool
+pub fn parse_bool(s: &str) -> Option<bool> {
+    match s.as_bytes() {
+        [b'y' | b'Y' | b'1', ..] => Some(true),
+        [b'n' | b'N' | b'0', ..] => Some(false),
+        [b'o' | b'O', b'n' | b'N', ..] => Some(true),
+        [b'o' | b'O', b'f' | b'F', ..] => Some(false),
+        _ => None,
+    }
+}
+
+/// A parameter without value is true.
+impl ParamValue for bool {
+    fn parse_param(val: Option<&str>) -> Result<Self, ParamHandleErr> {
+        match val {
+            None => Ok(true),
+            Some(s) => parse_bool(s).ok_or(ParamHandleErr::InvalidValue),
+        }
+    }
+}
+
+/// Parse an integer, decimal, octal with a leading `0` or hexadecimal with
+/// a leading `0x`, then times 1024 for each step of a `K`, `M`, `G`, `T`,
+/// `P` or `E` suffix.
+///
+/// Refer to linux: memparse
+pub fn parse_integer(s: &str) -> Result<i128, ParamHandleErr> {
+    let (neg, s) = match s.strip_prefix('-') {
+        Some(s) => (true, s),
+        None => (false, s.strip_prefix('+').unwrap_or(s)),
+    };
+    let (radix, digits) = if let Some(hex) = s.strip_prefix("0x").or(s.strip_prefix("0X")) {
+        (16, hex)
+    } else if s.len() > 1 && s.starts_with('0') {
+        (8, &s[1..])
+    } else {
+        (10, s)
+    };
+    let end = digits
+        .find(|c: char| !c.is_digit(radix))
+        .unwrap_or(digits.len());
+    let (digits, suffix) = digits.split_at(end);
+    if digits.is_empty() {
+        return Err(ParamHandleErr::InvalidValue);
+    }
+    let shift = match suffix {
+        "" => 0,
+        "k" | "K" => 10,
+        "m" | "M" => 20,
+        "g" | "G" => 30,
+        "t" | "T" => 40,
+        "p" | "P" => 50,
+        "e" | "E" => 60,
+        _ => return Err(ParamHandleErr::InvalidValue),
+    };
+    let value = u64::from_str_radix(digits, radix).map_err(|_| ParamHandleErr::OutOfRange)?;
+    let value = (value as i128)
+        .checked_mul(1 << shift)
+        .ok_or(ParamHandleErr::OutOfRange)?;
+    Ok(if neg { -value } else { value })
+}
+
+macro_rules! impl_param_integer {
+    ($($ty:ty),*) => {
+        $(
+            impl ParamValue for $ty {
+                fn parse_param(val: Option<&str>) -> Result<Self, ParamHandleErr> {
+                    let value = parse_integer(val.ok_or(ParamHandleErr::MissingValue)?)?;
+                    <$ty>::try_from(value).map_err(|_| ParamHandleErr::OutOfRange)
+                }
+            }
+        )*
+    };
+}
+
+impl_param_integer!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+/// A string parameter of at most `N` bytes
+#[derive(Copy, Clone)]
+pub struct ParamStr<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> ParamStr<N> {
+    /// Create a string from `s`, cut to `N` bytes.
+    pub const fn new(s: &str) -> Self {
+        let bytes = s.as_bytes();
+        let mut buf = [0; N];
+        let mut len = 0;
+        while len < bytes.len() && len < N {
+            buf[len] = bytes[len];
+            len += 1;
+        }
+        Self { buf, len }
+    }
+
+    /// The string
+    pub fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.buf[..self.len]).unwrap_or("")
+    }
+}
+
+impl<const N: usize> Default for ParamStr<N> {
+    fn default() -> Self {
+        Self::new("")
+    }
+}
+
+impl<const N: usize> ParamValue for ParamStr<N> {
+    fn parse_param(val: Option<&str>) -> Result<Self, ParamHandleErr> {
+        let val = val.ok_or(ParamHandleErr::MissingValue)?;
+        if val.len() > N {
+            return Err(ParamHandleErr::ParameterTooLarge);
+        }
+        Ok(Self::new(val))
+    }
+}
+
+/// A comma separated list of at most `N` values
+///
+/// Refer to linux: module_param_array
+#[derive(Copy, Clone)]
+pub struct ParamArray<T, const N: usize> {
+    items: [T; N],
+    len: usize,
+}
+
+impl<T: Copy, const N: usize> ParamArray<T, N> {
+    /// Create a list of the first `len` of `items`.
+    pub const fn new(items: [T; N], len: usize) -> Self {
+        assert!(len <= N);
+        Self { items, len }
+    }
+
+    /// The values
+    pub fn as_slice(&self) -> &[T] {
+        &self.items[..self.len]
+    }
+}
+
+impl<T: ParamValue + Copy + Default, const N: usize> ParamValue for ParamArray<T, N> {
+    fn parse_param(val: Option<&str>) -> Result<Self, ParamHandleErr> {
+        let val = val.ok_or(ParamHandleErr::MissingValue)?;
+        let mut array = Self::new([T::default(); N], 0);
+        for item in val.split(',') {
+            if array.len == N {
+                return Err(ParamHandleErr::ParameterTooLarge);
+            }
+            array.items[array.len] = T::parse_param(Some(item))?;
+            array.len += 1;
+        }
+        Ok(array)
+    }
+}
+
+/// Implement [`ParamValue`] for an enum, from its names.
+///
+/// Example:
+///
+/// param_enum!(Mode { "off" => Mode::Off, "on" => Mode::On });
+///
+#[macro_export]
+macro_rules! param_enum {
+    ($ty:ty { $($name:literal => $value:expr),+ $(,)? }) => {
+        impl $crate::param::kernel_param::ParamValue for $ty {
+            fn parse_param(
+                val: Option<&str>,
+            ) -> Result<Self, $crate::param::ParamHandleErr> {
+                match val {
+                    $(Some($name) => Ok($value),)+
+                    Some(_) => Err($crate::param::ParamHandleErr::InvalidValue),
+                    None => Err($crate::param::ParamHandleErr::MissingValue),
+                }
+            }
+        }
+    };
+}
+
+pub use param_enum;
+
+/// The storage of a parameter
+pub struct ParamCell<T> {
+    value: RawSpinLockNoIrq<T>,
+    set: AtomicBool,
+}
+
+impl<T: Copy> ParamCell<T> {
+    /// Create a cell holding the default value
+    pub const fn new(value: T) -> Self {
+        Self {
+            value: RawSpinLockNoIrq::new(value, Some("kernel_param")),
+            set: AtomicBool::new(false),
+        }
+    }
+
+    /// Current value
+    pub fn get(&self) -> T {
+        *self.value.lock()
+    }
+
+    /// Set the value.
+    pub fn set(&self, value: T) {
+        *self.value.lock() = value;
+        self.set.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether the value was set, by the command line or [`ParamCell::set`].
+    pub fn is_set(&self) -> bool {
+        self.set.load(Ordering::Relaxed)
+    }
+}
+
+impl<T: ParamValue + Copy> ParamCell<T> {
+    /// Parse and store `val`, the value is kept on error.
+    pub fn parse(&self, val: Option<&str>) -> Result<(), ParamHandleErr> {
+        self.set(T::parse_param(val)?);
+        Ok(())
+    }
+}
+
+/// The module name of a module path, its last part.
+pub const fn module_name(path: &'static str) -> &'static str {
+    let bytes = path.as_bytes();
+    let mut start = bytes.len();
+    while start > 0 && bytes[start - 1] != b':' {
+        start -= 1;
+    }
+    match core::str::from_utf8(bytes.split_at(start).1) {
+        Ok(name) => name,
+        Err(_) => path,
+    }
+}
+
+/// Declare a parameter `name` of type `ty` in static `id`.
+///
+/// Refer to linux: core_param
+#[macro_export]
+macro_rules! core_param {
+    ($vis:vis $id:ident, $name:expr, $ty:ty, $default:expr) => {
+        $vis static $id: $crate::param::kernel_param::ParamCell<$ty> =
+            $crate::param::kernel_param::ParamCell::new($default);
+
+        const _: () = {
+            fn set(val: Option<&str>) -> Result<(), $crate::param::ParamHandleErr> {
+                $id.parse(val)
+            }
+
+            #[unsafe(link_section = "__param")]
+            #[used]
+            static PARAM: $crate::param::kernel_param::KernelParam =
+                $crate::param::kernel_param::KernelParam::new($name, set);
+        };
+    };
+}
+
+pub use core_param;
+
+/// Declare a parameter `<module>.<name>` of type `ty` in static `id`.
+///
+/// Refer to linux: module_param
+#[macro_export]
+macro_rules! module_param {
+    ($vis:vis $id:ident, $name:literal, $ty:ty, $default:expr) => {
+        $crate::param::kernel_param::core_param!(
+            $vis $id,
+            $crate::const_format::concatcp!(
+                $crate::param::kernel_param::module_name(module_path!()),
+                ".",
+                $name
+            ),
+            $ty,
+            $default
+        );
+    };
+}
+
+pub use module_param;
+
+/// Whether two parameter names are equal, `-` and `_` are the same.
+///
+/// Refer to linux: parameq
+pub fn parameq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes().zip(b.bytes()).all(|(a, b)| {
+            let dash = |c| if c == b'-' { b'_' } else { c };
+            dash(a) == dash(b)
+        })
+}
+
+/// Parse `args` into `params`, the other parameters go to `unknown`.
+///
+/// A parameter that fails to parse keeps its value, one given more than
+/// once takes the last value. Malformed parameters and failures are
+/// reported as boot warnings with `doing`. Returns the arguments after
+/// `--`, if any.
+///
+/// Refer to linux: parse_args
+pub fn parse_args<'a>(
+    doing: &str,
+    args: &'a str,
+    params: &[KernelParam],
+    mut unknown: impl FnMut(&'a str, Option<&'a str>) -> Result<(), ParamHandleErr>,
+) -> Option<&'a str> {
+    let mut parser = ParamParser::new(args);
+    while let Some(next) = parser.next_param() {
+        let (_, param, val) = match next {
+            Ok(next) => next,
+            Err(e) => {
+                report_parse_err(doing, &e);
+                continue;
+            }
+        };
+        if param == "--" && val.is_none() {
+            return Some(parser.remaining());
+        }
+        let ret = match params.iter().find(|p| parameq(p.name, param)) {
+            // Given again, as appended by CMDLINE_EXTEND, the last one wins.
+            Some(p) => p.set(val),
+            None => unknown(param, val),
+        };
+        if let Err(e) = ret {
+            report_param_err(doing, param, val, e);
+        }
+    }
+    None
+}
+
+/// The parameters in section `__param`
+#[cfg(not(test))]
+pub fn kernel_params() -> &'static [KernelParam] {
+    use crate::global_sym::{__start___param, __stop___param};
+    // SAFETY: __start___param and __stop___param are defined in link script
+    unsafe {
+        let start = __start___param as *const KernelParam;
+        let end = __stop___param as *const KernelParam;
+        let n = (end as usize - start as usize) / core::mem::size_of::<KernelParam>();
+        core::slice::from_raw_parts(start, n)
+    }
+}
+
+/// Host tests are not linked with the `__param` section.
+#[cfg(test)]
+pub fn kernel_params() -> &'static [KernelParam] {
+    &[]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schedule::task::set_test_current;
+
+    #[derive(Copy, Clone, Default, PartialEq, Eq, Debug)]
+    enum Mode {
+        #[default]
+        Off,
+        On,
+        Force,
+    }
+
+    param_enum!(Mode { "off" => Mode::Off, "on" => Mode::On, "force" => Mode::Force });
+
+    core_param!(TEST_BOOL, "test_bool", bool, false);
+    module_param!(TEST_SIZE, "size", u64, 0);
+    module_param!(TEST_NAME, "name", ParamStr<8>, ParamStr::new("none"));
+    core_param!(TEST_MODE, "test_mode", Mode, Mode::Off);
+    core_param!(TEST_LIST, "test_list", ParamArray<i32, 4>, ParamArray::new([0; 4], 0));
+
+    #[test]
+    fn test_param_values() {
+        assert_eq!(bool::parse_param(None), Ok(true));
+        assert_eq!(bool::parse_param(Some("off")), Ok(false));
+        assert_eq!(bool::parse_param(Some("Y")), Ok(true));
+        assert!(bool::parse_param(Some("maybe")).is_err());
+
+        assert_eq!(u64::parse_param(Some("256M")), Ok(256 << 20));
+        assert_eq!(u32::parse_param(Some("0x10k")), Ok(16 << 10));
+        assert_eq!(u32::parse_param(Some("010")), Ok(8));
+        assert_eq!(i32::parse_param(Some("-12")), Ok(-12));
+        assert_eq!(u32::parse_param(Some("0")), Ok(0));
+        assert_eq!(
+            u8::parse_param(Some("256")),
+            Err(ParamHandleErr::OutOfRange)
+        );
+        assert_eq!(
+            u32::parse_param(Some("-1")),
+            Err(ParamHandleErr::OutOfRange)
+        );
+        assert_eq!(
+            u64::parse_param(Some("16E")),
+            Err(ParamHandleErr::OutOfRange)
+        );
+        assert_eq!(
+            u32::parse_param(Some("12Q")),
+            Err(ParamHandleErr::InvalidValue)
+        );
+        assert_eq!(u32::parse_param(None), Err(ParamHandleErr::MissingValue));
+
+        assert_eq!(Mode::parse_param(Some("force")), Ok(Mode::Force));
+        assert_eq!(
+            Mode::parse_param(Some("auto")),
+            Err(ParamHandleErr::InvalidValue)
+        );
+
+        assert_eq!(module_name("kernel::printk"), "printk");
+        assert_eq!(module_name("amba_pl011"), "amba_pl011");
+        assert!(parameq("foo-bar_baz", "foo_bar-baz"));
+        assert!(!parameq("foo", "foo_"));
+    }
+
+    #[test]
+    fn test_parse_args() {
+        set_test_current();
+        let params = [
+            KernelParam::new("test_bool", |v| TEST_BOOL.parse(v)),
+            KernelParam::new("tests.size", |v| TEST_SIZE.parse(v)),
+            KernelParam::new("tests.name", |v| TEST_NAME.parse(v)),
+            KernelParam::new("test_mode", |v| TEST_MODE.parse(v)),
+            KernelParam::new("test_list", |v| TEST_LIST.parse(v)),
+        ];
+        let mut unknown = std::vec::Vec::new();
+        let rest = parse_args(
+            "test",
+            "test-bool tests.size=1G tests.name=toolongname =zoo test_mode=on foo=bar \
+             test_list=1,-2,0x3 quiet tests.size=2G -- single x=y",
+            &params,
+            |param, val| {
+                unknown.push((param, val));
+                Ok(())
+            },
+        );
+        assert_eq!(rest, Some("single x=y"));
+        assert_eq!(unknown, [("foo", Some("bar")), ("quiet", None)]);
+        assert!(TEST_BOOL.get());
+        // Given twice, the last one is kept.
+        assert_eq!(TEST_SIZE.get(), 2 << 30);
+        // Too long, the default is kept.
+        assert_eq!(TEST_NAME.get().as_str(), "none");
+        assert!(!TEST_NAME.is_set());
+        assert_eq!(TEST_MODE.get(), Mode::On);
+        assert_eq!(TEST_LIST.get().as_slice(), [1, -2, 3]);
+        assert!(ParamArray::<i32, 4>::parse_param(Some("1,2,3,4,5")).is_err());
+    }
+}
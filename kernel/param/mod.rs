@@ -1,14 +1,59 @@
 //! Parameter management
 
 mod parser;
-pub use parser::ParamParser;
+pub use parser::{ParamParser, ParseError};
 
+pub mod kernel_param;
 pub mod obs_param;
 
 /// Parameter handle error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ParamHandleErr {
     /// Unknown parameter
     Unknown,
     /// Parameter too large
     ParameterTooLarge,
+    /// The value can not be parsed
+    InvalidValue,
+    /// The value is out of the range of the parameter
+    OutOfRange,
+    /// The parameter is given more than once
+    Duplicate,
+    /// The parameter needs a value
+    MissingValue,
+}
+
+/// Print a boot warning for a bad parameter, `doing` tells the pass.
+pub fn report_param_err(doing: &str, param: &str, val: Option<&str>, err: ParamHandleErr) {
+    let val = val.unwrap_or("");
+    match err {
+        ParamHandleErr::Unknown => {
+            crate::pr_warn!("{}: Unknown parameter `{}'\n", doing, param);
+        }
+        ParamHandleErr::ParameterTooLarge => {
+            crate::pr_warn!("{}: `{}' too large for parameter `{}'\n", doing, val, param);
+        }
+        ParamHandleErr::InvalidValue => {
+            crate::pr_warn!("{}: `{}' invalid for parameter `{}'\n", doing, val, param);
+        }
+        ParamHandleErr::OutOfRange => {
+            crate::pr_warn!(
+                "{}: `{}' out of range for parameter `{}'\n",
+                doing,
+                val,
+                param
+            );
+        }
+        ParamHandleErr::Duplicate => {
+            crate::pr_warn!("{}: parameter `{}' given more than once\n", doing, param);
+        }
+        ParamHandleErr::MissingValue => {
+            crate::pr_warn!("{}: parameter `{}' needs a value\n", doing, param);
+        }
+    }
+}
+
+/// Print a boot warning for a malformed parameter.
+pub fn report_parse_err(doing: &str, err: &ParseError<'_>) {
+    crate::pr_warn!("{}: {}\n", doing, err);
 }
@@ -38,7 +38,27 @@ macro_rules! early_setup_param {
 
 pub use early_setup_param;
 
-/// for each setup param
+/// A setup parameter handled after the early ones, unless a typed parameter
+/// of the same name exists.
+///
+/// Refer to linux: __setup
+#[macro_export]
+macro_rules! setup_param {
+    ($id:ident, $name:expr, $func:ident) => {
+        #[unsafe(link_section = ".init.setup")]
+        #[used]
+        static $id: $crate::param::obs_param::ObsKernelParam =
+            $crate::param::obs_param::ObsKernelParam {
+                name: $name,
+                func: $func,
+                early: false,
+            };
+    };
+}
+
+pub use setup_param;
+
+/// for each setup param, early or not
 #[cfg(not(test))]
 pub fn for_each_setup_param(mut f: impl FnMut(&ObsKernelParam)) {
     use crate::global_sym::{__setup_end, __setup_start};
@@ -48,8 +68,12 @@ pub fn for_each_setup_param(mut f: impl FnMut(&ObsKernelParam)) {
         let end = __setup_end as *const ObsKernelParam;
         let n = (end as usize - start as usize) / core::mem::size_of::<ObsKernelParam>();
         let slice = core::slice::from_raw_parts(start, n);
-        for p in slice.iter().filter(|p| p.early) {
+        for p in slice.iter() {
             f(p);
         }
     }
 }
+
+/// Host tests are not linked with the setup param section.
+#[cfg(test)]
+pub fn for_each_setup_param(_f: impl FnMut(&ObsKernelParam)) {}
@@ -1,39 +1,87 @@
 //! Parameter parsing
 
+use core::fmt;
+
+/// A malformed parameter, skipped by the parser
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct ParseError<'a> {
+    /// Byte offset of the parameter in the command line
+    pub offset: usize,
+    /// The malformed text
+    pub text: &'a str,
+}
+
+impl fmt::Display for ParseError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "missing parameter name in `{}' at offset {}",
+            self.text, self.offset
+        )
+    }
+}
+
 /// Parameter parser
-#[allow(dead_code)]
+///
+/// Iterating skips malformed parameters, [`ParamParser::next_param`]
+/// reports them.
 pub struct ParamParser<'a> {
     args: &'a str,
+    offset: usize,
 }
 
 impl<'a> ParamParser<'a> {
     /// Create a new parameter parser
     pub fn new(args: &'a str) -> Self {
-        ParamParser { args }
+        ParamParser { args, offset: 0 }
     }
-}
 
-impl<'a> Iterator for ParamParser<'a> {
-    type Item = (&'a str, Option<&'a str>);
+    /// The arguments not parsed yet
+    pub fn remaining(&self) -> &'a str {
+        self.args.trim_start()
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.args.is_empty() {
+    /// Byte offset of the arguments not parsed yet
+    pub fn offset(&self) -> usize {
+        self.offset + self.args.len() - self.remaining().len()
+    }
+
+    /// Next parameter with its byte offset, or the error if it is malformed.
+    pub fn next_param(
+        &mut self,
+    ) -> Option<Result<(usize, &'a str, Option<&'a str>), ParseError<'a>>> {
+        let start = self.offset();
+        let args = self.remaining();
+        if args.is_empty() {
             return None;
         }
 
-        let (rest, param, val) = next_arg(self.args);
+        let (rest, param, val) = next_arg(args);
+        self.offset += self.args.len() - rest.len();
+        self.args = rest;
 
         // if param is empty, val will also be dropped
         if param.is_empty() {
-            if !rest.is_empty() {
-                panic!("rest is not empty");
-            }
-            return None;
+            let text = args[..args.len() - rest.len()].trim_end();
+            return Some(Err(ParseError {
+                offset: start,
+                text,
+            }));
         }
 
-        self.args = rest;
+        Some(Ok((start, param, val)))
+    }
+}
+
+impl<'a> Iterator for ParamParser<'a> {
+    type Item = (&'a str, Option<&'a str>);
 
-        Some((param, val))
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Ok((_, param, val)) = self.next_param()? {
+                return Some((param, val));
+            }
+        }
     }
 }
 
@@ -126,7 +174,7 @@ mod tests {
     #[test]
     fn test_next_arg() {
         let args = "foo=bar,bar2 baz=fuz wiz";
-        let mut parser = ParamParser { args };
+        let mut parser = ParamParser::new(args);
         let next = parser.next();
         assert_eq!(next, Some(("foo", Some("bar,bar2"))));
         let next = parser.next();
@@ -140,23 +188,23 @@ mod tests {
     #[test]
     fn test_empty() {
         let args = "";
-        let mut parser = ParamParser { args };
+        let mut parser = ParamParser::new(args);
         let next = parser.next();
         assert_eq!(next, None);
 
         let args = " ";
-        let mut parser = ParamParser { args };
+        let mut parser = ParamParser::new(args);
         let next = parser.next();
         assert_eq!(next, None);
 
         let args = "\"";
-        let mut parser = ParamParser { args };
+        let mut parser = ParamParser::new(args);
         let next = parser.next();
         assert_eq!(next, None);
 
         // in quotes its raw value
         let args = "\"  ";
-        let mut parser = ParamParser { args };
+        let mut parser = ParamParser::new(args);
         let next = parser.next();
         assert_eq!(next, Some(("  ", None)));
         let next = parser.next();
@@ -166,7 +214,7 @@ mod tests {
     #[test]
     fn test_next_arg_no_value() {
         let args = "foo=";
-        let mut parser = ParamParser { args };
+        let mut parser = ParamParser::new(args);
         let next = parser.next();
         assert_eq!(next, Some(("foo", None)));
         let next = parser.next();
@@ -177,7 +225,7 @@ mod tests {
     fn test_next_no_param() {
         // if only have val, param is None, result is None
         let args = "=zoo";
-        let mut parser = ParamParser { args };
+        let mut parser = ParamParser::new(args);
         let next = parser.next();
         assert_eq!(next, None);
     }
@@ -185,7 +233,7 @@ mod tests {
     #[test]
     fn test_next_arg_quoted() {
         let args = "foo=\"bar,bar2\" baz=fuz \"wiz\"";
-        let mut parser = ParamParser { args };
+        let mut parser = ParamParser::new(args);
         let next = parser.next();
         assert_eq!(next, Some(("foo", Some("bar,bar2"))));
         let next = parser.next();
@@ -196,7 +244,7 @@ mod tests {
         assert_eq!(next, None);
 
         let args = "\"bar,bar2 ";
-        let mut parser = ParamParser { args };
+        let mut parser = ParamParser::new(args);
         let next = parser.next();
         assert_eq!(next, Some(("bar,bar2 ", None)));
     }
@@ -205,7 +253,7 @@ mod tests {
     fn test_space() {
         let args =
             r#"foo="bar baz" hello=world debug="yes" single "standalone" keyonly= path="/a b/c""#;
-        let mut parser = ParamParser { args };
+        let mut parser = ParamParser::new(args);
 
         let next = parser.next();
         assert_eq!(next, Some(("foo", Some("bar baz"))));
@@ -229,4 +277,32 @@ mod tests {
         let next = parser.next();
         assert_eq!(next, None);
     }
+
+    #[test]
+    fn test_malformed() {
+        let args = "=zoo foo \"=bar\" baz=1";
+        let mut parser = ParamParser::new(args);
+        let next = parser.next_param();
+        assert_eq!(
+            next,
+            Some(Err(ParseError {
+                offset: 0,
+                text: "=zoo"
+            }))
+        );
+        assert_eq!(parser.next_param(), Some(Ok((5, "foo", None))));
+        assert!(matches!(
+            parser.next_param(),
+            Some(Err(ParseError { offset: 9, .. }))
+        ));
+        assert_eq!(parser.next_param(), Some(Ok((16, "baz", Some("1")))));
+        assert_eq!(parser.next_param(), None);
+
+        // The iterator skips them
+        let parser = ParamParser::new("=zoo foo = bar");
+        assert_eq!(
+            parser.collect::<std::vec::Vec<_>>(),
+            [("foo", None), ("bar", None)]
+        );
+    }
 }
@@ -1,13 +1,30 @@
 //! console
+//!
+//! Refer to linux: kernel/printk/printk.c
+//!
+//! Boot consoles (`CON_BOOT`) print from early boot, until the real console
+//! of the same device registers and takes over from the record the boot
+//! console stopped at. Every console remembers the next record it prints, so
+//! a record is printed at most once on each console.
+
+use core::sync::atomic::{AtomicBool, AtomicI16, AtomicU32, AtomicU64, AtomicUsize, Ordering};
 
 use crate::bitflags::bitflags;
+use crate::drivers::tty::tty_io::TtyDriver;
+use crate::error::{Error, Result};
 use crate::list::def_node;
+use crate::macros::section_init_text;
+use crate::param::obs_param::early_setup_param;
+use crate::param::ParamHandleErr;
 use crate::sync::arc::Arc;
-use crate::sync::lock::Mutex;
+use crate::sync::lock::{Mutex, RawSpinLockNoIrq};
+
+use super::PRINTK_RB;
 
 bitflags! {
     /// Console flags
     #[repr(transparent)]
+    #[derive(Copy, Clone)]
     #[allow(dead_code)]
     pub struct ConsoleFlags: u32 {
         /// Used by newly registered consoles to avoid duplicate output of messages that were already shown by boot consoles or read by userspace via syslog() syscall.
@@ -31,6 +48,9 @@ bitflags! {
     }
 }
 
+/// Console index meaning any, the `console=` parameter picks it.
+pub const CONSOLE_INDEX_ANY: i16 = -1;
+
 /// console
 #[allow(dead_code)]
 pub struct Console {
@@ -38,8 +58,15 @@ pub struct Console {
     name_len: usize,
     write: Option<fn(&str)>,
     read: Option<fn(&mut [u8]) -> usize>,
-    flags: ConsoleFlags,
-    index: u32,
+    setup: Option<fn(&Console, Option<&str>) -> Result>,
+    flags: AtomicU32,
+    index: AtomicI16,
+    // next record to print, protected by the console lock
+    seq: AtomicU64,
+    // the hardware behind the console, 0 if unknown
+    device: AtomicUsize,
+    // the tty of the console, for /dev/console
+    tty_driver: Option<&'static dyn TtyDriver>,
 }
 
 #[allow(dead_code)]
@@ -56,21 +83,111 @@ impl Console {
     }
 
     /// Default console constructor
-    pub const fn empty(name: &str, flags: ConsoleFlags, index: u32) -> Self {
+    pub const fn empty(name: &str, flags: ConsoleFlags, index: i16) -> Self {
         Self {
             name: Self::name_as_array(name),
             name_len: if name.len() > 16 { 16 } else { name.len() },
             write: None,
             read: None,
-            flags,
-            index,
+            setup: None,
+            flags: AtomicU32::new(flags.bits()),
+            index: AtomicI16::new(index),
+            seq: AtomicU64::new(0),
+            device: AtomicUsize::new(0),
+            tty_driver: None,
         }
     }
 
+    /// Set the write callback.
+    pub const fn with_write(mut self, write: fn(&str)) -> Self {
+        self.write = Some(write);
+        self
+    }
+
+    /// Set the read callback.
+    pub const fn with_read(mut self, read: fn(&mut [u8]) -> usize) -> Self {
+        self.read = Some(read);
+        self
+    }
+
+    /// Set the setup callback, called with the `console=` options when the
+    /// console is enabled.
+    pub const fn with_setup(mut self, setup: fn(&Console, Option<&str>) -> Result) -> Self {
+        self.setup = Some(setup);
+        self
+    }
+
+    /// Set the tty driver, port [`Console::index`] of it backs /dev/console.
+    pub const fn with_tty_driver(mut self, driver: &'static dyn TtyDriver) -> Self {
+        self.tty_driver = Some(driver);
+        self
+    }
+
     /// name str
     pub fn name(&self) -> &str {
         core::str::from_utf8(&self.name[..self.name_len]).unwrap()
     }
+
+    /// Console flags
+    pub fn flags(&self) -> ConsoleFlags {
+        ConsoleFlags::from_bits_truncate(self.flags.load(Ordering::Relaxed))
+    }
+
+    /// Set console flags.
+    pub fn set_flags(&self, flags: ConsoleFlags) {
+        self.flags.fetch_or(flags.bits(), Ordering::Relaxed);
+    }
+
+    /// Clear console flags.
+    pub fn clear_flags(&self, flags: ConsoleFlags) {
+        self.flags.fetch_and(!flags.bits(), Ordering::Relaxed);
+    }
+
+    /// Console index, [`CONSOLE_INDEX_ANY`] until registered.
+    pub fn index(&self) -> i16 {
+        self.index.load(Ordering::Relaxed)
+    }
+
+    /// The hardware behind the console, usually its register base, or 0.
+    pub fn device(&self) -> usize {
+        self.device.load(Ordering::Relaxed)
+    }
+
+    /// Set the hardware behind the console, so the real console of the same
+    /// device replaces this boot console.
+    pub fn set_device(&self, device: usize) {
+        self.device.store(device, Ordering::Relaxed);
+    }
+
+    pub(crate) fn seq(&self) -> u64 {
+        self.seq.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_seq(&self, seq: u64) {
+        self.seq.store(seq, Ordering::Relaxed);
+    }
+
+    /// Write to the console, if it can be written.
+    pub fn write_str(&self, s: &str) {
+        if let Some(write) = self.write {
+            write(s);
+        }
+    }
+
+    fn call_setup(&self, options: Option<&str>) -> Result {
+        match self.setup {
+            Some(setup) => setup(self, options),
+            None => Ok(()),
+        }
+    }
+
+    // Whether this real console takes over from `boot`.
+    fn replaces(&self, boot: &Console) -> bool {
+        match (self.device(), boot.device()) {
+            (0, _) | (_, 0) => self.flags().contains(ConsoleFlags::CON_CONSDEV),
+            (dev, boot_dev) => dev == boot_dev,
+        }
+    }
 }
 
 def_node! {
@@ -86,13 +203,432 @@ pub static GLOBAL_CONSOLE: Mutex<ConsoleList> =
     Mutex::new(ConsoleList::new(), Some("GlobalConsoleList"));
 
 impl ConsoleList {
-    /// register a console
-    pub fn register(&mut self, console: Arc<ConsoleNode>) {
+    /// Register a console.
+    ///
+    /// Boot consoles are always enabled, but refused once a real console is
+    /// registered. A real console is enabled if it matches a `console=`
+    /// entry, or without any entry if it is the first one. It replaces the
+    /// boot consoles of its device, starting from the first record they did
+    /// not print, otherwise it starts from the oldest record with
+    /// `CON_PRINTBUFFER` or from new records only.
+    pub fn register(&mut self, console: Arc<ConsoleNode>) -> Result {
+        if self.is_register(&console) {
+            return Err(Error::Eexist);
+        }
+
+        if console.flags().contains(ConsoleFlags::CON_BOOT) {
+            if self
+                .iter()
+                .any(|c| !c.flags().contains(ConsoleFlags::CON_BOOT))
+            {
+                return Err(Error::Ebusy);
+            }
+            console.set_flags(ConsoleFlags::CON_ENABLED);
+        } else {
+            self.try_enable_console(&console)?;
+        }
+
+        let mut handover: Option<u64> = None;
+        if !console.flags().contains(ConsoleFlags::CON_BOOT) {
+            let keep = KEEP_BOOTCON.load(Ordering::Relaxed);
+            let mut cursor = self.cursor_front_mut();
+            while let Some(c) = cursor.current() {
+                if !c.flags().contains(ConsoleFlags::CON_BOOT) || !console.replaces(c) {
+                    cursor.move_next();
+                    continue;
+                }
+                let seq = c.seq();
+                handover = Some(handover.map_or(seq, |s| s.min(seq)));
+                if keep {
+                    cursor.move_next();
+                } else {
+                    c.clear_flags(ConsoleFlags::CON_ENABLED);
+                    cursor.remove_current();
+                }
+            }
+        }
+
+        let seq = match handover {
+            Some(seq) => seq,
+            None if console.flags().contains(ConsoleFlags::CON_PRINTBUFFER) => {
+                PRINTK_RB.first_seq()
+            }
+            None => PRINTK_RB.next_seq(),
+        };
+        console.set_seq(seq);
         self.push_back(console);
+        Ok(())
+    }
+
+    /// Unregister a console.
+    pub fn unregister(&mut self, console: &Arc<ConsoleNode>) -> Result {
+        if !self.is_register(console) {
+            return Err(Error::Enodev);
+        }
+        // SAFETY: the console is on this list.
+        unsafe {
+            self.remove(console);
+        }
+        let flags = console.flags();
+        console.clear_flags(ConsoleFlags::CON_ENABLED | ConsoleFlags::CON_CONSDEV);
+        if flags.contains(ConsoleFlags::CON_CONSDEV) {
+            if let Some(next) = self.front() {
+                next.set_flags(ConsoleFlags::CON_CONSDEV);
+            }
+        }
+        Ok(())
     }
 
     /// is register
     pub fn is_register(&self, console: &Arc<ConsoleNode>) -> bool {
         self.iter().any(|c| core::ptr::eq(c, &**console))
     }
+
+    // Enable a real console as `console=` asks.
+    fn try_enable_console(&self, console: &Console) -> Result {
+        let cmdline = CONSOLE_CMDLINE.lock();
+        if cmdline.len == 0 {
+            drop(cmdline);
+            // Without `console=`, the first real console backs /dev/console.
+            if self
+                .iter()
+                .any(|c| c.flags().contains(ConsoleFlags::CON_CONSDEV))
+            {
+                return Err(Error::Enoent);
+            }
+            if console.index() == CONSOLE_INDEX_ANY {
+                console.index.store(0, Ordering::Relaxed);
+            }
+            console.call_setup(None)?;
+            console.set_flags(ConsoleFlags::CON_ENABLED | ConsoleFlags::CON_CONSDEV);
+            return Ok(());
+        }
+
+        let found = cmdline.entries[..cmdline.len]
+            .iter()
+            .enumerate()
+            .find(|(_, e)| {
+                e.name() == console.name()
+                    && (console.index() == CONSOLE_INDEX_ANY || console.index() == e.index)
+            })
+            .map(|(i, e)| (*e, i == cmdline.preferred));
+        drop(cmdline);
+
+        let (entry, preferred) = found.ok_or(Error::Enoent)?;
+        console.index.store(entry.index, Ordering::Relaxed);
+        console.call_setup(entry.options())?;
+        console.set_flags(ConsoleFlags::CON_ENABLED);
+        if preferred {
+            console.set_flags(ConsoleFlags::CON_CONSDEV);
+        }
+        Ok(())
+    }
+}
+
+/// Register a console and print the records it is due.
+pub fn register_console(console: Arc<ConsoleNode>) -> Result {
+    GLOBAL_CONSOLE.lock().register(console)?;
+    super::console_flush();
+    Ok(())
+}
+
+/// Unregister a console.
+pub fn unregister_console(console: &Arc<ConsoleNode>) -> Result {
+    GLOBAL_CONSOLE.lock().unregister(console)
+}
+
+/// The tty behind /dev/console, of the preferred console if it has one.
+///
+/// Refer to linux: console_device
+pub fn console_device() -> Option<(&'static dyn TtyDriver, usize)> {
+    let consoles = GLOBAL_CONSOLE.lock();
+    let tty = |c: &ConsoleNode| match (c.tty_driver, usize::try_from(c.index())) {
+        (Some(driver), Ok(index)) if c.flags().contains(ConsoleFlags::CON_ENABLED) => {
+            Some((driver, index))
+        }
+        _ => None,
+    };
+    consoles
+        .iter()
+        .filter(|c| c.flags().contains(ConsoleFlags::CON_CONSDEV))
+        .find_map(tty)
+        .or_else(|| consoles.iter().find_map(tty))
+}
+
+const MAX_CMDLINECONSOLES: usize = 8;
+const CONSOLE_OPTIONS_MAX: usize = 32;
+
+// A `console=name<index>[,options]` entry.
+#[derive(Copy, Clone)]
+struct ConsoleCmdline {
+    name: [u8; 16],
+    name_len: usize,
+    index: i16,
+    options: [u8; CONSOLE_OPTIONS_MAX],
+    options_len: usize,
+}
+
+impl ConsoleCmdline {
+    const fn empty() -> Self {
+        Self {
+            name: [0; 16],
+            name_len: 0,
+            index: 0,
+            options: [0; CONSOLE_OPTIONS_MAX],
+            options_len: 0,
+        }
+    }
+
+    fn name(&self) -> &str {
+        core::str::from_utf8(&self.name[..self.name_len]).unwrap()
+    }
+
+    fn options(&self) -> Option<&str> {
+        match self.options_len {
+            0 => None,
+            len => Some(core::str::from_utf8(&self.options[..len]).unwrap()),
+        }
+    }
+}
+
+struct ConsoleCmdlines {
+    entries: [ConsoleCmdline; MAX_CMDLINECONSOLES],
+    len: usize,
+    // the last entry added, it backs /dev/console
+    preferred: usize,
+}
+
+static CONSOLE_CMDLINE: RawSpinLockNoIrq<ConsoleCmdlines> = RawSpinLockNoIrq::new(
+    ConsoleCmdlines {
+        entries: [const { ConsoleCmdline::empty() }; MAX_CMDLINECONSOLES],
+        len: 0,
+        preferred: 0,
+    },
+    Some("console_cmdline"),
+);
+
+static KEEP_BOOTCON: AtomicBool = AtomicBool::new(false);
+
+/// Add a console the way `console=` does, it becomes the preferred one.
+///
+/// Returns `E2big` if the name or options are too long, or there are too
+/// many entries.
+pub fn add_preferred_console(name: &str, index: i16, options: Option<&str>) -> Result {
+    let options = options.unwrap_or("");
+    if name.is_empty() {
+        return Err(Error::Einval);
+    }
+    if name.len() > 16 || options.len() > CONSOLE_OPTIONS_MAX {
+        return Err(Error::E2big);
+    }
+
+    let mut cmdline = CONSOLE_CMDLINE.lock();
+    let len = cmdline.len;
+    let i = match cmdline.entries[..len]
+        .iter()
+        .position(|e| e.name() == name && e.index == index)
+    {
+        Some(i) => i,
+        None if len < MAX_CMDLINECONSOLES => {
+            cmdline.len += 1;
+            len
+        }
+        None => return Err(Error::E2big),
+    };
+
+    let entry = &mut cmdline.entries[i];
+    entry.name[..name.len()].copy_from_slice(name.as_bytes());
+    entry.name_len = name.len();
+    entry.index = index;
+    entry.options[..options.len()].copy_from_slice(options.as_bytes());
+    entry.options_len = options.len();
+    cmdline.preferred = i;
+    Ok(())
+}
+
+#[section_init_text]
+fn console_setup(val: Option<&str>) -> Result<(), ParamHandleErr> {
+    let val = val.ok_or(ParamHandleErr::MissingValue)?;
+    let (dev, options) = match val.split_once(',') {
+        Some((dev, options)) => (dev, Some(options)),
+        None => (val, None),
+    };
+    // ttyS0 is console ttyS, index 0
+    let split = dev.find(|c: char| c.is_ascii_digit()).unwrap_or(dev.len());
+    let (name, index) = dev.split_at(split);
+    let index = match index {
+        "" => 0,
+        index => index
+            .parse::<i16>()
+            .map_err(|_| ParamHandleErr::InvalidValue)?,
+    };
+    add_preferred_console(name, index, options).map_err(|e| match e {
+        Error::E2big => ParamHandleErr::ParameterTooLarge,
+        _ => ParamHandleErr::InvalidValue,
+    })
+}
+
+#[section_init_text]
+fn keep_bootcon_setup(_val: Option<&str>) -> Result<(), ParamHandleErr> {
+    KEEP_BOOTCON.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+early_setup_param!(CONSOLE_PARAM, "console", console_setup);
+early_setup_param!(KEEP_BOOTCON_PARAM, "keep_bootcon", keep_bootcon_setup);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alloc::AllocFlags;
+    use crate::drivers::tty::termios::Termios;
+    use crate::drivers::tty::tty_io::{tty_init, Tty, TtyPort, TTYAUX_MAJOR};
+    use crate::fs::char_dev::{chrdev_open, mkdev};
+    use crate::schedule::task::set_test_current;
+    use std::string::String;
+    use std::sync::Mutex as StdMutex;
+    use std::vec::Vec;
+
+    static BOOT_OUT: StdMutex<String> = StdMutex::new(String::new());
+    static REAL_OUT: StdMutex<String> = StdMutex::new(String::new());
+    static REAL_OPTIONS: StdMutex<String> = StdMutex::new(String::new());
+
+    fn boot_write(s: &str) {
+        BOOT_OUT.lock().unwrap().push_str(s);
+    }
+
+    fn real_write(s: &str) {
+        REAL_OUT.lock().unwrap().push_str(s);
+    }
+
+    fn real_setup(_con: &Console, options: Option<&str>) -> Result {
+        REAL_OPTIONS.lock().unwrap().push_str(options.unwrap_or(""));
+        Ok(())
+    }
+
+    // The tty of the real console, keeps what is written to it.
+    struct TestTty {
+        out: StdMutex<Vec<u8>>,
+    }
+
+    impl TtyPort for TestTty {
+        fn activate(&self, _tty: &'static Tty) -> Result {
+            Ok(())
+        }
+        fn shutdown(&self) {}
+        fn write(&self, buf: &[u8]) -> usize {
+            self.out.lock().unwrap().extend_from_slice(buf);
+            buf.len()
+        }
+        fn write_room(&self) -> usize {
+            4096
+        }
+        fn chars_in_buffer(&self) -> usize {
+            0
+        }
+        fn set_termios(&self, _termios: &Termios) {}
+    }
+
+    impl TtyDriver for TestTty {
+        fn name(&self) -> &'static str {
+            "ttyTEST"
+        }
+        fn major(&self) -> u32 {
+            242
+        }
+        fn minor_start(&self) -> u32 {
+            0
+        }
+        fn num(&self) -> usize {
+            2
+        }
+        fn port(&'static self, index: usize) -> Option<&'static dyn TtyPort> {
+            (index == 1).then_some(self as &dyn TtyPort)
+        }
+    }
+
+    static TEST_TTY: TestTty = TestTty {
+        out: StdMutex::new(Vec::new()),
+    };
+
+    #[test]
+    fn test_console_handover() {
+        set_test_current();
+        assert!(console_setup(Some("ttyTEST1,9600")).is_ok());
+        assert!(console_setup(None).is_err());
+
+        crate::pr_info!("console test: before boot console");
+        let boot = Arc::new(
+            ConsoleNode::new(
+                Console::empty(
+                    "bootTEST",
+                    ConsoleFlags::CON_BOOT | ConsoleFlags::CON_PRINTBUFFER,
+                    0,
+                )
+                .with_write(boot_write),
+            ),
+            AllocFlags::GFP_KERNEL,
+        )
+        .unwrap();
+        boot.set_device(0x1000);
+        assert!(register_console(boot.clone()).is_ok());
+        assert_eq!(register_console(boot.clone()), Err(Error::Eexist));
+        crate::pr_info!("console test: on boot console");
+        crate::pr_debug!("console test: too verbose");
+
+        let real = Arc::new(
+            ConsoleNode::new(
+                Console::empty("ttyTEST", ConsoleFlags::CON_PRINTBUFFER, CONSOLE_INDEX_ANY)
+                    .with_write(real_write)
+                    .with_setup(real_setup)
+                    .with_tty_driver(&TEST_TTY),
+            ),
+            AllocFlags::GFP_KERNEL,
+        )
+        .unwrap();
+        real.set_device(0x1000);
+        assert!(register_console(real.clone()).is_ok());
+        assert_eq!(real.index(), 1);
+        assert_eq!(REAL_OPTIONS.lock().unwrap().as_str(), "9600");
+        assert!(real
+            .flags()
+            .contains(ConsoleFlags::CON_ENABLED | ConsoleFlags::CON_CONSDEV));
+        assert!(!GLOBAL_CONSOLE.lock().is_register(&boot));
+        crate::pr_info!("console test: on real console");
+
+        // /dev/console opens the tty of the preferred console.
+        assert!(tty_init().is_ok());
+        let file = chrdev_open(mkdev(TTYAUX_MAJOR, 1)).unwrap();
+        assert_eq!(file.write(b"console\n").unwrap(), 8);
+        assert_eq!(TEST_TTY.out.lock().unwrap().as_slice(), b"console\r\n");
+        drop(file);
+
+        // Not on the command line, and too late for a boot console.
+        let other = Arc::new(
+            ConsoleNode::new(Console::empty("ttyOTHER", ConsoleFlags::CON_PRINTBUFFER, 0)),
+            AllocFlags::GFP_KERNEL,
+        )
+        .unwrap();
+        assert_eq!(register_console(other), Err(Error::Enoent));
+        let late = Arc::new(
+            ConsoleNode::new(Console::empty("bootLATE", ConsoleFlags::CON_BOOT, 0)),
+            AllocFlags::GFP_KERNEL,
+        )
+        .unwrap();
+        assert_eq!(register_console(late), Err(Error::Ebusy));
+        assert!(unregister_console(&real).is_ok());
+        assert_eq!(
+            chrdev_open(mkdev(TTYAUX_MAJOR, 1)).err(),
+            Some(Error::Enodev)
+        );
+
+        let boot_out = BOOT_OUT.lock().unwrap();
+        let real_out = REAL_OUT.lock().unwrap();
+        assert_eq!(boot_out.matches("before boot console").count(), 1);
+        assert_eq!(boot_out.matches("on boot console").count(), 1);
+        assert!(!boot_out.contains("too verbose"));
+        assert!(!boot_out.contains("on real console"));
+        assert!(!real_out.contains("boot console"));
+        assert_eq!(real_out.matches("on real console").count(), 1);
+    }
 }
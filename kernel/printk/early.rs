@@ -0,0 +1,19 @@
+//! Early printing
+//!
+//! Writes straight to the early debug uart, usable before any console is
+//! registered and from contexts where taking a lock is not allowed.
+
+/// Early debug uart writer.
+pub struct EarlyWriter;
+
+impl core::fmt::Write for EarlyWriter {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        #[cfg(CONFIG_ARM64)]
+        crate::arch::arm64::early_debug::early_uart_put_str(s);
+        #[cfg(test)]
+        std::eprint!("{}", s);
+        #[cfg(not(any(CONFIG_ARM64, test)))]
+        let _ = s;
+        Ok(())
+    }
+}
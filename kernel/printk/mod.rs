@@ -1,3 +1,238 @@
 //! printk
+//!
+//! Refer to linux: kernel/printk/printk.c
+//!
+//! Messages are stored in the [`printk_ringbuffer`] first. Then whoever gets
+//! the console lock prints every pending record to the enabled consoles,
+//! callers that do not get it leave their records to the lock holder. So
+//! printk never waits for a slow console. Each console tracks the next record
+//! it prints, a newly registered one may start from older records.
 
 pub mod console;
+pub mod early;
+pub mod printk_ringbuffer;
+
+use core::fmt;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use crate::macros::section_init_text;
+use crate::param::kernel_param::module_param;
+use crate::param::obs_param::early_setup_param;
+use crate::param::ParamHandleErr;
+use console::{Console, ConsoleFlags, GLOBAL_CONSOLE};
+use printk_ringbuffer::{PrintkRecord, PrintkRingBuffer};
+
+/// Kernel log levels, lower is more important.
+///
+/// Refer to linux: include/linux/kern_levels.h
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[repr(u8)]
+pub enum LogLevel {
+    /// System is unusable
+    Emerg = 0,
+    /// Action must be taken immediately
+    Alert = 1,
+    /// Critical conditions
+    Crit = 2,
+    /// Error conditions
+    Err = 3,
+    /// Warning conditions
+    Warning = 4,
+    /// Normal but significant condition
+    Notice = 5,
+    /// Informational
+    Info = 6,
+    /// Debug-level messages
+    Debug = 7,
+}
+
+/// Messages more important than this are printed on consoles by default.
+pub const CONSOLE_LOGLEVEL_DEFAULT: u8 = 7;
+/// Console log level with `quiet`.
+pub const CONSOLE_LOGLEVEL_QUIET: u8 = 4;
+/// Console log level with `debug`, everything is printed.
+pub const CONSOLE_LOGLEVEL_DEBUG: u8 = 10;
+
+static CONSOLE_LOGLEVEL: AtomicU8 = AtomicU8::new(CONSOLE_LOGLEVEL_DEFAULT);
+
+/// Messages with a level below this are printed on consoles.
+pub fn console_loglevel() -> u8 {
+    CONSOLE_LOGLEVEL.load(Ordering::Relaxed)
+}
+
+/// Set the console log level.
+pub fn set_console_loglevel(level: u8) {
+    CONSOLE_LOGLEVEL.store(level, Ordering::Relaxed);
+}
+
+module_param!(PRINTK_TIME, "time", bool, cfg!(CONFIG_PRINTK_TIME));
+
+/// The kernel log
+pub static PRINTK_RB: PrintkRingBuffer = PrintkRingBuffer::new();
+
+/// Log a message, usually through [`pr_info!`] and friends.
+pub fn printk(level: LogLevel, args: fmt::Arguments<'_>) {
+    let ts_nsec = crate::time::ktime_get().max(0) as u64;
+    let cpu = crate::cpu::smp_processor_id() as u32;
+    PRINTK_RB.write(level, ts_nsec, cpu, args);
+    console_flush();
+}
+
+/// Format a record the way consoles print it, with a trailing newline.
+pub fn record_print_text(rec: &PrintkRecord, w: &mut dyn fmt::Write) -> fmt::Result {
+    if PRINTK_TIME.get() {
+        let ts = rec.ts_nsec();
+        write!(
+            w,
+            "[{:5}.{:06}] ",
+            ts / 1_000_000_000,
+            ts % 1_000_000_000 / 1000
+        )?;
+    }
+    w.write_str(rec.text())?;
+    w.write_char('\n')
+}
+
+// Writes to one console.
+struct ConsoleWriter<'a>(&'a Console);
+
+impl fmt::Write for ConsoleWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.write_str(s);
+        Ok(())
+    }
+}
+
+/// Print the pending records on the enabled consoles.
+///
+/// Does nothing if another context holds the console lock, it prints our
+/// records before releasing it.
+pub fn console_flush() {
+    loop {
+        let Some(consoles) = GLOBAL_CONSOLE.try_lock() else {
+            return;
+        };
+        let mut next = None;
+        for con in consoles.iter() {
+            if con.flags().contains(ConsoleFlags::CON_ENABLED) {
+                let seq = console_emit_pending(con);
+                next = Some(next.map_or(seq, |n: u64| n.min(seq)));
+            }
+        }
+        drop(consoles);
+
+        // A record committed before the unlock may have been left to us.
+        match next {
+            Some(seq) if PRINTK_RB.read(seq).is_some() => continue,
+            _ => return,
+        }
+    }
+}
+
+// Print the records `con` has not printed yet, return the next one.
+fn console_emit_pending(con: &Console) -> u64 {
+    let mut seq = con.seq();
+    loop {
+        // Records that were overwritten are lost.
+        seq = seq.max(PRINTK_RB.first_seq());
+        // None if not committed yet, its writer flushes it.
+        let Some(rec) = PRINTK_RB.read(seq) else {
+            break;
+        };
+        seq += 1;
+        if (rec.level() as u8) < console_loglevel() {
+            let _ = record_print_text(&rec, &mut ConsoleWriter(con));
+        }
+    }
+    con.set_seq(seq);
+    seq
+}
+
+/// Log a message with a [`LogLevel`].
+///
+/// ```
+/// use kernel::printk;
+/// use kernel::printk::LogLevel;
+///
+/// printk!(LogLevel::Notice, "booting on cpu {}\n", 0);
+/// ```
+#[macro_export]
+macro_rules! printk {
+    ($level:expr, $($arg:tt)+) => {
+        $crate::printk::printk($level, format_args!($($arg)+))
+    };
+}
+
+/// Log an emergency message.
+#[macro_export]
+macro_rules! pr_emerg {
+    ($($arg:tt)+) => { $crate::printk!($crate::printk::LogLevel::Emerg, $($arg)+) };
+}
+
+/// Log an alert message.
+#[macro_export]
+macro_rules! pr_alert {
+    ($($arg:tt)+) => { $crate::printk!($crate::printk::LogLevel::Alert, $($arg)+) };
+}
+
+/// Log a critical message.
+#[macro_export]
+macro_rules! pr_crit {
+    ($($arg:tt)+) => { $crate::printk!($crate::printk::LogLevel::Crit, $($arg)+) };
+}
+
+/// Log an error message.
+#[macro_export]
+macro_rules! pr_err {
+    ($($arg:tt)+) => { $crate::printk!($crate::printk::LogLevel::Err, $($arg)+) };
+}
+
+/// Log a warning message.
+#[macro_export]
+macro_rules! pr_warn {
+    ($($arg:tt)+) => { $crate::printk!($crate::printk::LogLevel::Warning, $($arg)+) };
+}
+
+/// Log a notice message.
+#[macro_export]
+macro_rules! pr_notice {
+    ($($arg:tt)+) => { $crate::printk!($crate::printk::LogLevel::Notice, $($arg)+) };
+}
+
+/// Log an informational message.
+#[macro_export]
+macro_rules! pr_info {
+    ($($arg:tt)+) => { $crate::printk!($crate::printk::LogLevel::Info, $($arg)+) };
+}
+
+/// Log a debug message.
+#[macro_export]
+macro_rules! pr_debug {
+    ($($arg:tt)+) => { $crate::printk!($crate::printk::LogLevel::Debug, $($arg)+) };
+}
+
+#[section_init_text]
+fn loglevel_setup(val: Option<&str>) -> Result<(), ParamHandleErr> {
+    let level = val
+        .ok_or(ParamHandleErr::MissingValue)?
+        .parse::<u8>()
+        .map_err(|_| ParamHandleErr::InvalidValue)?;
+    set_console_loglevel(level);
+    Ok(())
+}
+
+#[section_init_text]
+fn quiet_setup(_val: Option<&str>) -> Result<(), ParamHandleErr> {
+    set_console_loglevel(CONSOLE_LOGLEVEL_QUIET);
+    Ok(())
+}
+
+#[section_init_text]
+fn debug_setup(_val: Option<&str>) -> Result<(), ParamHandleErr> {
+    set_console_loglevel(CONSOLE_LOGLEVEL_DEBUG);
+    Ok(())
+}
+
+early_setup_param!(LOGLEVEL_PARAM, "loglevel", loglevel_setup);
+early_setup_param!(QUIET_PARAM, "quiet", quiet_setup);
+early_setup_param!(DEBUG_PARAM, "debug", debug_setup);
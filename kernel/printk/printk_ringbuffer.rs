@@ -0,0 +1,344 @@
+//! printk ring buffer
+//!
+//! Refer to linux: kernel/printk/printk_ringbuffer.c
+//!
+//! Linux keeps the text of the records in a separate data ring of variable
+//! sized blocks. Here every record has a fixed size text slot, which is much
+//! simpler, at the cost of truncating lines longer than [`LOG_LINE_MAX`].
+//!
+//! Writers reserve a sequence number with one atomic add, and own the slot
+//! `seq % PRB_RECORDS` until they commit it. Readers copy a record out, and
+//! throw the copy away if the slot changed meanwhile. Neither side takes a
+//! lock, so records can be stored from any context, even NMI-like ones. A
+//! writer that interrupted the writer of the previous lap of its slot cannot
+//! wait for it, it drops its record after [`WRITE_SPIN_MAX`] tries.
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::hint::spin_loop;
+use core::sync::atomic::{fence, AtomicU64, Ordering};
+
+use super::LogLevel;
+
+/// Number of records kept, older ones are overwritten.
+pub const PRB_RECORDS: usize = 512;
+
+/// Longest text of a record, longer text is truncated.
+pub const LOG_LINE_MAX: usize = 240;
+
+// A slot state is `seq << STATE_SHIFT | state` of its current record.
+const STATE_SHIFT: u32 = 2;
+const STATE_RESERVED: u64 = 1;
+const STATE_COMMITTED: u64 = 2;
+const STATE_MASK: u64 = (1 << STATE_SHIFT) - 1;
+
+/// Tries of a writer waiting for the previous lap of its slot to commit,
+/// before its record is dropped.
+pub const WRITE_SPIN_MAX: u32 = 1 << 16;
+
+#[derive(Copy, Clone)]
+struct RecordInfo {
+    ts_nsec: u64,
+    cpu: u32,
+    level: LogLevel,
+    text_len: u16,
+}
+
+struct Slot {
+    state: AtomicU64,
+    info: UnsafeCell<RecordInfo>,
+    text: UnsafeCell<[u8; LOG_LINE_MAX]>,
+}
+
+impl Slot {
+    const fn new() -> Self {
+        Self {
+            // Sequence 0 is neither reserved nor committed.
+            state: AtomicU64::new(0),
+            info: UnsafeCell::new(RecordInfo {
+                ts_nsec: 0,
+                cpu: 0,
+                level: LogLevel::Emerg,
+                text_len: 0,
+            }),
+            text: UnsafeCell::new([0; LOG_LINE_MAX]),
+        }
+    }
+}
+
+/// A record copied out of the ring buffer.
+#[derive(Clone)]
+pub struct PrintkRecord {
+    seq: u64,
+    info: RecordInfo,
+    text: [u8; LOG_LINE_MAX],
+}
+
+impl PrintkRecord {
+    /// Sequence number
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Monotonic time the record was stored at, in nanoseconds.
+    pub fn ts_nsec(&self) -> u64 {
+        self.info.ts_nsec
+    }
+
+    /// Cpu the record was stored on.
+    pub fn cpu(&self) -> u32 {
+        self.info.cpu
+    }
+
+    /// Log level
+    pub fn level(&self) -> LogLevel {
+        self.info.level
+    }
+
+    /// Message text, without the trailing newline.
+    pub fn text(&self) -> &str {
+        let text = &self.text[..self.info.text_len as usize];
+        // SAFETY: the writer only truncates text at char boundaries.
+        unsafe { core::str::from_utf8_unchecked(text) }
+    }
+}
+
+// Formats into a text slot, truncating at a char boundary.
+struct TextWriter<'a> {
+    buf: &'a mut [u8; LOG_LINE_MAX],
+    len: usize,
+    truncated: bool,
+}
+
+impl fmt::Write for TextWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.truncated {
+            return Ok(());
+        }
+        let mut n = s.len().min(LOG_LINE_MAX - self.len);
+        if n < s.len() {
+            self.truncated = true;
+            while !s.is_char_boundary(n) {
+                n -= 1;
+            }
+        }
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+/// The printk ring buffer
+pub struct PrintkRingBuffer {
+    // next sequence number to reserve
+    head: AtomicU64,
+    slots: [Slot; PRB_RECORDS],
+}
+
+// SAFETY: a slot is only written by the writer that reserved it, and readers
+// validate their copies against the slot state.
+unsafe impl Sync for PrintkRingBuffer {}
+
+impl PrintkRingBuffer {
+    /// Create an empty ring buffer
+    pub const fn new() -> Self {
+        Self {
+            head: AtomicU64::new(0),
+            slots: [const { Slot::new() }; PRB_RECORDS],
+        }
+    }
+
+    /// Sequence number of the next record to be stored.
+    pub fn next_seq(&self) -> u64 {
+        self.head.load(Ordering::Acquire)
+    }
+
+    /// Sequence number of the oldest record that may still be readable.
+    pub fn first_seq(&self) -> u64 {
+        self.next_seq().saturating_sub(PRB_RECORDS as u64)
+    }
+
+    fn slot(&self, seq: u64) -> &Slot {
+        &self.slots[seq as usize % PRB_RECORDS]
+    }
+
+    /// Store a record, return its sequence number.
+    ///
+    /// A trailing newline is dropped, readers print one per record.
+    pub fn write(&self, level: LogLevel, ts_nsec: u64, cpu: u32, args: fmt::Arguments<'_>) -> u64 {
+        let seq = self.head.fetch_add(1, Ordering::Relaxed);
+        let slot = self.slot(seq);
+        let reserved = (seq << STATE_SHIFT) | STATE_RESERVED;
+
+        // Wait for the writer of the previous lap to commit.
+        let mut tries = 0;
+        loop {
+            let state = slot.state.load(Ordering::Relaxed);
+            if state >> STATE_SHIFT > seq {
+                // We were so slow a later lap took the slot, the record is lost.
+                return seq;
+            }
+            if tries == WRITE_SPIN_MAX {
+                // We may have interrupted that writer, the record is lost.
+                return seq;
+            }
+            tries += 1;
+            if state & STATE_MASK != STATE_RESERVED
+                && slot
+                    .state
+                    .compare_exchange(state, reserved, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+            {
+                break;
+            }
+            spin_loop();
+        }
+
+        // SAFETY: we reserved the slot.
+        let (info, text) = unsafe { (&mut *slot.info.get(), &mut *slot.text.get()) };
+        let mut w = TextWriter {
+            buf: text,
+            len: 0,
+            truncated: false,
+        };
+        let _ = fmt::write(&mut w, args);
+        let mut len = w.len;
+        if len > 0 && text[len - 1] == b'\n' {
+            len -= 1;
+        }
+        *info = RecordInfo {
+            ts_nsec,
+            cpu,
+            level,
+            text_len: len as u16,
+        };
+
+        slot.state
+            .store((seq << STATE_SHIFT) | STATE_COMMITTED, Ordering::Release);
+        seq
+    }
+
+    /// Copy record `seq` out.
+    ///
+    /// Returns None if it is not committed yet, or was already overwritten.
+    pub fn read(&self, seq: u64) -> Option<PrintkRecord> {
+        let slot = self.slot(seq);
+        let committed = (seq << STATE_SHIFT) | STATE_COMMITTED;
+        if slot.state.load(Ordering::Acquire) != committed {
+            return None;
+        }
+        // SAFETY: the copies may be torn by a writer of a later lap, in which
+        // case the state changed and they are thrown away.
+        let (info, text) = unsafe {
+            (
+                core::ptr::read_volatile(slot.info.get()),
+                core::ptr::read_volatile(slot.text.get()),
+            )
+        };
+        fence(Ordering::Acquire);
+        if slot.state.load(Ordering::Relaxed) != committed {
+            return None;
+        }
+        Some(PrintkRecord { seq, info, text })
+    }
+}
+
+impl Default for PrintkRingBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::boxed::Box;
+    use std::format;
+    use std::string::String;
+
+    #[test]
+    fn test_ringbuffer() {
+        let prb = Box::new(PrintkRingBuffer::new());
+        assert!(prb.read(0).is_none());
+
+        let seq = prb.write(LogLevel::Info, 42, 1, format_args!("hello {}\n", 7));
+        let rec = prb.read(seq).unwrap();
+        assert_eq!(rec.text(), "hello 7");
+        assert_eq!(rec.level(), LogLevel::Info);
+        assert_eq!((rec.ts_nsec(), rec.cpu(), rec.seq()), (42, 1, 0));
+
+        // Truncated at a char boundary.
+        let long: String = core::iter::repeat('é').take(LOG_LINE_MAX).collect();
+        let seq = prb.write(LogLevel::Err, 0, 0, format_args!("{}", long));
+        let text = String::from(prb.read(seq).unwrap().text());
+        assert_eq!(text.len(), LOG_LINE_MAX);
+        assert!(long.starts_with(&text));
+
+        // Wrap around, old records are overwritten.
+        for i in 0..PRB_RECORDS as u64 {
+            prb.write(LogLevel::Debug, i, 0, format_args!("{}", i));
+        }
+        assert!(prb.read(0).is_none());
+        assert_eq!(prb.first_seq(), 2);
+        let last = prb.next_seq() - 1;
+        assert_eq!(
+            prb.read(last).unwrap().text(),
+            format!("{}", PRB_RECORDS - 1)
+        );
+    }
+
+    #[test]
+    fn test_ringbuffer_wrap_over_interrupted_writer() {
+        let prb = Box::new(PrintkRingBuffer::new());
+        // A writer interrupted before it commits record 0.
+        let seq = prb.head.fetch_add(1, Ordering::Relaxed);
+        prb.slot(seq)
+            .state
+            .store((seq << STATE_SHIFT) | STATE_RESERVED, Ordering::Relaxed);
+
+        // The interrupt wraps the ring, its record on slot 0 is dropped.
+        for i in 1..=PRB_RECORDS as u64 {
+            assert_eq!(prb.write(LogLevel::Info, 0, 0, format_args!("{}", i)), i);
+        }
+        assert!(prb.read(PRB_RECORDS as u64).is_none());
+        assert_eq!(prb.read(PRB_RECORDS as u64 - 1).unwrap().text(), "511");
+
+        // The next lap goes on once the interrupted writer committed.
+        prb.slot(seq)
+            .state
+            .store((seq << STATE_SHIFT) | STATE_COMMITTED, Ordering::Release);
+        let seq = prb.write(LogLevel::Info, 0, 0, format_args!("again"));
+        assert_eq!(seq, PRB_RECORDS as u64 + 1);
+        for _ in 0..PRB_RECORDS - 2 {
+            prb.write(LogLevel::Info, 0, 0, format_args!("again"));
+        }
+        let seq = prb.write(LogLevel::Info, 0, 0, format_args!("slot 0"));
+        assert_eq!(seq, 2 * PRB_RECORDS as u64);
+        assert_eq!(prb.read(seq).unwrap().text(), "slot 0");
+    }
+
+    #[test]
+    fn test_ringbuffer_concurrent() {
+        static PRB: PrintkRingBuffer = PrintkRingBuffer::new();
+        const PER_THREAD: usize = 100;
+
+        let writers: std::vec::Vec<_> = (0..4)
+            .map(|t| {
+                std::thread::spawn(move || {
+                    for i in 0..PER_THREAD {
+                        PRB.write(LogLevel::Info, i as u64, t, format_args!("{}:{}", t, i));
+                    }
+                })
+            })
+            .collect();
+        for w in writers {
+            w.join().unwrap();
+        }
+
+        assert_eq!(PRB.next_seq(), 4 * PER_THREAD as u64);
+        for seq in PRB.first_seq()..PRB.next_seq() {
+            let rec = PRB.read(seq).unwrap();
+            assert_eq!(rec.text(), format!("{}:{}", rec.cpu(), rec.ts_nsec()));
+        }
+    }
+}
@@ -5,10 +5,65 @@ pub mod task;
 
 mod wait_list;
 
+use core::sync::atomic::{AtomicBool, Ordering};
+
+pub use crate::time::sleep::schedule_timeout;
 pub use task::CurrentTask;
 pub use wait_list::{WaitQueue, WaitTaskList, WaitTaskNode};
 
+use task::{TaskRef, TaskState};
+
 /// Get current task
 pub fn current() -> CurrentTask {
     CurrentTask::get()
 }
+
+static SCHEDULER_RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Is the scheduler running?
+///
+/// Before it is, the boot task runs with preemption disabled and sleeping
+/// in atomic context is expected.
+#[inline]
+pub fn scheduler_running() -> bool {
+    SCHEDULER_RUNNING.load(Ordering::Relaxed)
+}
+
+/// Mark the scheduler running.
+pub fn set_scheduler_running() {
+    SCHEDULER_RUNNING.store(true, Ordering::Relaxed);
+}
+
+/// Give up the cpu until the current task is woken up.
+///
+/// Callers set the task state before checking their wait condition, so a
+/// wakeup between the check and `schedule` is not lost.
+///
+/// There is no run queue yet, so the current task keeps its cpu and spins
+/// until someone makes it `RUNNING` again.
+pub fn schedule() {
+    let curr = current();
+    while !curr.state().contains(TaskState::RUNNING) {
+        // Host tests may share one cpu with the task that wakes us.
+        #[cfg(test)]
+        std::thread::yield_now();
+        #[cfg(not(test))]
+        core::hint::spin_loop();
+    }
+}
+
+/// Timeout of a sleep that only ends on wakeup.
+pub const MAX_SCHEDULE_TIMEOUT: usize = isize::MAX as usize;
+
+/// Does the current task have a signal pending?
+///
+/// There are no signals yet, so interruptible sleeps only end on wakeup.
+#[inline]
+pub fn signal_pending() -> bool {
+    false
+}
+
+/// Wake up a sleeping task, return whether it was sleeping.
+pub fn wake_up_process(task: &TaskRef) -> bool {
+    task.try_wake_up(TaskState::NORMAL)
+}
@@ -1,8 +1,37 @@
 //! Rynux preempt module
+//!
+//! The preempt count is split like Linux:
+//!
+//! ```text
+//!         PREEMPT_MASK:   0x000000ff
+//!         SOFTIRQ_MASK:   0x0000ff00
+//!         HARDIRQ_MASK:   0x000f0000
+//!             NMI_MASK:   0x00f00000
+//! ```
 use crate::schedule::task::CurrentTask;
 
+const PREEMPT_BITS: u64 = 8;
+const SOFTIRQ_BITS: u64 = 8;
+const HARDIRQ_BITS: u64 = 4;
+const NMI_BITS: u64 = 4;
+
 const PREEMPT_SHIFT: u64 = 0;
+const SOFTIRQ_SHIFT: u64 = PREEMPT_SHIFT + PREEMPT_BITS;
+const HARDIRQ_SHIFT: u64 = SOFTIRQ_SHIFT + SOFTIRQ_BITS;
+const NMI_SHIFT: u64 = HARDIRQ_SHIFT + HARDIRQ_BITS;
+
 const PREEMPT_OFFSET: u64 = 1 << PREEMPT_SHIFT;
+/// Hardirq offset in preempt count.
+pub const HARDIRQ_OFFSET: u64 = 1 << HARDIRQ_SHIFT;
+
+/// Preempt disable depth mask.
+pub const PREEMPT_MASK: u64 = ((1 << PREEMPT_BITS) - 1) << PREEMPT_SHIFT;
+/// Softirq mask.
+pub const SOFTIRQ_MASK: u64 = ((1 << SOFTIRQ_BITS) - 1) << SOFTIRQ_SHIFT;
+/// Hardirq nesting mask.
+pub const HARDIRQ_MASK: u64 = ((1 << HARDIRQ_BITS) - 1) << HARDIRQ_SHIFT;
+/// NMI nesting mask.
+pub const NMI_MASK: u64 = ((1 << NMI_BITS) - 1) << NMI_SHIFT;
 
 /// Task init Disable preemption until the scheduler is running.
 pub const INIT_TASK_PREEMPT_COUNT: u64 = PREEMPT_OFFSET;
@@ -20,3 +49,27 @@ pub fn preempt_enable() {
     let curr = CurrentTask::get();
     curr.preempt_count_sub(1);
 }
+
+/// Current preempt count.
+#[inline(always)]
+pub fn preempt_count() -> u32 {
+    CurrentTask::get().preempt_count()
+}
+
+/// Are we running in hardirq context?
+#[inline(always)]
+pub fn in_hardirq() -> bool {
+    preempt_count() as u64 & HARDIRQ_MASK != 0
+}
+
+/// Enter hardirq context, called by irq entry before running handlers.
+#[inline(always)]
+pub fn irq_enter() {
+    CurrentTask::get().preempt_count_add(HARDIRQ_OFFSET as u32);
+}
+
+/// Leave hardirq context.
+#[inline(always)]
+pub fn irq_exit() {
+    CurrentTask::get().preempt_count_sub(HARDIRQ_OFFSET as u32);
+}
@@ -3,16 +3,49 @@
 use core::mem::ManuallyDrop;
 use core::ops::Deref;
 
+#[cfg(not(test))]
 use crate::arch::thread::{ArchCurrent, ArchCurrentTrait};
 use crate::schedule::task::{Task, TaskRef};
 use crate::sync::arc::Arc;
 
+// Host tests run every test on its own thread and cannot use the system
+// registers of the target, so keep one current task per thread to let them
+// lock and sleep like a real task.
+#[cfg(test)]
+std::thread_local! {
+    static CURRENT: core::cell::Cell<*const Task> = const { core::cell::Cell::new(core::ptr::null()) };
+}
+
+#[cfg(test)]
+#[inline(always)]
+fn read_current() -> *const Task {
+    CURRENT.with(|c| c.get())
+}
+
+#[cfg(test)]
+#[inline(always)]
+fn write_current(task: *const Task) {
+    CURRENT.with(|c| c.set(task));
+}
+
+#[cfg(not(test))]
+#[inline(always)]
+fn read_current() -> *const Task {
+    ArchCurrent::read()
+}
+
+#[cfg(not(test))]
+#[inline(always)]
+fn write_current(task: *const Task) {
+    ArchCurrent::write(task);
+}
+
 /// Current task Wrapper
 pub struct CurrentTask(ManuallyDrop<TaskRef>);
 
 impl CurrentTask {
     fn try_get() -> Option<Self> {
-        let ptr: *const Task = ArchCurrent::read();
+        let ptr: *const Task = read_current();
         if !ptr.is_null() {
             Some(Self(ManuallyDrop::new(unsafe { Arc::from_raw(ptr) })))
         } else {
@@ -53,7 +86,7 @@ impl CurrentTask {
     #[inline(always)]
     pub fn set_current(task: TaskRef) {
         let ptr = Arc::into_raw(task);
-        ArchCurrent::write(ptr);
+        write_current(ptr);
     }
 
     /// Switch current task.
@@ -22,3 +22,27 @@ pub type TaskRef = Arc<Task>;
 pub fn set_current_state(state: TaskState) {
     CurrentTask::get().set_state(state);
 }
+
+/// Give the calling test thread a running current task, so it can sleep on
+/// locks and wait queues like a real task.
+///
+/// The task is leaked, like the tasks of the kernel it is never freed: a
+/// mutex spinner may still look at it after its thread is gone.
+#[cfg(test)]
+pub(crate) fn set_test_current() {
+    use crate::alloc::AllocFlags;
+    use core::alloc::Layout;
+    use core::ptr::NonNull;
+
+    let task = Task::new(
+        TaskState::RUNNING,
+        TaskStack::new(
+            NonNull::new(0xf as *mut u8).unwrap(),
+            Layout::new::<u8>(),
+            false,
+        ),
+    );
+    let task = Arc::new(task, AllocFlags::GFP_KERNEL).unwrap();
+    let _ = Arc::into_raw(task.clone());
+    CurrentTask::set_current(task);
+}
@@ -2,11 +2,14 @@
 
 use core::mem::ManuallyDrop;
 use core::ptr::NonNull;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 use super::{TaskStack, TaskState};
 use crate::arch::thread::{ArchThreadInfo, ArchThreadInfoTrait};
 use crate::macros::cache_aligned;
 use crate::sync::lock::spinlock::{RawSpinLockNoIrq, RawSpinLockNoIrqGuard};
+#[cfg(CONFIG_LOCKDEP)]
+use crate::sync::lockdep::HeldLocks;
 
 /// Task struct
 #[allow(dead_code)]
@@ -19,8 +22,13 @@ pub struct Task {
     stack: TaskStack,
     // boot task?
     is_boot_task: bool,
+    // running on a cpu right now?
+    on_cpu: AtomicBool,
     /// magic number
     pub magic: u64,
+    // locks held by this task, tracked by lockdep
+    #[cfg(CONFIG_LOCKDEP)]
+    held_locks: core::cell::UnsafeCell<HeldLocks>,
 }
 
 impl Task {
@@ -33,7 +41,10 @@ impl Task {
             state: RawSpinLockNoIrq::new(state, None),
             stack,
             is_boot_task: false,
+            on_cpu: AtomicBool::new(false),
             magic: 0,
+            #[cfg(CONFIG_LOCKDEP)]
+            held_locks: core::cell::UnsafeCell::new(HeldLocks::new()),
         }
     }
 
@@ -44,7 +55,10 @@ impl Task {
             state: RawSpinLockNoIrq::new(TaskState::RUNNING, None),
             stack,
             is_boot_task: true,
+            on_cpu: AtomicBool::new(true),
             magic: Self::BOOT_TASK_MAGIC,
+            #[cfg(CONFIG_LOCKDEP)]
+            held_locks: core::cell::UnsafeCell::new(HeldLocks::new()),
         }
     }
 
@@ -107,6 +121,47 @@ impl Task {
     pub fn set_state(&self, state: TaskState) {
         *self.state.lock() = state
     }
+
+    #[inline(always)]
+    /// state
+    pub fn state(&self) -> TaskState {
+        *self.state.lock()
+    }
+
+    /// Wake up the task if its state is in `state`, return whether it was woken.
+    pub fn try_wake_up(&self, state: TaskState) -> bool {
+        let mut curr = self.state.lock();
+        if !curr.intersects(state) {
+            return false;
+        }
+        *curr = TaskState::RUNNING;
+        true
+    }
+
+    #[inline(always)]
+    /// Is the task running on a cpu?
+    pub fn on_cpu(&self) -> bool {
+        self.on_cpu.load(Ordering::Relaxed)
+    }
+
+    #[inline(always)]
+    /// Set the task running on a cpu or not, called by context switch.
+    pub fn set_on_cpu(&self, on_cpu: bool) {
+        self.on_cpu.store(on_cpu, Ordering::Relaxed)
+    }
+
+    /// Locks held by this task.
+    ///
+    /// # Safety
+    ///
+    /// Only the task itself may access its held locks, with local irqs disabled.
+    #[cfg(CONFIG_LOCKDEP)]
+    #[inline(always)]
+    #[allow(clippy::mut_from_ref)]
+    pub(crate) unsafe fn held_locks(&self) -> &mut HeldLocks {
+        // SAFETY: guaranteed by the caller.
+        unsafe { &mut *self.held_locks.get() }
+    }
 }
 
 unsafe impl Send for Task {}
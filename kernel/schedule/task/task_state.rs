@@ -37,5 +37,8 @@ bitflags! {
         const NOLOAD = 1 << 11;
         /// Task is NEW
         const NEW = 1 << 12;
+
+        /// Task is sleeping, interruptible or not
+        const NORMAL = Self::INTERRUPTIBLE.bits() | Self::UNINTERRUPTIBLE.bits();
     }
 }
@@ -2,12 +2,15 @@
 //!
 //! allow waiter use stack mem
 
+use crate::error::{Error, Result};
 use crate::list::{GetLinks, Links, RawList};
 use crate::schedule::task::{set_current_state, TaskRef, TaskState};
-use crate::sync::lock::spinlock::RawSpinLockNoIrq;
+use crate::schedule::{
+    schedule, schedule_timeout, signal_pending, wake_up_process, MAX_SCHEDULE_TIMEOUT,
+};
+use crate::sync::lock::spinlock::{RawSpinLockNoIrq, RawSpinLockNoIrqGuard};
 
 /// Wait queue Node
-#[allow(dead_code)]
 pub struct WaitTaskNode {
     links: Links<Self>,
     task: TaskRef,
@@ -21,6 +24,12 @@ impl WaitTaskNode {
             task,
         }
     }
+
+    /// The waiting task
+    #[inline]
+    pub fn task(&self) -> &TaskRef {
+        &self.task
+    }
 }
 
 impl GetLinks for WaitTaskNode {
@@ -113,7 +122,7 @@ impl WaitTaskList {
 #[macro_export]
 macro_rules! declare_waiter {
     ($name: ident) => {
-        let $name: $crate::schedule::wait_list::WaitTaskNode =
+        let $name: $crate::schedule::WaitTaskNode =
             $crate::schedule::WaitTaskNode::new($crate::schedule::current().as_task_ref().clone());
     };
 }
@@ -131,65 +140,121 @@ impl WaitQueue {
         }
     }
 
-    /// Wait until notified
-    pub fn wait(&self, state: TaskState) {
-        declare_waiter!(waiter);
-        let mut queue = self.queue.lock();
+    /// Lock the wait list, for primitives that manage their own waiters.
+    #[inline]
+    pub(crate) fn lock_list(&self) -> RawSpinLockNoIrqGuard<'_, WaitTaskList> {
+        self.queue.lock()
+    }
 
-        set_current_state(state);
-        // Safety: Once waiter is created, it will not be dropped
-        // before we return from this function(the waiter is removed
-        // from the list)
+    /// Queue the waiter if it is not queued yet, then set the current state.
+    ///
+    /// # Safety
+    ///
+    /// The waiter must stay alive until [`WaitQueue::finish_wait`] removes it.
+    pub(crate) unsafe fn prepare_to_wait(&self, waiter: &WaitTaskNode, state: TaskState) {
+        let mut queue = self.queue.lock();
+        // Safety: guaranteed by the caller, pushing a queued node is a no-op.
         unsafe {
-            queue.push_back(&waiter);
+            queue.push_back(waiter);
         }
-        drop(queue);
+        set_current_state(state);
+    }
 
-        // TODO: call schedule
-        todo!();
+    /// Set the current task running and dequeue the waiter.
+    pub(crate) fn finish_wait(&self, waiter: &WaitTaskNode) {
+        set_current_state(TaskState::RUNNING);
+        let mut queue = self.queue.lock();
+        // Safety: a notifier may already have removed it, removing twice is ok.
+        unsafe {
+            queue.remove(waiter);
+        }
+    }
 
-        /*
+    /// Wait until notified
+    pub fn wait(&self, state: TaskState) {
+        declare_waiter!(waiter);
+        // Safety: the waiter is removed before we return from this function.
+        unsafe {
+            self.prepare_to_wait(&waiter, state);
+        }
+        schedule();
         // Maybe wakeup by signal or other reasons, so need to remove it from the list
-        let mut queue = self.queue.lock();
-        set_current_state(TaskState::RUNNING);
-        // Safety: Once waiter is created, it will not be dropped,
-        // we active remove it from list make sure it is safe
+        self.finish_wait(&waiter);
+    }
+
+    /// Wait until notified or `timeout` jiffies pass, return the jiffies left.
+    pub fn wait_timeout(&self, state: TaskState, timeout: usize) -> usize {
+        declare_waiter!(waiter);
+        // Safety: the waiter is removed before we return from this function.
         unsafe {
-            queue.remove(&waiter);
+            self.prepare_to_wait(&waiter, state);
         }
-        drop(queue);
-        */
+        let left = schedule_timeout(timeout);
+        self.finish_wait(&waiter);
+        left
     }
 
     /// Wait until condition is met
+    ///
+    /// The condition is checked after the task state is set, so a notify
+    /// racing with the check wakes us up instead of being lost. An
+    /// interruptible sleep also ends on a pending signal.
     pub fn wait_until<F>(&self, state: TaskState, condition: F)
     where
         F: Fn() -> bool,
     {
+        let _ = self.wait_event(state, condition, MAX_SCHEDULE_TIMEOUT);
+    }
+
+    /// Wait interruptibly until condition is met.
+    ///
+    /// Returns `Erestartsys` if a signal is pending.
+    pub fn wait_until_interruptible<F>(&self, condition: F) -> Result
+    where
+        F: Fn() -> bool,
+    {
+        self.wait_event(TaskState::INTERRUPTIBLE, condition, MAX_SCHEDULE_TIMEOUT)
+            .map(|_| ())
+    }
+
+    /// Wait until condition is met or `timeout` jiffies pass.
+    ///
+    /// Returns 0 if it timed out, else the jiffies left and at least 1.
+    pub fn wait_until_timeout<F>(&self, condition: F, timeout: usize) -> usize
+    where
+        F: Fn() -> bool,
+    {
+        self.wait_event(TaskState::UNINTERRUPTIBLE, condition, timeout)
+            .unwrap_or(0)
+    }
+
+    // Like Linux `___wait_event()`.
+    fn wait_event<F>(&self, state: TaskState, condition: F, mut timeout: usize) -> Result<usize>
+    where
+        F: Fn() -> bool,
+    {
+        if condition() {
+            return Ok(timeout.max(1));
+        }
         declare_waiter!(waiter);
-        loop {
+        let ret = loop {
+            // Safety: the waiter is removed before we return from this function.
+            unsafe {
+                self.prepare_to_wait(&waiter, state);
+            }
             if condition() {
-                break;
+                break Ok(timeout.max(1));
             }
-            let mut queue = self.queue.lock();
-            set_current_state(state);
-            // Safety: Once waiter is created, it will not be dropped
-            // before we return from this function(the waiter is removed
-            // from the list)
-            unsafe {
-                queue.push_back(&waiter);
+            if state.contains(TaskState::INTERRUPTIBLE) && signal_pending() {
+                break Err(Error::Erestartsys);
             }
-            drop(queue);
-            // TODO: call schedule
-            todo!();
-        }
-
-        let mut queue = self.queue.lock();
-        set_current_state(TaskState::RUNNING);
-        unsafe {
-            queue.remove(&waiter);
-        }
-        drop(queue);
+            if timeout == 0 {
+                break Ok(0);
+            }
+            timeout = schedule_timeout(timeout);
+        };
+        self.finish_wait(&waiter);
+        ret
     }
 
     /// Notify one waiter
@@ -205,23 +270,37 @@ impl WaitQueue {
     ///                            timer_list.lock.get()        wait.lock.get()
     ///                            try_wakeup()                     try_wakeup()          
     ///
-    ///
-    ///
-    ///
+    /// A waiter already woken by someone else is skipped, and the next one
+    /// is woken instead.
     pub fn notify_one(&self) -> bool {
         let mut queue = self.queue.lock();
-        if let Some(_node) = queue.pop_front() {
-            // TODO: call wakeup
-            todo!();
-            //return true;
+        // The waiter can not leave `finish_wait` while we hold the queue
+        // lock, so the node stays alive here.
+        while let Some(node) = queue.pop_front() {
+            if wake_up_process(&node.task) {
+                return true;
+            }
         }
         false
     }
+
+    /// Notify all waiters, return how many were woken.
+    pub fn notify_all(&self) -> usize {
+        let mut queue = self.queue.lock();
+        let mut woken = 0;
+        while let Some(node) = queue.pop_front() {
+            if wake_up_process(&node.task) {
+                woken += 1;
+            }
+        }
+        woken
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::alloc::AllocFlags;
     use crate::schedule::task::{Task, TaskStack, TaskState};
     use crate::sync::arc::Arc;
     use core::ptr::NonNull;
@@ -243,7 +322,7 @@ mod tests {
         let mut list = WaitTaskList::new();
         assert!(list.is_empty());
 
-        let waiter = WaitTaskNode::new(Arc::new(task));
+        let waiter = WaitTaskNode::new(Arc::new(task, AllocFlags::GFP_KERNEL).unwrap());
         // Safety: waiter life time is longer than the list
         unsafe {
             list.push_back(&waiter);
@@ -263,12 +342,36 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_wait_notify() {
+        use crate::schedule::task::CurrentTask;
+        use core::sync::atomic::{AtomicBool, Ordering};
+
+        static QUEUE: WaitQueue = WaitQueue::new();
+        static DONE: AtomicBool = AtomicBool::new(false);
+
+        let waiter = std::thread::spawn(|| {
+            CurrentTask::set_current(Arc::new(new_task(), AllocFlags::GFP_KERNEL).unwrap());
+            QUEUE.wait_until(TaskState::UNINTERRUPTIBLE, || DONE.load(Ordering::Acquire));
+        });
+
+        CurrentTask::set_current(Arc::new(new_task(), AllocFlags::GFP_KERNEL).unwrap());
+        while QUEUE.lock_list().is_empty() {
+            std::thread::yield_now();
+        }
+        DONE.store(true, Ordering::Release);
+        assert!(QUEUE.notify_one());
+        waiter.join().unwrap();
+        assert!(QUEUE.lock_list().is_empty());
+        assert!(!QUEUE.notify_one());
+    }
+
     #[test]
     fn test_double_remove_is_ok() {
         let task = new_task();
         let mut list = WaitTaskList::new();
         assert!(list.is_empty());
-        let waiter = WaitTaskNode::new(Arc::new(task));
+        let waiter = WaitTaskNode::new(Arc::new(task, AllocFlags::GFP_KERNEL).unwrap());
         unsafe {
             list.push_back(&waiter);
             assert!(list.remove(&waiter));
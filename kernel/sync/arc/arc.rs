@@ -10,14 +10,14 @@
 //!
 //! It is different from the standard library's [`Arc`] in a few ways:
 //! 1. It is backed by the kernel's `refcount_t` type.
-//! 2. It does not support weak references, which allows it to be half the size.
+//! 2. Its [`Weak`] references can only be upgraded, they are meant to break reference cycles.
 //! 3. It saturates the reference count instead of aborting when it goes over a threshold.
 //! 4. It does not provide a `get_mut` method, so the ref counted object is pinned.
 //! 5. The object in [`Arc`] is pinned implicitly.
 //!
 //! [`Arc`]: https://doc.rust-lang.org/std/sync/struct.Arc.html
 
-//use crate::alloc::{AllocError, AllocFlags, KBox};
+use crate::alloc::{kbox::MBox, AllocError, AllocFlags, Allocator, MemblockAllocator};
 use core::{
     alloc::Layout,
     marker::PhantomData,
@@ -116,7 +116,6 @@ use core::{
 /// let coerced: Arc<dyn MyTrait> = obj;
 /// # Ok::<(), Error>(())
 /// ```
-
 #[repr(transparent)]
 #[derive(core::marker::CoercePointee)]
 pub struct Arc<T: ?Sized> {
@@ -136,6 +135,8 @@ pub struct Arc<T: ?Sized> {
 #[repr(C)]
 pub struct ArcInner<T: ?Sized> {
     refcont: AtomicU32,
+    // The weak references, plus one held by all the strong ones together.
+    weak: AtomicU32,
     is_static: bool,
     data: T,
 }
@@ -146,15 +147,17 @@ impl<T> ArcInner<T> {
     const fn new(data: T) -> Self {
         Self {
             refcont: AtomicU32::new(1),
+            weak: AtomicU32::new(1),
             is_static: false,
             data,
         }
     }
 
     /// Creates a new [`ArcInner<T>`] with a static reference count.
-    pub(crate) const fn new_static(data: T) -> Self {
+    pub const fn new_static(data: T) -> Self {
         Self {
             refcont: AtomicU32::new(1),
+            weak: AtomicU32::new(1),
             is_static: true,
             data,
         }
@@ -174,25 +177,18 @@ unsafe impl<T: ?Sized + Sync + Send> Send for Arc<T> {}
 // the reference count reaches zero and `T` is dropped.
 unsafe impl<T: ?Sized + Sync + Send> Sync for Arc<T> {}
 
-/*
 impl<T> Arc<T> {
     /// Constructs a new reference counted instance of `T`.
-    pub fn new(contents: T, flags: Flags) -> Result<Self, AllocError> {
+    pub fn new(contents: T, flags: AllocFlags) -> Result<Self, AllocError> {
         // INVARIANT: The refcount is initialised to a non-zero value.
-        let value = ArcInner {
-            refcont: atomic::AtomicUsize::new(1),
-            data: contents,
-        };
-
-        let inner = KBox::new(value, flags)?;
-        let inner = KBox::leak(inner).into();
+        let inner = MBox::new(ArcInner::new(contents), flags)?;
+        let inner = NonNull::from(MBox::leak(inner));
 
         // SAFETY: We just created `inner` with a reference count of 1, which is owned by the new
         // `Arc` object.
         Ok(unsafe { Self::from_inner(inner) })
     }
 }
-*/
 
 #[inline]
 fn data_offset_align(align: usize) -> usize {
@@ -300,6 +296,15 @@ impl<T: ?Sized> Arc<T> {
     pub fn ptr_eq(this: &Self, other: &Self) -> bool {
         core::ptr::eq(this.ptr.as_ptr(), other.ptr.as_ptr())
     }
+
+    /// Create a [`Weak`] pointer to the object.
+    pub fn downgrade(this: &Self) -> Weak<T> {
+        let old_size = this.inner().weak.fetch_add(1, Ordering::Relaxed);
+        if old_size == u32::MAX {
+            panic!("Weak overflow");
+        }
+        Weak { ptr: this.ptr }
+    }
 }
 
 impl<T: ?Sized> Deref for Arc<T> {
@@ -333,7 +338,7 @@ impl<T: ?Sized> Clone for Arc<T> {
         // [1]: (www.boost.org/doc/libs/1_55_0/doc/html/atomic/usage_examples.html)
         let old_size = self.inner().refcont.fetch_add(1, Ordering::Relaxed);
 
-        if old_size > u32::MAX {
+        if old_size == u32::MAX {
             panic!("Arc overflow");
         }
 
@@ -352,7 +357,6 @@ impl<T: ?Sized> Drop for Arc<T> {
             }
         }
 
-        /*
         // This fence is needed to prevent reordering of use of the data and
         // deletion of the data. Because it is marked `Release`, the decreasing
         // of the reference count synchronizes with this `Acquire` fence. This
@@ -381,13 +385,87 @@ impl<T: ?Sized> Drop for Arc<T> {
         //
         // [1]: (www.boost.org/doc/libs/1_55_0/doc/html/atomic/usage_examples.html)
         // [2]: (https://github.com/rust-lang/rust/pull/41714)
-        acquire!(self.inner().refcont);
+        core::sync::atomic::fence(Ordering::Acquire);
 
-        // The count reached zero, we must free the memory.
+        // The count reached zero, we must drop the data, the memory is freed with the last weak
+        // reference.
         //
-        // SAFETY: The pointer was initialised from the result of `KBox::leak`.
-        unsafe { drop(KBox::from_raw(self.ptr.as_ptr())) };
-        */
+        // SAFETY: This is the last strong reference, the data is never used again.
+        unsafe { core::ptr::drop_in_place(&raw mut (*self.ptr.as_ptr()).data) };
+        drop(Weak { ptr: self.ptr });
+    }
+}
+
+/// A pointer to the object of an [`Arc`] that does not keep it alive.
+///
+/// The memory of the object stays allocated until the last [`Weak`] is dropped, the object is
+/// only reached through [`Weak::upgrade`].
+#[repr(transparent)]
+#[derive(core::marker::CoercePointee)]
+pub struct Weak<T: ?Sized> {
+    ptr: NonNull<ArcInner<T>>,
+}
+
+// SAFETY: A `Weak<T>` can be upgraded to an `Arc<T>`, so it is `Send` and `Sync` like `Arc<T>`.
+unsafe impl<T: ?Sized + Sync + Send> Send for Weak<T> {}
+
+// SAFETY: see above.
+unsafe impl<T: ?Sized + Sync + Send> Sync for Weak<T> {}
+
+impl<T: ?Sized> Weak<T> {
+    // The counts, the data may be dropped already so no reference to the `ArcInner` is made.
+    #[inline]
+    fn counts(&self) -> (&AtomicU32, &AtomicU32) {
+        let ptr = self.ptr.as_ptr();
+        // SAFETY: The weak reference keeps the `ArcInner` allocated.
+        unsafe { (&(*ptr).refcont, &(*ptr).weak) }
+    }
+
+    /// An [`Arc`] to the object, `None` if it was dropped.
+    pub fn upgrade(&self) -> Option<Arc<T>> {
+        let refcont = self.counts().0;
+        let mut n = refcont.load(Ordering::Relaxed);
+        loop {
+            if n == 0 {
+                return None;
+            }
+            if n == u32::MAX {
+                panic!("Arc overflow");
+            }
+            match refcont.compare_exchange_weak(n, n + 1, Ordering::Acquire, Ordering::Relaxed) {
+                // SAFETY: The refcount was non-zero and was just incremented, this increment is
+                // owned by the new `Arc`.
+                Ok(_) => return Some(unsafe { Arc::from_inner(self.ptr) }),
+                Err(old) => n = old,
+            }
+        }
+    }
+}
+
+impl<T: ?Sized> Clone for Weak<T> {
+    fn clone(&self) -> Self {
+        let old_size = self.counts().1.fetch_add(1, Ordering::Relaxed);
+        if old_size == u32::MAX {
+            panic!("Weak overflow");
+        }
+        Self { ptr: self.ptr }
+    }
+}
+
+impl<T: ?Sized> Drop for Weak<T> {
+    fn drop(&mut self) {
+        if self.counts().1.fetch_sub(1, Ordering::Release) != 1 {
+            return;
+        }
+        // Same as `Arc::drop`, the uses of the other weak references happen before the free.
+        core::sync::atomic::fence(Ordering::Acquire);
+
+        // SAFETY: The pointer was initialised from the result of `MBox::leak` in `Arc::new` with
+        // this layout, static ones never get here as their strong reference is never dropped.
+        unsafe {
+            let layout = Layout::for_value_raw(self.ptr.as_ptr());
+            MemblockAllocator::free(self.ptr.cast(), layout);
+        }
     }
 }
 
@@ -395,12 +473,20 @@ impl<T: ?Sized> Drop for Arc<T> {
 mod tests {
     use super::*;
 
+    // Counts the drops of the data.
+    struct Tracked<'a>(&'a AtomicU32);
+
+    impl Drop for Tracked<'_> {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
     #[test]
     fn test_arc_from_inner() {
-        let innter = ArcInner {
-            refcont: AtomicU32::new(1),
-            data: 42,
-        };
+        let innter = ArcInner::new(42);
+        // One more reference than the Arc, the stack memory is never freed.
+        innter.refcont.fetch_add(1, Ordering::Relaxed);
         let arc = unsafe { Arc::from_inner(NonNull::from(&innter)) };
         assert_eq!(*arc, 42);
 
@@ -408,5 +494,44 @@ mod tests {
         let raw = Arc::into_raw(arc);
         let arc = unsafe { Arc::from_raw(raw) };
         assert_eq!(*arc, 42);
+        drop(arc);
+        assert_eq!(innter.refcont.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_arc_weak_drop() {
+        let drops = AtomicU32::new(0);
+        let arc = Arc::new(Tracked(&drops), AllocFlags::GFP_KERNEL).unwrap();
+        let weak = Arc::downgrade(&arc);
+        let other = arc.clone();
+        assert_eq!(weak.counts().0.load(Ordering::Relaxed), 2);
+        // The strong references together hold one weak.
+        assert_eq!(weak.counts().1.load(Ordering::Relaxed), 2);
+
+        drop(arc);
+        let upgraded = weak.upgrade().unwrap();
+        drop(other);
+        assert_eq!(drops.load(Ordering::Relaxed), 0);
+
+        // The last strong reference drops the data, not the memory.
+        drop(upgraded);
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+        assert!(weak.upgrade().is_none());
+        assert_eq!(weak.counts().1.load(Ordering::Relaxed), 1);
+
+        // The last weak reference frees it.
+        let cloned = weak.clone();
+        drop(weak);
+        assert_eq!(cloned.counts().1.load(Ordering::Relaxed), 1);
+        assert!(cloned.upgrade().is_none());
+        drop(cloned);
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+
+        // Without weak references, the last strong one frees it.
+        let arc: Arc<dyn Send + Sync> = Arc::new(Tracked(&drops), AllocFlags::GFP_KERNEL).unwrap();
+        let raw = Arc::into_raw(arc.clone());
+        drop(arc);
+        drop(unsafe { Arc::from_raw(raw) });
+        assert_eq!(drops.load(Ordering::Relaxed), 2);
     }
 }
@@ -2,11 +2,15 @@
 
 cfg_if::cfg_if! {
     if #[cfg(test)] {
-        pub use std::sync::Arc;
+        mod test_arc;
+        pub use test_arc::{Arc, Weak};
+        // Only its own tests use it, over the test allocator.
+        #[allow(dead_code, unreachable_pub, clippy::module_inception)]
+        mod arc;
     } else {
         mod std_vendor;
         mod arc;
-        pub use arc::Arc;
-        pub(crate) use arc::ArcInner;
+        pub use arc::{Arc, Weak};
+        pub use arc::ArcInner;
     }
 }
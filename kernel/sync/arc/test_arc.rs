@@ -0,0 +1,95 @@
+//! [`Arc`] and [`Weak`] of the host tests
+//!
+//! The interface of the kernel ones on top of the std ones.
+
+use core::ops::Deref;
+
+use crate::alloc::{AllocError, AllocFlags};
+
+/// A reference-counted pointer to an instance of `T`.
+#[repr(transparent)]
+#[derive(core::marker::CoercePointee)]
+pub struct Arc<T: ?Sized>(std::sync::Arc<T>);
+
+impl<T> Arc<T> {
+    /// Constructs a new reference counted instance of `T`.
+    pub fn new(contents: T, _flags: AllocFlags) -> Result<Self, AllocError> {
+        Ok(Self(std::sync::Arc::new(contents)))
+    }
+}
+
+impl<T: ?Sized> Arc<T> {
+    /// Consume the arc, the raw pointer owns its refcount.
+    pub fn into_raw(this: Self) -> *const T {
+        std::sync::Arc::into_raw(this.0)
+    }
+
+    /// Return a raw pointer to the data in this arc.
+    pub fn as_ptr(this: &Self) -> *const T {
+        std::sync::Arc::as_ptr(&this.0)
+    }
+
+    /// Recreates an [`Arc`] instance previously deconstructed via [`Arc::into_raw`].
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by a previous call to [`Arc::into_raw`]. Additionally, it
+    /// must not be called more than once for each previous call to [`Arc::into_raw`].
+    pub unsafe fn from_raw(ptr: *const T) -> Self {
+        // SAFETY: guaranteed by the caller.
+        Self(unsafe { std::sync::Arc::from_raw(ptr) })
+    }
+
+    /// Compare whether two [`Arc`] pointers reference the same underlying object.
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        std::sync::Arc::ptr_eq(&this.0, &other.0)
+    }
+
+    /// Number of references to the object.
+    pub fn strong_count(this: &Self) -> usize {
+        std::sync::Arc::strong_count(&this.0)
+    }
+
+    /// Create a [`Weak`] pointer to the object.
+    pub fn downgrade(this: &Self) -> Weak<T> {
+        Weak(std::sync::Arc::downgrade(&this.0))
+    }
+}
+
+impl<T: ?Sized> Deref for Arc<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: ?Sized> AsRef<T> for Arc<T> {
+    fn as_ref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: ?Sized> Clone for Arc<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+/// A pointer to the object of an [`Arc`] that does not keep it alive.
+#[repr(transparent)]
+#[derive(core::marker::CoercePointee)]
+pub struct Weak<T: ?Sized>(std::sync::Weak<T>);
+
+impl<T: ?Sized> Weak<T> {
+    /// An [`Arc`] to the object, `None` if it was dropped.
+    pub fn upgrade(&self) -> Option<Arc<T>> {
+        self.0.upgrade().map(Arc)
+    }
+}
+
+impl<T: ?Sized> Clone for Weak<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
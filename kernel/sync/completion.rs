@@ -0,0 +1,143 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Completion
+//!
+//! Refer to linux: kernel/sched/completion.c
+//!
+//! One side waits for an event, such as a device finishing a transfer, and
+//! the other side signals it with [`Completion::complete`].
+
+use crate::error::Result;
+use crate::schedule::task::TaskState;
+use crate::schedule::WaitQueue;
+use crate::sync::lock::RawSpinLockNoIrq;
+
+// `done` value after `complete_all`.
+const COMPLETE_ALL: u32 = u32::MAX;
+
+/// A completion.
+///
+/// ```
+/// use kernel::sync::Completion;
+///
+/// static DONE: Completion = Completion::new();
+///
+/// fn irq_handler() {
+///     DONE.complete();
+/// }
+///
+/// fn transfer() {
+///     // start the transfer...
+///     DONE.wait_for_completion();
+/// }
+/// ```
+pub struct Completion {
+    done: RawSpinLockNoIrq<u32>,
+    wait: WaitQueue,
+}
+
+impl Completion {
+    /// Create a new completion
+    pub const fn new() -> Self {
+        Self {
+            done: RawSpinLockNoIrq::new(0, Some("completion")),
+            wait: WaitQueue::new(),
+        }
+    }
+
+    /// Reset to not done, to reuse the completion.
+    pub fn reinit(&self) {
+        *self.done.lock() = 0;
+    }
+
+    /// Signal one waiter, or the next one to wait.
+    pub fn complete(&self) {
+        {
+            let mut done = self.done.lock();
+            if *done != COMPLETE_ALL {
+                *done += 1;
+            }
+        }
+        self.wait.notify_one();
+    }
+
+    /// Signal all waiters, and everyone waiting later until [`Completion::reinit`].
+    pub fn complete_all(&self) {
+        *self.done.lock() = COMPLETE_ALL;
+        self.wait.notify_all();
+    }
+
+    /// Consume one `complete`, return false if there is none.
+    pub fn try_wait_for_completion(&self) -> bool {
+        let mut done = self.done.lock();
+        if *done == 0 {
+            return false;
+        }
+        if *done != COMPLETE_ALL {
+            *done -= 1;
+        }
+        true
+    }
+
+    /// Has the completion been signaled and not consumed yet?
+    pub fn completion_done(&self) -> bool {
+        *self.done.lock() != 0
+    }
+
+    /// Wait for the completion.
+    pub fn wait_for_completion(&self) {
+        self.wait.wait_until(TaskState::UNINTERRUPTIBLE, || {
+            self.try_wait_for_completion()
+        });
+    }
+
+    /// Wait for the completion at most `timeout` jiffies.
+    ///
+    /// Returns 0 if it timed out, else the jiffies left and at least 1.
+    pub fn wait_for_completion_timeout(&self, timeout: usize) -> usize {
+        self.wait
+            .wait_until_timeout(|| self.try_wait_for_completion(), timeout)
+    }
+
+    /// Wait for the completion, or until a signal is pending.
+    pub fn wait_for_completion_interruptible(&self) -> Result {
+        self.wait
+            .wait_until_interruptible(|| self.try_wait_for_completion())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schedule::task::set_test_current;
+
+    #[test]
+    fn test_completion() {
+        static DONE: Completion = Completion::new();
+        set_test_current();
+
+        assert!(!DONE.try_wait_for_completion());
+        DONE.complete();
+        assert!(DONE.completion_done());
+        DONE.wait_for_completion();
+        assert!(!DONE.completion_done());
+
+        let waiters: std::vec::Vec<_> = (0..3)
+            .map(|_| {
+                std::thread::spawn(|| {
+                    set_test_current();
+                    DONE.wait_for_completion();
+                })
+            })
+            .collect();
+        DONE.complete_all();
+        for w in waiters {
+            w.join().unwrap();
+        }
+        assert!(DONE.completion_done());
+        assert_eq!(DONE.wait_for_completion_timeout(10), 10);
+
+        DONE.reinit();
+        assert!(!DONE.completion_done());
+    }
+}
@@ -0,0 +1,126 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Condition variable
+//!
+//! Refer to linux: rust/kernel/sync/condvar.rs
+
+use crate::schedule::task::TaskState;
+use crate::schedule::{schedule_timeout, signal_pending, WaitQueue, MAX_SCHEDULE_TIMEOUT};
+use crate::sync::lock::MutexGuard;
+
+/// A condition variable.
+///
+/// Releases the mutex and sleeps atomically, so a notify between unlocking
+/// and sleeping is not lost. Waiters must check their condition in a loop,
+/// as they may wake up spuriously.
+///
+/// ```
+/// use kernel::sync::{CondVar, Mutex};
+///
+/// fn wait_for_value(m: &Mutex<u32>, cv: &CondVar, v: u32) {
+///     let mut guard = m.lock();
+///     while *guard != v {
+///         cv.wait(&mut guard);
+///     }
+/// }
+/// ```
+pub struct CondVar {
+    wait: WaitQueue,
+}
+
+impl CondVar {
+    /// Create a new condition variable
+    pub const fn new() -> Self {
+        Self {
+            wait: WaitQueue::new(),
+        }
+    }
+
+    fn wait_internal<T: ?Sized>(
+        &self,
+        state: TaskState,
+        guard: &mut MutexGuard<'_, T>,
+        timeout: usize,
+    ) -> usize {
+        crate::declare_waiter!(waiter);
+        // Safety: the waiter is removed before we return from this function.
+        unsafe {
+            self.wait.prepare_to_wait(&waiter, state);
+        }
+        let remaining = guard.do_unlocked(|| schedule_timeout(timeout));
+        self.wait.finish_wait(&waiter);
+        remaining
+    }
+
+    /// Release the mutex and sleep until notified, then lock it again.
+    pub fn wait<T: ?Sized>(&self, guard: &mut MutexGuard<'_, T>) {
+        self.wait_internal(TaskState::UNINTERRUPTIBLE, guard, MAX_SCHEDULE_TIMEOUT);
+    }
+
+    /// Like [`CondVar::wait`], but also wakes up on a signal.
+    ///
+    /// Returns whether a signal is pending.
+    pub fn wait_interruptible<T: ?Sized>(&self, guard: &mut MutexGuard<'_, T>) -> bool {
+        self.wait_internal(TaskState::INTERRUPTIBLE, guard, MAX_SCHEDULE_TIMEOUT);
+        signal_pending()
+    }
+
+    /// Like [`CondVar::wait`], but sleeps at most `timeout` jiffies.
+    ///
+    /// Returns 0 if it timed out, else the jiffies left.
+    pub fn wait_timeout<T: ?Sized>(&self, guard: &mut MutexGuard<'_, T>, timeout: usize) -> usize {
+        self.wait_internal(TaskState::UNINTERRUPTIBLE, guard, timeout)
+    }
+
+    /// Wake up one waiter.
+    pub fn notify_one(&self) {
+        self.wait.notify_one();
+    }
+
+    /// Wake up all waiters.
+    pub fn notify_all(&self) {
+        self.wait.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schedule::task::set_test_current;
+
+    use crate::sync::lock::Mutex;
+
+    #[test]
+    fn test_condvar_ping_pong() {
+        const ROUNDS: u32 = 100;
+        static VALUE: Mutex<u32> = Mutex::new(0, Some("test_condvar"));
+        static CV: CondVar = CondVar::new();
+
+        let odd = std::thread::spawn(|| {
+            set_test_current();
+            let mut guard = VALUE.lock();
+            while *guard < ROUNDS {
+                if *guard % 2 == 0 {
+                    *guard += 1;
+                    CV.notify_all();
+                } else {
+                    CV.wait(&mut guard);
+                }
+            }
+        });
+
+        set_test_current();
+        let mut guard = VALUE.lock();
+        while *guard < ROUNDS {
+            if *guard % 2 == 1 {
+                *guard += 1;
+                CV.notify_all();
+            } else {
+                CV.wait(&mut guard);
+            }
+        }
+        drop(guard);
+        odd.join().unwrap();
+        assert_eq!(*VALUE.lock(), ROUNDS);
+    }
+}
@@ -0,0 +1,44 @@
+//! Global lock debugging switch.
+//!
+//! Refer to linux: lib/debug_locks.c
+//!
+//! Lock debugging turns itself off after the first problem it reports, the
+//! state of the locks can not be trusted anymore and reporting again would
+//! only print noise.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+static DEBUG_LOCKS: AtomicBool = AtomicBool::new(true);
+
+/// Is lock debugging still on?
+#[inline]
+pub fn debug_locks() -> bool {
+    DEBUG_LOCKS.load(Ordering::Relaxed)
+}
+
+/// Turn lock debugging off, return whether it was on.
+pub fn debug_locks_off() -> bool {
+    DEBUG_LOCKS.swap(false, Ordering::Relaxed)
+}
+
+/// Warn once about a broken lock invariant and turn lock debugging off.
+///
+/// Evaluates to the condition, like Linux `DEBUG_LOCKS_WARN_ON()`.
+#[macro_export]
+macro_rules! debug_locks_warn_on {
+    ($cond:expr) => {{
+        let cond: bool = $cond;
+        if cond && $crate::sync::debug_locks::debug_locks_off() {
+            let _ = core::fmt::Write::write_fmt(
+                &mut $crate::printk::early::EarlyWriter,
+                format_args!(
+                    "DEBUG_LOCKS_WARN_ON({}) at {}:{}\n",
+                    stringify!($cond),
+                    file!(),
+                    line!()
+                ),
+            );
+        }
+        cond
+    }};
+}
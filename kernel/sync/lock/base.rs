@@ -1,5 +1,6 @@
 //! Common lock base types.
 
+use crate::sync::lockdep::LockDepMap;
 use crate::types::NotThreadSafe;
 use core::cell::UnsafeCell;
 
@@ -25,6 +26,11 @@ pub trait Backend {
     /// [`unlock`]: Backend::unlock
     type GuardState;
 
+    /// Whether the lock disables local irqs while it is held.
+    ///
+    /// Lockdep treats such locks as never held with hardirqs enabled.
+    const IRQ_SAFE: bool = false;
+
     /// Acquires the lock, making the caller its owner.
     ///
     /// # Safety
@@ -49,6 +55,8 @@ pub struct Lock<T: ?Sized, B: Backend> {
     pub(crate) inner: UnsafeCell<B::Inner>,
     // Name
     pub(crate) _name: Option<&'static str>,
+    // Lock class and held state tracking of lockdep.
+    pub(crate) dep_map: LockDepMap,
     // The data protected by the lock.
     pub(crate) data: UnsafeCell<T>,
 }
@@ -62,15 +70,21 @@ unsafe impl<T: ?Sized + Send, B: Backend> Sync for Lock<T, B> {}
 
 impl<T: ?Sized, B: Backend> Lock<T, B> {
     /// Acquires the lock, blocking the current thread until it is able to do so.
+    #[track_caller]
     pub fn lock(&self) -> BaseLockGuard<'_, T, B> {
+        self.dep_map.acquire(false, B::IRQ_SAFE);
         // SAFETY: inner already be initialised.
         unsafe { BaseLockGuard::new(self, B::lock(&mut *self.inner.get())) }
     }
 
     /// Tries to acquire the lock.
+    #[track_caller]
     pub fn try_lock(&self) -> Option<BaseLockGuard<'_, T, B>> {
         // SAFETY: inner already be initialised.
-        unsafe { B::try_lock(&mut *self.inner.get()).map(|state| BaseLockGuard::new(self, state)) }
+        let state = unsafe { B::try_lock(&mut *self.inner.get()) }?;
+        self.dep_map.acquire(true, B::IRQ_SAFE);
+        // SAFETY: we own the lock.
+        Some(unsafe { BaseLockGuard::new(self, state) })
     }
 }
 
@@ -121,13 +135,23 @@ impl<'a, T: ?Sized, B: Backend> BaseLockGuard<'a, T, B> {
         self.lock
     }
 
-    #[allow(dead_code)]
     pub(crate) fn force_unlock(&mut self) {
+        self.lock.dep_map.release();
         // SAFETY: The caller owns the lock, so it is safe to unlock it.
         unsafe {
             B::unlock(&mut *self.lock.inner.get(), &self.state);
         }
     }
+
+    /// Releases the lock, runs `cb` and acquires the lock again.
+    pub(crate) fn do_unlocked<U>(&mut self, cb: impl FnOnce() -> U) -> U {
+        self.force_unlock();
+        let ret = cb();
+        self.lock.dep_map.acquire(false, B::IRQ_SAFE);
+        // SAFETY: inner already be initialised, and we released the lock above.
+        self.state = unsafe { B::lock(&mut *self.lock.inner.get()) };
+        ret
+    }
 }
 
 impl<T: ?Sized, B: Backend> core::ops::Deref for BaseLockGuard<'_, T, B> {
@@ -148,6 +172,7 @@ impl<T: ?Sized, B: Backend> core::ops::DerefMut for BaseLockGuard<'_, T, B> {
 
 impl<T: ?Sized, B: Backend> Drop for BaseLockGuard<'_, T, B> {
     fn drop(&mut self) {
+        self.lock.dep_map.release();
         // SAFETY: The caller owns the lock, so it is safe to unlock it.
         unsafe {
             B::unlock(&mut *self.lock.inner.get(), &self.state);
@@ -2,12 +2,33 @@
 
 //! Rynux mutex implement
 //!
+//! Refer to linux: kernel/locking/mutex.c
 //!
+//! The owner word holds the owner task pointer, with flags in the low bits
+//! that are free because tasks are cache aligned:
+//!
+//! - `WAITERS`: the wait list is not empty, unlock must wake the first waiter.
+//! - `HANDOFF`: the first waiter wants the lock handed to it on unlock, so
+//!   spinners can not keep stealing it.
+//! - `PICKUP`: the lock has been handed off to the owner task, which still
+//!   has to pick it up.
+//!
+//! A locker first spins while the owner is running on another cpu, hoping
+//! it will release the lock soon, then queues itself on the wait queue and
+//! sleeps.
 
-use crate::schedule::{current, task::TaskState, WaitQueue};
+use crate::schedule::task::{set_current_state, Task, TaskRef, TaskState};
+use crate::schedule::{current, schedule, wake_up_process, WaitQueue};
+use crate::sync::arc::Arc;
+use crate::sync::lockdep::LockDepMap;
 use core::cell::UnsafeCell;
 use core::sync::atomic::{AtomicUsize, Ordering};
 
+const MUTEX_FLAG_WAITERS: usize = 0x01;
+const MUTEX_FLAG_HANDOFF: usize = 0x02;
+const MUTEX_FLAG_PICKUP: usize = 0x04;
+const MUTEX_FLAGS: usize = 0x07;
+
 /// A mutual exclusion primitive.
 ///
 /// ```
@@ -37,7 +58,7 @@ pub struct MutexBackend;
 
 /// Mutex inner.
 pub struct MutexLockInner {
-    /// The atomic
+    /// Owner task pointer and flags
     owner: AtomicUsize,
     wait_queue: WaitQueue,
 }
@@ -49,64 +70,298 @@ impl MutexLockInner {
             wait_queue: WaitQueue::new(),
         }
     }
+
+    #[inline]
+    fn owner(&self) -> usize {
+        self.owner.load(Ordering::Relaxed) & !MUTEX_FLAGS
+    }
+
+    // Try to acquire the lock, return whether it was acquired.
+    //
+    // With `handoff`, ask the owner to hand the lock to us on unlock.
+    fn trylock_common(&self, handoff: bool) -> bool {
+        let curr = current().as_ptr() as usize;
+        let mut owner = self.owner.load(Ordering::Relaxed);
+        loop {
+            let mut flags = owner & MUTEX_FLAGS;
+            let mut task = owner & !MUTEX_FLAGS;
+            if task != 0 {
+                if flags & MUTEX_FLAG_PICKUP != 0 {
+                    if task != curr {
+                        return false;
+                    }
+                    flags &= !MUTEX_FLAG_PICKUP;
+                } else if handoff {
+                    if flags & MUTEX_FLAG_HANDOFF != 0 {
+                        return false;
+                    }
+                    flags |= MUTEX_FLAG_HANDOFF;
+                } else {
+                    return false;
+                }
+            } else {
+                debug_assert!(flags & (MUTEX_FLAG_HANDOFF | MUTEX_FLAG_PICKUP) == 0);
+                task = curr;
+            }
+            match self.owner.compare_exchange_weak(
+                owner,
+                task | flags,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return task == curr,
+                Err(old) => owner = old,
+            }
+        }
+    }
+
+    // Spin while `owner` keeps the lock and runs on a cpu, return whether
+    // the owner changed.
+    fn spin_on_owner(&self, owner: usize) -> bool {
+        while self.owner() == owner {
+            // SAFETY: tasks are never freed, so a stale owner pointer is still
+            // valid: there is no task exit, the boot tasks are static and the
+            // tasks of the host tests are leaked. TODO: use rcu once tasks can
+            // exit.
+            let task = unsafe { &*(owner as *const Task) };
+            if !task.on_cpu() {
+                return false;
+            }
+            core::hint::spin_loop();
+        }
+        true
+    }
+
+    // Optimistic spinning, return whether the lock was acquired.
+    //
+    // Sleeping and waking up is expensive, and a running owner is likely to
+    // release the lock soon, so keep trying while it runs.
+    fn optimistic_spin(&self) -> bool {
+        loop {
+            let owner = self.owner();
+            if owner != 0 && !self.spin_on_owner(owner) {
+                return false;
+            }
+            if self.trylock_common(false) {
+                return true;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    fn lock_slowpath(&self) {
+        if self.optimistic_spin() {
+            return;
+        }
+
+        let mut list = self.wait_queue.lock_list();
+        // The owner may have released it before we took the wait lock.
+        if self.trylock_common(false) {
+            return;
+        }
+
+        crate::declare_waiter!(waiter);
+        // Safety: the waiter is removed before we return from this function.
+        unsafe {
+            list.push_back(&waiter);
+        }
+        if list.front_eq(&waiter) {
+            self.owner.fetch_or(MUTEX_FLAG_WAITERS, Ordering::Relaxed);
+        }
+        set_current_state(TaskState::UNINTERRUPTIBLE);
+
+        loop {
+            // Unlock wakes us under the wait lock, so no wakeup is lost.
+            if self.trylock_common(false) {
+                break;
+            }
+            drop(list);
+            schedule();
+
+            let first = self.wait_queue.lock_list().front_eq(&waiter);
+            set_current_state(TaskState::UNINTERRUPTIBLE);
+            // Only the first waiter asks for a handoff and spins, the others
+            // would just fight over the lock.
+            if self.trylock_common(first) || (first && self.optimistic_spin()) {
+                list = self.wait_queue.lock_list();
+                break;
+            }
+            list = self.wait_queue.lock_list();
+        }
+
+        set_current_state(TaskState::RUNNING);
+        // Safety: the waiter is on the list, and only we remove it.
+        unsafe {
+            list.remove(&waiter);
+        }
+        if list.is_empty() {
+            self.owner.fetch_and(
+                !(MUTEX_FLAG_WAITERS | MUTEX_FLAG_HANDOFF),
+                Ordering::Relaxed,
+            );
+        }
+    }
+
+    // Give the lock to `task`, keeping only the waiters flag.
+    fn handoff(&self, task: Option<&TaskRef>) {
+        let mut owner = self.owner.load(Ordering::Relaxed);
+        loop {
+            let mut new = owner & MUTEX_FLAG_WAITERS;
+            if let Some(task) = task {
+                new |= Arc::as_ptr(task) as usize | MUTEX_FLAG_PICKUP;
+            }
+            match self
+                .owner
+                .compare_exchange_weak(owner, new, Ordering::Release, Ordering::Relaxed)
+            {
+                Ok(_) => return,
+                Err(old) => owner = old,
+            }
+        }
+    }
+
+    fn unlock_slowpath(&self, owner: usize) {
+        let list = self.wait_queue.lock_list();
+        let next = list.front().map(|waiter| waiter.task().clone());
+        if owner & MUTEX_FLAG_HANDOFF != 0 {
+            self.handoff(next.as_ref());
+        }
+        if let Some(next) = next {
+            wake_up_process(&next);
+        }
+    }
 }
 
 impl<T> Mutex<T> {
     /// Constructs a new mutex.
+    #[track_caller]
     pub const fn new(t: T, name: Option<&'static str>) -> Self {
         Self {
             inner: UnsafeCell::new(MutexLockInner::new()),
             data: UnsafeCell::new(t),
             _name: name,
+            dep_map: LockDepMap::new(name),
         }
     }
 }
 
+// Warn about a mutex taken where sleeping is not allowed.
+#[cfg(CONFIG_DEBUG_MUTEXES)]
+#[track_caller]
+fn might_sleep() {
+    use crate::arch::irq::{ArchIrq, IRQ};
+    use crate::schedule::{preempt::preempt_count, scheduler_running};
+
+    if !scheduler_running() {
+        return;
+    }
+    let count = preempt_count();
+    let irqs_disabled = IRQ::local_is_disabled();
+    if count == 0 && !irqs_disabled {
+        return;
+    }
+    let _ = core::fmt::Write::write_fmt(
+        &mut crate::printk::early::EarlyWriter,
+        format_args!(
+            "BUG: sleeping function called from invalid context at {}\n\
+             preempt_count: {:#x}, irqs_disabled(): {}\n",
+            core::panic::Location::caller(),
+            count,
+            irqs_disabled as u8,
+        ),
+    );
+    crate::sync::lockdep::debug_show_held_locks();
+}
+
 impl super::Backend for MutexBackend {
     type Inner = MutexLockInner;
     type GuardState = ();
 
+    #[track_caller]
     fn lock(inner: &mut Self::Inner) -> Self::GuardState {
-        // fast path TODO: use optimistic spinning
-        for _ in 0..100 {
-            if let Some(guard) = Self::try_lock(inner) {
-                return guard;
-            }
+        #[cfg(CONFIG_DEBUG_MUTEXES)]
+        might_sleep();
+
+        let curr = current().as_ptr() as usize;
+        if inner
+            .owner
+            .compare_exchange(0, curr, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            inner.lock_slowpath();
         }
+    }
+
+    fn unlock(inner: &mut Self::Inner, _guard_state: &Self::GuardState) {
+        #[cfg(CONFIG_DEBUG_MUTEXES)]
+        crate::debug_locks_warn_on!(inner.owner() != current().as_ptr() as usize);
+
+        let mut owner = inner.owner.load(Ordering::Relaxed);
         loop {
-            if let Some(guard) = Self::try_lock(inner) {
-                return guard;
+            // The first waiter asked for the lock, hand it over.
+            if owner & MUTEX_FLAG_HANDOFF != 0 {
+                break;
+            }
+            match inner.owner.compare_exchange_weak(
+                owner,
+                owner & MUTEX_FLAGS,
+                Ordering::Release,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => {
+                    if owner & MUTEX_FLAG_WAITERS == 0 {
+                        return;
+                    }
+                    break;
+                }
+                Err(old) => owner = old,
             }
-            inner.wait_queue.wait_until(TaskState::UNINTERRUPTIBLE, || {
-                inner.owner.load(Ordering::Acquire) == 0
-            });
         }
+        inner.unlock_slowpath(owner);
     }
 
-    fn unlock(inner: &mut Self::Inner, _guard_state: &Self::GuardState) {
-        let owner = inner.owner.load(Ordering::Relaxed);
-        assert_eq!(owner, current().as_ptr() as usize);
-        inner.owner.store(0, Ordering::Release);
-        inner.wait_queue.notify_one();
+    fn try_lock(inner: &mut Self::Inner) -> Option<Self::GuardState> {
+        inner.trylock_common(false).then_some(())
     }
 
-    fn try_lock(inner: &mut Self::Inner) -> Option<Self::GuardState> {
-        let curr_ptr = current().as_ptr() as usize;
+    fn assert_is_held(inner: &Self::Inner) {
+        assert_eq!(inner.owner(), current().as_ptr() as usize);
+    }
+}
 
-        if inner
-            .owner
-            .compare_exchange(0, curr_ptr, Ordering::Acquire, Ordering::Relaxed)
-            .is_ok()
-        {
-            return Some(());
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alloc::AllocFlags;
+    use crate::schedule::task::set_test_current;
+
+    #[test]
+    fn test_mutex_contended() {
+        const THREADS: usize = 4;
+        const LOOPS: usize = 1000;
+
+        let m = Arc::new(Mutex::new(0, Some("test_mutex")), AllocFlags::GFP_KERNEL).unwrap();
+        let threads: std::vec::Vec<_> = (0..THREADS)
+            .map(|_| {
+                let m = m.clone();
+                std::thread::spawn(move || {
+                    set_test_current();
+                    for _ in 0..LOOPS {
+                        let mut guard = m.lock();
+                        *guard += 1;
+                        std::thread::yield_now();
+                    }
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
         }
-        None
-    }
 
-    fn assert_is_held(inner: &Self::Inner) {
-        assert_eq!(
-            inner.owner.load(Ordering::Relaxed),
-            current().as_ptr() as usize
-        );
+        set_test_current();
+        assert_eq!(*m.lock(), THREADS * LOOPS);
+        // SAFETY: no one else uses the mutex any more.
+        let inner = unsafe { &*m.inner.get() };
+        assert_eq!(inner.owner.load(Ordering::Relaxed), 0);
     }
 }
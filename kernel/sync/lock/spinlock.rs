@@ -1,13 +1,12 @@
 // SPDX-License-Identifier: GPL-2.0
 
 //! A kernel spinlock.
-//! TODO:
-//!    - support lock_class_key
 
 use crate::{
     arch::irq::{ArchIrq, IRQ},
     macros::section_spinlock_text,
     schedule::preempt::{preempt_disable, preempt_enable},
+    sync::lockdep::LockDepMap,
 };
 use core::cell::UnsafeCell;
 use core::sync::atomic::{AtomicBool, Ordering};
@@ -56,6 +55,7 @@ pub struct SpinLockInner {
 
 impl<T> RawSpinLockNoIrq<T> {
     /// Constructs a new raw spinlock.
+    #[track_caller]
     pub const fn new(t: T, name: Option<&'static str>) -> Self {
         Self {
             inner: UnsafeCell::new(SpinLockInner {
@@ -63,6 +63,7 @@ impl<T> RawSpinLockNoIrq<T> {
             }),
             data: UnsafeCell::new(t),
             _name: name,
+            dep_map: LockDepMap::new(name),
         }
     }
 }
@@ -70,6 +71,7 @@ impl<T> RawSpinLockNoIrq<T> {
 impl super::Backend for RawSpinLockNoIrqBackend {
     type Inner = SpinLockInner;
     type GuardState = <IRQ as ArchIrq>::IrqState;
+    const IRQ_SAFE: bool = true;
 
     #[section_spinlock_text]
     fn lock(inner: &mut Self::Inner) -> Self::GuardState {
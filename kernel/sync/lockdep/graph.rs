@@ -0,0 +1,883 @@
+//! Lock dependency graph
+//!
+//! Every lock class is a vertex, and an edge `A -> B` is recorded the first
+//! time some task acquires a lock of class `B` while holding a lock of class
+//! `A`. A new edge that closes a cycle is a possible ABBA deadlock.
+//!
+//! The graph only uses fixed size tables, so it never allocates and can be
+//! used from the very first spinlock taken during boot.
+
+use core::fmt::{self, Write};
+use core::panic::Location;
+
+use crate::bitflags::bitflags;
+
+/// Lock class index in the class table.
+pub type ClassId = u16;
+
+/// Max lock classes.
+pub const MAX_LOCKDEP_KEYS: usize = 256;
+/// Max dependency entries.
+pub const MAX_LOCKDEP_ENTRIES: usize = 2048;
+/// Max locks a task can hold at the same time.
+pub const MAX_LOCK_DEPTH: usize = 48;
+
+const KEY_WORDS: usize = MAX_LOCKDEP_KEYS / 64;
+
+type Ip = Option<&'static Location<'static>>;
+
+bitflags! {
+    /// How a lock class has been used so far.
+    #[repr(transparent)]
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    pub struct LockUsage: u8 {
+        /// Acquired in hardirq context (the class is hardirq-safe).
+        const USED_IN_HARDIRQ = 1 << 0;
+        /// Acquired with hardirqs enabled (the class is hardirq-unsafe).
+        const ENABLED_HARDIRQ = 1 << 1;
+    }
+}
+
+impl LockUsage {
+    fn name(self) -> &'static str {
+        if self.contains(Self::USED_IN_HARDIRQ) {
+            "IN-HARDIRQ"
+        } else {
+            "HARDIRQ-ON"
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct LockClass {
+    key: Ip,
+    name: Option<&'static str>,
+    usage: LockUsage,
+    used_in_hardirq_ip: Ip,
+    enabled_hardirq_ip: Ip,
+    // First forward dependency entry, index + 1, 0 means none.
+    deps: u32,
+}
+
+impl LockClass {
+    const EMPTY: Self = Self {
+        key: None,
+        name: None,
+        usage: LockUsage::empty(),
+        used_in_hardirq_ip: None,
+        enabled_hardirq_ip: None,
+        deps: 0,
+    };
+}
+
+#[derive(Clone, Copy)]
+struct LockDep {
+    from: ClassId,
+    to: ClassId,
+    // Where `from` was acquired when this dependency was first seen.
+    from_ip: Ip,
+    // Where `to` was acquired when this dependency was first seen.
+    to_ip: Ip,
+    // Next entry with the same `from`, index + 1.
+    next: u32,
+}
+
+impl LockDep {
+    const EMPTY: Self = Self {
+        from: 0,
+        to: 0,
+        from_ip: None,
+        to_ip: None,
+        next: 0,
+    };
+}
+
+// A lock held by a task.
+#[derive(Clone, Copy)]
+struct HeldLock {
+    class: ClassId,
+    ip: Ip,
+    trylock: bool,
+}
+
+impl HeldLock {
+    const EMPTY: Self = Self {
+        class: 0,
+        ip: None,
+        trylock: false,
+    };
+}
+
+/// The stack of locks held by a task, in acquisition order.
+pub struct HeldLocks {
+    depth: usize,
+    locks: [HeldLock; MAX_LOCK_DEPTH],
+}
+
+impl HeldLocks {
+    /// Create an empty held lock stack.
+    pub const fn new() -> Self {
+        Self {
+            depth: 0,
+            locks: [HeldLock::EMPTY; MAX_LOCK_DEPTH],
+        }
+    }
+
+    /// Number of held locks.
+    #[inline]
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Is a lock of `class` held?
+    pub fn is_held(&self, class: ClassId) -> bool {
+        self.as_slice().iter().any(|h| h.class == class)
+    }
+
+    fn as_slice(&self) -> &[HeldLock] {
+        &self.locks[..self.depth]
+    }
+}
+
+/// Context of an acquisition.
+#[derive(Clone, Copy)]
+pub struct AcquireCtx {
+    /// Acquired with `try_lock`, which never blocks.
+    pub trylock: bool,
+    /// Running in hardirq context.
+    pub hardirq: bool,
+    /// Hardirqs are enabled while the lock is held.
+    pub irqs_enabled: bool,
+}
+
+/// A locking rule violation.
+#[derive(Clone, Copy, Debug)]
+pub enum Violation {
+    /// Acquiring a class already held by this task.
+    Recursive {
+        /// Index of the held lock with the same class.
+        held: usize,
+        /// Class being acquired.
+        next: ClassId,
+        /// Where it is acquired.
+        ip: Ip,
+    },
+    /// The new dependency closes a cycle.
+    Circular {
+        /// Index of the held lock the new dependency starts from.
+        held: usize,
+        /// Class being acquired.
+        next: ClassId,
+        /// Where it is acquired.
+        ip: Ip,
+    },
+    /// A hardirq-safe class would depend on a hardirq-unsafe one.
+    IrqSafeToUnsafe {
+        /// The hardirq-safe class.
+        safe: ClassId,
+        /// The hardirq-unsafe class.
+        unsafe_: ClassId,
+        /// The new dependency `(from, to)`, if an edge caused it.
+        edge: Option<(ClassId, ClassId)>,
+        /// Where the lock is acquired.
+        ip: Ip,
+    },
+    /// A class is used both in hardirq and with hardirqs enabled.
+    InconsistentUsage {
+        /// The class.
+        class: ClassId,
+        /// The usage recorded before.
+        prev: LockUsage,
+        /// Where the lock is acquired.
+        ip: Ip,
+    },
+    /// Releasing a lock which is not held.
+    BadUnlock {
+        /// The class.
+        class: ClassId,
+    },
+    /// Too many locks held.
+    LockStackOverflow,
+    /// Class table is full.
+    OutOfClasses,
+    /// Dependency table is full.
+    OutOfEntries,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Forward,
+    Backward,
+}
+
+/// The lock dependency graph.
+pub struct LockGraph {
+    nr_classes: usize,
+    classes: [LockClass; MAX_LOCKDEP_KEYS],
+    nr_entries: usize,
+    entries: [LockDep; MAX_LOCKDEP_ENTRIES],
+    // BFS scratch
+    visited: [u64; KEY_WORDS],
+    queue: [ClassId; MAX_LOCKDEP_KEYS],
+    parent: [u32; MAX_LOCKDEP_KEYS],
+    // Paths found by the last forward/backward search, as entry indexes.
+    fwd_path: [u32; MAX_LOCKDEP_KEYS],
+    fwd_len: usize,
+    bwd_path: [u32; MAX_LOCKDEP_KEYS],
+    bwd_len: usize,
+}
+
+impl LockGraph {
+    /// Create an empty graph.
+    pub const fn new() -> Self {
+        Self {
+            nr_classes: 0,
+            classes: [LockClass::EMPTY; MAX_LOCKDEP_KEYS],
+            nr_entries: 0,
+            entries: [LockDep::EMPTY; MAX_LOCKDEP_ENTRIES],
+            visited: [0; KEY_WORDS],
+            queue: [0; MAX_LOCKDEP_KEYS],
+            parent: [0; MAX_LOCKDEP_KEYS],
+            fwd_path: [0; MAX_LOCKDEP_KEYS],
+            fwd_len: 0,
+            bwd_path: [0; MAX_LOCKDEP_KEYS],
+            bwd_len: 0,
+        }
+    }
+
+    /// Find or register the class of a static lock site.
+    pub fn register_class(
+        &mut self,
+        key: &'static Location<'static>,
+        name: Option<&'static str>,
+    ) -> Result<ClassId, Violation> {
+        let found = self.classes[..self.nr_classes]
+            .iter()
+            .position(|c| c.key.is_some_and(|k| k == key));
+        if let Some(id) = found {
+            return Ok(id as ClassId);
+        }
+
+        if self.nr_classes == MAX_LOCKDEP_KEYS {
+            return Err(Violation::OutOfClasses);
+        }
+        let id = self.nr_classes;
+        self.classes[id] = LockClass {
+            key: Some(key),
+            name,
+            ..LockClass::EMPTY
+        };
+        self.nr_classes += 1;
+        Ok(id as ClassId)
+    }
+
+    /// Usage of a class.
+    pub fn usage(&self, class: ClassId) -> LockUsage {
+        self.classes[class as usize].usage
+    }
+
+    /// Is there a recorded dependency `from -> to`?
+    pub fn has_dep(&self, from: ClassId, to: ClassId) -> bool {
+        self.deps_of(from).any(|e| self.entries[e].to == to)
+    }
+
+    /// Validate and record the acquisition of `class` by the owner of `held`.
+    pub fn acquire(
+        &mut self,
+        held: &mut HeldLocks,
+        class: ClassId,
+        ip: Ip,
+        ctx: AcquireCtx,
+    ) -> Result<(), Violation> {
+        if ctx.hardirq {
+            self.mark_usage(class, LockUsage::USED_IN_HARDIRQ, ip)?;
+        }
+        if ctx.irqs_enabled {
+            self.mark_usage(class, LockUsage::ENABLED_HARDIRQ, ip)?;
+        }
+
+        if !ctx.trylock {
+            if let Some(i) = held.as_slice().iter().position(|h| h.class == class) {
+                return Err(Violation::Recursive {
+                    held: i,
+                    next: class,
+                    ip,
+                });
+            }
+
+            // Depend on the last held lock, and on the ones before it as long as
+            // they were only trylocked: nothing orders those against `class` yet.
+            for i in (0..held.depth).rev() {
+                let prev = held.locks[i];
+                self.check_prev_add(i, prev, class, ip)?;
+                if !prev.trylock {
+                    break;
+                }
+            }
+        }
+
+        if held.depth == MAX_LOCK_DEPTH {
+            return Err(Violation::LockStackOverflow);
+        }
+        held.locks[held.depth] = HeldLock {
+            class,
+            ip,
+            trylock: ctx.trylock,
+        };
+        held.depth += 1;
+        Ok(())
+    }
+
+    /// Record the release of `class`, which need not be the last acquired lock.
+    pub fn release(&mut self, held: &mut HeldLocks, class: ClassId) -> Result<(), Violation> {
+        let Some(i) = held.as_slice().iter().rposition(|h| h.class == class) else {
+            return Err(Violation::BadUnlock { class });
+        };
+        held.locks.copy_within(i + 1..held.depth, i);
+        held.depth -= 1;
+        Ok(())
+    }
+
+    fn check_prev_add(
+        &mut self,
+        held: usize,
+        prev: HeldLock,
+        next: ClassId,
+        ip: Ip,
+    ) -> Result<(), Violation> {
+        if self.has_dep(prev.class, next) {
+            return Ok(());
+        }
+
+        // next -> ... -> prev already exists, adding prev -> next closes the loop.
+        if self
+            .search(next, Direction::Forward, |_, id| id == prev.class)
+            .is_some()
+        {
+            return Err(Violation::Circular { held, next, ip });
+        }
+
+        let safe = self.search(prev.class, Direction::Backward, |c, _| {
+            c.usage.contains(LockUsage::USED_IN_HARDIRQ)
+        });
+        if let Some(safe) = safe {
+            let unsafe_ = self.search(next, Direction::Forward, |c, _| {
+                c.usage.contains(LockUsage::ENABLED_HARDIRQ)
+            });
+            if let Some(unsafe_) = unsafe_ {
+                return Err(Violation::IrqSafeToUnsafe {
+                    safe,
+                    unsafe_,
+                    edge: Some((prev.class, next)),
+                    ip,
+                });
+            }
+        }
+
+        self.add_dep(prev, next, ip)
+    }
+
+    fn add_dep(&mut self, prev: HeldLock, next: ClassId, ip: Ip) -> Result<(), Violation> {
+        if self.nr_entries == MAX_LOCKDEP_ENTRIES {
+            return Err(Violation::OutOfEntries);
+        }
+        let idx = self.nr_entries;
+        let from = &mut self.classes[prev.class as usize];
+        self.entries[idx] = LockDep {
+            from: prev.class,
+            to: next,
+            from_ip: prev.ip,
+            to_ip: ip,
+            next: from.deps,
+        };
+        from.deps = idx as u32 + 1;
+        self.nr_entries += 1;
+        Ok(())
+    }
+
+    fn mark_usage(&mut self, class: ClassId, new: LockUsage, ip: Ip) -> Result<(), Violation> {
+        let c = &mut self.classes[class as usize];
+        if c.usage.contains(new) {
+            return Ok(());
+        }
+        let prev = c.usage;
+        c.usage |= new;
+        if new == LockUsage::USED_IN_HARDIRQ {
+            c.used_in_hardirq_ip = ip;
+        } else {
+            c.enabled_hardirq_ip = ip;
+        }
+
+        if !prev.is_empty() {
+            return Err(Violation::InconsistentUsage { class, prev, ip });
+        }
+
+        // A newly hardirq-safe class must not reach an unsafe one, and a newly
+        // unsafe class must not be reachable from a safe one.
+        if new == LockUsage::USED_IN_HARDIRQ {
+            let found = self.search(class, Direction::Forward, |c, _| {
+                c.usage.contains(LockUsage::ENABLED_HARDIRQ)
+            });
+            if let Some(unsafe_) = found {
+                return Err(Violation::IrqSafeToUnsafe {
+                    safe: class,
+                    unsafe_,
+                    edge: None,
+                    ip,
+                });
+            }
+        } else {
+            let found = self.search(class, Direction::Backward, |c, _| {
+                c.usage.contains(LockUsage::USED_IN_HARDIRQ)
+            });
+            if let Some(safe) = found {
+                return Err(Violation::IrqSafeToUnsafe {
+                    safe,
+                    unsafe_: class,
+                    edge: None,
+                    ip,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn deps_of(&self, class: ClassId) -> impl Iterator<Item = usize> + '_ {
+        let mut e = self.classes[class as usize].deps;
+        core::iter::from_fn(move || {
+            if e == 0 {
+                return None;
+            }
+            let idx = e as usize - 1;
+            e = self.entries[idx].next;
+            Some(idx)
+        })
+    }
+
+    /// Breadth first search from `start` (included) for a class matching `pred`.
+    ///
+    /// On success the path from `start` is saved in the path of `dir`.
+    fn search(
+        &mut self,
+        start: ClassId,
+        dir: Direction,
+        pred: impl Fn(&LockClass, ClassId) -> bool,
+    ) -> Option<ClassId> {
+        self.visited = [0; KEY_WORDS];
+        let (mut head, mut tail) = (0, 1);
+        self.queue[0] = start;
+        self.parent[start as usize] = 0;
+        self.visit(start);
+
+        while head < tail {
+            let id = self.queue[head];
+            head += 1;
+            if pred(&self.classes[id as usize], id) {
+                self.save_path(start, id, dir);
+                return Some(id);
+            }
+
+            for e in 0..self.nr_entries {
+                let dep = self.entries[e];
+                let (from, to) = match dir {
+                    Direction::Forward => (dep.from, dep.to),
+                    Direction::Backward => (dep.to, dep.from),
+                };
+                if from != id || self.is_visited(to) {
+                    continue;
+                }
+                self.visit(to);
+                self.parent[to as usize] = e as u32 + 1;
+                self.queue[tail] = to;
+                tail += 1;
+            }
+        }
+        None
+    }
+
+    fn save_path(&mut self, start: ClassId, end: ClassId, dir: Direction) {
+        let (path, len) = match dir {
+            Direction::Forward => (&mut self.fwd_path, &mut self.fwd_len),
+            Direction::Backward => (&mut self.bwd_path, &mut self.bwd_len),
+        };
+        *len = 0;
+        let mut id = end;
+        while id != start {
+            let e = self.parent[id as usize] as usize - 1;
+            path[*len] = e as u32;
+            *len += 1;
+            id = match dir {
+                Direction::Forward => self.entries[e].from,
+                Direction::Backward => self.entries[e].to,
+            };
+        }
+    }
+
+    #[inline]
+    fn visit(&mut self, id: ClassId) {
+        self.visited[id as usize / 64] |= 1 << (id % 64);
+    }
+
+    #[inline]
+    fn is_visited(&self, id: ClassId) -> bool {
+        self.visited[id as usize / 64] & (1 << (id % 64)) != 0
+    }
+
+    fn write_class(&self, w: &mut impl Write, class: ClassId) -> fmt::Result {
+        let c = &self.classes[class as usize];
+        match (c.name, c.key) {
+            (Some(name), _) => write!(w, "({})", name),
+            (None, Some(key)) => write!(w, "({})", key),
+            (None, None) => write!(w, "(#{})", class),
+        }
+    }
+
+    fn write_ip(w: &mut impl Write, ip: Ip) -> fmt::Result {
+        match ip {
+            Some(ip) => write!(w, "{}", ip),
+            None => w.write_str("<unknown>"),
+        }
+    }
+
+    /// Print the held lock stack.
+    pub fn write_held_locks(&self, w: &mut impl Write, held: &HeldLocks) -> fmt::Result {
+        writeln!(w, "{} lock(s) held:", held.depth)?;
+        for (i, h) in held.as_slice().iter().enumerate() {
+            write!(w, " #{}: ", i)?;
+            self.write_class(w, h.class)?;
+            w.write_str(", at: ")?;
+            Self::write_ip(w, h.ip)?;
+            writeln!(w, "{}", if h.trylock { " (trylock)" } else { "" })?;
+        }
+        Ok(())
+    }
+
+    fn write_dep(&self, w: &mut impl Write, n: usize, e: u32) -> fmt::Result {
+        let dep = &self.entries[e as usize];
+        write!(w, "-> #{} ", n)?;
+        self.write_class(w, dep.to)?;
+        w.write_str(" acquired at ")?;
+        Self::write_ip(w, dep.to_ip)?;
+        w.write_str("\n       while holding ")?;
+        self.write_class(w, dep.from)?;
+        w.write_str(" acquired at ")?;
+        Self::write_ip(w, dep.from_ip)?;
+        w.write_str("\n")
+    }
+
+    /// Print a report for a violation found by the last call to `acquire` or `release`.
+    pub fn report(&self, w: &mut impl Write, held: &HeldLocks, v: &Violation) -> fmt::Result {
+        const SEP: &str = "======================================================\n";
+        w.write_str("\n")?;
+        w.write_str(SEP)?;
+        match *v {
+            Violation::Recursive { held: i, next, ip } => {
+                w.write_str("WARNING: possible recursive locking detected\n")?;
+                w.write_str("task is trying to acquire lock:\n  ")?;
+                self.write_class(w, next)?;
+                w.write_str(", at: ")?;
+                Self::write_ip(w, ip)?;
+                w.write_str("\n\nbut task is already holding lock:\n  ")?;
+                self.write_class(w, held.locks[i].class)?;
+                w.write_str(", at: ")?;
+                Self::write_ip(w, held.locks[i].ip)?;
+                w.write_str("\n\n")?;
+            }
+            Violation::Circular { held: i, next, ip } => {
+                w.write_str("WARNING: possible circular locking dependency detected\n")?;
+                w.write_str("task is trying to acquire lock:\n  ")?;
+                self.write_class(w, next)?;
+                w.write_str(", at: ")?;
+                Self::write_ip(w, ip)?;
+                w.write_str("\n\nbut task is already holding lock:\n  ")?;
+                self.write_class(w, held.locks[i].class)?;
+                w.write_str(", at: ")?;
+                Self::write_ip(w, held.locks[i].ip)?;
+                w.write_str("\n\nwhich lock already depends on the new lock.\n\n")?;
+                w.write_str("the existing dependency chain (in reverse order) is:\n\n")?;
+                for n in 0..self.fwd_len {
+                    self.write_dep(w, self.fwd_len - 1 - n, self.fwd_path[n])?;
+                }
+                w.write_str("\n")?;
+            }
+            Violation::IrqSafeToUnsafe {
+                safe,
+                unsafe_,
+                edge,
+                ip,
+            } => {
+                w.write_str("WARNING: HARDIRQ-safe -> HARDIRQ-unsafe lock order detected\n")?;
+                if let Some((from, to)) = edge {
+                    w.write_str("task is trying to acquire:\n  ")?;
+                    self.write_class(w, to)?;
+                    w.write_str(", at: ")?;
+                    Self::write_ip(w, ip)?;
+                    w.write_str("\nwhile holding:\n  ")?;
+                    self.write_class(w, from)?;
+                    w.write_str("\n")?;
+                } else {
+                    w.write_str("lock usage changed at: ")?;
+                    Self::write_ip(w, ip)?;
+                    w.write_str("\n")?;
+                }
+                w.write_str("\nthe HARDIRQ-safe lock ")?;
+                self.write_class(w, safe)?;
+                w.write_str(" was taken in hardirq at: ")?;
+                Self::write_ip(w, self.classes[safe as usize].used_in_hardirq_ip)?;
+                w.write_str("\nthe HARDIRQ-unsafe lock ")?;
+                self.write_class(w, unsafe_)?;
+                w.write_str(" was taken with irqs on at: ")?;
+                Self::write_ip(w, self.classes[unsafe_ as usize].enabled_hardirq_ip)?;
+                w.write_str("\n\nthe dependency chain from the safe lock:\n\n")?;
+                let mut n = 0;
+                for k in 0..self.bwd_len {
+                    self.write_dep(w, n, self.bwd_path[k])?;
+                    n += 1;
+                }
+                if edge.is_some() {
+                    w.write_str("-> (new dependency)\n")?;
+                }
+                for k in (0..self.fwd_len).rev() {
+                    self.write_dep(w, n, self.fwd_path[k])?;
+                    n += 1;
+                }
+                w.write_str("\n")?;
+            }
+            Violation::InconsistentUsage { class, prev, ip } => {
+                let new = self.classes[class as usize].usage.difference(prev);
+                writeln!(
+                    w,
+                    "WARNING: inconsistent {{{}}} -> {{{}}} usage.",
+                    prev.name(),
+                    new.name()
+                )?;
+                self.write_class(w, class)?;
+                w.write_str(" acquired at: ")?;
+                Self::write_ip(w, ip)?;
+                w.write_str("\n{IN-HARDIRQ} state was registered at: ")?;
+                Self::write_ip(w, self.classes[class as usize].used_in_hardirq_ip)?;
+                w.write_str("\n{HARDIRQ-ON} state was registered at: ")?;
+                Self::write_ip(w, self.classes[class as usize].enabled_hardirq_ip)?;
+                w.write_str("\n\n")?;
+            }
+            Violation::BadUnlock { class } => {
+                w.write_str("WARNING: bad unlock balance detected!\n")?;
+                w.write_str("task is trying to release lock ")?;
+                self.write_class(w, class)?;
+                w.write_str(" but there are no more locks to release!\n\n")?;
+            }
+            Violation::LockStackOverflow => {
+                writeln!(w, "BUG: MAX_LOCK_DEPTH too low! ({})", MAX_LOCK_DEPTH)?;
+            }
+            Violation::OutOfClasses => {
+                writeln!(w, "BUG: MAX_LOCKDEP_KEYS too low! ({})", MAX_LOCKDEP_KEYS)?;
+            }
+            Violation::OutOfEntries => {
+                writeln!(w, "BUG: MAX_LOCKDEP_ENTRIES too low! ({})", MAX_LOCKDEP_ENTRIES)?;
+            }
+        }
+        self.write_held_locks(w, held)?;
+        w.write_str(SEP)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROCESS: AcquireCtx = AcquireCtx {
+        trylock: false,
+        hardirq: false,
+        irqs_enabled: true,
+    };
+
+    const IRQS_OFF: AcquireCtx = AcquireCtx {
+        trylock: false,
+        hardirq: false,
+        irqs_enabled: false,
+    };
+
+    const HARDIRQ: AcquireCtx = AcquireCtx {
+        trylock: false,
+        hardirq: true,
+        irqs_enabled: false,
+    };
+
+    #[track_caller]
+    fn here() -> Ip {
+        Some(Location::caller())
+    }
+
+    fn classes<const N: usize>(g: &mut LockGraph, names: [&'static str; N]) -> [ClassId; N] {
+        let sites = [here(), here(), here(), here()];
+        let mut ids = [0; N];
+        for i in 0..N {
+            ids[i] = g.register_class(sites[i].unwrap(), Some(names[i])).unwrap();
+        }
+        ids
+    }
+
+    #[test]
+    fn test_register_class_per_site() {
+        let mut g = LockGraph::new();
+        let site = Location::caller();
+        let a = g.register_class(site, Some("a")).unwrap();
+        let b = g.register_class(site, Some("a")).unwrap();
+        assert_eq!(a, b);
+        let [c] = classes(&mut g, ["c"]);
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn test_abba() {
+        let mut g = LockGraph::new();
+        let mut held = HeldLocks::new();
+        let [a, b] = classes(&mut g, ["A", "B"]);
+
+        assert!(g.acquire(&mut held, a, here(), PROCESS).is_ok());
+        assert!(g.acquire(&mut held, b, here(), PROCESS).is_ok());
+        assert!(g.has_dep(a, b));
+        assert!(g.release(&mut held, b).is_ok());
+        assert!(g.release(&mut held, a).is_ok());
+
+        assert!(g.acquire(&mut held, b, here(), PROCESS).is_ok());
+        let v = g.acquire(&mut held, a, here(), PROCESS).unwrap_err();
+        assert!(matches!(v, Violation::Circular { held: 0, next, .. } if next == a));
+
+        let mut out = std::string::String::new();
+        g.report(&mut out, &held, &v).unwrap();
+        assert!(out.contains("circular locking dependency"));
+        assert!(out.contains("-> #0 (B) acquired at"));
+        assert!(out.contains("while holding (A)"));
+        assert!(out.contains(" #0: (B), at: "));
+    }
+
+    #[test]
+    fn test_transitive_cycle() {
+        let mut g = LockGraph::new();
+        let mut held = HeldLocks::new();
+        let [a, b, c] = classes(&mut g, ["A", "B", "C"]);
+
+        for (x, y) in [(a, b), (b, c)] {
+            g.acquire(&mut held, x, here(), PROCESS).unwrap();
+            g.acquire(&mut held, y, here(), PROCESS).unwrap();
+            g.release(&mut held, y).unwrap();
+            g.release(&mut held, x).unwrap();
+        }
+
+        g.acquire(&mut held, c, here(), PROCESS).unwrap();
+        let v = g.acquire(&mut held, a, here(), PROCESS).unwrap_err();
+        assert!(matches!(v, Violation::Circular { .. }));
+        assert_eq!(g.fwd_len, 2);
+    }
+
+    #[test]
+    fn test_recursive() {
+        let mut g = LockGraph::new();
+        let mut held = HeldLocks::new();
+        let [a] = classes(&mut g, ["A"]);
+
+        g.acquire(&mut held, a, here(), PROCESS).unwrap();
+        let v = g.acquire(&mut held, a, here(), PROCESS).unwrap_err();
+        assert!(matches!(v, Violation::Recursive { held: 0, .. }));
+
+        // trylock never blocks, so it can not deadlock on itself
+        let try_ctx = AcquireCtx {
+            trylock: true,
+            ..PROCESS
+        };
+        assert!(g.acquire(&mut held, a, here(), try_ctx).is_ok());
+    }
+
+    #[test]
+    fn test_trylock_no_dependency() {
+        let mut g = LockGraph::new();
+        let mut held = HeldLocks::new();
+        let [a, b, c] = classes(&mut g, ["A", "B", "C"]);
+        let try_ctx = AcquireCtx {
+            trylock: true,
+            ..PROCESS
+        };
+
+        g.acquire(&mut held, a, here(), PROCESS).unwrap();
+        g.acquire(&mut held, b, here(), try_ctx).unwrap();
+        assert!(!g.has_dep(a, b));
+        g.acquire(&mut held, c, here(), PROCESS).unwrap();
+        assert!(g.has_dep(b, c));
+        assert!(g.has_dep(a, c));
+    }
+
+    #[test]
+    fn test_release_out_of_order() {
+        let mut g = LockGraph::new();
+        let mut held = HeldLocks::new();
+        let [a, b] = classes(&mut g, ["A", "B"]);
+
+        g.acquire(&mut held, a, here(), PROCESS).unwrap();
+        g.acquire(&mut held, b, here(), PROCESS).unwrap();
+        g.release(&mut held, a).unwrap();
+        assert_eq!(held.depth(), 1);
+        assert!(held.is_held(b));
+        assert!(!held.is_held(a));
+        g.release(&mut held, b).unwrap();
+        let v = g.release(&mut held, b).unwrap_err();
+        assert!(matches!(v, Violation::BadUnlock { .. }));
+    }
+
+    #[test]
+    fn test_irq_safe_to_unsafe() {
+        let mut g = LockGraph::new();
+        let mut held = HeldLocks::new();
+        let [safe, unsafe_] = classes(&mut g, ["safe", "unsafe"]);
+
+        g.acquire(&mut held, safe, here(), HARDIRQ).unwrap();
+        g.release(&mut held, safe).unwrap();
+        g.acquire(&mut held, unsafe_, here(), PROCESS).unwrap();
+        g.release(&mut held, unsafe_).unwrap();
+
+        g.acquire(&mut held, safe, here(), IRQS_OFF).unwrap();
+        let v = g.acquire(&mut held, unsafe_, here(), IRQS_OFF).unwrap_err();
+        assert!(matches!(
+            v,
+            Violation::IrqSafeToUnsafe { safe: s, unsafe_: u, edge: Some(_), .. }
+                if s == safe && u == unsafe_
+        ));
+
+        let mut out = std::string::String::new();
+        g.report(&mut out, &held, &v).unwrap();
+        assert!(out.contains("HARDIRQ-safe -> HARDIRQ-unsafe"));
+    }
+
+    #[test]
+    fn test_irq_usage_after_dependency() {
+        let mut g = LockGraph::new();
+        let mut held = HeldLocks::new();
+        let [a, b] = classes(&mut g, ["A", "B"]);
+
+        // A -> B recorded with irqs off, B also used with irqs on
+        g.acquire(&mut held, a, here(), IRQS_OFF).unwrap();
+        g.acquire(&mut held, b, here(), IRQS_OFF).unwrap();
+        g.release(&mut held, b).unwrap();
+        g.release(&mut held, a).unwrap();
+        g.acquire(&mut held, b, here(), PROCESS).unwrap();
+        g.release(&mut held, b).unwrap();
+
+        // A becomes hardirq-safe
+        let v = g.acquire(&mut held, a, here(), HARDIRQ).unwrap_err();
+        assert!(matches!(v, Violation::IrqSafeToUnsafe { edge: None, .. }));
+        assert_eq!(g.fwd_len, 1);
+    }
+
+    #[test]
+    fn test_inconsistent_usage() {
+        let mut g = LockGraph::new();
+        let mut held = HeldLocks::new();
+        let [a] = classes(&mut g, ["A"]);
+
+        g.acquire(&mut held, a, here(), HARDIRQ).unwrap();
+        g.release(&mut held, a).unwrap();
+        let v = g.acquire(&mut held, a, here(), PROCESS).unwrap_err();
+        assert!(matches!(v, Violation::InconsistentUsage { .. }));
+        assert!(g.usage(a).contains(LockUsage::USED_IN_HARDIRQ | LockUsage::ENABLED_HARDIRQ));
+    }
+}
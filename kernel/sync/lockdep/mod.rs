@@ -0,0 +1,240 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Runtime locking correctness validator.
+//!
+//! Refer to linux: kernel/locking/lockdep.c
+//!
+//! Every lock constructor is `#[track_caller]`, and the place a lock is
+//! created becomes its lock class. All locks created by the same line of
+//! code share one class, e.g. the `state` lock of every [`Task`].
+//!
+//! Each task keeps a stack of the locks it holds. When it acquires a new lock,
+//! lockdep checks for:
+//!
+//! - recursive locking of a class it already holds,
+//! - ABBA inversions, i.e. a new dependency that closes a cycle in the graph
+//!   of lock classes,
+//! - a hardirq-safe class depending on a hardirq-unsafe one, and a class being
+//!   used both in hardirq context and with hardirqs enabled.
+//!
+//! The first problem found is printed with both acquisition chains, and then
+//! lockdep turns itself off like Linux `debug_locks_off()`.
+//!
+//! [`Task`]: crate::schedule::task::Task
+
+#[cfg(any(CONFIG_LOCKDEP, test))]
+mod graph;
+#[cfg(any(CONFIG_LOCKDEP, test))]
+pub use graph::{
+    AcquireCtx, ClassId, HeldLocks, LockGraph, LockUsage, Violation, MAX_LOCKDEP_ENTRIES,
+    MAX_LOCKDEP_KEYS, MAX_LOCK_DEPTH,
+};
+
+pub use super::debug_locks::{debug_locks, debug_locks_off};
+
+#[cfg(CONFIG_LOCKDEP)]
+pub use enabled::*;
+
+#[cfg(not(CONFIG_LOCKDEP))]
+pub use disabled::*;
+
+#[cfg(CONFIG_LOCKDEP)]
+mod enabled {
+    use core::cell::UnsafeCell;
+    use core::panic::Location;
+    use core::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+
+    use super::graph::{AcquireCtx, ClassId, HeldLocks, LockGraph, Violation};
+    use super::{debug_locks, debug_locks_off};
+    use crate::arch::irq::{ArchIrq, IRQ};
+    use crate::printk::early::EarlyWriter;
+    use crate::schedule::{current, preempt::in_hardirq};
+
+    // The graph is protected by a bare spinlock: it must not be a `Lock`,
+    // which would recurse into lockdep.
+    struct GraphCell {
+        locked: AtomicBool,
+        graph: UnsafeCell<LockGraph>,
+    }
+
+    // SAFETY: the graph is only accessed with `locked` held.
+    unsafe impl Sync for GraphCell {}
+
+    static LOCKDEP_GRAPH: GraphCell = GraphCell {
+        locked: AtomicBool::new(false),
+        graph: UnsafeCell::new(LockGraph::new()),
+    };
+
+    fn with_graph<R>(f: impl FnOnce(&mut LockGraph) -> R) -> R {
+        let irq = IRQ::local_save_and_disable();
+        while LOCKDEP_GRAPH
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            core::hint::spin_loop();
+        }
+        // SAFETY: we own `locked`.
+        let r = f(unsafe { &mut *LOCKDEP_GRAPH.graph.get() });
+        LOCKDEP_GRAPH.locked.store(false, Ordering::Release);
+        IRQ::local_restore(irq);
+        r
+    }
+
+    fn report(graph: &LockGraph, held: &HeldLocks, v: &Violation) {
+        if debug_locks_off() {
+            let _ = graph.report(&mut EarlyWriter, held, v);
+        }
+    }
+
+    /// Per lock lockdep data, embedded in every [`Lock`].
+    ///
+    /// [`Lock`]: crate::sync::lock::Lock
+    pub struct LockDepMap {
+        key: &'static Location<'static>,
+        name: Option<&'static str>,
+        // Cached class id + 1, 0 if not registered yet.
+        class: AtomicU16,
+    }
+
+    impl LockDepMap {
+        /// Create a lockdep map, the caller location is the lock class.
+        #[track_caller]
+        pub const fn new(name: Option<&'static str>) -> Self {
+            Self {
+                key: Location::caller(),
+                name,
+                class: AtomicU16::new(0),
+            }
+        }
+
+        fn class(&self, graph: &mut LockGraph) -> Result<ClassId, Violation> {
+            let cached = self.class.load(Ordering::Relaxed);
+            if cached != 0 {
+                return Ok(cached - 1);
+            }
+            let id = graph.register_class(self.key, self.name)?;
+            self.class.store(id + 1, Ordering::Relaxed);
+            Ok(id)
+        }
+
+        /// Validate and record acquiring this lock.
+        ///
+        /// `irq_safe` means the lock disables local irqs while held.
+        #[track_caller]
+        pub fn acquire(&self, trylock: bool, irq_safe: bool) {
+            if !debug_locks() {
+                return;
+            }
+            let ip = Location::caller();
+            let ctx = AcquireCtx {
+                trylock,
+                hardirq: in_hardirq(),
+                irqs_enabled: !irq_safe && !IRQ::local_is_disabled(),
+            };
+            let curr = current();
+            with_graph(|graph| {
+                // SAFETY: only the current task touches its held locks, and
+                // irqs are disabled.
+                let held = unsafe { curr.held_locks() };
+                let r = self
+                    .class(graph)
+                    .and_then(|class| graph.acquire(held, class, Some(ip), ctx));
+                if let Err(v) = r {
+                    report(graph, held, &v);
+                }
+            });
+        }
+
+        /// Record releasing this lock.
+        pub fn release(&self) {
+            if !debug_locks() {
+                return;
+            }
+            let curr = current();
+            with_graph(|graph| {
+                // SAFETY: only the current task touches its held locks, and
+                // irqs are disabled.
+                let held = unsafe { curr.held_locks() };
+                let r = self
+                    .class(graph)
+                    .and_then(|class| graph.release(held, class));
+                if let Err(v) = r {
+                    report(graph, held, &v);
+                }
+            });
+        }
+
+        /// Is this lock held by the current task?
+        pub fn is_held(&self) -> bool {
+            let cached = self.class.load(Ordering::Relaxed);
+            let curr = current();
+            // SAFETY: only read by the current task.
+            cached != 0 && unsafe { curr.held_locks() }.is_held(cached - 1)
+        }
+    }
+
+    /// Print the locks held by the current task.
+    pub fn debug_show_held_locks() {
+        let curr = current();
+        with_graph(|graph| {
+            // SAFETY: only the current task touches its held locks, and
+            // irqs are disabled.
+            let held = unsafe { curr.held_locks() };
+            let _ = graph.write_held_locks(&mut EarlyWriter, held);
+        });
+    }
+}
+
+#[cfg(not(CONFIG_LOCKDEP))]
+mod disabled {
+    /// Per lock lockdep data, empty without `CONFIG_LOCKDEP`.
+    pub struct LockDepMap;
+
+    impl LockDepMap {
+        /// Create a lockdep map.
+        #[inline(always)]
+        pub const fn new(_name: Option<&'static str>) -> Self {
+            Self
+        }
+
+        /// Validate and record acquiring this lock.
+        #[inline(always)]
+        pub fn acquire(&self, _trylock: bool, _irq_safe: bool) {}
+
+        /// Record releasing this lock.
+        #[inline(always)]
+        pub fn release(&self) {}
+    }
+
+    /// Print the locks held by the current task.
+    #[inline(always)]
+    pub fn debug_show_held_locks() {}
+}
+
+#[cfg(all(test, CONFIG_LOCKDEP))]
+mod tests {
+    use super::*;
+    use crate::schedule::task::set_test_current;
+    use crate::sync::lock::{Mutex, RawSpinLockNoIrq};
+
+    #[test]
+    fn test_lockdep_abba() {
+        set_test_current();
+        let a = RawSpinLockNoIrq::new(0, Some("test_a"));
+        let b = Mutex::new(0, Some("test_b"));
+
+        {
+            let _ga = a.lock();
+            let _gb = b.lock();
+            assert!(a.dep_map.is_held());
+            assert!(b.dep_map.is_held());
+        }
+        assert!(!a.dep_map.is_held());
+        assert!(debug_locks());
+
+        let _gb = b.lock();
+        let _ga = a.lock();
+        assert!(!debug_locks());
+    }
+}
@@ -1,4 +1,16 @@
 //! Synchronisation primitives.
 
 pub mod arc;
+pub mod completion;
+pub mod condvar;
+pub mod debug_locks;
 pub mod lock;
+pub mod lockdep;
+pub mod semaphore;
+pub mod seqlock;
+
+pub use completion::Completion;
+pub use condvar::CondVar;
+pub use lock::{Mutex, MutexGuard};
+pub use semaphore::Semaphore;
+pub use seqlock::{SeqCount, SeqLock};
@@ -0,0 +1,139 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Counting semaphore
+//!
+//! Refer to linux: kernel/locking/semaphore.c
+//!
+//! Unlike a [`Mutex`], a semaphore has no owner, it may be released by
+//! another task or from irq context, and allows `count` holders at once.
+//!
+//! [`Mutex`]: crate::sync::lock::Mutex
+
+use crate::error::{Error, Result};
+use crate::schedule::task::TaskState;
+use crate::schedule::WaitQueue;
+use crate::sync::lock::RawSpinLockNoIrq;
+
+/// A counting semaphore.
+pub struct Semaphore {
+    count: RawSpinLockNoIrq<u32>,
+    wait: WaitQueue,
+}
+
+impl Semaphore {
+    /// Create a semaphore allowing `count` holders.
+    pub const fn new(count: u32) -> Self {
+        Self {
+            count: RawSpinLockNoIrq::new(count, Some("semaphore")),
+            wait: WaitQueue::new(),
+        }
+    }
+
+    /// Try to acquire the semaphore without sleeping, return whether it was acquired.
+    pub fn down_trylock(&self) -> bool {
+        let mut count = self.count.lock();
+        if *count == 0 {
+            return false;
+        }
+        *count -= 1;
+        true
+    }
+
+    /// Acquire the semaphore, sleep until it is available.
+    pub fn down(&self) {
+        self.wait
+            .wait_until(TaskState::UNINTERRUPTIBLE, || self.down_trylock());
+    }
+
+    /// Acquire the semaphore, or return `Erestartsys` if a signal is pending.
+    pub fn down_interruptible(&self) -> Result {
+        self.wait.wait_until_interruptible(|| self.down_trylock())
+    }
+
+    /// Acquire the semaphore, or return `Etime` after `timeout` jiffies.
+    pub fn down_timeout(&self, timeout: usize) -> Result {
+        match self
+            .wait
+            .wait_until_timeout(|| self.down_trylock(), timeout)
+        {
+            0 => Err(Error::Etime),
+            _ => Ok(()),
+        }
+    }
+
+    /// Release the semaphore, may be called from any context.
+    pub fn up(&self) {
+        *self.count.lock() += 1;
+        self.wait.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schedule::task::set_test_current;
+    use crate::time::{time_test_lock, TestTicker};
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_semaphore_contended() {
+        const THREADS: usize = 4;
+        const LOOPS: usize = 500;
+        static SEM: Semaphore = Semaphore::new(2);
+        static HOLDERS: AtomicUsize = AtomicUsize::new(0);
+        static MAX_HOLDERS: AtomicUsize = AtomicUsize::new(0);
+        set_test_current();
+
+        let threads: std::vec::Vec<_> = (0..THREADS)
+            .map(|i| {
+                std::thread::spawn(move || {
+                    set_test_current();
+                    for _ in 0..LOOPS {
+                        if i % 2 == 0 {
+                            SEM.down();
+                        } else {
+                            SEM.down_interruptible().unwrap();
+                        }
+                        let holders = HOLDERS.fetch_add(1, Ordering::SeqCst) + 1;
+                        MAX_HOLDERS.fetch_max(holders, Ordering::SeqCst);
+                        std::thread::yield_now();
+                        HOLDERS.fetch_sub(1, Ordering::SeqCst);
+                        SEM.up();
+                    }
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        assert!(MAX_HOLDERS.load(Ordering::SeqCst) <= 2);
+        assert!(SEM.down_trylock());
+        assert!(SEM.down_trylock());
+        assert!(!SEM.down_trylock());
+    }
+
+    #[test]
+    fn test_semaphore_timeout() {
+        static SEM: Semaphore = Semaphore::new(1);
+        set_test_current();
+        let _guard = time_test_lock();
+
+        SEM.down_timeout(1).unwrap();
+        assert_eq!(SEM.down_timeout(0), Err(Error::Etime));
+
+        // Woken by up before the timeout.
+        let upper = std::thread::spawn(|| {
+            set_test_current();
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            SEM.up();
+        });
+        SEM.down_timeout(usize::MAX / 2).unwrap();
+        upper.join().unwrap();
+
+        // Nobody releases it.
+        let _ticker = TestTicker::start();
+        assert_eq!(SEM.down_timeout(2), Err(Error::Etime));
+        SEM.up();
+    }
+}
@@ -0,0 +1,159 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Sequence locks
+//!
+//! Refer to linux: include/linux/seqlock.h
+//!
+//! Readers never block the writer: they read optimistically and retry if
+//! the sequence count shows a write happened meanwhile. Good for small data
+//! that is read often and written rarely, such as the timekeeper.
+
+use core::cell::UnsafeCell;
+use core::hint::spin_loop;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{fence, AtomicUsize, Ordering};
+
+use super::lock::{RawSpinLockNoIrq, RawSpinLockNoIrqGuard};
+
+/// A sequence counter, odd while a write is in progress.
+///
+/// Writers must be serialized by the caller, see [`SeqLock`].
+pub struct SeqCount {
+    sequence: AtomicUsize,
+}
+
+impl SeqCount {
+    /// Create a new sequence counter
+    pub const fn new() -> Self {
+        Self {
+            sequence: AtomicUsize::new(0),
+        }
+    }
+
+    /// Begin a read section, waiting for a pending write.
+    #[inline]
+    pub fn read_begin(&self) -> usize {
+        loop {
+            let seq = self.sequence.load(Ordering::Acquire);
+            if seq & 1 == 0 {
+                return seq;
+            }
+            spin_loop();
+        }
+    }
+
+    /// End a read section, return true if it must be retried.
+    #[inline]
+    pub fn read_retry(&self, start: usize) -> bool {
+        fence(Ordering::Acquire);
+        self.sequence.load(Ordering::Relaxed) != start
+    }
+
+    /// Begin a write section.
+    #[inline]
+    pub fn write_begin(&self) {
+        self.sequence.fetch_add(1, Ordering::Relaxed);
+        fence(Ordering::Release);
+    }
+
+    /// End a write section.
+    #[inline]
+    pub fn write_end(&self) {
+        self.sequence.fetch_add(1, Ordering::Release);
+    }
+}
+
+impl Default for SeqCount {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A sequence counter with a spinlock serializing the writers.
+///
+/// ```
+/// use kernel::sync::SeqLock;
+///
+/// static CLOCK: SeqLock<(u64, u64)> = SeqLock::new((0, 0), Some("clock"));
+///
+/// fn update(sec: u64, nsec: u64) {
+///     *CLOCK.write() = (sec, nsec);
+/// }
+///
+/// fn get() -> (u64, u64) {
+///     CLOCK.read()
+/// }
+/// ```
+pub struct SeqLock<T> {
+    seq: SeqCount,
+    lock: RawSpinLockNoIrq<()>,
+    data: UnsafeCell<T>,
+}
+
+// SAFETY: writers are serialized by the lock, and readers only copy the data
+// out, retrying on a concurrent write.
+unsafe impl<T: Copy + Send> Sync for SeqLock<T> {}
+
+impl<T: Copy> SeqLock<T> {
+    /// Create a new seqlock
+    #[track_caller]
+    pub const fn new(data: T, name: Option<&'static str>) -> Self {
+        Self {
+            seq: SeqCount::new(),
+            lock: RawSpinLockNoIrq::new((), name),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Read a consistent copy of the data.
+    pub fn read(&self) -> T {
+        loop {
+            let seq = self.seq.read_begin();
+            // SAFETY: the copy may be torn by a concurrent writer, in which
+            // case the sequence changed and it is thrown away.
+            let data = unsafe { core::ptr::read_volatile(self.data.get()) };
+            if !self.seq.read_retry(seq) {
+                return data;
+            }
+        }
+    }
+
+    /// Lock out other writers and begin a write section.
+    #[track_caller]
+    pub fn write(&self) -> SeqLockWriteGuard<'_, T> {
+        let guard = self.lock.lock();
+        self.seq.write_begin();
+        SeqLockWriteGuard {
+            lock: self,
+            _guard: guard,
+        }
+    }
+}
+
+/// A write section of a [`SeqLock`], ended on drop.
+pub struct SeqLockWriteGuard<'a, T: Copy> {
+    lock: &'a SeqLock<T>,
+    _guard: RawSpinLockNoIrqGuard<'a, ()>,
+}
+
+impl<T: Copy> Deref for SeqLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: we are the only writer.
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: Copy> DerefMut for SeqLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: we are the only writer.
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: Copy> Drop for SeqLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.seq.write_end();
+    }
+}
@@ -0,0 +1,55 @@
+//! Clock event devices
+//!
+//! Refer to linux: kernel/time/clockevents.c
+//!
+//! Every cpu has one event device, programmed in one shot mode to the
+//! earliest hrtimer. The periodic tick is an hrtimer as well.
+
+use super::{hrtimer, tick, Ktime};
+use crate::arch::cpu::MAX_CPUS;
+use crate::cpu::smp_processor_id;
+use crate::sync::lock::RawSpinLockNoIrq;
+
+/// A clock event device, a timer that interrupts its cpu.
+pub trait ClockEventDevice: Sync {
+    /// Device name
+    fn name(&self) -> &'static str;
+
+    /// Program the next event at the monotonic time `expires`.
+    ///
+    /// An event that is already due must fire as soon as possible.
+    fn set_next_ktime(&self, expires: Ktime);
+
+    /// Stop the device.
+    fn shutdown(&self);
+}
+
+type DeviceSlot = RawSpinLockNoIrq<Option<&'static dyn ClockEventDevice>>;
+
+static CLOCKEVENT_DEVICES: [DeviceSlot; MAX_CPUS] =
+    [const { RawSpinLockNoIrq::new(None, Some("clockevents")) }; MAX_CPUS];
+
+/// Register the event device of the current cpu, and start its tick.
+pub fn clockevents_register_device(dev: &'static dyn ClockEventDevice) {
+    let cpu = smp_processor_id();
+    if let Some(old) = CLOCKEVENT_DEVICES[cpu].lock().replace(dev) {
+        old.shutdown();
+    }
+    tick::tick_setup(cpu);
+}
+
+/// Program the device of `cpu`, return false if it has none.
+pub(crate) fn clockevents_program_event(cpu: usize, expires: Ktime) -> bool {
+    match *CLOCKEVENT_DEVICES[cpu].lock() {
+        Some(dev) => {
+            dev.set_next_ktime(expires);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Handle an event of the current cpu device, called by its interrupt handler.
+pub fn clockevents_handle_event() {
+    hrtimer::hrtimer_interrupt(smp_processor_id());
+}
@@ -0,0 +1,37 @@
+//! Clock sources
+//!
+//! Refer to linux: kernel/time/clocksource.c
+//!
+//! A clock source is a free running counter timekeeping reads the time
+//! from. Timekeeping starts on jiffies and switches to the best rated clock
+//! source registered.
+
+use super::timekeeping::{timekeeping_change_clocksource, timekeeping_clocksource};
+
+/// A free running counter
+///
+/// Refer to linux: struct clocksource
+pub trait Clocksource: Sync {
+    /// Clock source name
+    fn name(&self) -> &'static str;
+
+    /// Read the counter, it never goes backwards.
+    fn read(&self) -> u64;
+
+    /// Counter frequency in Hz.
+    fn frequency(&self) -> u64;
+
+    /// Quality of the clock source, the highest rated one is used.
+    ///
+    /// 1-99 is only usable at boot, 300-399 is fast and accurate.
+    fn rating(&self) -> u32;
+}
+
+/// Register a clock source, timekeeping switches to it if it is rated
+/// higher than the current one.
+pub fn clocksource_register(cs: &'static dyn Clocksource) {
+    if cs.rating() > timekeeping_clocksource().rating() {
+        timekeeping_change_clocksource(cs);
+        crate::pr_info!("clocksource: Switched to clocksource {}\n", cs.name());
+    }
+}
@@ -0,0 +1,328 @@
+//! High resolution timers
+//!
+//! Refer to linux: kernel/time/hrtimer.c
+//!
+//! Every cpu keeps its queued hrtimers in a red-black tree ordered by
+//! expiry, and programs its clock event device for the first one.
+
+use core::ptr;
+use core::sync::atomic::{AtomicI64, AtomicUsize, Ordering};
+
+use super::clockevents::clockevents_program_event;
+use super::{ktime_get, Ktime};
+use crate::arch::cpu::MAX_CPUS;
+use crate::cpu::smp_processor_id;
+use crate::klib::rbtree::{GetRbLinks, RawRbTree, RbLinks};
+use crate::sync::lock::{RawSpinLockNoIrq, RawSpinLockNoIrqGuard};
+
+const NO_BASE: usize = usize::MAX;
+
+/// Returned by an hrtimer callback.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum HrTimerRestart {
+    /// The timer is done.
+    NoRestart,
+    /// Queue the timer again, with the expiry set by the callback.
+    Restart,
+}
+
+/// How the expiry time of [`HrTimer::start`] is given.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum HrTimerMode {
+    /// Absolute monotonic time
+    Abs,
+    /// Relative to now
+    Rel,
+}
+
+/// A high resolution timer.
+///
+/// The callback runs in the interrupt of the clock event device, on the cpu
+/// the timer was started on, so it must not sleep.
+///
+/// # Safety
+///
+/// Like a [`Timer`], a queued hrtimer must not move. Dropping it cancels it
+/// and waits for its callback.
+///
+/// [`Timer`]: super::Timer
+pub struct HrTimer {
+    node: RbLinks<HrTimer>,
+    expires: AtomicI64,
+    function: fn(&HrTimer) -> HrTimerRestart,
+    data: usize,
+    // cpu of the last base the timer was queued on
+    cpu: AtomicUsize,
+}
+
+impl GetRbLinks for HrTimer {
+    type EntryType = Self;
+    fn get_links(data: &Self) -> &RbLinks<Self> {
+        &data.node
+    }
+}
+
+struct HrTimerBase {
+    queue: RawRbTree<HrTimer>,
+    running: *const HrTimer,
+}
+
+// SAFETY: `running` is only compared, never dereferenced.
+unsafe impl Send for HrTimerBase {}
+
+fn expires_before(a: &HrTimer, b: &HrTimer) -> bool {
+    a.expires() < b.expires()
+}
+
+impl HrTimerBase {
+    const fn new() -> Self {
+        Self {
+            queue: RawRbTree::new(),
+            running: ptr::null(),
+        }
+    }
+
+    // Returns true if the timer is the new first one.
+    fn enqueue(&mut self, timer: &HrTimer, cpu: usize) -> bool {
+        // SAFETY: the timer does not move while queued, and its drop
+        // dequeues it.
+        unsafe {
+            self.queue.insert(timer, expires_before);
+        }
+        timer.cpu.store(cpu, Ordering::Relaxed);
+        self.queue
+            .first()
+            .map(|first| ptr::eq(first.as_ptr(), timer))
+            .unwrap_or(false)
+    }
+
+    fn remove(&mut self, timer: &HrTimer) -> bool {
+        // SAFETY: a queued timer is on the tree of the base it was queued on.
+        unsafe { self.queue.remove(timer) }
+    }
+
+    fn first_expiry(&self) -> Option<Ktime> {
+        // SAFETY: queued timers are alive.
+        self.queue
+            .first()
+            .map(|first| unsafe { first.as_ref() }.expires())
+    }
+}
+
+static HRTIMER_BASES: [RawSpinLockNoIrq<HrTimerBase>; MAX_CPUS] =
+    [const { RawSpinLockNoIrq::new(HrTimerBase::new(), Some("hrtimer_base")) }; MAX_CPUS];
+
+impl HrTimer {
+    /// Create an hrtimer calling `function` on expiry.
+    pub const fn new(function: fn(&HrTimer) -> HrTimerRestart) -> Self {
+        Self::with_data(function, 0)
+    }
+
+    /// Create an hrtimer with private data for its callback.
+    pub const fn with_data(function: fn(&HrTimer) -> HrTimerRestart, data: usize) -> Self {
+        Self {
+            node: RbLinks::new(),
+            expires: AtomicI64::new(0),
+            function,
+            data,
+            cpu: AtomicUsize::new(NO_BASE),
+        }
+    }
+
+    /// Private data
+    #[inline]
+    pub fn data(&self) -> usize {
+        self.data
+    }
+
+    /// Expiry time
+    #[inline]
+    pub fn expires(&self) -> Ktime {
+        self.expires.load(Ordering::Relaxed)
+    }
+
+    /// Is the timer queued?
+    pub fn is_queued(&self) -> bool {
+        match self.lock_base() {
+            Some(_base) => self.node.is_linked(),
+            None => false,
+        }
+    }
+
+    // Lock the base the timer was last queued on.
+    fn lock_base(&self) -> Option<RawSpinLockNoIrqGuard<'static, HrTimerBase>> {
+        loop {
+            let cpu = self.cpu.load(Ordering::Relaxed);
+            if cpu == NO_BASE {
+                return None;
+            }
+            let base = HRTIMER_BASES[cpu].lock();
+            if self.cpu.load(Ordering::Relaxed) == cpu {
+                return Some(base);
+            }
+        }
+    }
+
+    /// (Re)start the timer on the current cpu.
+    ///
+    /// # Safety
+    ///
+    /// The timer must not move until it is cancelled or has expired.
+    pub unsafe fn start(&self, time: Ktime, mode: HrTimerMode) {
+        let expires = match mode {
+            HrTimerMode::Abs => time,
+            HrTimerMode::Rel => ktime_get().saturating_add(time),
+        };
+        if let Some(mut base) = self.lock_base() {
+            base.remove(self);
+        }
+
+        let cpu = smp_processor_id();
+        let mut base = HRTIMER_BASES[cpu].lock();
+        // Raced with another `start`, requeue it here.
+        base.remove(self);
+        self.expires.store(expires, Ordering::Relaxed);
+        if base.enqueue(self, cpu) && base.running.is_null() {
+            clockevents_program_event(cpu, expires);
+        }
+    }
+
+    /// Try to cancel the timer, return whether it was queued.
+    ///
+    /// The callback may still be running on another cpu.
+    pub fn try_to_cancel(&self) -> bool {
+        match self.lock_base() {
+            Some(mut base) => base.remove(self),
+            None => false,
+        }
+    }
+
+    /// Cancel the timer and wait for its callback to finish.
+    ///
+    /// Must not be called from the callback.
+    pub fn cancel(&self) -> bool {
+        loop {
+            let Some(mut base) = self.lock_base() else {
+                return false;
+            };
+            if !ptr::eq(base.running, self) {
+                return base.remove(self);
+            }
+            drop(base);
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Move the expiry forward by whole `interval`s until it is after `now`.
+    ///
+    /// Returns the number of intervals, only called on a timer that is not
+    /// queued, usually from its callback.
+    pub fn forward(&self, now: Ktime, interval: Ktime) -> u64 {
+        let expires = self.expires();
+        let delta = now - expires;
+        if delta < 0 {
+            return 0;
+        }
+        let overruns = delta / interval + 1;
+        self.expires
+            .store(expires + overruns * interval, Ordering::Relaxed);
+        overruns as u64
+    }
+}
+
+impl Drop for HrTimer {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+/// Run the expired hrtimers of `cpu`, and program its device for the next one.
+pub(crate) fn hrtimer_interrupt(cpu: usize) {
+    let mut base = HRTIMER_BASES[cpu].lock();
+    loop {
+        let Some(first) = base.queue.first() else {
+            break;
+        };
+        // SAFETY: the timer is queued, and its drop waits for us through
+        // `running`.
+        let timer = unsafe { &*first.as_ptr() };
+        if timer.expires() > ktime_get() {
+            break;
+        }
+        base.remove(timer);
+        base.running = timer;
+        drop(base);
+        let restart = (timer.function)(timer);
+        base = HRTIMER_BASES[cpu].lock();
+        // The callback may have restarted it itself.
+        if restart == HrTimerRestart::Restart && !timer.node.is_linked() {
+            base.enqueue(timer, cpu);
+        }
+        base.running = ptr::null();
+    }
+    if let Some(expires) = base.first_expiry() {
+        clockevents_program_event(cpu, expires);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schedule::task::set_test_current;
+
+    use crate::time::{time_test_lock, NSEC_PER_MSEC};
+
+    static ORDER: AtomicUsize = AtomicUsize::new(0);
+    static FIRED: [AtomicUsize; 3] = [const { AtomicUsize::new(0) }; 3];
+
+    fn record(timer: &HrTimer) -> HrTimerRestart {
+        assert!(timer.expires() <= ktime_get());
+        let order = ORDER.fetch_add(1, Ordering::Relaxed);
+        FIRED[timer.data()].store(order, Ordering::Relaxed);
+        HrTimerRestart::NoRestart
+    }
+
+    static PERIODS: AtomicUsize = AtomicUsize::new(0);
+
+    fn periodic(timer: &HrTimer) -> HrTimerRestart {
+        PERIODS.fetch_add(1, Ordering::Relaxed);
+        timer.forward(ktime_get(), NSEC_PER_MSEC);
+        if PERIODS.load(Ordering::Relaxed) < 3 {
+            HrTimerRestart::Restart
+        } else {
+            HrTimerRestart::NoRestart
+        }
+    }
+
+    #[test]
+    fn test_hrtimer() {
+        set_test_current();
+        let _guard = time_test_lock();
+
+        let timers = [
+            HrTimer::with_data(record, 0),
+            HrTimer::with_data(record, 1),
+            HrTimer::with_data(record, 2),
+        ];
+        let cancelled = HrTimer::with_data(record, 0);
+        let periodic = HrTimer::new(periodic);
+        // SAFETY: the timers do not move while queued.
+        unsafe {
+            timers[0].start(3 * NSEC_PER_MSEC, HrTimerMode::Rel);
+            timers[1].start(NSEC_PER_MSEC, HrTimerMode::Rel);
+            timers[2].start(2 * NSEC_PER_MSEC, HrTimerMode::Rel);
+            cancelled.start(NSEC_PER_MSEC, HrTimerMode::Rel);
+            periodic.start(NSEC_PER_MSEC, HrTimerMode::Rel);
+        }
+        assert!(cancelled.cancel());
+        assert!(!cancelled.is_queued());
+
+        while timers.iter().any(|t| t.is_queued()) || periodic.is_queued() {
+            hrtimer_interrupt(0);
+            std::thread::yield_now();
+        }
+        let order: std::vec::Vec<_> = FIRED.iter().map(|f| f.load(Ordering::Relaxed)).collect();
+        assert!(order[1] < order[2] && order[2] < order[0]);
+        assert_eq!(PERIODS.load(Ordering::Relaxed), 3);
+    }
+}
@@ -0,0 +1,119 @@
+//! Jiffies
+//!
+//! Refer to linux: kernel/time/jiffies.c
+//!
+//! Jiffies wrap around, so always compare them with [`time_after`] and
+//! friends. They start 5 minutes before the wrap, to catch code that does not.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::clocksource::Clocksource;
+use super::{Ktime, MSEC_PER_SEC, NSEC_PER_SEC, NSEC_PER_USEC};
+
+cfg_if::cfg_if! {
+    if #[cfg(CONFIG_HZ_100)] {
+        /// Ticks per second
+        pub const HZ: usize = 100;
+    } else if #[cfg(CONFIG_HZ_1000)] {
+        /// Ticks per second
+        pub const HZ: usize = 1000;
+    } else {
+        /// Ticks per second
+        pub const HZ: usize = 250;
+    }
+}
+
+/// Nanoseconds per tick
+pub const TICK_NSEC: Ktime = NSEC_PER_SEC / HZ as Ktime;
+
+/// Jiffies at boot
+pub const INITIAL_JIFFIES: usize = (-300 * HZ as i64) as u32 as usize;
+
+/// The longest timeout in jiffies.
+pub const MAX_JIFFY_OFFSET: usize = (isize::MAX as usize >> 1) - 1;
+
+static JIFFIES: AtomicUsize = AtomicUsize::new(INITIAL_JIFFIES);
+
+/// Ticks since boot, plus [`INITIAL_JIFFIES`].
+#[inline]
+pub fn jiffies() -> usize {
+    JIFFIES.load(Ordering::Relaxed)
+}
+
+/// Account `ticks` ticks, done by one cpu only.
+pub(crate) fn do_timer(ticks: usize) {
+    JIFFIES.fetch_add(ticks, Ordering::Relaxed);
+}
+
+/// The tick counter as a clock source, only as precise as the tick and only
+/// used until a better one is registered.
+///
+/// Refer to linux: clocksource_jiffies
+pub(crate) struct ClocksourceJiffies;
+
+impl Clocksource for ClocksourceJiffies {
+    fn name(&self) -> &'static str {
+        "jiffies"
+    }
+
+    fn read(&self) -> u64 {
+        jiffies() as u64
+    }
+
+    fn frequency(&self) -> u64 {
+        HZ as u64
+    }
+
+    fn rating(&self) -> u32 {
+        1
+    }
+}
+
+/// Is `a` after `b`?
+#[inline]
+pub const fn time_after(a: usize, b: usize) -> bool {
+    (b.wrapping_sub(a) as isize) < 0
+}
+
+/// Is `a` after or equal to `b`?
+#[inline]
+pub const fn time_after_eq(a: usize, b: usize) -> bool {
+    (a.wrapping_sub(b) as isize) >= 0
+}
+
+/// Is `a` before `b`?
+#[inline]
+pub const fn time_before(a: usize, b: usize) -> bool {
+    time_after(b, a)
+}
+
+/// Is `a` before or equal to `b`?
+#[inline]
+pub const fn time_before_eq(a: usize, b: usize) -> bool {
+    time_after_eq(b, a)
+}
+
+/// Convert nanoseconds to jiffies, rounding up.
+pub const fn nsecs_to_jiffies(ns: u64) -> usize {
+    let j = ns.div_ceil(TICK_NSEC as u64);
+    if j > MAX_JIFFY_OFFSET as u64 {
+        MAX_JIFFY_OFFSET
+    } else {
+        j as usize
+    }
+}
+
+/// Convert milliseconds to jiffies, rounding up.
+pub const fn msecs_to_jiffies(ms: u32) -> usize {
+    nsecs_to_jiffies(ms as u64 * (NSEC_PER_SEC / MSEC_PER_SEC) as u64)
+}
+
+/// Convert microseconds to jiffies, rounding up.
+pub const fn usecs_to_jiffies(us: u32) -> usize {
+    nsecs_to_jiffies(us as u64 * NSEC_PER_USEC as u64)
+}
+
+/// Convert jiffies to milliseconds.
+pub const fn jiffies_to_msecs(j: usize) -> u64 {
+    j as u64 * (MSEC_PER_SEC as u64) / HZ as u64
+}
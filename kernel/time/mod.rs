@@ -0,0 +1,140 @@
+//! Rynux time subsystem
+//!
+//! Refer to linux: kernel/time/
+//!
+//! - [`jiffies`]: the tick counter, incremented `HZ` times per second.
+//! - [`timer`]: the timer wheel, cheap timers with jiffies resolution.
+//! - [`hrtimer`]: high resolution timers in nanoseconds.
+//! - [`clockevents`]: the per cpu event devices driving both, the periodic
+//!   tick runs as an hrtimer.
+//! - [`sleep`]: sleeping on top of them.
+//! - [`timekeeping`]: the monotonic, boottime and realtime clocks, read from
+//!   the best [`clocksource`].
+
+pub mod clockevents;
+pub mod clocksource;
+pub mod hrtimer;
+pub mod jiffies;
+pub mod sleep;
+mod tick;
+pub mod time64;
+pub mod timekeeping;
+pub mod timer;
+
+pub use hrtimer::{HrTimer, HrTimerMode, HrTimerRestart};
+pub use jiffies::{jiffies, HZ};
+pub use sleep::{msleep, schedule_timeout, usleep_range};
+pub use time64::Timespec64;
+pub use timekeeping::{
+    do_settimeofday64, ktime_get, ktime_get_boottime, ktime_get_boottime_ts64, ktime_get_real,
+    ktime_get_real_seconds, ktime_get_real_ts64, ktime_get_ts64,
+};
+pub use timer::Timer;
+
+/// Time in nanoseconds.
+pub type Ktime = i64;
+
+/// Nanoseconds per microsecond
+pub const NSEC_PER_USEC: i64 = 1000;
+/// Nanoseconds per millisecond
+pub const NSEC_PER_MSEC: i64 = 1000 * NSEC_PER_USEC;
+/// Nanoseconds per second
+pub const NSEC_PER_SEC: i64 = 1000 * NSEC_PER_MSEC;
+/// Microseconds per millisecond
+pub const USEC_PER_MSEC: i64 = 1000;
+/// Milliseconds per second
+pub const MSEC_PER_SEC: i64 = 1000;
+
+/// Start timekeeping and the clockevent of the boot cpu, which starts the
+/// tick, then set the wall clock from the RTC.
+pub fn time_init() {
+    timekeeping::timekeeping_init();
+    #[cfg(CONFIG_ARM64)]
+    crate::drivers::clocksource::arm_arch_timer::arch_timer_init();
+    // Without an RTC the wall clock starts at the epoch.
+    let _ = crate::drivers::rtc::rtc_hctosys();
+}
+
+// Nanoseconds of the host clock.
+#[cfg(test)]
+struct TestClocksource(std::sync::OnceLock<std::time::Instant>);
+
+#[cfg(test)]
+impl clocksource::Clocksource for TestClocksource {
+    fn name(&self) -> &'static str {
+        "test"
+    }
+    fn read(&self) -> u64 {
+        self.0
+            .get_or_init(std::time::Instant::now)
+            .elapsed()
+            .as_nanos() as u64
+    }
+    fn frequency(&self) -> u64 {
+        NSEC_PER_SEC as u64
+    }
+    fn rating(&self) -> u32 {
+        400
+    }
+}
+
+// Time tests share the clocks, the global jiffies and the timer bases of cpu 0.
+#[cfg(test)]
+pub(crate) fn time_test_lock() -> std::sync::MutexGuard<'static, ()> {
+    static TIME_TEST_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    static INIT: std::sync::Once = std::sync::Once::new();
+    static TEST_CLOCKSOURCE: TestClocksource = TestClocksource(std::sync::OnceLock::new());
+    let guard = TIME_TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    INIT.call_once(|| {
+        timekeeping::timekeeping_init();
+        clocksource::clocksource_register(&TEST_CLOCKSOURCE);
+    });
+    guard
+}
+
+// Polled by a ticker thread instead of interrupting.
+#[cfg(test)]
+struct TestClockEvent;
+
+#[cfg(test)]
+impl clockevents::ClockEventDevice for TestClockEvent {
+    fn name(&self) -> &'static str {
+        "test"
+    }
+    fn set_next_ktime(&self, _expires: Ktime) {}
+    fn shutdown(&self) {}
+}
+
+/// Runs the clock events of cpu 0 from a host thread until dropped, like the
+/// timer interrupt would. Hold [`time_test_lock`] while it lives.
+#[cfg(test)]
+pub(crate) struct TestTicker(Option<std::thread::JoinHandle<()>>);
+
+#[cfg(test)]
+static TICKER_STOP: core::sync::atomic::AtomicBool = core::sync::atomic::AtomicBool::new(false);
+
+#[cfg(test)]
+impl TestTicker {
+    pub(crate) fn start() -> Self {
+        static TEST_CLOCKEVENT: TestClockEvent = TestClockEvent;
+        clockevents::clockevents_register_device(&TEST_CLOCKEVENT);
+        TICKER_STOP.store(false, core::sync::atomic::Ordering::Relaxed);
+        Self(Some(std::thread::spawn(|| {
+            crate::schedule::task::set_test_current();
+            while !TICKER_STOP.load(core::sync::atomic::Ordering::Relaxed) {
+                clockevents::clockevents_handle_event();
+                std::thread::yield_now();
+            }
+        })))
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestTicker {
+    fn drop(&mut self) {
+        TICKER_STOP.store(true, core::sync::atomic::Ordering::Relaxed);
+        if let Some(ticker) = self.0.take() {
+            ticker.join().unwrap();
+        }
+    }
+}
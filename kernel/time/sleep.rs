@@ -0,0 +1,124 @@
+//! Sleeping with a timeout
+//!
+//! Refer to linux: kernel/time/sleep_timeout.c
+
+use super::hrtimer::{HrTimer, HrTimerMode, HrTimerRestart};
+use super::jiffies::{jiffies, msecs_to_jiffies, time_after};
+use super::{Ktime, NSEC_PER_USEC};
+use crate::error::{Error, Result};
+use crate::schedule::task::{set_current_state, Task, TaskState};
+use crate::schedule::{current, schedule, MAX_SCHEDULE_TIMEOUT};
+use crate::time::Timer;
+
+fn process_timeout(timer: &Timer) {
+    // SAFETY: the sleeping task deletes the timer before it returns, so it
+    // is alive.
+    let task = unsafe { &*(timer.data() as *const Task) };
+    task.try_wake_up(TaskState::NORMAL);
+}
+
+/// Sleep until woken up or `timeout` jiffies pass, return the jiffies left.
+///
+/// Like [`schedule`], the caller sets the task state first. It returns 0 if
+/// the timeout expired, [`MAX_SCHEDULE_TIMEOUT`] sleeps until woken up.
+pub fn schedule_timeout(timeout: usize) -> usize {
+    if timeout == MAX_SCHEDULE_TIMEOUT {
+        schedule();
+        return timeout;
+    }
+
+    let expire = jiffies().wrapping_add(timeout);
+    let curr = current();
+    let timer = Timer::with_data(process_timeout, curr.as_ptr() as usize);
+    // SAFETY: the timer is deleted below, before it goes out of scope.
+    unsafe {
+        timer.mod_timer(expire);
+    }
+    schedule();
+    timer.del_timer_sync();
+
+    let now = jiffies();
+    if time_after(expire, now) {
+        expire.wrapping_sub(now)
+    } else {
+        0
+    }
+}
+
+/// Sleep for at least `msecs` milliseconds.
+pub fn msleep(msecs: u32) {
+    // One more jiffy, the sleep may start right before a tick.
+    let mut timeout = msecs_to_jiffies(msecs) + 1;
+    while timeout > 0 {
+        set_current_state(TaskState::UNINTERRUPTIBLE);
+        timeout = schedule_timeout(timeout);
+    }
+}
+
+fn hrtimer_wakeup(timer: &HrTimer) -> HrTimerRestart {
+    // SAFETY: see `process_timeout`.
+    let task = unsafe { &*(timer.data() as *const Task) };
+    task.try_wake_up(TaskState::NORMAL);
+    HrTimerRestart::NoRestart
+}
+
+/// Sleep until woken up or `expires` is reached, with hrtimer precision.
+///
+/// Like [`schedule_timeout`], the caller sets the task state first. Returns
+/// `Eintr` if woken up before the time.
+pub fn schedule_hrtimeout(expires: Ktime, mode: HrTimerMode) -> Result {
+    let curr = current();
+    let timer = HrTimer::with_data(hrtimer_wakeup, curr.as_ptr() as usize);
+    // SAFETY: the timer is cancelled below, before it goes out of scope.
+    unsafe {
+        timer.start(expires, mode);
+    }
+    schedule();
+    if timer.cancel() {
+        return Err(Error::Eintr);
+    }
+    Ok(())
+}
+
+/// Sleep between `min` and `max` microseconds.
+///
+/// The range lets Linux merge wakeups. Timers are not coalesced here, so it
+/// sleeps `min` microseconds.
+pub fn usleep_range(min: u64, max: u64) {
+    debug_assert!(min <= max);
+    let expires = super::ktime_get().saturating_add(min as Ktime * NSEC_PER_USEC);
+    loop {
+        set_current_state(TaskState::UNINTERRUPTIBLE);
+        if schedule_hrtimeout(expires, HrTimerMode::Abs).is_ok() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schedule::task::set_test_current;
+    use crate::sync::Completion;
+    use crate::time::{ktime_get, time_test_lock, TestTicker, NSEC_PER_MSEC};
+
+    #[test]
+    fn test_sleep() {
+        set_test_current();
+        let _guard = time_test_lock();
+        let _ticker = TestTicker::start();
+
+        let start = ktime_get();
+        msleep(20);
+        assert!(ktime_get() - start >= 20 * NSEC_PER_MSEC);
+
+        let start = ktime_get();
+        usleep_range(500, 1000);
+        assert!(ktime_get() - start >= 500 * NSEC_PER_USEC);
+
+        let done = Completion::new();
+        assert_eq!(done.wait_for_completion_timeout(2), 0);
+        done.complete();
+        assert_eq!(done.wait_for_completion_timeout(2), 2);
+    }
+}
@@ -0,0 +1,38 @@
+//! Periodic tick
+//!
+//! Refer to linux: kernel/time/tick-sched.c
+//!
+//! The tick is an hrtimer on every cpu. The boot cpu accounts jiffies, and
+//! every cpu runs its expired timer wheel timers.
+
+use super::hrtimer::{HrTimer, HrTimerMode, HrTimerRestart};
+use super::jiffies::{do_timer, TICK_NSEC};
+use super::timekeeping::update_wall_time;
+use super::{ktime_get, timer};
+use crate::arch::cpu::MAX_CPUS;
+use crate::cpu::smp_processor_id;
+
+// The cpu accounting jiffies.
+const TICK_DO_TIMER_CPU: usize = 0;
+
+static TICK_TIMERS: [HrTimer; MAX_CPUS] = [const { HrTimer::new(tick_sched_timer) }; MAX_CPUS];
+
+fn tick_sched_timer(timer: &HrTimer) -> HrTimerRestart {
+    let ticks = timer.forward(ktime_get(), TICK_NSEC);
+    let cpu = smp_processor_id();
+    if cpu == TICK_DO_TIMER_CPU {
+        do_timer(ticks as usize);
+        update_wall_time();
+    }
+    // TODO: run from a softirq once there is one, not in hardirq context.
+    timer::run_timers(cpu);
+    HrTimerRestart::Restart
+}
+
+/// Start the tick of `cpu`, called on that cpu.
+pub(super) fn tick_setup(cpu: usize) {
+    // SAFETY: tick timers are static.
+    unsafe {
+        TICK_TIMERS[cpu].start(ktime_get() + TICK_NSEC, HrTimerMode::Abs);
+    }
+}
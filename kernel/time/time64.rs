@@ -0,0 +1,41 @@
+//! 64 bit time types
+//!
+//! Refer to linux: include/linux/time64.h
+
+use super::{Ktime, NSEC_PER_SEC};
+
+/// Seconds and nanoseconds, like the `timespec64` of Linux.
+#[derive(Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct Timespec64 {
+    /// Seconds
+    pub tv_sec: i64,
+    /// Nanoseconds, in `0..NSEC_PER_SEC` when valid
+    pub tv_nsec: i64,
+}
+
+impl Timespec64 {
+    /// Create a timespec
+    pub const fn new(tv_sec: i64, tv_nsec: i64) -> Self {
+        Self { tv_sec, tv_nsec }
+    }
+
+    /// Convert nanoseconds to a timespec, the nanoseconds are never negative.
+    pub const fn from_ktime(kt: Ktime) -> Self {
+        Self {
+            tv_sec: kt.div_euclid(NSEC_PER_SEC),
+            tv_nsec: kt.rem_euclid(NSEC_PER_SEC),
+        }
+    }
+
+    /// Convert to nanoseconds, saturating on overflow.
+    pub const fn to_ktime(self) -> Ktime {
+        self.tv_sec
+            .saturating_mul(NSEC_PER_SEC)
+            .saturating_add(self.tv_nsec)
+    }
+
+    /// Are the nanoseconds in range and the seconds not negative?
+    pub const fn is_valid(&self) -> bool {
+        self.tv_sec >= 0 && self.tv_nsec >= 0 && self.tv_nsec < NSEC_PER_SEC
+    }
+}
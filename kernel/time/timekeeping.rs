@@ -0,0 +1,197 @@
+//! Timekeeping
+//!
+//! Refer to linux: kernel/time/timekeeping.c
+//!
+//! The clocks are all read from the current [`Clocksource`]:
+//! - monotonic: time since [`timekeeping_init`], does not count suspend.
+//! - boottime: monotonic plus the time spent in suspend.
+//! - realtime: the wall clock, set from the RTC or by [`do_settimeofday64`].
+//!
+//! The tick accumulates the clock source into the timekeeper, which readers
+//! copy under a seqlock.
+
+use super::clocksource::Clocksource;
+use super::jiffies::ClocksourceJiffies;
+use super::time64::Timespec64;
+use super::{Ktime, NSEC_PER_SEC};
+use crate::error::{Error, Result};
+use crate::sync::SeqLock;
+
+// Nanoseconds are cycles * mult >> SHIFT.
+const SHIFT: u32 = 32;
+
+#[derive(Copy, Clone)]
+struct Timekeeper {
+    clock: &'static dyn Clocksource,
+    cycle_last: u64,
+    mult: u64,
+    // monotonic time at `cycle_last`, in nanoseconds and shifted fractions
+    base_mono: Ktime,
+    xtime_snsec: u64,
+    // realtime - monotonic
+    offs_real: Ktime,
+    // boottime - monotonic
+    offs_boot: Ktime,
+}
+
+impl Timekeeper {
+    // Cycles since `cycle_last` in shifted nanoseconds.
+    fn delta_snsec(&self, now: u64) -> u128 {
+        now.wrapping_sub(self.cycle_last) as u128 * self.mult as u128 + self.xtime_snsec as u128
+    }
+
+    fn mono(&self, now: u64) -> Ktime {
+        self.base_mono + (self.delta_snsec(now) >> SHIFT) as Ktime
+    }
+
+    fn now(&self) -> Ktime {
+        self.mono(self.clock.read())
+    }
+
+    // Move the clock source into the base time.
+    fn accumulate(&mut self) {
+        let now = self.clock.read();
+        let snsec = self.delta_snsec(now);
+        self.base_mono += (snsec >> SHIFT) as Ktime;
+        self.xtime_snsec = (snsec & ((1 << SHIFT) - 1)) as u64;
+        self.cycle_last = now;
+    }
+
+    fn setup(&mut self, clock: &'static dyn Clocksource) {
+        self.clock = clock;
+        self.mult = (((NSEC_PER_SEC as u128) << SHIFT) / clock.frequency() as u128) as u64;
+        self.cycle_last = clock.read();
+    }
+}
+
+static CLOCKSOURCE_JIFFIES: ClocksourceJiffies = ClocksourceJiffies;
+
+static TIMEKEEPER: SeqLock<Timekeeper> = SeqLock::new(
+    Timekeeper {
+        clock: &CLOCKSOURCE_JIFFIES,
+        cycle_last: 0,
+        mult: 0,
+        base_mono: 0,
+        xtime_snsec: 0,
+        offs_real: 0,
+        offs_boot: 0,
+    },
+    Some("timekeeper"),
+);
+
+/// Start the clocks on the current clock source, monotonic time starts at
+/// 0 and the wall clock at the epoch until it is set.
+pub fn timekeeping_init() {
+    let mut tk = TIMEKEEPER.write();
+    let clock = tk.clock;
+    tk.setup(clock);
+    tk.base_mono = 0;
+    tk.xtime_snsec = 0;
+}
+
+/// The clock source timekeeping reads.
+pub(crate) fn timekeeping_clocksource() -> &'static dyn Clocksource {
+    TIMEKEEPER.read().clock
+}
+
+/// Switch to `clock`, the clocks go on from where the old one left them.
+pub(crate) fn timekeeping_change_clocksource(clock: &'static dyn Clocksource) {
+    let mut tk = TIMEKEEPER.write();
+    tk.accumulate();
+    tk.setup(clock);
+}
+
+/// Accumulate the clock source into the timekeeper, called from the tick.
+pub(crate) fn update_wall_time() {
+    TIMEKEEPER.write().accumulate();
+}
+
+/// Monotonic time since boot.
+pub fn ktime_get() -> Ktime {
+    TIMEKEEPER.read().now()
+}
+
+/// Monotonic time including suspend.
+pub fn ktime_get_boottime() -> Ktime {
+    let tk = TIMEKEEPER.read();
+    tk.now() + tk.offs_boot
+}
+
+/// Wall clock time since the epoch.
+pub fn ktime_get_real() -> Ktime {
+    let tk = TIMEKEEPER.read();
+    tk.now() + tk.offs_real
+}
+
+/// Monotonic time as a timespec.
+pub fn ktime_get_ts64() -> Timespec64 {
+    Timespec64::from_ktime(ktime_get())
+}
+
+/// Boottime as a timespec.
+pub fn ktime_get_boottime_ts64() -> Timespec64 {
+    Timespec64::from_ktime(ktime_get_boottime())
+}
+
+/// Wall clock time as a timespec.
+pub fn ktime_get_real_ts64() -> Timespec64 {
+    Timespec64::from_ktime(ktime_get_real())
+}
+
+/// Wall clock seconds since the epoch.
+pub fn ktime_get_real_seconds() -> i64 {
+    ktime_get_real_ts64().tv_sec
+}
+
+/// Set the wall clock.
+pub fn do_settimeofday64(ts: &Timespec64) -> Result {
+    if !ts.is_valid() {
+        return Err(Error::Einval);
+    }
+    let mut tk = TIMEKEEPER.write();
+    let mono = tk.now();
+    tk.offs_real = ts.to_ktime() - mono;
+    Ok(())
+}
+
+/// Account time spent in suspend, which moves boottime and the wall clock.
+pub fn timekeeping_inject_sleeptime64(delta: &Timespec64) -> Result {
+    if !delta.is_valid() {
+        return Err(Error::Einval);
+    }
+    let mut tk = TIMEKEEPER.write();
+    tk.offs_boot += delta.to_ktime();
+    tk.offs_real += delta.to_ktime();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schedule::task::set_test_current;
+
+    use crate::time::time_test_lock;
+
+    #[test]
+    fn test_timekeeping() {
+        set_test_current();
+        let _guard = time_test_lock();
+
+        let mono = ktime_get();
+        update_wall_time();
+        assert!(ktime_get() >= mono);
+        assert!(ktime_get_boottime() >= mono);
+
+        let ts = Timespec64::new(1_700_000_000, 500);
+        assert!(do_settimeofday64(&ts).is_ok());
+        let real = ktime_get_real_ts64();
+        assert!(real >= ts);
+        assert!(real.tv_sec - ts.tv_sec < 10);
+        assert_eq!(ktime_get_real_seconds(), real.tv_sec);
+        assert!(do_settimeofday64(&Timespec64::new(0, NSEC_PER_SEC)).is_err());
+
+        let ts = Timespec64::from_ktime(-1);
+        assert_eq!(ts, Timespec64::new(-1, NSEC_PER_SEC - 1));
+        assert_eq!(ts.to_ktime(), -1);
+    }
+}
@@ -0,0 +1,402 @@
+//! Timer wheel
+//!
+//! Refer to linux: kernel/time/timer.c
+//!
+//! Every cpu has a wheel of `LVL_DEPTH` levels with 64 buckets each. Level 0
+//! buckets are one jiffy wide, and every level is 8 times coarser than the
+//! previous one. A timer is queued in the level its timeout falls in, and
+//! expires at the end of its bucket, so it never fires early but may fire up
+//! to one bucket width (1/8 of its timeout) late. In return adding, deleting
+//! and expiring timers are all O(1).
+//!
+//! Most timers are timeouts that get deleted before they expire, for them
+//! the slack does not matter.
+
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::jiffies::{jiffies, time_after, time_after_eq, HZ, INITIAL_JIFFIES};
+use crate::arch::cpu::MAX_CPUS;
+use crate::cpu::smp_processor_id;
+use crate::list::{GetLinks, Links, RawList};
+use crate::sync::lock::{RawSpinLockNoIrq, RawSpinLockNoIrqGuard};
+
+const LVL_CLK_SHIFT: usize = 3;
+const LVL_CLK_MASK: usize = (1 << LVL_CLK_SHIFT) - 1;
+const LVL_BITS: usize = 6;
+const LVL_SIZE: usize = 1 << LVL_BITS;
+const LVL_MASK: usize = LVL_SIZE - 1;
+const LVL_DEPTH: usize = if HZ > 100 { 9 } else { 8 };
+const WHEEL_SIZE: usize = LVL_SIZE * LVL_DEPTH;
+
+const fn lvl_shift(n: usize) -> usize {
+    n * LVL_CLK_SHIFT
+}
+
+const fn lvl_gran(n: usize) -> usize {
+    1 << lvl_shift(n)
+}
+
+// First timeout of level `n`, `n` > 0.
+const fn lvl_start(n: usize) -> usize {
+    (LVL_SIZE - 1) << ((n - 1) * LVL_CLK_SHIFT)
+}
+
+// Longer timeouts are cut to the capacity of the last level.
+const WHEEL_TIMEOUT_CUTOFF: usize = lvl_start(LVL_DEPTH);
+const WHEEL_TIMEOUT_MAX: usize = WHEEL_TIMEOUT_CUTOFF - lvl_gran(LVL_DEPTH - 1);
+
+const NOT_QUEUED: usize = usize::MAX;
+const NO_BASE: usize = usize::MAX;
+
+fn calc_index(expires: usize, lvl: usize) -> usize {
+    // Round up to the end of the bucket, the timer must not fire early.
+    let expires = (expires >> lvl_shift(lvl)) + 1;
+    lvl * LVL_SIZE + (expires & LVL_MASK)
+}
+
+fn calc_wheel_index(expires: usize, clk: usize) -> usize {
+    let delta = expires.wrapping_sub(clk);
+    if (delta as isize) < 0 {
+        // Already expired, fire on the next run.
+        return clk & LVL_MASK;
+    }
+    for lvl in 0..LVL_DEPTH - 1 {
+        if delta < lvl_start(lvl + 1) {
+            return calc_index(expires, lvl);
+        }
+    }
+    let expires = if delta >= WHEEL_TIMEOUT_CUTOFF {
+        clk.wrapping_add(WHEEL_TIMEOUT_MAX)
+    } else {
+        expires
+    };
+    calc_index(expires, LVL_DEPTH - 1)
+}
+
+/// A timer with jiffies resolution.
+///
+/// The callback runs on the cpu the timer was added on, in the tick
+/// interrupt, so it must not sleep.
+///
+/// # Safety
+///
+/// Like a [`WaitTaskNode`], a queued timer must not move. Dropping a timer
+/// deletes it and waits for its callback, so it may be a stack variable.
+///
+/// [`WaitTaskNode`]: crate::schedule::WaitTaskNode
+pub struct Timer {
+    links: Links<Timer>,
+    expires: AtomicUsize,
+    function: fn(&Timer),
+    data: usize,
+    // cpu of the last base the timer was queued on
+    cpu: AtomicUsize,
+    // bucket in the wheel, `NOT_QUEUED` if not pending
+    idx: AtomicUsize,
+}
+
+impl GetLinks for Timer {
+    type EntryType = Self;
+    fn get_links(data: &Self) -> &Links<Self> {
+        &data.links
+    }
+}
+
+struct WheelInner {
+    clk: usize,
+    vectors: [RawList<Timer>; WHEEL_SIZE],
+    pending: [u64; LVL_DEPTH],
+    running: *const Timer,
+}
+
+// SAFETY: `running` is only compared, never dereferenced.
+unsafe impl Send for WheelInner {}
+
+impl WheelInner {
+    const fn new() -> Self {
+        Self {
+            clk: INITIAL_JIFFIES,
+            vectors: [const { RawList::new() }; WHEEL_SIZE],
+            pending: [0; LVL_DEPTH],
+            running: ptr::null(),
+        }
+    }
+
+    fn has_pending(&self) -> bool {
+        self.pending.iter().any(|&p| p != 0)
+    }
+
+    fn test_and_clear_pending(&mut self, idx: usize) -> bool {
+        let bit = 1 << (idx % LVL_SIZE);
+        let set = self.pending[idx / LVL_SIZE] & bit != 0;
+        self.pending[idx / LVL_SIZE] &= !bit;
+        set
+    }
+
+    // Returns false if the timer was queued concurrently on another base.
+    fn enqueue(&mut self, timer: &Timer, cpu: usize) -> bool {
+        let idx = calc_wheel_index(timer.expires.load(Ordering::Relaxed), self.clk);
+        // SAFETY: the timer does not move while queued, and its drop
+        // dequeues it.
+        if !unsafe { self.vectors[idx].push_back(timer) } {
+            return false;
+        }
+        self.pending[idx / LVL_SIZE] |= 1 << (idx % LVL_SIZE);
+        timer.idx.store(idx, Ordering::Relaxed);
+        timer.cpu.store(cpu, Ordering::Relaxed);
+        true
+    }
+
+    fn detach(&mut self, timer: &Timer) -> bool {
+        let idx = timer.idx.load(Ordering::Relaxed);
+        if idx == NOT_QUEUED {
+            return false;
+        }
+        // SAFETY: the timer is queued in this bucket.
+        unsafe {
+            self.vectors[idx].remove(timer);
+        }
+        if self.vectors[idx].is_empty() {
+            self.test_and_clear_pending(idx);
+        }
+        timer.idx.store(NOT_QUEUED, Ordering::Relaxed);
+        true
+    }
+
+    // Collect the buckets expiring at `clk`, lowest level first.
+    fn collect_expired(&mut self, mut clk: usize, buckets: &mut [usize; LVL_DEPTH]) -> usize {
+        let mut n = 0;
+        for lvl in 0..LVL_DEPTH {
+            let idx = (clk & LVL_MASK) + lvl * LVL_SIZE;
+            if self.test_and_clear_pending(idx) {
+                buckets[n] = idx;
+                n += 1;
+            }
+            // Upper levels only expire when all the lower level buckets
+            // have wrapped around.
+            if clk & LVL_CLK_MASK != 0 {
+                break;
+            }
+            clk >>= LVL_CLK_SHIFT;
+        }
+        n
+    }
+}
+
+static TIMER_BASES: [RawSpinLockNoIrq<WheelInner>; MAX_CPUS] =
+    [const { RawSpinLockNoIrq::new(WheelInner::new(), Some("timer_base")) }; MAX_CPUS];
+
+impl Timer {
+    /// Create a timer calling `function` on expiry.
+    pub const fn new(function: fn(&Timer)) -> Self {
+        Self::with_data(function, 0)
+    }
+
+    /// Create a timer with private data for its callback.
+    pub const fn with_data(function: fn(&Timer), data: usize) -> Self {
+        Self {
+            links: Links::new(),
+            expires: AtomicUsize::new(0),
+            function,
+            data,
+            cpu: AtomicUsize::new(NO_BASE),
+            idx: AtomicUsize::new(NOT_QUEUED),
+        }
+    }
+
+    /// Private data
+    #[inline]
+    pub fn data(&self) -> usize {
+        self.data
+    }
+
+    /// Expiry time in jiffies.
+    #[inline]
+    pub fn expires(&self) -> usize {
+        self.expires.load(Ordering::Relaxed)
+    }
+
+    /// Is the timer queued?
+    #[inline]
+    pub fn pending(&self) -> bool {
+        self.idx.load(Ordering::Relaxed) != NOT_QUEUED
+    }
+
+    // Lock the base the timer was last queued on.
+    fn lock_base(&self) -> Option<RawSpinLockNoIrqGuard<'static, WheelInner>> {
+        loop {
+            let cpu = self.cpu.load(Ordering::Relaxed);
+            if cpu == NO_BASE {
+                return None;
+            }
+            let base = TIMER_BASES[cpu].lock();
+            if self.cpu.load(Ordering::Relaxed) == cpu {
+                return Some(base);
+            }
+        }
+    }
+
+    /// Set the expiry time and queue the timer, return whether it was pending.
+    ///
+    /// A pending timer stays on its cpu, else it is queued on the current cpu.
+    ///
+    /// # Safety
+    ///
+    /// The timer must not move until it is deleted or has expired.
+    pub unsafe fn mod_timer(&self, expires: usize) -> bool {
+        loop {
+            if let Some(mut base) = self.lock_base() {
+                if base.detach(self) {
+                    self.expires.store(expires, Ordering::Relaxed);
+                    let cpu = self.cpu.load(Ordering::Relaxed);
+                    base.enqueue(self, cpu);
+                    return true;
+                }
+            }
+
+            let cpu = smp_processor_id();
+            let mut base = TIMER_BASES[cpu].lock();
+            if self.pending() {
+                // Raced with another `mod_timer`, modify it where it is.
+                continue;
+            }
+            self.expires.store(expires, Ordering::Relaxed);
+            if base.enqueue(self, cpu) {
+                return false;
+            }
+        }
+    }
+
+    /// Queue the timer to expire at `expires`.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`Timer::mod_timer`].
+    pub unsafe fn add_timer(&self, expires: usize) {
+        // SAFETY: guaranteed by the caller.
+        unsafe {
+            self.mod_timer(expires);
+        }
+    }
+
+    /// Deactivate the timer, return whether it was pending.
+    ///
+    /// The callback may still be running on another cpu.
+    pub fn del_timer(&self) -> bool {
+        match self.lock_base() {
+            Some(mut base) => base.detach(self),
+            None => false,
+        }
+    }
+
+    /// Deactivate the timer and wait for its callback to finish.
+    ///
+    /// Must not be called from the callback, nor with a lock the callback takes.
+    pub fn del_timer_sync(&self) -> bool {
+        loop {
+            let Some(mut base) = self.lock_base() else {
+                return false;
+            };
+            if !ptr::eq(base.running, self) {
+                return base.detach(self);
+            }
+            drop(base);
+            core::hint::spin_loop();
+        }
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        self.del_timer_sync();
+    }
+}
+
+/// Run the expired timers of `cpu`, called from its tick.
+pub(crate) fn run_timers(cpu: usize) {
+    let now = jiffies();
+    let mut base = TIMER_BASES[cpu].lock();
+    // Nothing to expire, skip the idle jiffies.
+    if !base.has_pending() && time_after(now, base.clk) {
+        base.clk = now;
+    }
+
+    let mut buckets = [0; LVL_DEPTH];
+    while time_after_eq(now, base.clk) {
+        let clk = base.clk;
+        let n = base.collect_expired(clk, &mut buckets);
+        base.clk = clk.wrapping_add(1);
+
+        // Expire the most delayed timers first.
+        for &idx in buckets[..n].iter().rev() {
+            while let Some(timer) = base.vectors[idx].pop_front() {
+                // SAFETY: the timer was queued, and its drop waits for us
+                // through `running`.
+                let timer = unsafe { &*timer.as_ptr() };
+                timer.idx.store(NOT_QUEUED, Ordering::Relaxed);
+                base.running = timer;
+                drop(base);
+                (timer.function)(timer);
+                base = TIMER_BASES[cpu].lock();
+                base.running = ptr::null();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schedule::task::set_test_current;
+
+    use crate::time::jiffies::do_timer;
+    use crate::time::time_test_lock;
+
+    static FIRED: [AtomicUsize; 5] = [const { AtomicUsize::new(0) }; 5];
+
+    fn record(timer: &Timer) {
+        FIRED[timer.data()].store(jiffies(), Ordering::Relaxed);
+    }
+
+    #[test]
+    fn test_timer_wheel() {
+        set_test_current();
+        let _guard = time_test_lock();
+        run_timers(0);
+
+        let timeouts = [1, 10, 100, 1000, 5000];
+        let timers: std::vec::Vec<Timer> = (0..timeouts.len())
+            .map(|i| Timer::with_data(record, i))
+            .collect();
+        let start = jiffies();
+        for (timer, timeout) in timers.iter().zip(timeouts) {
+            // SAFETY: the timers do not move while queued.
+            assert!(!unsafe { timer.mod_timer(start + timeout) });
+        }
+        let deleted = Timer::with_data(record, 0);
+        // SAFETY: see above.
+        unsafe { deleted.add_timer(start + 2) };
+        assert!(deleted.del_timer());
+        assert!(!deleted.pending());
+        // SAFETY: see above.
+        assert!(unsafe { timers[4].mod_timer(start + 2000) });
+
+        for _ in 0..3000 {
+            do_timer(1);
+            run_timers(0);
+        }
+        for (i, timeout) in [1, 10, 100, 1000, 2000].into_iter().enumerate() {
+            let fired = FIRED[i].load(Ordering::Relaxed).wrapping_sub(start);
+            // Never early, and at most one bucket late.
+            assert!(fired >= timeout, "timer {} fired at {}", i, fired);
+            assert!(
+                fired <= timeout + timeout / 8 + 1,
+                "timer {} fired at {}",
+                i,
+                fired
+            );
+            assert!(!timers[i].pending());
+        }
+    }
+}
@@ -281,6 +281,11 @@ const RO_DATA: &str = concatcp! {
     ".rodata1 : AT(ADDR(.rodata1) -", LOAD_OFFSET, ") { \n",
         "*(.rodata1) \n",
     "} \n",
+    "__param : AT(ADDR(__param) -", LOAD_OFFSET, ") { \n",
+        "__start___param = .; \n",
+        "KEEP(*(__param)) \n",
+        "__stop___param = .; \n",
+    "} \n",
     NOTES,
     ". = ALIGN(", RO_DATA_ALIGN, "); \n",
     "__end_rodata = .; \n",
@@ -341,6 +346,7 @@ pub static EXPORT_INIT_SETUP: [u8; INIT_SETUP.len() + 1] =
     const_str_to_u8_array_with_null!(INIT_SETUP);
 
 const INIT_CALLS: &str = concatcp! {
+    ". = ALIGN(8); \n",
     "__initcall_start = .; \n",
     "KEEP(*(.initcallearly.init)) \n",
     "__initcall0_start = .; KEEP(*(.initcall0.init)) KEEP(*(.initcall0s.init)) \n",
@@ -0,0 +1,55 @@
+//! Initcall impl
+
+use proc_macro::TokenStream;
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, Ident, ItemFn};
+
+// Refer to linux: include/linux/init.h
+fn initcall_section(level: &str) -> Option<&'static str> {
+    let section = match level {
+        "early" => ".initcallearly.init",
+        "core" => ".initcall1.init",
+        "postcore" => ".initcall2.init",
+        "arch" => ".initcall3.init",
+        "subsys" => ".initcall4.init",
+        "fs" => ".initcall5.init",
+        "device" => ".initcall6.init",
+        "late" => ".initcall7.init",
+        _ => return None,
+    };
+    Some(section)
+}
+
+pub(crate) fn initcall_impl(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let level = parse_macro_input!(attr as Ident);
+    let Some(section) = initcall_section(&level.to_string()) else {
+        return syn::Error::new(
+            level.span(),
+            "unknown initcall level, expected one of early, core, postcore, arch, subsys, fs, device or late",
+        )
+        .to_compile_error()
+        .into();
+    };
+
+    let mut f = parse_macro_input!(item as ItemFn);
+    f.attrs
+        .insert(0, syn::parse_quote!(#[unsafe(link_section = ".init.text")]));
+    let name = &f.sig.ident;
+    let name_str = name.to_string();
+    let id = format_ident!("__initcall_{}", name);
+
+    quote! {
+        #f
+
+        // The initcall tables are only linked in the real image.
+        #[cfg(not(test))]
+        #[unsafe(link_section = #section)]
+        #[used]
+        #[allow(non_upper_case_globals)]
+        static #id: ::kernel::init::initcall::InitCall = ::kernel::init::initcall::InitCall::new(
+            #name,
+            concat!(module_path!(), "::", #name_str),
+        );
+    }
+    .into()
+}
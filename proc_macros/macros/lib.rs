@@ -12,6 +12,7 @@
 mod concat_idents;
 mod aligned;
 mod helpers;
+mod initcall;
 mod link;
 mod paste;
 
@@ -216,6 +217,27 @@ pub fn section_bss_page_aligned(_attr: TokenStream, item: TokenStream) -> TokenS
     link::section_impl(quote!(section = ".bss..page_aligned").into(), item)
 }
 
+/// Run the function at boot, in the initcall `level`.
+///
+/// `level` is one of `early`, `core`, `postcore`, `arch`, `subsys`, `fs`,
+/// `device` or `late`, the function is `fn() -> Result` and goes to
+/// `.init.text`.
+///
+/// # Examples
+///
+/// ```ignore
+/// use kernel::macros::initcall;
+///
+/// #[initcall(device)]
+/// fn foo_init() -> Result {
+///     Ok(())
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn initcall(attr: TokenStream, item: TokenStream) -> TokenStream {
+    initcall::initcall_impl(attr, item)
+}
+
 use syn::parse_macro_input;
 use syn::Item;
 
@@ -0,0 +1,14 @@
+//! Parse arbitrary blobs and ask them everything, nothing may panic
+//!
+//! Run with the test blobs as the seed corpus:
+//!
+//! ```sh
+//! cargo fuzz run parse fuzz/corpus/parse dtb
+//! ```
+
+#![no_main]
+
+use fdtree_rs::fuzzing::exercise;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| exercise(data));
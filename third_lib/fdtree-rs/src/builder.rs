@@ -0,0 +1,286 @@
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Building a devicetree in a caller provided buffer
+//!
+//! As with the sequential write functions of libfdt, the memory reservations
+//! are added first, then the nodes in depth first order. The structure block
+//! grows from the start of the buffer and the strings block from its end,
+//! they are put together by [`FdtBuilder::finish`].
+
+use crate::{
+    error::FdtError,
+    node::{FDT_BEGIN_NODE, FDT_END, FDT_END_NODE, FDT_PROP},
+};
+
+const FDT_MAGIC: u32 = 0xd00dfeed;
+const FDT_VERSION: u32 = 17;
+const FDT_LAST_COMP_VERSION: u32 = 16;
+const FDT_HEADER_SIZE: usize = 40;
+const FDT_RESERVE_ENTRY_SIZE: usize = 16;
+
+const fn fdt_align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/// Writes a devicetree blob
+///
+/// ```
+/// # use fdtree_rs::{FdtBuilder, LinuxFdt};
+/// let mut buf = [0; 256];
+/// let mut builder = FdtBuilder::new(&mut buf).unwrap();
+/// builder.add_reservation(0x8000_0000, 0x1000).unwrap();
+/// builder.begin_node("").unwrap();
+/// builder.property_str("model", "builder").unwrap();
+/// builder.begin_node("chosen").unwrap();
+/// builder.property_str("bootargs", "console=ttyS0").unwrap();
+/// builder.end_node().unwrap();
+/// builder.end_node().unwrap();
+///
+/// let fdt = LinuxFdt::new(builder.finish().unwrap()).unwrap();
+/// assert_eq!(fdt.machine(), Some("builder"));
+/// ```
+#[derive(Debug)]
+pub struct FdtBuilder<'a> {
+    buf: &'a mut [u8],
+    // End of the memory reservations, then of the structure block
+    pos: usize,
+    off_struct: Option<usize>,
+    // The strings are at the end of `buf`.
+    strings_len: usize,
+    depth: usize,
+    boot_cpuid_phys: u32,
+}
+
+impl<'a> FdtBuilder<'a> {
+    /// Start a devicetree in `buf`
+    pub fn new(buf: &'a mut [u8]) -> Result<Self, FdtError> {
+        if buf.len() < FDT_HEADER_SIZE + FDT_RESERVE_ENTRY_SIZE {
+            return Err(FdtError::NoSpace);
+        }
+        Ok(Self {
+            buf,
+            pos: FDT_HEADER_SIZE,
+            off_struct: None,
+            strings_len: 0,
+            depth: 0,
+            boot_cpuid_phys: 0,
+        })
+    }
+
+    /// Set the physical ID of the boot CPU in the header
+    pub fn set_boot_cpuid_phys(&mut self, cpuid: u32) {
+        self.boot_cpuid_phys = cpuid;
+    }
+
+    fn reserve(&mut self, len: usize) -> Result<&mut [u8], FdtError> {
+        let (start, end) = (self.pos, fdt_align(self.pos + len));
+        if end > self.buf.len() - self.strings_len {
+            return Err(FdtError::NoSpace);
+        }
+        self.buf[start..end].fill(0);
+        self.pos = end;
+        Ok(&mut self.buf[start..start + len])
+    }
+
+    fn put(&mut self, bytes: &[u8]) -> Result<(), FdtError> {
+        self.reserve(bytes.len())?.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    fn put_u32(&mut self, val: u32) -> Result<(), FdtError> {
+        self.put(&val.to_be_bytes())
+    }
+
+    /// Add a memory reservation, before the root node
+    pub fn add_reservation(&mut self, address: u64, size: u64) -> Result<(), FdtError> {
+        if self.off_struct.is_some() {
+            return Err(FdtError::BadState);
+        }
+        // The terminating entry must still fit.
+        if self.pos + 2 * FDT_RESERVE_ENTRY_SIZE > self.buf.len() {
+            return Err(FdtError::NoSpace);
+        }
+        self.put(&address.to_be_bytes())?;
+        self.put(&size.to_be_bytes())
+    }
+
+    /// Start the node `name`, the root node is `""`.
+    pub fn begin_node(&mut self, name: &str) -> Result<(), FdtError> {
+        match self.off_struct {
+            Some(_) if self.depth == 0 => return Err(FdtError::BadState),
+            Some(_) => {}
+            None => {
+                self.put(&[0; FDT_RESERVE_ENTRY_SIZE])?;
+                self.off_struct = Some(self.pos);
+            }
+        }
+        if name.contains('\0') {
+            return Err(FdtError::BadValue);
+        }
+        self.put_u32(FDT_BEGIN_NODE)?;
+        // The name is zero terminated, as the reserved bytes are zeroed.
+        self.reserve(name.len() + 1)?[..name.len()].copy_from_slice(name.as_bytes());
+        self.depth += 1;
+        Ok(())
+    }
+
+    /// End the last started node
+    pub fn end_node(&mut self) -> Result<(), FdtError> {
+        if self.depth == 0 {
+            return Err(FdtError::BadState);
+        }
+        self.put_u32(FDT_END_NODE)?;
+        self.depth -= 1;
+        Ok(())
+    }
+
+    // Distance of `name` from the end of the buffer, it is added if not found.
+    fn string(&mut self, name: &str) -> Result<usize, FdtError> {
+        let len = self.buf.len();
+        let strings = &self.buf[len - self.strings_len..];
+        let found = strings
+            .windows(name.len() + 1)
+            .position(|s| s[..name.len()] == *name.as_bytes() && s[name.len()] == 0);
+        if let Some(offset) = found {
+            return Ok(self.strings_len - offset);
+        }
+        if self.pos + self.strings_len + name.len() + 1 > len {
+            return Err(FdtError::NoSpace);
+        }
+        self.strings_len += name.len() + 1;
+        let start = len - self.strings_len;
+        self.buf[start..start + name.len()].copy_from_slice(name.as_bytes());
+        self.buf[start + name.len()] = 0;
+        Ok(self.strings_len)
+    }
+
+    fn property_with(
+        &mut self,
+        name: &str,
+        len: usize,
+        fill: impl FnOnce(&mut [u8]),
+    ) -> Result<(), FdtError> {
+        if self.depth == 0 {
+            return Err(FdtError::BadState);
+        }
+        if name.contains('\0') {
+            return Err(FdtError::BadValue);
+        }
+        let nameoff = self.string(name)?;
+        if self.pos + 12 + fdt_align(len) > self.buf.len() - self.strings_len {
+            return Err(FdtError::NoSpace);
+        }
+        // Fixed up by `finish`, once the strings block is complete.
+        self.put_u32(FDT_PROP)?;
+        self.put_u32(len as u32)?;
+        self.put_u32(nameoff as u32)?;
+        fill(self.reserve(len)?);
+        Ok(())
+    }
+
+    /// Add the property `name` of the last started node
+    pub fn property(&mut self, name: &str, value: &[u8]) -> Result<(), FdtError> {
+        self.property_with(name, value.len(), |dst| dst.copy_from_slice(value))
+    }
+
+    /// Add a property without a value
+    pub fn property_empty(&mut self, name: &str) -> Result<(), FdtError> {
+        self.property(name, &[])
+    }
+
+    /// Add a property of one big endian cell
+    pub fn property_u32(&mut self, name: &str, val: u32) -> Result<(), FdtError> {
+        self.property(name, &val.to_be_bytes())
+    }
+
+    /// Add a property of two big endian cells
+    pub fn property_u64(&mut self, name: &str, val: u64) -> Result<(), FdtError> {
+        self.property(name, &val.to_be_bytes())
+    }
+
+    /// Add a property of big endian cells, such as `reg`
+    pub fn property_cells(&mut self, name: &str, cells: &[u32]) -> Result<(), FdtError> {
+        self.property_with(name, cells.len() * 4, |dst| {
+            for (dst, cell) in dst.chunks_exact_mut(4).zip(cells) {
+                dst.copy_from_slice(&cell.to_be_bytes());
+            }
+        })
+    }
+
+    /// Add a zero terminated string property
+    pub fn property_str(&mut self, name: &str, val: &str) -> Result<(), FdtError> {
+        self.property_str_list(name, &[val])
+    }
+
+    /// Add a list of zero terminated strings, such as `compatible`
+    pub fn property_str_list(&mut self, name: &str, vals: &[&str]) -> Result<(), FdtError> {
+        if vals.iter().any(|val| val.contains('\0')) {
+            return Err(FdtError::BadValue);
+        }
+        let len = vals.iter().map(|val| val.len() + 1).sum();
+        self.property_with(name, len, |dst| {
+            let mut pos = 0;
+            for val in vals {
+                dst[pos..pos + val.len()].copy_from_slice(val.as_bytes());
+                pos += val.len() + 1;
+            }
+        })
+    }
+
+    /// Complete the devicetree once the root node is ended, it is at the
+    /// start of the buffer.
+    pub fn finish(mut self) -> Result<&'a [u8], FdtError> {
+        let Some(off_struct) = self.off_struct else {
+            return Err(FdtError::BadState);
+        };
+        if self.depth != 0 {
+            return Err(FdtError::BadState);
+        }
+        self.put_u32(FDT_END)?;
+        let (buf, off_strings, strings_len) = (self.buf, self.pos, self.strings_len);
+
+        // The names of the properties were written as distances from the end.
+        let mut pos = off_struct;
+        let cell = |buf: &[u8], pos: usize| {
+            u32::from_be_bytes(buf[pos..pos + 4].try_into().unwrap()) as usize
+        };
+        loop {
+            match cell(buf, pos) as u32 {
+                FDT_BEGIN_NODE => {
+                    let name_len = buf[pos + 4..].iter().position(|&b| b == 0).unwrap();
+                    pos += 4 + fdt_align(name_len + 1);
+                }
+                FDT_PROP => {
+                    let (len, nameoff) = (cell(buf, pos + 4), cell(buf, pos + 8));
+                    let nameoff = (strings_len - nameoff) as u32;
+                    buf[pos + 8..pos + 12].copy_from_slice(&nameoff.to_be_bytes());
+                    pos += 12 + fdt_align(len);
+                }
+                FDT_END_NODE => pos += 4,
+                _ => break,
+            }
+        }
+
+        let len = buf.len();
+        buf.copy_within(len - strings_len.., off_strings);
+        let total = off_strings + strings_len;
+        let header = [
+            FDT_MAGIC,
+            total as u32,
+            off_struct as u32,
+            off_strings as u32,
+            FDT_HEADER_SIZE as u32,
+            FDT_VERSION,
+            FDT_LAST_COMP_VERSION,
+            self.boot_cpuid_phys,
+            strings_len as u32,
+            (off_strings - off_struct) as u32,
+        ];
+        for (i, field) in header.iter().enumerate() {
+            buf[i * 4..i * 4 + 4].copy_from_slice(&field.to_be_bytes());
+        }
+        Ok(&buf[..total])
+    }
+}
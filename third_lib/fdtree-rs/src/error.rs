@@ -10,6 +10,20 @@ pub enum FdtError {
     /// The slice passed in was too small to fit the given total size of the FDT
     /// structure
     BufferTooSmall,
+    /// The version is older than 17, or newer versions are not compatible
+    BadVersion,
+    /// A block is misaligned or not inside the total size
+    BadLayout,
+    /// The structure block is malformed
+    BadStructure,
+    /// The index buffer has fewer entries than the FDT has nodes
+    IndexTooSmall,
+    /// The buffer of the builder is full
+    NoSpace,
+    /// The builder calls are out of order
+    BadState,
+    /// A name or string has a zero byte
+    BadValue,
 }
 
 impl core::fmt::Display for FdtError {
@@ -20,6 +34,13 @@ impl core::fmt::Display for FdtError {
             FdtError::BufferTooSmall => {
                 write!(f, "the given buffer was too small to contain a FDT header")
             }
+            FdtError::BadVersion => write!(f, "unsupported FDT version"),
+            FdtError::BadLayout => write!(f, "misaligned or out of bounds FDT block"),
+            FdtError::BadStructure => write!(f, "malformed FDT structure block"),
+            FdtError::IndexTooSmall => write!(f, "the index buffer was too small for the nodes"),
+            FdtError::NoSpace => write!(f, "the builder buffer was too small for the FDT"),
+            FdtError::BadState => write!(f, "the FDT builder calls were out of order"),
+            FdtError::BadValue => write!(f, "a name or string contained a zero byte"),
         }
     }
 }
@@ -0,0 +1,101 @@
+//! Helpers of the fuzz target and the malformed blob tests
+
+extern crate alloc;
+
+use crate::{FdtIndexEntry, LinuxFdt};
+
+/// Everything a devicetree can be asked, which must not panic
+pub fn exercise(data: &[u8]) {
+    let Ok(fdt) = LinuxFdt::new(data) else {
+        return;
+    };
+    let _ = alloc::format!("{:?}", fdt);
+    let _ = fdt.machine();
+    if let Some(root) = fdt.root() {
+        let _ = (
+            root.model(),
+            root.cell_sizes(),
+            root.compatible().map(|c| c.all().count()),
+        );
+    }
+    if let Some(chosen) = fdt.chosen() {
+        let _ = (chosen.bootargs(), chosen.stdout(), chosen.initrd());
+        let _ = (chosen.rng_seed(), chosen.kaslr_seed());
+        let _ = chosen.usable_mem_region().map(|r| r.count());
+    }
+    let _ = fdt.sys_memory_reservations().count();
+    for mem in fdt.mem_nodes() {
+        let _ = (mem.regions().map(|r| r.count()), mem.initial_mapped_area());
+    }
+    if let Some(reserved) = fdt.linux_reserved_memory() {
+        for node in reserved.valid_reserved_nodes() {
+            let _ = node.regions().count();
+        }
+        for node in reserved.dynamic_nodes() {
+            let _ = (node.alignment(), node.shared_dma_pool());
+            let _ = node.alloc_ranges().map(|r| r.count());
+        }
+    }
+    let _ = fdt.aliases().map(|a| a.all().count());
+    for cpu in fdt.cpus() {
+        let _ = (cpu.mpidr(), cpu.enable_method(), cpu.cpu_release_addr());
+        let _ = (cpu.capacity_dmips_mhz(), cpu.clock_frequency());
+    }
+    if let Some(psci) = fdt.psci() {
+        let _ = (psci.method(), psci.cpu_on(), psci.cpu_off());
+    }
+
+    for node in fdt.all_nodes() {
+        let _ = (
+            node.properties().count(),
+            node.children().count(),
+            node.is_available(),
+        );
+        let _ = (
+            node.reg().map(|r| r.count()),
+            node.raw_reg().map(|r| r.count()),
+        );
+        let _ = node.compatible().map(|c| (c.first(), c.all().count()));
+        let _ = (
+            node.cell_sizes(),
+            node.interrupt_parent(),
+            node.interrupts().map(|i| i.count()),
+        );
+        let _ = (
+            node.translate_address(0),
+            node.dma_ranges().map(|r| r.count()),
+        );
+        let _ = node.clocks().map(|c| {
+            c.map(|c| c.args_count() + c.arg(0).is_some() as usize)
+                .sum::<usize>()
+        });
+        let _ = node
+            .interrupts_extended()
+            .map(|i| i.map(|i| i.args().count()).sum::<usize>());
+        let _ = (node.irq_find_parent(), node.interrupt(0), node.interrupt(1));
+        let _ = (
+            node.gpios(None).map(|g| g.count()),
+            node.gpio(Some("reset"), 1),
+        );
+        if let Some(phandle) = node.property("phandle").and_then(|p| p.as_usize()) {
+            let _ = fdt.find_phandle(phandle as u32);
+        }
+    }
+    for path in [
+        "/",
+        "/soc/uart",
+        "/soc/serial@7e215040",
+        "/cpus/cpu@0",
+        "serial0",
+    ] {
+        let _ = fdt.find_node(path);
+    }
+
+    let mut entries = alloc::vec![FdtIndexEntry::default(); fdt.node_count()];
+    if let Ok(indexed) = fdt.build_index(&mut entries) {
+        let _ = (indexed.find_node("/chosen"), indexed.find_phandle(1));
+        for node in indexed.all_nodes() {
+            let _ = indexed.find_node(node.name).map(|n| n.translate_address(0));
+        }
+    }
+}
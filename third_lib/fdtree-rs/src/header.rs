@@ -1,6 +1,15 @@
 //! FdtHeader
 
-use crate::parsing::{BigEndianU32, FdtData};
+use crate::{
+    error::FdtError,
+    parsing::{BigEndianU32, FdtData},
+};
+
+const FDT_MAGIC: u32 = 0xd00dfeed;
+// The struct block size is in the header since version 17.
+const FDT_FIRST_SUPPORTED_VERSION: u32 = 17;
+const FDT_LAST_SUPPORTED_VERSION: u32 = 17;
+const FDT_RESERVE_ENTRY_SIZE: usize = 16;
 
 #[derive(Debug, Clone, Copy)]
 #[repr(C)]
@@ -29,8 +38,44 @@ pub(crate) struct FdtHeader {
 }
 
 impl FdtHeader {
+    pub(crate) const SIZE: usize = core::mem::size_of::<Self>();
+
     pub(crate) fn valid_magic(&self) -> bool {
-        self.magic.get() == 0xd00dfeed
+        self.magic.get() == FDT_MAGIC
+    }
+
+    /// Check the version, and that the blocks are aligned and inside the
+    /// total size
+    pub(crate) fn check(&self) -> Result<(), FdtError> {
+        if !self.valid_magic() {
+            return Err(FdtError::BadMagic);
+        }
+        if self.version.get() < FDT_FIRST_SUPPORTED_VERSION
+            || self.last_comp_version.get() > FDT_LAST_SUPPORTED_VERSION
+        {
+            return Err(FdtError::BadVersion);
+        }
+
+        let total = self.totalsize.get() as usize;
+        let inside = |offset: BigEndianU32, size: usize| {
+            let offset = offset.get() as usize;
+            offset >= Self::SIZE && offset.checked_add(size).is_some_and(|end| end <= total)
+        };
+        let rsvmap = self.off_mem_rsvmap.get() as usize;
+        let (structs, structs_size) = (
+            self.off_dt_struct.get() as usize,
+            self.size_dt_struct.get() as usize,
+        );
+        if !rsvmap.is_multiple_of(8)
+            || !inside(self.off_mem_rsvmap, FDT_RESERVE_ENTRY_SIZE)
+            || !structs.is_multiple_of(4)
+            || !structs_size.is_multiple_of(4)
+            || !inside(self.off_dt_struct, structs_size)
+            || !inside(self.off_dt_strings, self.size_dt_strings.get() as usize)
+        {
+            return Err(FdtError::BadLayout);
+        }
+        Ok(())
     }
 
     pub(crate) fn struct_range(&self) -> core::ops::Range<usize> {
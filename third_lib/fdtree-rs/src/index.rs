@@ -0,0 +1,184 @@
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+//! An index of the nodes, built once in a caller provided buffer
+//!
+//! The nodes are in depth first order, linked to their parent, first child
+//! and next sibling. A phandle smaller than the number of nodes is found in
+//! the `by_phandle` slot of the entry of the same number, the others are
+//! searched for.
+
+use crate::{
+    error::FdtError,
+    node::{FdtNode, FDT_BEGIN_NODE, FDT_END_NODE, FDT_NOP, FDT_PROP},
+    parsing::{BigEndianU32, CStr, FdtData},
+    LinuxFdt,
+};
+
+const NONE: u32 = u32::MAX;
+
+/// A node of the index built by [`LinuxFdt::build_index`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FdtIndexEntry {
+    // Offsets in the struct block
+    name: u32,
+    props: u32,
+    parent: u32,
+    first_child: u32,
+    next_sibling: u32,
+    phandle: u32,
+    // The node whose phandle is the position of this entry
+    by_phandle: u32,
+}
+
+pub(crate) fn build<'a>(
+    fdt: &LinuxFdt<'a>,
+    entries: &mut [FdtIndexEntry],
+) -> Result<usize, FdtError> {
+    let structs = fdt.structs_block();
+    let mut stream = FdtData::new(structs);
+    let offset = |stream: &FdtData<'a>| (structs.len() - stream.remaining().len()) as u32;
+    let (mut nr, mut cur, mut closed) = (0, NONE, NONE);
+
+    loop {
+        while stream.peek_u32().map(|n| n.get()) == Some(FDT_NOP) {
+            stream.skip(4);
+        }
+        match stream.u32().map(|n| n.get()) {
+            Some(FDT_BEGIN_NODE) => {
+                let name = offset(&stream);
+                let name_len = CStr::new(stream.remaining()).ok_or(FdtError::BadStructure)?.len();
+                stream.skip((name_len + 4) & !0x3);
+
+                let entry = entries.get_mut(nr).ok_or(FdtError::IndexTooSmall)?;
+                *entry = FdtIndexEntry {
+                    name,
+                    props: offset(&stream),
+                    parent: cur,
+                    first_child: NONE,
+                    next_sibling: NONE,
+                    phandle: 0,
+                    by_phandle: NONE,
+                };
+                let new = nr as u32;
+                if closed != NONE && entries[closed as usize].parent == cur {
+                    entries[closed as usize].next_sibling = new;
+                } else if cur != NONE {
+                    entries[cur as usize].first_child = new;
+                }
+                cur = new;
+                nr += 1;
+            }
+            Some(FDT_PROP) => {
+                let len = stream.u32().ok_or(FdtError::BadStructure)?.get() as usize;
+                let name = stream.u32().ok_or(FdtError::BadStructure)?.get() as usize;
+                let value = stream.remaining().get(..len).ok_or(FdtError::BadStructure)?;
+                if cur != NONE && fdt.str_at_offset(name) == Some("phandle") {
+                    entries[cur as usize].phandle =
+                        BigEndianU32::from_bytes(value).ok_or(FdtError::BadStructure)?.get();
+                }
+                stream.skip((len + 3) & !0x3);
+            }
+            Some(FDT_END_NODE) if cur != NONE => {
+                closed = cur;
+                cur = entries[cur as usize].parent;
+            }
+            _ if cur == NONE && nr > 0 => break,
+            _ => return Err(FdtError::BadStructure),
+        }
+    }
+
+    for i in 0..nr {
+        let phandle = entries[i].phandle as usize;
+        if phandle != 0 && phandle < nr && entries[phandle].by_phandle == NONE {
+            entries[phandle].by_phandle = i as u32;
+        }
+    }
+    Ok(nr)
+}
+
+fn node<'b, 'a>(fdt: &'b LinuxFdt<'a>, index: &[FdtIndexEntry], i: u32) -> FdtNode<'b, 'a> {
+    let structs = fdt.structs_block();
+    let entry = &index[i as usize];
+    let name = CStr::new(&structs[entry.name as usize..]).and_then(|s| s.as_str()).unwrap_or("");
+    let parent_props = match entry.parent {
+        NONE => None,
+        parent => Some(&structs[index[parent as usize].props as usize..]),
+    };
+    FdtNode::new(name, fdt, &structs[entry.props as usize..], parent_props)
+}
+
+fn children(index: &[FdtIndexEntry], i: u32) -> impl Iterator<Item = u32> + '_ {
+    let some = |i: u32| (i != NONE).then_some(i);
+    core::iter::successors(some(index[i as usize].first_child), move |&c| {
+        some(index[c as usize].next_sibling)
+    })
+}
+
+pub(crate) fn find_phandle<'b, 'a>(
+    fdt: &'b LinuxFdt<'a>,
+    index: &[FdtIndexEntry],
+    phandle: u32,
+) -> Option<FdtNode<'b, 'a>> {
+    if phandle == 0 {
+        return None;
+    }
+    let i = match index.get(phandle as usize) {
+        Some(slot) if slot.by_phandle == NONE => return None,
+        Some(slot) => slot.by_phandle,
+        None => index.iter().position(|e| e.phandle == phandle)? as u32,
+    };
+    Some(node(fdt, index, i))
+}
+
+pub(crate) fn find_node<'b, 'a>(
+    fdt: &'b LinuxFdt<'a>,
+    index: &[FdtIndexEntry],
+    path: &str,
+) -> Option<FdtNode<'b, 'a>> {
+    // Siblings of the same name are tried in turn, as when parsing.
+    fn lookup(fdt: &LinuxFdt<'_>, index: &[FdtIndexEntry], i: u32, path: &str) -> Option<u32> {
+        let (looking_for, rest) = path.split_once('/').unwrap_or((path, ""));
+        if looking_for.is_empty() {
+            return Some(i);
+        }
+        children(index, i)
+            .filter(|&c| {
+                let name = node(fdt, index, c).name;
+                match looking_for.contains('@') {
+                    true => name == looking_for,
+                    false => name.split('@').next() == Some(looking_for),
+                }
+            })
+            .find_map(|c| lookup(fdt, index, c, rest))
+    }
+
+    let path = path.strip_prefix('/')?;
+    if index.is_empty() {
+        return None;
+    }
+    Some(node(fdt, index, lookup(fdt, index, 0, path)?))
+}
+
+// The properties of the ancestors of the node whose properties are `props`,
+// from the root.
+pub(crate) fn parents<'a, const N: usize>(
+    fdt: &LinuxFdt<'a>,
+    index: &[FdtIndexEntry],
+    props: &'a [u8],
+) -> Option<([&'a [u8]; N], usize)> {
+    let structs = fdt.structs_block();
+    let offset = (props.as_ptr() as usize).checked_sub(structs.as_ptr() as usize)? as u32;
+    let mut i = index.binary_search_by_key(&offset, |e| e.props).ok()? as u32;
+
+    let mut parents: [&[u8]; N] = [&[]; N];
+    let mut depth = 0;
+    while index[i as usize].parent != NONE {
+        i = index[i as usize].parent;
+        *parents.get_mut(depth)? = &structs[index[i as usize].props as usize..];
+        depth += 1;
+    }
+    parents[..depth].reverse();
+    Some((parents, depth))
+}
@@ -1,6 +1,7 @@
 //! Linux kernel chosen nodes
 
 use crate::node::FdtNode;
+use crate::parsing::BigEndianU64;
 use crate::standard_nodes::RegIter;
 
 /// Represents the `/chosen` node with specific helper methods
@@ -24,7 +25,8 @@ impl<'b, 'a: 'b> Chosen<'b, 'a> {
         self.node
             .properties()
             .find(|n| n.name == "bootargs")
-            .and_then(|n| core::str::from_utf8(&n.value[..n.value.len() - 1]).ok())
+            .and_then(|n| core::str::from_utf8(n.value).ok())
+            .map(|s| s.trim_end_matches('\0'))
     }
 
     /// Searches for the node representing `stdout`, if the property exists,
@@ -44,8 +46,7 @@ impl<'b, 'a: 'b> Chosen<'b, 'a> {
             }
         }
 
-        let stdout_path = stdout_path.unwrap();
-        let stdout_path = core::str::from_utf8(&stdout_path.value[..stdout_path.value.len() - 1]).ok()?;
+        let stdout_path = stdout_path?.as_str()?;
         let (node_name, options) = stdout_path.split_once(':').unwrap_or((stdout_path, ""));
         let node = self.node.header.find_node(node_name)?;
 
@@ -56,6 +57,31 @@ impl<'b, 'a: 'b> Chosen<'b, 'a> {
         }
     }
 
+    /// The initrd physical range `[start, end)`, from the `linux,initrd-start`
+    /// and `linux,initrd-end` properties
+    pub fn initrd(self) -> Option<(usize, usize)> {
+        let prop = |name| self.node.property(name).and_then(|p| p.as_usize());
+        let start = prop("linux,initrd-start")?;
+        let end = prop("linux,initrd-end")?;
+        (start < end).then_some((start, end))
+    }
+
+    /// `rng-seed` property, entropy for the random pool from the bootloader
+    pub fn rng_seed(self) -> Option<&'a [u8]> {
+        self.node.property("rng-seed").map(|p| p.value)
+    }
+
+    /// `kaslr-seed` property, 64 bits to randomize the kernel address
+    ///
+    /// Refer to linux: get_kaslr_seed
+    pub fn kaslr_seed(self) -> Option<u64> {
+        let value = self.node.property("kaslr-seed")?.value;
+        if value.len() != 8 {
+            return None;
+        }
+        Some(BigEndianU64::from_bytes(value)?.get())
+    }
+
     /// `linux,usable-memory-range` property
     ///
     /// Important: this method assumes that the value(s) inside the `linux,usable-memory-range`
@@ -26,21 +26,17 @@ impl<'b, 'a: 'b> Memory<'b, 'a> {
 
     /// Returns the initial mapped area, if it exists
     pub fn initial_mapped_area(&self) -> Option<MappedArea> {
-        let mut mapped_area = None;
-
-        if let Some(init_mapped_area) = self.node.property("initial_mapped_area") {
-            let mut stream = FdtData::new(init_mapped_area.value);
-            let effective_address = stream.u64().expect("effective address");
-            let physical_address = stream.u64().expect("physical address");
-            let size = stream.u32().expect("size");
-
-            mapped_area = Some(MappedArea {
-                effective_address: effective_address.get() as usize,
-                physical_address: physical_address.get() as usize,
-                size: size.get() as usize,
-            });
-        }
-        mapped_area
+        let init_mapped_area = self.node.property("initial_mapped_area")?;
+        let mut stream = FdtData::new(init_mapped_area.value);
+        let effective_address = stream.u64()?;
+        let physical_address = stream.u64()?;
+        let size = stream.u32()?;
+
+        Some(MappedArea {
+            effective_address: effective_address.get() as usize,
+            physical_address: physical_address.get() as usize,
+            size: size.get() as usize,
+        })
     }
 }
 
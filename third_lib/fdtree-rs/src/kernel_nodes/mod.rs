@@ -2,8 +2,10 @@
 
 pub mod chosen;
 pub mod memory;
+pub mod psci;
 pub mod reserved_memory;
 
 pub use chosen::Chosen;
 pub use memory::Memory;
+pub use psci::{Psci, PsciMethod};
 pub use reserved_memory::ReservedMemory;
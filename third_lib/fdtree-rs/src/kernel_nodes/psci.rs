@@ -0,0 +1,75 @@
+//! Linux kernel psci nodes
+
+use crate::node::{FdtNode, NodeProperty};
+use crate::parsing::BigEndianU32;
+use crate::standard_nodes::Compatible;
+
+/// The compatibles of a PSCI node
+///
+/// Refer to linux: psci_of_match
+pub(crate) const PSCI_COMPATIBLES: [&str; 3] = ["arm,psci", "arm,psci-0.2", "arm,psci-1.0"];
+
+/// How the PSCI firmware is called
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PsciMethod {
+    /// `smc`, the secure monitor
+    Smc,
+    /// `hvc`, the hypervisor
+    Hvc,
+}
+
+/// Represents the PSCI node with specific helper methods
+#[derive(Debug, Clone, Copy)]
+pub struct Psci<'b, 'a> {
+    pub(crate) node: FdtNode<'b, 'a>,
+}
+
+impl<'b, 'a: 'b> Psci<'b, 'a> {
+    /// `compatible` property, `arm,psci-0.2` and later have the standard
+    /// function IDs
+    pub fn compatible(self) -> Option<Compatible<'a>> {
+        self.node.compatible()
+    }
+
+    /// `method` property, `None` for an unknown method
+    ///
+    /// Refer to linux: get_set_conduit_method
+    pub fn method(self) -> Option<PsciMethod> {
+        match self.node.property("method")?.as_str()? {
+            "smc" => Some(PsciMethod::Smc),
+            "hvc" => Some(PsciMethod::Hvc),
+            _ => None,
+        }
+    }
+
+    fn function_id(self, name: &str) -> Option<u32> {
+        Some(BigEndianU32::from_bytes(self.node.property(name)?.value)?.get())
+    }
+
+    /// `cpu_suspend` function ID, of `arm,psci` only
+    ///
+    /// Refer to linux: psci_0_1_init
+    pub fn cpu_suspend(self) -> Option<u32> {
+        self.function_id("cpu_suspend")
+    }
+
+    /// `cpu_off` function ID, of `arm,psci` only
+    pub fn cpu_off(self) -> Option<u32> {
+        self.function_id("cpu_off")
+    }
+
+    /// `cpu_on` function ID, of `arm,psci` only
+    pub fn cpu_on(self) -> Option<u32> {
+        self.function_id("cpu_on")
+    }
+
+    /// `migrate` function ID, of `arm,psci` only
+    pub fn migrate(self) -> Option<u32> {
+        self.function_id("migrate")
+    }
+
+    /// Attempts to find the a property by its name
+    pub fn property(self, name: &str) -> Option<NodeProperty<'a>> {
+        self.node.property(name)
+    }
+}
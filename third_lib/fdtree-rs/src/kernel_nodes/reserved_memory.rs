@@ -3,6 +3,7 @@
 //! Reference: https://www.kernel.org/doc/Documentation/devicetree/bindings/reserved-memory/reserved-memory.yaml
 
 use crate::node::FdtNode;
+use crate::parsing::FdtData;
 use crate::standard_nodes::RegIter;
 
 /// Represents the `/reserved-memory/*` node, it status is ok and have `reg` property
@@ -15,7 +16,8 @@ pub struct ValidReservedMemoryNode<'b, 'a> {
 impl <'b, 'a: 'b> ValidReservedMemoryNode<'b, 'a> {
     /// Returns an iterator over all of the valid regs
     pub fn regions(&self) -> RegIter<'a> {
-        self.node.reg().unwrap()
+        // Empty if the node was not one of `valid_reserved_nodes`
+        self.node.reg().unwrap_or_else(|| RegIter::new(FdtData::new(&[]), self.node.parent_cell_sizes()))
     }
 
     /// return nomap property
@@ -43,7 +45,7 @@ impl <'b, 'a: 'b> DynamicReservedMemoryNode<'b, 'a> {
     /// return alignment
     pub fn alignment(&self) -> usize {
         // if no alignment, default is 0
-        self.node.property("alignment").map(|p| p.as_usize().unwrap()).unwrap_or(0)
+        self.node.property("alignment").and_then(|p| p.as_usize()).unwrap_or(0)
     }
 
     /// return nomap
@@ -55,7 +57,7 @@ impl <'b, 'a: 'b> DynamicReservedMemoryNode<'b, 'a> {
     /// Address and Length pairs. Specifies regions of memory that are
     /// acceptable to allocate from.
     pub fn alloc_ranges(&self) -> Option<RegIter<'a>> {
-        self.node.property("alloc-ranges").map(|p| p.as_reg(self.node.parent_cell_sizes()).unwrap())
+        self.node.property("alloc-ranges").and_then(|p| p.as_reg(self.node.parent_cell_sizes()))
     }
 
     /// reusable property
@@ -65,7 +67,7 @@ impl <'b, 'a: 'b> DynamicReservedMemoryNode<'b, 'a> {
 
     /// shared_dma_pool compatible
     pub fn shared_dma_pool(&self) -> bool {
-        self.node.compatible().map(|p| p.first() == "shared-dma-pool").unwrap_or(false)
+        self.node.compatible().and_then(|p| p.first()) == Some("shared-dma-pool")
     }
 
 }
@@ -115,7 +117,7 @@ impl<'b, 'a: 'b> ReservedMemory<'b, 'a> {
         self.node.children().filter_map(|node| {
             if node.is_available() {
                 if let Some(size) = node.property("size") && node.reg().is_none() {
-                    return Some(DynamicReservedMemoryNode { node, size: size.as_usize().unwrap() });
+                    return Some(DynamicReservedMemoryNode { node, size: size.as_usize()? });
                 }
             }
             None
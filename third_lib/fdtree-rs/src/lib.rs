@@ -9,12 +9,19 @@ mod error;
 mod parsing;
 mod node;
 mod header;
+mod index;
+mod builder;
 mod pretty_print;
+#[cfg(feature = "fuzzing")]
+#[doc(hidden)]
+pub mod fuzzing;
 
 pub use kernel_nodes::*;
 pub use standard_nodes::*;
 pub use error::FdtError;
 pub use node::FdtNode;
+pub use index::FdtIndexEntry;
+pub use builder::FdtBuilder;
 use parsing::{FdtData, BigEndianU32, CStr};
 use header::FdtHeader;
 use node::MemoryReservation;
@@ -25,36 +32,55 @@ use node::MemoryReservation;
 pub struct LinuxFdt<'a> {
     data: &'a [u8],
     header: FdtHeader,
+    index: Option<&'a [FdtIndexEntry]>,
 }
 
 impl core::fmt::Debug for LinuxFdt<'_> {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        pretty_print::print_node(f, self.root().node, 0)?;
-        Ok(())
+        match self.root() {
+            Some(root) => pretty_print::print_node(f, root.node, 0),
+            None => write!(f, "<no root node>"),
+        }
     }
 }
 
 impl<'a> LinuxFdt<'a> {
     /// Construct a new `Fdt` from a byte buffer
     ///
+    /// The header and the structure block are checked, the accessors then
+    /// return `None` rather than panic on unexpected values.
+    ///
     /// Note: this function does ***not*** require that the data be 4-byte
     /// aligned
     pub fn new(data: &'a [u8]) -> Result<Self, FdtError> {
-        let mut stream = FdtData::new(data);
-        let header = FdtHeader::from_bytes(&mut stream).ok_or(FdtError::BufferTooSmall)?;
-
-        if !header.valid_magic() {
-            return Err(FdtError::BadMagic);
-        } else if data.len() < header.totalsize.get() as usize {
+        let total_size = Self::check_header(data)?;
+        if data.len() < total_size {
             return Err(FdtError::BufferTooSmall);
         }
+        let header = FdtHeader::from_bytes(&mut FdtData::new(data)).ok_or(FdtError::BufferTooSmall)?;
 
-        Ok(Self { data, header })
+        let fdt = Self { data: &data[..total_size], header, index: None };
+        node::check_structure(&fdt)?;
+        Ok(fdt)
+    }
+
+    /// Size of the header, what [`LinuxFdt::check_header`] reads
+    pub const HEADER_SIZE: usize = FdtHeader::SIZE;
+
+    /// Check the header at the start of `data`, and return the total size
+    /// of the devicetree
+    ///
+    /// Only the header needs to be readable, such as to map the rest of the
+    /// devicetree.
+    pub fn check_header(data: &[u8]) -> Result<usize, FdtError> {
+        let header = FdtHeader::from_bytes(&mut FdtData::new(data)).ok_or(FdtError::BufferTooSmall)?;
+        header.check()?;
+        Ok(header.totalsize.get() as usize)
     }
 
     /// # Safety
-    /// This function performs a read to verify the magic value. If the pointer
-    /// is invalid this can result in undefined behavior.
+    /// This function reads the header, then the total size it gives. If the
+    /// pointer is invalid this can result in undefined behavior.
     ///
     /// Note: this function does ***not*** require that the data be 4-byte
     /// aligned
@@ -65,37 +91,75 @@ impl<'a> LinuxFdt<'a> {
 
         // SAFETY: we assume that the pointer is valid and points to a valid FDT
         let tmp_header = unsafe {
-                core::slice::from_raw_parts(ptr, core::mem::size_of::<FdtHeader>())
+                core::slice::from_raw_parts(ptr, FdtHeader::SIZE)
         };
 
-        let real_size =
-            FdtHeader::from_bytes(&mut FdtData::new(tmp_header)).unwrap().totalsize.get() as usize;
+        let real_size = Self::check_header(tmp_header)?;
 
         unsafe {
             Self::new(core::slice::from_raw_parts(ptr, real_size))
         }
     }
 
+    /// Number of nodes, the entries needed by [`LinuxFdt::build_index`]
+    pub fn node_count(&self) -> usize {
+        self.all_nodes().count()
+    }
+
+    /// Index the nodes in `entries`, at least [`LinuxFdt::node_count`] of
+    /// them
+    ///
+    /// The returned devicetree finds phandles in constant time, and paths
+    /// without parsing the structure block again. Without an index, the
+    /// lookups parse the devicetree and allocate nothing.
+    pub fn build_index(self, entries: &'a mut [FdtIndexEntry]) -> Result<Self, FdtError> {
+        let nr = index::build(&self, entries)?;
+        Ok(Self { index: Some(&entries[..nr]), ..self })
+    }
+
     /// Total size of the devicetree in bytes
     pub fn total_size(&self) -> usize {
         self.header.totalsize.get() as usize
     }
 
-    /// Return the root (`/`) node, which is always available
-    pub fn root(&self) -> Root<'_, 'a> {
-        Root { node: self.find_node("/").expect("/ is a required node") }
+    /// Return the root (`/`) node
+    pub fn root(&self) -> Option<Root<'_, 'a>> {
+        Some(Root { node: self.find_node("/")? })
     }
 
-    /// Returns the machine name
-    pub fn machine(&self) -> &'a str {
-        core::str::from_utf8(self.root().property("model").expect("expected model property").value).unwrap().trim_end_matches('\0')
+    /// Returns the machine name, the `model` property of the root
+    pub fn machine(&self) -> Option<&'a str> {
+        self.root()?.model()
     }
 
-    /// Returns the chosen node
-    pub fn chosen(&self) -> Chosen<'_, 'a> {
+    /// Returns the chosen node, `/chosen` or `/chosen@0`, if it exists
+    pub fn chosen(&self) -> Option<Chosen<'_, 'a>> {
         node::find_node(&mut FdtData::new(self.structs_block()), "/chosen", self, None)
             .map(|node| Chosen { node })
-            .expect("/chosen is required")
+    }
+
+    /// Returns the `/cpus/cpu*` nodes, those of `device_type = "cpu"`
+    pub fn cpus(&self) -> impl Iterator<Item = Cpu<'_, 'a>> {
+        self.find_node("/cpus").into_iter().flat_map(|parent| {
+            parent
+                .children()
+                .filter(|node| node.property("device_type").and_then(|p| p.as_str()) == Some("cpu"))
+                .map(move |node| Cpu { parent, node })
+        })
+    }
+
+    /// Returns the available PSCI node, if it exists
+    ///
+    /// Refer to linux: psci_dt_init
+    pub fn psci(&self) -> Option<Psci<'_, 'a>> {
+        self.all_nodes()
+            .find(|node| {
+                node.is_available()
+                    && node
+                        .compatible()
+                        .is_some_and(|c| c.all().any(|c| psci::PSCI_COMPATIBLES.contains(&c)))
+            })
+            .map(|node| Psci { node })
     }
 
     /// Return the reserved memory nodes
@@ -161,12 +225,18 @@ impl<'a> LinuxFdt<'a> {
     /// the first node that has a matching name, ignoring the address portion if
     /// it exists.
     pub fn find_node(&self, path: &str) -> Option<node::FdtNode<'_, 'a>> {
-        let node = node::find_node(&mut FdtData::new(self.structs_block()), path, self, None);
+        let node = match self.index {
+            Some(index) => index::find_node(self, index, path),
+            None => node::find_node(&mut FdtData::new(self.structs_block()), path, self, None),
+        };
         node.or_else(|| self.aliases()?.resolve_node(path))
     }
 
     /// Searches for the given `phandle`
     pub fn find_phandle(&self, phandle: u32) -> Option<node::FdtNode<'_, 'a>> {
+        if let Some(index) = self.index {
+            return index::find_phandle(self, index, phandle);
+        }
         self.all_nodes().find(|n| {
             n.properties()
                 .find(|p| p.name == "phandle")
@@ -180,12 +250,12 @@ impl<'a> LinuxFdt<'a> {
         node::all_nodes(self)
     }
 
-    fn cstr_at_offset(&self, offset: usize) -> CStr<'a> {
-        CStr::new(&self.strings_block()[offset..]).expect("no null terminating string on C str?")
+    fn cstr_at_offset(&self, offset: usize) -> Option<CStr<'a>> {
+        CStr::new(self.strings_block().get(offset..)?)
     }
 
-    fn str_at_offset(&self, offset: usize) -> &'a str {
-        self.cstr_at_offset(offset).as_str().expect("not utf-8 cstr")
+    fn str_at_offset(&self, offset: usize) -> Option<&'a str> {
+        self.cstr_at_offset(offset)?.as_str()
     }
 
     fn structs_block(&self) -> &'a [u8] {
@@ -3,16 +3,17 @@
 //! obtain one at https://mozilla.org/MPL/2.0/.
 
 use crate::{
+    error::FdtError,
     parsing::{BigEndianU32, BigEndianU64, CStr, FdtData},
-    standard_nodes::{Compatible, RegIter},
+    standard_nodes::{Compatible, DmaRange, PhandleArgs, RegIter},
     LinuxFdt,
 };
 
-const FDT_BEGIN_NODE: u32 = 1;
-const FDT_END_NODE: u32 = 2;
-const FDT_PROP: u32 = 3;
+pub(crate) const FDT_BEGIN_NODE: u32 = 1;
+pub(crate) const FDT_END_NODE: u32 = 2;
+pub(crate) const FDT_PROP: u32 = 3;
 pub(crate) const FDT_NOP: u32 = 4;
-const FDT_END: u32 = 5;
+pub(crate) const FDT_END: u32 = 9;
 
 #[derive(Debug, Clone, Copy)]
 #[repr(C)]
@@ -41,7 +42,7 @@ pub struct FdtNode<'b, 'a> {
 }
 
 impl<'b, 'a: 'b> FdtNode<'b, 'a> {
-    fn new(
+    pub(crate) fn new(
         name: &'a str,
         header: &'b LinuxFdt<'a>,
         props: &'a [u8],
@@ -64,8 +65,8 @@ impl<'b, 'a: 'b> FdtNode<'b, 'a> {
                 stream.skip(4);
             }
 
-            if stream.peek_u32().unwrap().get() == FDT_PROP {
-                Some(NodeProperty::parse(&mut stream, self.header))
+            if stream.peek_u32()?.get() == FDT_PROP {
+                NodeProperty::parse(&mut stream, self.header)
             } else {
                 done = true;
                 None
@@ -93,16 +94,8 @@ impl<'b, 'a: 'b> FdtNode<'b, 'a> {
     /// Returns an iterator over the children of the current node
     pub fn children(self) -> impl Iterator<Item = FdtNode<'b, 'a>> {
         let mut stream = FdtData::new(self.props);
-
-        while stream.peek_u32().unwrap().get() == FDT_NOP {
-            stream.skip(4);
-        }
-
-        while stream.peek_u32().unwrap().get() == FDT_PROP {
-            NodeProperty::parse(&mut stream, self.header);
-        }
-
-        let mut done = false;
+        stream.skip_nops();
+        let mut done = skip_properties(&mut stream, self.header).is_none();
 
         core::iter::from_fn(move || {
             if stream.is_empty() || done {
@@ -117,7 +110,7 @@ impl<'b, 'a: 'b> FdtNode<'b, 'a> {
                 let origin = stream.remaining();
                 let ret = {
                     stream.skip(4);
-                    let unit_name = CStr::new(stream.remaining()).expect("unit name").as_str()?;
+                    let unit_name = CStr::new(stream.remaining())?.as_str()?;
                     let full_name_len = unit_name.len() + 1;
                     stream.skip(full_name_len);
 
@@ -130,7 +123,9 @@ impl<'b, 'a: 'b> FdtNode<'b, 'a> {
 
                 stream = FdtData::new(origin);
 
-                skip_current_node(&mut stream, self.header);
+                if skip_current_node(&mut stream, self.header).is_none() {
+                    done = true;
+                }
 
                 ret
             } else {
@@ -202,15 +197,16 @@ impl<'b, 'a: 'b> FdtNode<'b, 'a> {
 
         for property in self.properties() {
             match property.name {
+                // The defaults are kept for a short value.
                 "#address-cells" => {
-                    cell_sizes.address_cells = BigEndianU32::from_bytes(property.value)
-                        .expect("not enough bytes for #address-cells value")
-                        .get() as usize;
+                    if let Some(cells) = BigEndianU32::from_bytes(property.value) {
+                        cell_sizes.address_cells = cells.get() as usize;
+                    }
                 }
                 "#size-cells" => {
-                    cell_sizes.size_cells = BigEndianU32::from_bytes(property.value)
-                        .expect("not enough bytes for #size-cells value")
-                        .get() as usize;
+                    if let Some(cells) = BigEndianU32::from_bytes(property.value) {
+                        cell_sizes.size_cells = cells.get() as usize;
+                    }
                 }
                 _ => {}
             }
@@ -261,6 +257,198 @@ impl<'b, 'a: 'b> FdtNode<'b, 'a> {
         interrupt
     }
 
+    /// `interrupts-extended` property, the interrupt parent and the
+    /// specifier of each interrupt
+    pub fn interrupts_extended(self) -> Option<impl Iterator<Item = PhandleArgs<'b, 'a>> + 'b> {
+        self.phandle_list("interrupts-extended", "#interrupt-cells")
+    }
+
+    /// The interrupt parent of the node: the node named by its own
+    /// `interrupt-parent`, or else by the one of its closest ancestor, up to
+    /// a node with `#interrupt-cells`
+    ///
+    /// Refer to linux: of_irq_find_parent
+    pub fn irq_find_parent(self) -> Option<FdtNode<'b, 'a>> {
+        let mut child = self;
+        // Bounded, the interrupt parents may loop.
+        for _ in 0..MAX_DEPTH {
+            let parent = match child.property("interrupt-parent") {
+                Some(prop) => self.header.find_phandle(BigEndianU32::from_bytes(prop.value)?.get())?,
+                None => {
+                    let (parents, depth) = node_parents(self.header, child.props)?;
+                    let props = parents[..depth].last()?.as_ptr();
+                    self.header.all_nodes().find(|n| core::ptr::eq(n.props.as_ptr(), props))?
+                }
+            };
+            if parent.interrupt_cells().is_some() {
+                return Some(parent);
+            }
+            child = parent;
+        }
+        None
+    }
+
+    /// The interrupt `index`, its parent and specifier, from the
+    /// `interrupts-extended` property or else from `interrupts`
+    ///
+    /// Refer to linux: of_irq_parse_one
+    pub fn interrupt(self, index: usize) -> Option<PhandleArgs<'b, 'a>> {
+        if let Some(prop) = self.property("interrupts-extended") {
+            return self.phandle_entries(prop.value, "#interrupt-cells").nth(index)?;
+        }
+        let value = self.property("interrupts")?.value;
+        let node = self.irq_find_parent()?;
+        let len = node.interrupt_cells()?.checked_mul(4).filter(|&len| len > 0)?;
+        let args = value.get(index.checked_mul(len)?..)?.get(..len)?;
+        Some(PhandleArgs { node, args })
+    }
+
+    /// `clocks` property, the provider and the specifier of each clock
+    pub fn clocks(self) -> Option<impl Iterator<Item = PhandleArgs<'b, 'a>> + 'b> {
+        self.phandle_list("clocks", "#clock-cells")
+    }
+
+    /// The clock called `name` in the `clock-names` property
+    ///
+    /// Refer to linux: of_clk_get_by_name
+    pub fn clock_by_name(self, name: &str) -> Option<PhandleArgs<'b, 'a>> {
+        let index = self.property_match_string("clock-names", name)?;
+        self.parse_phandle_with_args("clocks", "#clock-cells", index)
+    }
+
+    /// The GPIOs of the `<con_id>-gpios` property, or of `gpios` without a
+    /// `con_id`
+    ///
+    /// The deprecated `-gpio` suffix is accepted as well. Empty entries are
+    /// skipped, [`FdtNode::gpio`] counts them.
+    pub fn gpios(
+        self,
+        con_id: Option<&str>,
+    ) -> Option<impl Iterator<Item = PhandleArgs<'b, 'a>> + 'b> {
+        let prop = self.gpio_property(con_id)?;
+        Some(self.phandle_entries(prop.value, "#gpio-cells").flatten())
+    }
+
+    /// The GPIO `index` of the `<con_id>-gpios` property, or of `gpios`
+    /// without a `con_id`
+    ///
+    /// Refer to linux: of_get_named_gpiod_flags
+    pub fn gpio(self, con_id: Option<&str>, index: usize) -> Option<PhandleArgs<'b, 'a>> {
+        let prop = self.gpio_property(con_id)?;
+        self.phandle_entries(prop.value, "#gpio-cells").nth(index)?
+    }
+
+    /// Returns an iterator over the phandle list `name`, each phandle with
+    /// as many argument cells as the `cells_name` property of its node, such
+    /// as `#clock-cells`
+    ///
+    /// Empty entries, a phandle of 0, are skipped. The iteration ends at an
+    /// unknown phandle, a provider without `cells_name` or a truncated entry.
+    ///
+    /// Refer to linux: of_for_each_phandle
+    pub fn phandle_list(
+        self,
+        name: &str,
+        cells_name: &'b str,
+    ) -> Option<impl Iterator<Item = PhandleArgs<'b, 'a>> + 'b> {
+        let prop = self.property(name)?;
+        Some(self.phandle_entries(prop.value, cells_name).flatten())
+    }
+
+    /// The entry `index` of the phandle list `name`, `None` if it is empty
+    ///
+    /// Refer to linux: of_parse_phandle_with_args
+    pub fn parse_phandle_with_args(
+        self,
+        name: &str,
+        cells_name: &str,
+        index: usize,
+    ) -> Option<PhandleArgs<'b, 'a>> {
+        let prop = self.property(name)?;
+        self.phandle_entries(prop.value, cells_name).nth(index)?
+    }
+
+    /// Index of `string` in the string list property `name`, such as a
+    /// clock in `clock-names`
+    ///
+    /// Refer to linux: of_property_match_string
+    pub fn property_match_string(self, name: &str, string: &str) -> Option<usize> {
+        self.property(name)?.as_str_list().position(|s| s == string)
+    }
+
+    /// Translate `addr`, an address of the `reg` property, to a CPU physical
+    /// address through the `ranges` properties of the parent buses
+    ///
+    /// An empty `ranges` maps the child addresses to the same parent
+    /// addresses. Returns `None` if a parent bus has no `ranges`, or if none
+    /// of its ranges contains the address.
+    pub fn translate_address(self, addr: u64) -> Option<u64> {
+        let (parents, depth) = node_parents(self.header, self.props)?;
+        translate(self.header, &parents[..depth], "ranges", addr)
+    }
+
+    /// `dma-ranges` property, the CPU physical addresses of the DMA addresses
+    /// of the child nodes
+    ///
+    /// Returns `None` without a `dma-ranges` property. An empty `dma-ranges`
+    /// yields no range, the DMA addresses are the CPU addresses then. The
+    /// parent addresses are translated through the `dma-ranges` of the parent
+    /// buses, a bus without one maps them unchanged. The ranges that do not
+    /// translate to a CPU address are skipped.
+    ///
+    /// Refer to linux: of_translate_dma_address
+    pub fn dma_ranges(self) -> Option<impl Iterator<Item = DmaRange> + 'b> {
+        let prop = self.property("dma-ranges")?;
+        let (parents, depth) = node_parents(self.header, self.props)?;
+        let header = self.header;
+        let mut entries =
+            range_entries(prop.value, self.cell_sizes(), self.parent_cell_sizes().address_cells);
+
+        Some(core::iter::from_fn(move || {
+            entries.find_map(|(bus_address, parent_address, size)| {
+                let cpu_address =
+                    translate(header, &parents[..depth], "dma-ranges", parent_address)?;
+                Some(DmaRange { bus_address, cpu_address, size })
+            })
+        }))
+    }
+
+    // The entries of a phandle list, `None` for an empty one
+    fn phandle_entries<'c>(
+        self,
+        value: &'a [u8],
+        cells_name: &'c str,
+    ) -> impl Iterator<Item = Option<PhandleArgs<'b, 'a>>> + 'c
+    where
+        'b: 'c,
+    {
+        let header = self.header;
+        let mut stream = FdtData::new(value);
+
+        core::iter::from_fn(move || {
+            let phandle = stream.u32()?.get();
+            if phandle == 0 {
+                return Some(None);
+            }
+            let node = header.find_phandle(phandle)?;
+            let count = BigEndianU32::from_bytes(node.property(cells_name)?.value)?.get() as usize;
+            let args = stream.take(count.checked_mul(4)?)?;
+            Some(Some(PhandleArgs { node, args }))
+        })
+    }
+
+    fn gpio_property(self, con_id: Option<&str>) -> Option<NodeProperty<'a>> {
+        ["gpios", "gpio"].into_iter().find_map(|suffix| {
+            self.properties().find(|p| match con_id {
+                Some(con_id) => {
+                    p.name.strip_suffix(suffix).and_then(|name| name.strip_suffix('-'))
+                        == Some(con_id)
+                }
+                None => p.name == suffix,
+            })
+        })
+    }
+
     pub(crate) fn parent_cell_sizes(self) -> CellSizes {
         let mut cell_sizes = CellSizes::default();
 
@@ -321,6 +509,102 @@ pub struct RawReg<'a> {
     pub size: &'a [u8],
 }
 
+// Deepest nesting of nodes
+const MAX_DEPTH: usize = 64;
+
+// The properties of the ancestors of the node whose properties are `props`,
+// from the root.
+fn node_parents<'a>(
+    header: &LinuxFdt<'a>,
+    props: &'a [u8],
+) -> Option<([&'a [u8]; MAX_DEPTH], usize)> {
+    if let Some(index) = header.index {
+        return crate::index::parents(header, index, props);
+    }
+
+    let mut stream = FdtData::new(header.structs_block());
+    let mut parents: [&[u8]; MAX_DEPTH] = [&[]; MAX_DEPTH];
+    let mut depth = 0;
+
+    loop {
+        stream.skip_nops();
+        match stream.u32()?.get() {
+            FDT_BEGIN_NODE => {
+                let name_len = CStr::new(stream.remaining())?.len();
+                skip_4_aligned(&mut stream, name_len + 1);
+                if core::ptr::eq(stream.remaining().as_ptr(), props.as_ptr()) {
+                    return Some((parents, depth));
+                }
+                *parents.get_mut(depth)? = stream.remaining();
+                depth += 1;
+            }
+            FDT_PROP => {
+                let prop = FdtProperty::from_bytes(&mut stream)?;
+                skip_4_aligned(&mut stream, prop.len.get() as usize);
+            }
+            FDT_END_NODE => depth = depth.checked_sub(1)?,
+            _ => return None,
+        }
+    }
+}
+
+// Translate `addr` from the address space of the last of `parents` up to the
+// root one, through the `ranges` or `dma-ranges` property `name` of each bus.
+// A bus without `dma-ranges` maps the addresses unchanged.
+fn translate<'a>(
+    header: &LinuxFdt<'a>,
+    parents: &[&'a [u8]],
+    name: &str,
+    mut addr: u64,
+) -> Option<u64> {
+    let node = |props| FdtNode { name: "", header, props, parent_props: None };
+
+    for pair in parents.windows(2).rev() {
+        let (up, bus) = (node(pair[0]), node(pair[1]));
+        let ranges = match bus.property(name) {
+            Some(ranges) => ranges,
+            None if name == "dma-ranges" => continue,
+            None => return None,
+        };
+        if ranges.value.is_empty() {
+            continue;
+        }
+
+        addr = range_entries(ranges.value, bus.cell_sizes(), up.cell_sizes().address_cells)
+            .find(|&(child, _, size)| addr >= child && addr - child < size)
+            .and_then(|(child, parent, _)| parent.checked_add(addr - child))?;
+    }
+
+    Some(addr)
+}
+
+// The `(child address, parent address, size)` entries of a `ranges` or
+// `dma-ranges` property.
+fn range_entries(
+    value: &[u8],
+    sizes: CellSizes,
+    parent_address_cells: usize,
+) -> impl Iterator<Item = (u64, u64, u64)> + '_ {
+    let parent_offset = sizes.address_cells * 4;
+    let size_offset = parent_offset + parent_address_cells * 4;
+    let entry_len = size_offset + sizes.size_cells * 4;
+
+    value.chunks_exact(entry_len.max(1)).map(move |entry| {
+        (
+            read_cells(&entry[..parent_offset]),
+            read_cells(&entry[parent_offset..size_offset]),
+            read_cells(&entry[size_offset..]),
+        )
+    })
+}
+
+// A number of big endian cells, only the low 64 bits of wider ones are kept.
+fn read_cells(bytes: &[u8]) -> u64 {
+    bytes.chunks_exact(4).fold(0, |acc, cell| {
+        acc.wrapping_shl(32) | u32::from_be_bytes(cell.try_into().unwrap()) as u64
+    })
+}
+
 pub(crate) fn find_node<'b, 'a: 'b>(
     stream: &mut FdtData<'a>,
     name: &str,
@@ -339,7 +623,7 @@ pub(crate) fn find_node<'b, 'a: 'b>(
         _ => return None,
     }
 
-    let unit_name = CStr::new(stream.remaining()).expect("unit name C str").as_str()?;
+    let unit_name = CStr::new(stream.remaining())?.as_str()?;
 
     let full_name_len = unit_name.len() + 1;
     skip_4_aligned(stream, full_name_len);
@@ -350,7 +634,7 @@ pub(crate) fn find_node<'b, 'a: 'b>(
 
     if (looking_contains_addr && !addr_name_same) || (!looking_contains_addr && !base_name_same) {
         *stream = FdtData::new(curr_data);
-        skip_current_node(stream, header);
+        skip_current_node(stream, header)?;
 
         return None;
     }
@@ -366,9 +650,7 @@ pub(crate) fn find_node<'b, 'a: 'b>(
 
     let parent_props = Some(stream.remaining());
 
-    while stream.peek_u32()?.get() == FDT_PROP {
-        let _ = NodeProperty::parse(stream, header);
-    }
+    skip_properties(stream, header)?;
 
     while stream.peek_u32()?.get() == FDT_BEGIN_NODE {
         if let Some(p) = find_node(stream, next_part, header, parent_props) {
@@ -389,8 +671,8 @@ pub(crate) fn find_node<'b, 'a: 'b>(
 pub(crate) fn all_nodes<'b, 'a: 'b>(header: &'b LinuxFdt<'a>) -> impl Iterator<Item = FdtNode<'b, 'a>> {
     let mut stream = FdtData::new(header.structs_block());
     let mut done = false;
-    let mut parents: [&[u8]; 64] = [&[]; 64];
-    let mut parent_index = 0;
+    let mut parents: [&[u8]; MAX_DEPTH] = [&[]; MAX_DEPTH];
+    let mut parent_index: usize = 0;
 
     core::iter::from_fn(move || {
         if stream.is_empty() || done {
@@ -398,7 +680,7 @@ pub(crate) fn all_nodes<'b, 'a: 'b>(header: &'b LinuxFdt<'a>) -> impl Iterator<I
         }
 
         while stream.peek_u32()?.get() == FDT_END_NODE {
-            parent_index -= 1;
+            parent_index = parent_index.checked_sub(1)?;
             stream.skip(4);
         }
 
@@ -416,22 +698,20 @@ pub(crate) fn all_nodes<'b, 'a: 'b>(header: &'b LinuxFdt<'a>) -> impl Iterator<I
             _ => return None,
         }
 
-        let unit_name = CStr::new(stream.remaining()).expect("unit name C str").as_str().unwrap();
+        let unit_name = CStr::new(stream.remaining())?.as_str()?;
         let full_name_len = unit_name.len() + 1;
         skip_4_aligned(&mut stream, full_name_len);
 
         let curr_node = stream.remaining();
 
         parent_index += 1;
-        parents[parent_index] = curr_node;
+        *parents.get_mut(parent_index)? = curr_node;
 
         while stream.peek_u32()?.get() == FDT_NOP {
             stream.skip(4);
         }
 
-        while stream.peek_u32()?.get() == FDT_PROP {
-            NodeProperty::parse(&mut stream, header);
-        }
+        skip_properties(&mut stream, header)?;
 
         Some(FdtNode {
             name: if unit_name.is_empty() { "/" } else { unit_name },
@@ -445,24 +725,66 @@ pub(crate) fn all_nodes<'b, 'a: 'b>(header: &'b LinuxFdt<'a>) -> impl Iterator<I
     })
 }
 
-pub(crate) fn skip_current_node<'a>(stream: &mut FdtData<'a>, header: &LinuxFdt<'a>) {
-    assert_eq!(stream.u32().unwrap().get(), FDT_BEGIN_NODE, "bad node");
+pub(crate) fn skip_current_node<'a>(stream: &mut FdtData<'a>, header: &LinuxFdt<'a>) -> Option<()> {
+    if stream.u32()?.get() != FDT_BEGIN_NODE {
+        return None;
+    }
 
-    let unit_name = CStr::new(stream.remaining()).expect("unit_name C str").as_str().unwrap();
-    let full_name_len = unit_name.len() + 1;
+    let full_name_len = CStr::new(stream.remaining())?.len() + 1;
     skip_4_aligned(stream, full_name_len);
 
-    while stream.peek_u32().unwrap().get() == FDT_PROP {
-        NodeProperty::parse(stream, header);
-    }
+    skip_properties(stream, header)?;
 
-    while stream.peek_u32().unwrap().get() == FDT_BEGIN_NODE {
-        skip_current_node(stream, header);
+    while stream.peek_u32()?.get() == FDT_BEGIN_NODE {
+        skip_current_node(stream, header)?;
     }
 
     stream.skip_nops();
 
-    assert_eq!(stream.u32().unwrap().get(), FDT_END_NODE, "bad node");
+    (stream.u32()?.get() == FDT_END_NODE).then_some(())
+}
+
+// Skips the properties, and the NOPs between them.
+fn skip_properties<'a>(stream: &mut FdtData<'a>, header: &LinuxFdt<'a>) -> Option<()> {
+    stream.skip_nops();
+    while stream.peek_u32()?.get() == FDT_PROP {
+        NodeProperty::parse(stream, header)?;
+        stream.skip_nops();
+    }
+    Some(())
+}
+
+// Walks the whole structure block once, so that a malformed one is rejected
+// before it is parsed.
+//
+// Refer to libfdt: fdt_check_full
+pub(crate) fn check_structure(header: &LinuxFdt<'_>) -> Result<(), FdtError> {
+    let mut stream = FdtData::new(header.structs_block());
+    let mut depth = 0;
+    let mut root_seen = false;
+
+    loop {
+        match stream.u32().ok_or(FdtError::BadStructure)?.get() {
+            FDT_BEGIN_NODE if depth > 0 || !root_seen => {
+                let name = CStr::new(stream.remaining()).ok_or(FdtError::BadStructure)?;
+                name.as_str().ok_or(FdtError::BadStructure)?;
+                skip_4_aligned(&mut stream, name.len() + 1);
+                depth += 1;
+                // Deeper nodes do not fit the parents of `all_nodes`.
+                if depth >= MAX_DEPTH {
+                    return Err(FdtError::BadStructure);
+                }
+                root_seen = true;
+            }
+            FDT_PROP if depth > 0 => {
+                NodeProperty::parse_prop(&mut stream, header).ok_or(FdtError::BadStructure)?;
+            }
+            FDT_END_NODE if depth > 0 => depth -= 1,
+            FDT_NOP => {}
+            FDT_END if depth == 0 && root_seen => return Ok(()),
+            _ => return Err(FdtError::BadStructure),
+        }
+    }
 }
 
 /// A node property
@@ -489,20 +811,28 @@ impl<'a> NodeProperty<'a> {
         core::str::from_utf8(self.value).map(|s| s.trim_end_matches('\0')).ok()
     }
 
-    fn parse(stream: &mut FdtData<'a>, header: &LinuxFdt<'a>) -> Self {
-        match stream.u32().unwrap().get() {
-            FDT_PROP => {}
-            other => panic!("bad prop, tag: {}", other),
+    /// Attempt to parse the property value as a list of strings
+    pub fn as_str_list(self) -> impl Iterator<Item = &'a str> {
+        Compatible { data: self.value }.all()
+    }
+
+    fn parse(stream: &mut FdtData<'a>, header: &LinuxFdt<'a>) -> Option<Self> {
+        if stream.u32()?.get() != FDT_PROP {
+            return None;
         }
+        Self::parse_prop(stream, header)
+    }
 
-        let prop = FdtProperty::from_bytes(stream).expect("FDT property");
+    // The property after its `FDT_PROP` token
+    fn parse_prop(stream: &mut FdtData<'a>, header: &LinuxFdt<'a>) -> Option<Self> {
+        let prop = FdtProperty::from_bytes(stream)?;
         let data_len = prop.len.get() as usize;
 
-        let data = &stream.remaining()[..data_len];
+        let data = stream.remaining().get(..data_len)?;
 
         skip_4_aligned(stream, data_len);
 
-        NodeProperty { name: header.str_at_offset(prop.name_offset.get() as usize), value: data }
+        Some(NodeProperty { name: header.str_at_offset(prop.name_offset.get() as usize)?, value: data })
     }
 
     /// Attempt to parse the property value as a `reg` property
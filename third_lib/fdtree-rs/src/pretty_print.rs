@@ -16,7 +16,7 @@ pub fn print_node(
         match prop.name {
             "reg" => {
                 write!(f, "{:width$}reg = <", ' ', width = n_spaces + 4)?;
-                for (i, reg) in node.reg().unwrap().enumerate() {
+                for (i, reg) in node.reg().into_iter().flatten().enumerate() {
                     if i > 0 {
                         write!(f, " ")?;
                     }
@@ -29,16 +29,16 @@ pub fn print_node(
                 f,
                 "{:width$}compatible = {:?}",
                 ' ',
-                prop.as_str().unwrap(),
+                prop.as_str().unwrap_or_default(),
                 width = n_spaces + 4
             )?,
-            name if name.contains("-cells") => {
+            name if name.contains("-cells") && prop.as_usize().is_some() => {
                 writeln!(
                     f,
                     "{:width$}{} = <{:#x}>",
                     ' ',
                     name,
-                    prop.as_usize().unwrap(),
+                    prop.as_usize().unwrap_or_default(),
                     width = n_spaces + 4
                 )?;
             }
@@ -55,7 +55,7 @@ pub fn print_node(
                         "{:width$}{} = <{:#x}>",
                         ' ',
                         prop.name,
-                        prop.as_usize().unwrap(),
+                        prop.as_usize().unwrap_or_default(),
                         width = n_spaces + 4
                     )?,
                     _ => writeln!(
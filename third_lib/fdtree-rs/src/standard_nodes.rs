@@ -19,17 +19,16 @@ impl<'b, 'a> Root<'b, 'a> {
     }
 
     /// `model` property
-    pub fn model(self) -> &'a str {
+    pub fn model(self) -> Option<&'a str> {
         self.node
             .properties()
             .find(|p| p.name == "model")
             .and_then(|p| core::str::from_utf8(p.value).map(|s| s.trim_end_matches('\0')).ok())
-            .unwrap()
     }
 
     /// `compatible` property
-    pub fn compatible(self) -> Compatible<'a> {
-        self.node.compatible().unwrap()
+    pub fn compatible(self) -> Option<Compatible<'a>> {
+        self.node.compatible()
     }
 
     /// Returns an iterator over all of the available properties
@@ -80,46 +79,64 @@ pub struct Cpu<'b, 'a> {
 }
 
 impl<'b, 'a> Cpu<'b, 'a> {
-    /// Return the IDs for the given CPU
-    pub fn ids(self) -> CpuIds<'a> {
+    /// Return the IDs for the given CPU, from its required `reg` property
+    pub fn ids(self) -> Option<CpuIds<'a>> {
         let address_cells = self.node.parent_cell_sizes().address_cells;
 
-        CpuIds {
-            reg: self
-                .node
-                .properties()
-                .find(|p| p.name == "reg")
-                .expect("reg is a required property of cpu nodes"),
-            address_cells,
-        }
+        Some(CpuIds { reg: self.node.property("reg")?, address_cells })
     }
 
-    /// `clock-frequency` property
-    pub fn clock_frequency(self) -> usize {
+    /// `clock-frequency` property, of the CPU or of `/cpus`
+    pub fn clock_frequency(self) -> Option<usize> {
         self.node
-            .properties()
-            .find(|p| p.name == "clock-frequency")
+            .property("clock-frequency")
             .or_else(|| self.parent.property("clock-frequency"))
-            .map(|p| match p.value.len() {
-                4 => BigEndianU32::from_bytes(p.value).unwrap().get() as usize,
-                8 => BigEndianU64::from_bytes(p.value).unwrap().get() as usize,
-                _ => unreachable!(),
-            })
-            .expect("clock-frequency is a required property of cpu nodes")
+            .and_then(|p| p.as_usize())
     }
 
-    /// `timebase-frequency` property
-    pub fn timebase_frequency(self) -> usize {
+    /// `timebase-frequency` property, of the CPU or of `/cpus`
+    pub fn timebase_frequency(self) -> Option<usize> {
         self.node
-            .properties()
-            .find(|p| p.name == "timebase-frequency")
+            .property("timebase-frequency")
             .or_else(|| self.parent.property("timebase-frequency"))
-            .map(|p| match p.value.len() {
-                4 => BigEndianU32::from_bytes(p.value).unwrap().get() as usize,
-                8 => BigEndianU64::from_bytes(p.value).unwrap().get() as usize,
-                _ => unreachable!(),
-            })
-            .expect("timebase-frequency is a required property of cpu nodes")
+            .and_then(|p| p.as_usize())
+    }
+
+    /// MPIDR of the CPU, its first ID, `None` if it has bits set outside of
+    /// the affinity fields
+    ///
+    /// Refer to linux: of_get_cpu_hwid, arch/arm64/kernel/smp.c
+    pub fn mpidr(self) -> Option<u64> {
+        const MPIDR_HWID_BITMASK: u64 = 0xff_00ff_ffff;
+        let hwid = self.ids()?.first()? as u64;
+        (hwid & !MPIDR_HWID_BITMASK == 0).then_some(hwid)
+    }
+
+    /// `enable-method` property
+    pub fn enable_method(self) -> Option<EnableMethod<'a>> {
+        Some(match self.property("enable-method")?.as_str_list().next()? {
+            "psci" => EnableMethod::Psci,
+            "spin-table" => EnableMethod::SpinTable,
+            method => EnableMethod::Other(method),
+        })
+    }
+
+    /// `cpu-release-addr` property of a spin-table CPU, always 64 bits
+    ///
+    /// Refer to linux: smp_spin_table_cpu_init
+    pub fn cpu_release_addr(self) -> Option<u64> {
+        Some(BigEndianU64::from_bytes(self.property("cpu-release-addr")?.value)?.get())
+    }
+
+    /// `capacity-dmips-mhz` property, the capacity of the CPU relative to
+    /// the others
+    pub fn capacity_dmips_mhz(self) -> Option<u32> {
+        Some(BigEndianU32::from_bytes(self.property("capacity-dmips-mhz")?.value)?.get())
+    }
+
+    /// The CPU node, such as for its `clocks`
+    pub fn node(self) -> FdtNode<'b, 'a> {
+        self.node
     }
 
     /// Returns an iterator over all of the properties for the CPU node
@@ -133,6 +150,18 @@ impl<'b, 'a> Cpu<'b, 'a> {
     }
 }
 
+/// How a secondary CPU is brought up, the `enable-method` of a CPU node
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnableMethod<'a> {
+    /// The PSCI `CPU_ON` call, see [`LinuxFdt::psci`]
+    Psci,
+    /// The CPU spins until an entry point is written to its
+    /// `cpu-release-addr`
+    SpinTable,
+    /// Another method
+    Other(&'a str),
+}
+
 /// Represents the value of the `reg` property of a `/cpus/cpu*` node which may
 /// contain more than one CPU or thread ID
 #[derive(Debug, Clone, Copy)]
@@ -142,13 +171,9 @@ pub struct CpuIds<'a> {
 }
 
 impl<'a> CpuIds<'a> {
-    /// The first listed CPU ID, which will always exist
-    pub fn first(self) -> usize {
-        match self.address_cells {
-            1 => BigEndianU32::from_bytes(self.reg.value).unwrap().get() as usize,
-            2 => BigEndianU64::from_bytes(self.reg.value).unwrap().get() as usize,
-            n => panic!("address-cells of size {} is currently not supported", n),
-        }
+    /// The first listed CPU ID, `None` if `#address-cells` is not 1 or 2
+    pub fn first(self) -> Option<usize> {
+        self.all().next()
     }
 
     /// Returns an iterator over all of the listed CPU IDs
@@ -159,7 +184,7 @@ impl<'a> CpuIds<'a> {
             _ => Some(match self.address_cells {
                 1 => vals.u32()?.get() as usize,
                 2 => vals.u64()?.get() as usize,
-                n => panic!("address-cells of size {} is currently not supported", n),
+                _ => return None,
             }),
         })
     }
@@ -173,8 +198,8 @@ pub struct Compatible<'a> {
 
 impl<'a> Compatible<'a> {
     /// First compatible string
-    pub fn first(self) -> &'a str {
-        CStr::new(self.data).expect("expected C str").as_str().unwrap()
+    pub fn first(self) -> Option<&'a str> {
+        CStr::new(self.data)?.as_str()
     }
 
     /// Returns an iterator over all available compatible strings
@@ -203,6 +228,35 @@ impl<'a> Compatible<'a> {
     }
 }
 
+/// An entry of a phandle list such as `clocks`, `interrupts-extended` or
+/// `*-gpios`: the node of the phandle and its argument cells
+///
+/// Refer to linux: struct of_phandle_args
+#[derive(Debug, Clone, Copy)]
+pub struct PhandleArgs<'b, 'a> {
+    /// Node of the phandle, the provider
+    pub node: FdtNode<'b, 'a>,
+    pub(crate) args: &'a [u8],
+}
+
+impl<'b, 'a> PhandleArgs<'b, 'a> {
+    /// Number of argument cells, the `#*-cells` of the provider
+    pub fn args_count(self) -> usize {
+        self.args.len() / 4
+    }
+
+    /// The argument cell `index`
+    pub fn arg(self, index: usize) -> Option<u32> {
+        Some(BigEndianU32::from_bytes(self.args.get(index.checked_mul(4)?..)?)?.get())
+    }
+
+    /// Returns an iterator over the argument cells
+    pub fn args(self) -> impl Iterator<Item = u32> + 'a {
+        let mut args = FdtData::new(self.args);
+        core::iter::from_fn(move || Some(args.u32()?.get()))
+    }
+}
+
 /// A memory region
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct MemoryRegion {
@@ -212,6 +266,17 @@ pub struct MemoryRegion {
     pub size: usize,
 }
 
+/// A `dma-ranges` entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DmaRange {
+    /// First DMA address of the child nodes
+    pub bus_address: u64,
+    /// CPU physical address of `bus_address`
+    pub cpu_address: u64,
+    /// Size of the range
+    pub size: u64,
+}
+
 
 /// An iterator over the `reg` property of a node
 #[derive(Debug, Clone)]
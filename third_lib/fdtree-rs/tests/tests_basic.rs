@@ -1,6 +1,7 @@
 static DTB_DATA: &[u8] = include_bytes!("../dtb/test.dtb");
+static RANGES_DTB_DATA: &[u8] = include_bytes!("../dtb/ranges.dtb");
 
-use fdtree_rs::LinuxFdt;
+use fdtree_rs::{DmaRange, FdtBuilder, FdtError, FdtIndexEntry, FdtNode, LinuxFdt};
 
 fn setup() -> LinuxFdt<'static> {
     LinuxFdt::new(DTB_DATA).unwrap()
@@ -10,16 +11,17 @@ fn setup() -> LinuxFdt<'static> {
 #[test]
 fn get_model() {
     let fdt = setup();
-    assert_eq!(fdt.machine(), "riscv-virtio,qemu");
+    assert_eq!(fdt.machine(), Some("riscv-virtio,qemu"));
 }
 
 #[test]
 fn chosen_node() {
     let fdt = setup();
-    let chosen = fdt.chosen();
+    let chosen = fdt.chosen().unwrap();
     assert_eq!(chosen.bootargs().unwrap(), "console=ttyS0");
     assert_eq!(chosen.stdout().unwrap().node.name, "uart@10000000");
     assert_eq!(chosen.stdout().unwrap().options.unwrap(), "115200n8");
+    assert_eq!(chosen.initrd(), None);
 
     let usable_memory_range = chosen.usable_mem_region().unwrap();
 
@@ -128,4 +130,206 @@ fn sys_memory_reservations() {
     assert!(reservations.next().is_none());
 }
 
+#[test]
+fn translate_address() {
+    let fdt = LinuxFdt::new(RANGES_DTB_DATA).unwrap();
+    let translate = |path: &str| {
+        let node = fdt.find_node(path).unwrap();
+        let reg = node.reg().unwrap().next().unwrap();
+        node.translate_address(reg.starting_address as u64)
+    };
+
+    assert_eq!(translate("/soc/serial@7e215040"), Some(0xfe215040));
+    // No range of the bus contains it
+    assert_eq!(translate("/soc/gpio@7f800000"), None);
+    // Through two buses
+    assert_eq!(translate("/soc/bus@7e300000/dev@100"), Some(0xfe300100));
+    // An empty ranges is an identity mapping.
+    assert_eq!(translate("/soc/flat/dev@7c001000"), Some(0xfc001000));
+    // No ranges, the bus addresses are not CPU addresses.
+    assert_eq!(translate("/soc/opaque/dev@0"), None);
+    // Below the root
+    let soc = fdt.find_node("/soc").unwrap();
+    assert_eq!(soc.translate_address(0x1234), Some(0x1234));
+}
+
+#[test]
+fn dma_ranges() {
+    let fdt = LinuxFdt::new(RANGES_DTB_DATA).unwrap();
+    let soc = fdt.find_node("/soc").unwrap();
+    let mut ranges = soc.dma_ranges().unwrap();
+    assert_eq!(
+        ranges.next(),
+        Some(DmaRange { bus_address: 0xc0000000, cpu_address: 0, size: 0x3c000000 })
+    );
+    assert_eq!(
+        ranges.next(),
+        Some(DmaRange { bus_address: 0, cpu_address: 0x1_0000_0000, size: 0x1000 })
+    );
+    assert_eq!(ranges.next(), None);
+
+    // Through the soc dma-ranges, not its ranges: the second range is only
+    // in the soc ranges.
+    let opaque = fdt.find_node("/soc/opaque").unwrap();
+    let ranges: Vec<_> = opaque.dma_ranges().unwrap().collect();
+    assert_eq!(ranges, [DmaRange { bus_address: 0, cpu_address: 0x1000, size: 0x1000 }]);
+    // The flat bus has no dma-ranges, it does not change the addresses.
+    let dma = fdt.find_node("/soc/flat/dma").unwrap();
+    let ranges: Vec<_> = dma.dma_ranges().unwrap().collect();
+    assert_eq!(ranges, [DmaRange { bus_address: 0, cpu_address: 0x2000, size: 0x1000 }]);
+
+    assert!(fdt.find_node("/soc/flat").unwrap().dma_ranges().is_none());
+}
+
+#[test]
+fn indexed_lookups() {
+    let fdt = setup();
+    let nr = fdt.node_count();
+    let mut small = vec![FdtIndexEntry::default(); nr - 1];
+    assert_eq!(fdt.build_index(&mut small).err(), Some(FdtError::IndexTooSmall));
+
+    let mut entries = vec![FdtIndexEntry::default(); nr];
+    let indexed = fdt.build_index(&mut entries).unwrap();
+
+    let mut phandles = 0;
+    for node in fdt.all_nodes() {
+        let Some(phandle) = node.property("phandle").and_then(|p| p.as_usize()) else {
+            continue;
+        };
+        let found = indexed.find_phandle(phandle as u32).unwrap();
+        assert_eq!(found.name, node.name);
+        assert_eq!(found.name, fdt.find_phandle(phandle as u32).unwrap().name);
+        phandles += 1;
+    }
+    assert!(phandles > 0);
+    assert!(indexed.find_phandle(0).is_none());
+    assert!(indexed.find_phandle(0xdead).is_none());
+
+    for path in ["/", "/soc", "/soc/uart@10000000", "/soc/uart", "/cpus/cpu@0", "/chosen"] {
+        let found = indexed.find_node(path).unwrap();
+        let expected = fdt.find_node(path).unwrap();
+        assert_eq!(found.name, expected.name);
+        assert_eq!(found.cell_sizes(), expected.cell_sizes());
+        assert_eq!(found.children().count(), expected.children().count());
+    }
+    assert!(indexed.find_node("/soc/uart@20000000").is_none());
+    assert!(indexed.find_node("/nothing").is_none());
+    assert!(indexed.find_node("soc").is_none());
+    assert_eq!(indexed.chosen().unwrap().bootargs(), Some("console=ttyS0"));
+}
+
+#[test]
+fn indexed_translate_address() {
+    let fdt = LinuxFdt::new(RANGES_DTB_DATA).unwrap();
+    let mut entries = vec![FdtIndexEntry::default(); fdt.node_count()];
+    let fdt = fdt.build_index(&mut entries).unwrap();
+
+    let dev = fdt.find_node("/soc/bus@7e300000/dev@100").unwrap();
+    assert_eq!(dev.translate_address(0x100), Some(0xfe300100));
+    assert_eq!(fdt.find_node("/soc/opaque/dev@0").unwrap().translate_address(0), None);
+    let opaque = fdt.find_node("/soc/opaque").unwrap();
+    assert_eq!(opaque.dma_ranges().unwrap().count(), 1);
+}
+
+fn copy_node(builder: &mut FdtBuilder<'_>, node: FdtNode<'_, '_>) {
+    builder.begin_node(node.name.trim_start_matches('/')).unwrap();
+    for prop in node.properties() {
+        builder.property(prop.name, prop.value).unwrap();
+    }
+    for child in node.children() {
+        copy_node(builder, child);
+    }
+    builder.end_node().unwrap();
+}
 
+#[test]
+fn builder_copy() {
+    let fdt = setup();
+    let mut buf = vec![0; fdt.total_size()];
+    let mut builder = FdtBuilder::new(&mut buf).unwrap();
+    for res in fdt.sys_memory_reservations() {
+        builder.add_reservation(res.address() as u64, res.size() as u64).unwrap();
+    }
+    copy_node(&mut builder, fdt.find_node("/").unwrap());
+    let copy = LinuxFdt::new(builder.finish().unwrap()).unwrap();
+
+    assert_eq!(copy.all_nodes().count(), fdt.all_nodes().count());
+    for (a, b) in fdt.all_nodes().zip(copy.all_nodes()) {
+        assert_eq!(a.name, b.name);
+        assert!(a.properties().map(|p| (p.name, p.value)).eq(b.properties().map(|p| (p.name, p.value))));
+    }
+    assert!(fdt
+        .sys_memory_reservations()
+        .map(|r| (r.address(), r.size()))
+        .eq(copy.sys_memory_reservations().map(|r| (r.address(), r.size()))));
+    assert_eq!(copy.chosen().unwrap().bootargs(), Some("console=ttyS0"));
+    assert_eq!(copy.find_phandle(1).map(|n| n.name), fdt.find_phandle(1).map(|n| n.name));
+}
+
+#[test]
+fn builder_properties() {
+    let mut buf = [0; 512];
+    let mut builder = FdtBuilder::new(&mut buf).unwrap();
+    builder.set_boot_cpuid_phys(1);
+    builder.begin_node("").unwrap();
+    builder.property_u32("#address-cells", 2).unwrap();
+    builder.property_u32("#size-cells", 1).unwrap();
+    builder.property_str("model", "edge").unwrap();
+    builder.begin_node("memory@80000000").unwrap();
+    builder.property_str("device_type", "memory").unwrap();
+    builder.property_cells("reg", &[0, 0x80000000, 0x4000000]).unwrap();
+    builder.end_node().unwrap();
+    builder.begin_node("uart@10000000").unwrap();
+    builder.property_str_list("compatible", &["vendor,uart", "ns16550a"]).unwrap();
+    builder.property_u64("clock", 0x1_0000_0000).unwrap();
+    builder.property_empty("no-loopback-test").unwrap();
+    builder.property_str("status", "okay").unwrap();
+    assert_eq!(builder.property("bad\0name", &[]), Err(FdtError::BadValue));
+    builder.end_node().unwrap();
+    builder.begin_node("empty").unwrap();
+    builder.property_str("status", "disabled").unwrap();
+    builder.end_node().unwrap();
+    builder.end_node().unwrap();
+    assert_eq!(builder.begin_node("second-root"), Err(FdtError::BadState));
+    let blob = builder.finish().unwrap();
+
+    // The names are shared, "cells" of "#size-cells" is not a name.
+    let strings = u32::from_be_bytes(blob[32..36].try_into().unwrap()) as usize;
+    let names = ["#address-cells", "#size-cells", "model", "device_type", "reg", "compatible", "clock"];
+    let names_len: usize = names.iter().map(|n| n.len() + 1).sum();
+    assert_eq!(strings, names_len + "no-loopback-test".len() + 1 + "status".len() + 1);
+    assert_eq!(u32::from_be_bytes(blob[28..32].try_into().unwrap()), 1);
+
+    let fdt = LinuxFdt::new(blob).unwrap();
+    assert_eq!(fdt.machine(), Some("edge"));
+    assert_eq!(fdt.sys_memory_reservations().count(), 0);
+    let region = fdt.mem_nodes().next().unwrap().regions().unwrap().next().unwrap();
+    assert_eq!((region.starting_address as usize, region.size), (0x80000000, 0x4000000));
+    let uart = fdt.find_node("/uart").unwrap();
+    assert!(uart.compatible().unwrap().all().eq(["vendor,uart", "ns16550a"]));
+    assert_eq!(uart.property("clock").unwrap().as_usize(), Some(0x1_0000_0000));
+    assert_eq!(uart.property("no-loopback-test").unwrap().value, &[] as &[u8]);
+    assert_eq!(fdt.find_node("/empty").unwrap().property("status").unwrap().as_str(), Some("disabled"));
+}
+
+#[test]
+fn builder_errors() {
+    let mut buf = [0; 40];
+    assert_eq!(FdtBuilder::new(&mut buf).err(), Some(FdtError::NoSpace));
+
+    let mut buf = [0; 128];
+    let mut builder = FdtBuilder::new(&mut buf).unwrap();
+    assert_eq!(builder.property_u32("early", 0), Err(FdtError::BadState));
+    assert_eq!(builder.end_node(), Err(FdtError::BadState));
+    builder.begin_node("").unwrap();
+    assert_eq!(builder.add_reservation(0, 0x1000), Err(FdtError::BadState));
+    assert_eq!(builder.property("big", &[0; 128]), Err(FdtError::NoSpace));
+    // The failed property left nothing behind.
+    builder.property_u32("small", 1).unwrap();
+    builder.begin_node("open").unwrap();
+    assert_eq!(builder.finish().err(), Some(FdtError::BadState));
+
+    let mut buf = [0; 128];
+    let builder = FdtBuilder::new(&mut buf).unwrap();
+    assert_eq!(builder.finish().err(), Some(FdtError::BadState));
+}
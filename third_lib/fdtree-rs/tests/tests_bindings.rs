@@ -0,0 +1,202 @@
+static BINDINGS_DTB_DATA: &[u8] = include_bytes!("../dtb/bindings.dtb");
+
+use fdtree_rs::{EnableMethod, FdtBuilder, LinuxFdt, PhandleArgs, PsciMethod};
+
+fn setup() -> LinuxFdt<'static> {
+    LinuxFdt::new(BINDINGS_DTB_DATA).unwrap()
+}
+
+fn args<'a>(entry: PhandleArgs<'_, 'a>) -> (&'a str, Vec<u32>) {
+    (entry.node.name, entry.args().collect())
+}
+
+#[test]
+fn cpus() {
+    let fdt = setup();
+    let cpus: Vec<_> = fdt.cpus().collect();
+    // Not cpu-map, which has no device_type
+    assert_eq!(cpus.len(), 3);
+
+    assert_eq!(cpus[0].mpidr(), Some(0));
+    assert_eq!(cpus[0].enable_method(), Some(EnableMethod::SpinTable));
+    assert_eq!(cpus[0].cpu_release_addr(), Some(0xd8));
+    assert_eq!(cpus[0].capacity_dmips_mhz(), Some(578));
+
+    assert_eq!(cpus[1].mpidr(), Some(0x100));
+    assert_eq!(cpus[1].enable_method(), Some(EnableMethod::Psci));
+    assert_eq!(cpus[1].cpu_release_addr(), None);
+    assert_eq!(cpus[1].capacity_dmips_mhz(), Some(1024));
+    let clock = cpus[1].node().clocks().unwrap().next().unwrap();
+    assert_eq!(args(clock), ("cru", vec![1]));
+
+    assert_eq!(cpus[2].mpidr(), Some(0xff_0000_0000));
+    assert_eq!(
+        cpus[2].enable_method(),
+        Some(EnableMethod::Other("acme,mailbox"))
+    );
+    assert_eq!(cpus[2].capacity_dmips_mhz(), None);
+
+    // The cpu-map refers to the CPUs by phandle.
+    let core0 = fdt.find_node("/cpus/cpu-map/cluster0/core0").unwrap();
+    let cpu = fdt.find_phandle(core0.property("cpu").unwrap().as_usize().unwrap() as u32);
+    assert_eq!(cpu.unwrap().name, "cpu@0");
+
+    // Bits outside of the affinity fields
+    let mut buf = vec![0; 512];
+    let mut builder = FdtBuilder::new(&mut buf).unwrap();
+    builder.begin_node("").unwrap();
+    builder.begin_node("cpus").unwrap();
+    builder.property_u32("#address-cells", 1).unwrap();
+    builder.property_u32("#size-cells", 0).unwrap();
+    builder.begin_node("cpu@1000000").unwrap();
+    builder.property_str("device_type", "cpu").unwrap();
+    builder.property_u32("reg", 0x100_0000).unwrap();
+    builder.end_node().unwrap();
+    builder.end_node().unwrap();
+    builder.end_node().unwrap();
+    let fdt = LinuxFdt::new(builder.finish().unwrap()).unwrap();
+    let cpu = fdt.cpus().next().unwrap();
+    assert_eq!(cpu.ids().unwrap().first(), Some(0x100_0000));
+    assert_eq!((cpu.mpidr(), cpu.enable_method()), (None, None));
+}
+
+#[test]
+fn psci() {
+    let fdt = setup();
+    let psci = fdt.psci().unwrap();
+    assert_eq!(psci.compatible().unwrap().first(), Some("arm,psci-1.0"));
+    assert_eq!(psci.method(), Some(PsciMethod::Hvc));
+    assert_eq!(psci.cpu_suspend(), Some(0xc400_0001));
+    assert_eq!(psci.cpu_off(), Some(0x8400_0002));
+    assert_eq!(psci.cpu_on(), Some(0xc400_0003));
+    assert_eq!(psci.migrate(), Some(0xc400_0005));
+
+    assert!(
+        LinuxFdt::new(include_bytes!("../dtb/test.dtb"))
+            .unwrap()
+            .psci()
+            .is_none()
+    );
+
+    // A disabled node does not hide a later available one.
+    let mut buf = vec![0; 512];
+    let mut builder = FdtBuilder::new(&mut buf).unwrap();
+    builder.begin_node("").unwrap();
+    builder.begin_node("psci-old").unwrap();
+    builder.property_str("compatible", "arm,psci").unwrap();
+    builder.property_str("status", "disabled").unwrap();
+    builder.property_str("method", "hvc").unwrap();
+    builder.end_node().unwrap();
+    builder.begin_node("psci").unwrap();
+    builder.property_str("compatible", "arm,psci-1.0").unwrap();
+    builder.property_str("method", "smc").unwrap();
+    builder.end_node().unwrap();
+    builder.end_node().unwrap();
+    let fdt = LinuxFdt::new(builder.finish().unwrap()).unwrap();
+    assert_eq!(fdt.psci().unwrap().method(), Some(PsciMethod::Smc));
+}
+
+#[test]
+fn chosen() {
+    let fdt = setup();
+    let chosen = fdt.chosen().unwrap();
+    assert_eq!(chosen.initrd(), Some((0x4800_0000, 0x4810_0000)));
+    assert_eq!(chosen.rng_seed(), Some(&(1..=16).collect::<Vec<u8>>()[..]));
+    assert_eq!(chosen.kaslr_seed(), Some(0x1234_5678_9abc_def0));
+}
+
+#[test]
+fn clocks() {
+    let fdt = setup();
+    let mmc = fdt.find_node("/mmc").unwrap();
+    let clocks: Vec<_> = mmc.clocks().unwrap().map(args).collect();
+    assert_eq!(
+        clocks,
+        [("cru", vec![7]), ("osc", vec![]), ("cru", vec![9])]
+    );
+
+    assert_eq!(
+        mmc.property_match_string("clock-names", "ciu-drive"),
+        Some(2)
+    );
+    let ciu = mmc.clock_by_name("ciu").unwrap();
+    assert_eq!(
+        (ciu.node.name, ciu.args_count(), ciu.arg(0)),
+        ("osc", 0, None)
+    );
+    let biu = mmc.clock_by_name("biu").unwrap();
+    assert_eq!((biu.args_count(), biu.arg(0)), (1, Some(7)));
+    assert!(mmc.clock_by_name("ciu-sample").is_none());
+
+    // The cru needs a cell, which is missing.
+    let broken = fdt.find_node("/broken").unwrap();
+    assert_eq!(broken.clocks().unwrap().count(), 0);
+    assert!(fdt.find_node("/osc").unwrap().clocks().is_none());
+}
+
+#[test]
+fn interrupts_extended() {
+    let fdt = setup();
+    let mmc = fdt.find_node("/mmc").unwrap();
+    let irqs: Vec<_> = mmc.interrupts_extended().unwrap().map(args).collect();
+    assert_eq!(irqs, [("intc", vec![0, 30, 4]), ("gic-its", vec![8, 1])]);
+
+    // An unknown interrupt parent
+    let broken = fdt.find_node("/broken").unwrap();
+    assert_eq!(broken.interrupts_extended().unwrap().count(), 0);
+}
+
+#[test]
+fn interrupt() {
+    let fdt = setup();
+    let mmc = fdt.find_node("/mmc").unwrap();
+    assert_eq!(args(mmc.interrupt(1).unwrap()), ("gic-its", vec![8, 1]));
+    assert!(mmc.interrupt(2).is_none());
+
+    // The interrupt parent of an ancestor, and of the node itself
+    let timer = fdt.find_node("/soc/timer").unwrap();
+    assert_eq!(timer.irq_find_parent().unwrap().name, "armctrl");
+    let irqs: Vec<_> = (0..).map_while(|i| timer.interrupt(i)).map(args).collect();
+    assert_eq!(irqs, [("armctrl", vec![1, 0]), ("armctrl", vec![1, 1])]);
+    let uart = fdt.find_node("/soc/uart").unwrap();
+    assert_eq!(args(uart.interrupt(0).unwrap()), ("intc", vec![0, 57, 4]));
+    assert!(uart.interrupt(1).is_none());
+
+    // The one of the root
+    let psci = fdt.find_node("/psci").unwrap();
+    assert_eq!(psci.irq_find_parent().unwrap().name, "intc");
+    assert!(psci.interrupt(0).is_none());
+    let broken = fdt.find_node("/broken").unwrap();
+    assert!(broken.interrupt(0).is_none());
+}
+
+#[test]
+fn gpios() {
+    let fdt = setup();
+    let mmc = fdt.find_node("/mmc").unwrap();
+    let cd: Vec<_> = mmc.gpios(Some("cd")).unwrap().map(args).collect();
+    assert_eq!(cd, [("gpio", vec![17, 1])]);
+    // The deprecated suffix
+    assert_eq!(
+        args(mmc.gpio(Some("reset"), 0).unwrap()),
+        ("gpio", vec![3, 0])
+    );
+    assert!(mmc.gpios(Some("wp")).is_none());
+    assert!(mmc.gpios(Some("c")).is_none());
+
+    // The second entry is empty.
+    let gpios: Vec<_> = mmc.gpios(None).unwrap().map(args).collect();
+    assert_eq!(gpios, [("gpio", vec![1, 0]), ("gpio", vec![2, 1])]);
+    assert!(mmc.gpio(None, 1).is_none());
+    assert_eq!(args(mmc.gpio(None, 2).unwrap()), ("gpio", vec![2, 1]));
+    assert!(mmc.gpio(None, 3).is_none());
+
+    let index = mmc
+        .parse_phandle_with_args("gpios", "#gpio-cells", 2)
+        .unwrap();
+    assert_eq!(index.arg(0), Some(2));
+
+    // Truncated, the controller has two cells
+    let broken = fdt.find_node("/broken").unwrap();
+    assert_eq!(broken.gpios(Some("power")).unwrap().count(), 0);
+}
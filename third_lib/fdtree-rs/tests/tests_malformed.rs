@@ -0,0 +1,139 @@
+static DTB_DATA: &[u8] = include_bytes!("../dtb/test.dtb");
+static RANGES_DTB_DATA: &[u8] = include_bytes!("../dtb/ranges.dtb");
+static BINDINGS_DTB_DATA: &[u8] = include_bytes!("../dtb/bindings.dtb");
+
+use fdtree_rs::fuzzing::exercise;
+use fdtree_rs::{FdtBuilder, FdtError, LinuxFdt};
+
+fn set_u32(blob: &mut [u8], offset: usize, val: u32) {
+    blob[offset..offset + 4].copy_from_slice(&val.to_be_bytes());
+}
+
+const CORRUPTIONS: [u8; 8] = [0x00, 0x01, 0x02, 0x03, 0x09, 0x7f, 0x80, 0xff];
+
+#[test]
+fn corrupted_bytes() {
+    exercise(RANGES_DTB_DATA);
+    for i in 0..RANGES_DTB_DATA.len() {
+        for val in CORRUPTIONS {
+            let mut corrupted = RANGES_DTB_DATA.to_vec();
+            corrupted[i] = val;
+            exercise(&corrupted);
+        }
+    }
+
+    // Some bytes of the bigger trees, the stride reaches every byte lane and
+    // corruption.
+    for blob in [DTB_DATA, BINDINGS_DTB_DATA] {
+        exercise(blob);
+        for i in (0..blob.len()).step_by(7) {
+            let mut corrupted = blob.to_vec();
+            corrupted[i] = CORRUPTIONS[i % CORRUPTIONS.len()];
+            exercise(&corrupted);
+        }
+    }
+}
+
+#[test]
+fn truncated() {
+    for blob in [DTB_DATA, RANGES_DTB_DATA] {
+        for len in 0..blob.len() {
+            assert_eq!(
+                LinuxFdt::new(&blob[..len]).err(),
+                Some(FdtError::BufferTooSmall)
+            );
+
+            // The header claims what is left, the blocks no longer fit.
+            if len >= 40 {
+                let mut truncated = blob[..len].to_vec();
+                set_u32(&mut truncated, 4, len as u32);
+                assert!(LinuxFdt::new(&truncated).is_err());
+                exercise(&truncated);
+            }
+        }
+    }
+}
+
+#[test]
+fn bad_header() {
+    let fdt = LinuxFdt::new(DTB_DATA).unwrap();
+    assert_eq!(LinuxFdt::check_header(DTB_DATA), Ok(fdt.total_size()));
+
+    let with = |offset, val| {
+        let mut blob = DTB_DATA.to_vec();
+        set_u32(&mut blob, offset, val);
+        LinuxFdt::new(&blob).err()
+    };
+    assert_eq!(with(0, 0xfeedd00d), Some(FdtError::BadMagic));
+    // version, last_comp_version
+    assert_eq!(with(20, 16), Some(FdtError::BadVersion));
+    assert_eq!(with(24, 18), Some(FdtError::BadVersion));
+    // off_dt_struct, off_dt_strings, off_mem_rsvmap
+    assert_eq!(with(8, 0x42), Some(FdtError::BadLayout));
+    assert_eq!(with(8, 0xffff_fffc), Some(FdtError::BadLayout));
+    assert_eq!(with(12, fdt.total_size() as u32), Some(FdtError::BadLayout));
+    assert_eq!(with(16, 0x2c), Some(FdtError::BadLayout));
+    assert_eq!(with(16, 8), Some(FdtError::BadLayout));
+    // size_dt_strings, size_dt_struct
+    assert_eq!(with(32, 0xffff_fff0), Some(FdtError::BadLayout));
+    assert_eq!(with(36, 6), Some(FdtError::BadLayout));
+    // The struct block ends early, without FDT_END.
+    assert_eq!(with(36, 8), Some(FdtError::BadStructure));
+}
+
+fn nested(depth: usize) -> Result<(), FdtError> {
+    let mut buf = vec![0; 4096];
+    let mut builder = FdtBuilder::new(&mut buf).unwrap();
+    for _ in 0..depth {
+        builder.begin_node("n").unwrap();
+    }
+    for _ in 0..depth {
+        builder.end_node().unwrap();
+    }
+    let blob = builder.finish().unwrap();
+    LinuxFdt::new(blob).map(|fdt| assert_eq!(fdt.all_nodes().count(), depth))
+}
+
+#[test]
+fn bad_structure() {
+    assert_eq!(nested(63), Ok(()));
+    assert_eq!(nested(64), Err(FdtError::BadStructure));
+
+    // The name of a property outside of the strings block
+    let off_struct = u32::from_be_bytes(DTB_DATA[8..12].try_into().unwrap()) as usize;
+    let root_name_end = off_struct + 8;
+    let mut blob = DTB_DATA.to_vec();
+    assert_eq!(
+        u32::from_be_bytes(blob[root_name_end..root_name_end + 4].try_into().unwrap()),
+        3
+    );
+    set_u32(&mut blob, root_name_end + 8, 0x10000);
+    assert_eq!(LinuxFdt::new(&blob).err(), Some(FdtError::BadStructure));
+
+    // A second root node
+    let mut buf = vec![0; 4096];
+    let mut builder = FdtBuilder::new(&mut buf).unwrap();
+    builder.begin_node("").unwrap();
+    builder.end_node().unwrap();
+    let blob = builder.finish().unwrap();
+    let mut twice = blob.to_vec();
+    let end = twice.len() - u32::from_be_bytes(blob[32..36].try_into().unwrap()) as usize - 4;
+    // FDT_BEGIN_NODE "" FDT_END_NODE in place of FDT_END, then FDT_END
+    twice.splice(
+        end..end + 4,
+        [0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 9],
+    );
+    let total = twice.len() as u32;
+    set_u32(&mut twice, 4, total);
+    set_u32(
+        &mut twice,
+        12,
+        u32::from_be_bytes(blob[12..16].try_into().unwrap()) + 12,
+    );
+    set_u32(
+        &mut twice,
+        36,
+        u32::from_be_bytes(blob[36..40].try_into().unwrap()) + 12,
+    );
+    assert_eq!(LinuxFdt::new(&twice).err(), Some(FdtError::BadStructure));
+}